@@ -13,6 +13,7 @@ enum HashFunctions {
   HASH_FUNCTIONS_MURMUR64_PROTEIN = 2,
   HASH_FUNCTIONS_MURMUR64_DAYHOFF = 3,
   HASH_FUNCTIONS_MURMUR64_HP = 4,
+  HASH_FUNCTIONS_NT_HASH64_DNA = 5,
 };
 typedef uint32_t HashFunctions;
 
@@ -34,12 +35,16 @@ enum SourmashErrorCode {
   SOURMASH_ERROR_CODE_NO_MIN_HASH_FOUND = 110,
   SOURMASH_ERROR_CODE_EMPTY_SIGNATURE = 111,
   SOURMASH_ERROR_CODE_MULTIPLE_SKETCHES_FOUND = 112,
+  SOURMASH_ERROR_CODE_MISMATCH_HASH_FUNCTION = 113,
   SOURMASH_ERROR_CODE_INVALID_DNA = 1101,
   SOURMASH_ERROR_CODE_INVALID_PROT = 1102,
   SOURMASH_ERROR_CODE_INVALID_CODON_LENGTH = 1103,
   SOURMASH_ERROR_CODE_INVALID_HASH_FUNCTION = 1104,
   SOURMASH_ERROR_CODE_READ_DATA = 1201,
   SOURMASH_ERROR_CODE_STORAGE = 1202,
+  SOURMASH_ERROR_CODE_INVALID_PICKLIST = 1203,
+  SOURMASH_ERROR_CODE_DATASET_NOT_FOUND = 1204,
+  SOURMASH_ERROR_CODE_INTEGRITY_ERROR = 1205,
   SOURMASH_ERROR_CODE_HLL_PRECISION_BOUNDS = 1301,
   SOURMASH_ERROR_CODE_ANI_ESTIMATION_ERROR = 1401,
   SOURMASH_ERROR_CODE_IO = 100001,
@@ -50,6 +55,7 @@ enum SourmashErrorCode {
   SOURMASH_ERROR_CODE_CSV_ERROR = 100006,
   SOURMASH_ERROR_CODE_ROCKS_DB_ERROR = 100007,
   SOURMASH_ERROR_CODE_ZIP_ERROR = 100008,
+  SOURMASH_ERROR_CODE_NEEDLETAIL_ERROR = 100009,
 };
 typedef uint32_t SourmashErrorCode;
 
@@ -206,7 +212,7 @@ const uint64_t *kmerminhash_get_mins(const SourmashKmerMinHash *ptr, uintptr_t *
 
 uintptr_t kmerminhash_get_mins_size(const SourmashKmerMinHash *ptr);
 
-HashFunctions kmerminhash_hash_function(const SourmashKmerMinHash *ptr);
+uint32_t kmerminhash_hash_function(const SourmashKmerMinHash *ptr);
 
 void kmerminhash_hash_function_set(SourmashKmerMinHash *ptr, HashFunctions hash_function);
 
@@ -319,6 +325,13 @@ SourmashNodegraph *nodegraph_with_tables(uintptr_t ksize,
                                          uintptr_t starting_size,
                                          uintptr_t n_tables);
 
+const SourmashSearchResult *const *revindex_best_matches(const SourmashRevIndex *ptr,
+                                                         const SourmashSignature *sig_ptr,
+                                                         uintptr_t k,
+                                                         double threshold,
+                                                         bool do_containment,
+                                                         uintptr_t *size);
+
 void revindex_free(SourmashRevIndex *ptr);
 
 const SourmashSearchResult *const *revindex_gather(const SourmashRevIndex *ptr,
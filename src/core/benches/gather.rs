@@ -4,7 +4,6 @@ use std::path::PathBuf;
 
 use sourmash::collection::Collection;
 use sourmash::signature::Signature;
-use sourmash::sketch::Sketch;
 use sourmash::{index::calculate_gather_stats, storage::SigStore};
 
 use codspeed_criterion_compat::{black_box, criterion_group, criterion_main, Criterion};
@@ -38,13 +37,13 @@ fn gather_stats_benchmarks(c: &mut Criterion) {
         group.bench_function(&test_name, |b| {
             b.iter(|| {
                 calculate_gather_stats(
-                    black_box(&orig_query),
+                    black_box(orig_query),
                     black_box(query.clone()),
                     black_box(match_sig.clone()),
                     black_box(42), // Example match_size
                     black_box(1),  // Example gather_result_rank
                     black_box(200),
-                    black_box(total_weighted_hashes.try_into().unwrap()),
+                    black_box(total_weighted_hashes),
                     black_box(calc_abund_stats),
                     black_box(calc_ani_ci),
                     black_box(None), // don't set custom confidence intervals
@@ -46,16 +46,7 @@ fn r1_to_q(k: f64, r1: f64) -> f64 {
     1.0 - (1.0 - r1).powi(k as i32)
 }
 
-// prior versions of ani estimation also returned this value by default.
-// BUT, it's not really something we need to calculate for prefetch/gather,
-// since there will always be something in common (or comparison would not be happening)
-// I'm not sure where the right place to put this back in is.. perhaps add minhash.contained_ani()
-// and return this there.
-// Usage:
-// let prob_nothing_in_common =
-//     get_exp_probability_nothing_common(n_unique_kmers, ksize, point_estimate, f_scaled)?;
-// let prob_threshold = prob_threshold.unwrap_or(1e-3);
-#[allow(dead_code)]
+// Probability of sharing no hashes by chance alone, given the ANI estimate.
 fn get_exp_probability_nothing_common(
     ani_estimate: f64,
     ksize: f64,
@@ -77,6 +68,25 @@ fn get_exp_probability_nothing_common(
     }
 }
 
+/// Whether a match at `ani_estimate` could have been missed because no hashes
+/// are shared by chance alone (probability above `prob_threshold`, 1e-3 by default).
+pub fn potential_false_negative(
+    ani_estimate: f64,
+    ksize: f64,
+    scaled: ScaledType,
+    n_unique_kmers: u64,
+    prob_threshold: Option<f64>,
+) -> bool {
+    let prob_threshold = prob_threshold.unwrap_or(1e-3);
+    get_exp_probability_nothing_common(
+        ani_estimate,
+        ksize,
+        1.0 / scaled as f64,
+        n_unique_kmers as f64,
+    )
+    .map_or(false, |prob| prob > prob_threshold)
+}
+
 /// Streamlined function for ANI from containment.
 /// todo: report ANI as % in 5.0?
 pub fn ani_from_containment(containment: f64, ksize: f64) -> f64 {
@@ -147,7 +157,7 @@ pub fn ani_ci_from_containment(
 mod tests {
 
     use super::*;
-    use std::f64::EPSILON;
+    const EPSILON: f64 = f64::EPSILON;
 
     #[test]
     fn test_containment_to_ani_zero() {
@@ -344,7 +354,7 @@ mod tests {
     #[test]
     fn test_get_exp_probability_nothing_common() {
         let contain = 0.1;
-        let ksize = 31 as f64;
+        let ksize = 31_f64;
         let scaled = 10;
         let f_scaled = 1.0 / scaled as f64;
         let n_unique_kmers = 1000;
@@ -354,7 +364,7 @@ mod tests {
             get_exp_probability_nothing_common(ani, ksize, f_scaled, n_unique_kmers as f64)
                 .unwrap();
         assert!(
-            result >= 0.0 && result <= 1.0,
+            (0.0..=1.0).contains(&result),
             "The result should be a valid probability"
         );
         assert!((result - 0.000026561398887587855) < EPSILON);
@@ -1,10 +1,19 @@
+use std::fs::File;
+use std::io::{self, BufReader, Read};
+use std::path::Path;
+
 use getset::{CopyGetters, Getters, Setters};
+use needletail::errors::ParseErrorKind;
+use needletail::parse_fastx_reader;
+#[cfg(feature = "parallel")]
+use rayon::prelude::*;
 use typed_builder::TypedBuilder;
 
 use crate::encodings::HashFunctions;
 use crate::signature::Signature;
 use crate::sketch::minhash::{max_hash_for_scaled, KmerMinHashBTree};
 use crate::sketch::Sketch;
+use crate::Error;
 
 impl Signature {
     pub fn from_params(params: &ComputeParameters) -> Signature {
@@ -14,6 +23,7 @@ impl Signature {
             .hash_function("0.murmur64")
             .name(params.merge.clone())
             .filename(None)
+            .license(params.license.clone())
             .signatures(template)
             .build()
     }
@@ -186,3 +196,296 @@ pub fn build_template(params: &ComputeParameters) -> Vec<Sketch> {
         })
         .collect()
 }
+
+/// Sketch the FASTA/FASTQ files in `filenames` (optionally compressed),
+/// following the same rules as `sourmash compute`.
+///
+/// A filename of `-` reads from stdin.
+pub fn compute<P: AsRef<Path>>(
+    filenames: &[P],
+    params: &ComputeParameters,
+) -> Result<Vec<Signature>, Error> {
+    let inputs = filenames
+        .iter()
+        .map(|path| {
+            let path = path.as_ref();
+            let filename = path.to_string_lossy().into_owned();
+            let rdr: Box<dyn Read + Send> = if filename == "-" {
+                Box::new(io::stdin())
+            } else {
+                Box::new(File::open(path)?)
+            };
+            Ok((filename, rdr))
+        })
+        .collect::<Result<Vec<_>, Error>>()?;
+
+    compute_from_readers(inputs, params)
+}
+
+/// Sketch sequences from `(filename, reader)` pairs.
+///
+/// - `singleton`: one signature per record, named after the record.
+/// - `merge`: one signature for all inputs, named after `merge`.
+/// - otherwise one signature per input, named after the first record
+///   if `name_from_first` is set.
+///
+/// Inputs without any records don't produce signatures.
+/// Under the `parallel` feature inputs are processed concurrently,
+/// using up to `processes` threads.
+pub fn compute_from_readers<R: Read + Send>(
+    inputs: Vec<(String, R)>,
+    params: &ComputeParameters,
+) -> Result<Vec<Signature>, Error> {
+    let run = move || -> Result<Vec<Signature>, Error> {
+        cfg_if::cfg_if! {
+            if #[cfg(feature = "parallel")] {
+                let iter = inputs.into_par_iter();
+            } else {
+                let iter = inputs.into_iter();
+            }
+        }
+
+        let per_input = iter
+            .map(|(filename, rdr)| compute_from_reader(rdr, &filename, params))
+            .collect::<Result<Vec<_>, Error>>()?;
+
+        if let Some(name) = params.merge() {
+            let mut merged: Option<Signature> = None;
+            for sig in per_input.into_iter().flatten() {
+                match merged {
+                    None => merged = Some(sig),
+                    Some(ref mut m) => merge_sketches(m, &sig)?,
+                }
+            }
+            Ok(merged
+                .map(|mut sig| {
+                    sig.set_name(name);
+                    vec![sig]
+                })
+                .unwrap_or_default())
+        } else {
+            Ok(per_input.into_iter().flatten().collect())
+        }
+    };
+
+    cfg_if::cfg_if! {
+        if #[cfg(feature = "parallel")] {
+            let pool = rayon::ThreadPoolBuilder::new()
+                .num_threads(params.processes)
+                .build()
+                .map_err(|e| Error::Internal {
+                    message: e.to_string(),
+                })?;
+            pool.install(run)
+        } else {
+            run()
+        }
+    }
+}
+
+/// Sketch sequences from a single input.
+///
+/// `merge` is ignored here: all records end up in one signature,
+/// same as the non-singleton case.
+pub fn compute_from_reader<R: Read + Send>(
+    reader: R,
+    filename: &str,
+    params: &ComputeParameters,
+) -> Result<Vec<Signature>, Error> {
+    let (rdr, _format) = match niffler::send::get_reader(Box::new(reader)) {
+        Ok(r) => r,
+        Err(niffler::Error::FileTooShort) => return Ok(vec![]),
+        Err(e) => return Err(e.into()),
+    };
+    let mut parser = match parse_fastx_reader(BufReader::new(rdr)) {
+        Ok(parser) => parser,
+        Err(e) if e.kind == ParseErrorKind::EmptyFile => return Ok(vec![]),
+        Err(e) => return Err(e.into()),
+    };
+
+    // stdin doesn't have a meaningful filename
+    let filename = if filename == "-" { "" } else { filename };
+
+    let template = Signature::from_params(params);
+    let mut sigs = vec![];
+    let mut current: Option<Signature> = None;
+
+    while let Some(record) = parser.next() {
+        let record = record?;
+
+        let sig = if params.singleton {
+            let mut sig = template.clone();
+            sig.set_name(&String::from_utf8_lossy(record.id()));
+            sig.set_filename(filename);
+            sigs.push(sig);
+            sigs.last_mut().unwrap()
+        } else {
+            current.get_or_insert_with(|| {
+                let mut sig = template.clone();
+                sig.name = if params.name_from_first {
+                    Some(String::from_utf8_lossy(record.id()).into_owned())
+                } else {
+                    None
+                };
+                sig.set_filename(filename);
+                sig
+            })
+        };
+
+        add_seq(sig, &record.seq(), params)?;
+    }
+
+    sigs.extend(current);
+    Ok(sigs)
+}
+
+fn add_seq(sig: &mut Signature, seq: &[u8], params: &ComputeParameters) -> Result<(), Error> {
+    if params.input_is_protein {
+        sig.add_protein(seq)
+    } else {
+        sig.add_sequence(seq, params.force || !params.check_sequence)
+    }
+}
+
+fn merge_sketches(sig: &mut Signature, other: &Signature) -> Result<(), Error> {
+    for (sketch, other) in sig.signatures.iter_mut().zip(other.signatures.iter()) {
+        match (sketch, other) {
+            (Sketch::LargeMinHash(mh), Sketch::LargeMinHash(other)) => mh.merge(other)?,
+            (Sketch::MinHash(mh), Sketch::MinHash(other)) => mh.merge(other)?,
+            _ => return Err(Error::MismatchSignatureType),
+        }
+    }
+    sig.set_filename(&other.filename());
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use std::path::PathBuf;
+
+    use super::*;
+    use crate::signature::SigsTrait;
+
+    fn test_data(name: &str) -> PathBuf {
+        let mut filename = PathBuf::from(env!("CARGO_MANIFEST_DIR"));
+        filename.push("../../tests/test-data");
+        filename.push(name);
+        filename
+    }
+
+    #[test]
+    fn compute_one_sig_per_file() {
+        let params = ComputeParameters::builder().ksizes(vec![21, 31]).build();
+
+        let inputs = [test_data("short.fa"), test_data("genome-s10.fa.gz")];
+        let sigs = compute(&inputs, &params).unwrap();
+
+        assert_eq!(sigs.len(), 2);
+        for (sig, input) in sigs.iter().zip(inputs.iter()) {
+            assert_eq!(sig.signatures.len(), 2);
+            assert_eq!(sig.name(), None);
+            assert_eq!(sig.filename(), input.to_string_lossy());
+            assert!(sig.sketches().iter().all(|s| match s {
+                Sketch::LargeMinHash(mh) => mh.size() > 0,
+                _ => false,
+            }));
+        }
+    }
+
+    const TWO_RECORDS: &[u8] = b">first record\nACGTACGTTAGCTAGCTACGATCGATCGATTTAGCTAGCTAGCTAGC\n>second\nGGCTAGCTTTACGATCGACGAGCTAGCATCGACTACGACTACGACTAC\n";
+
+    #[test]
+    fn compute_singleton() {
+        let params = ComputeParameters::builder()
+            .ksizes(vec![21])
+            .singleton(true)
+            .build();
+
+        let sigs = compute_from_readers(vec![("-".into(), TWO_RECORDS)], &params).unwrap();
+
+        assert_eq!(sigs.len(), 2);
+        assert_eq!(sigs[0].name(), Some("first record".into()));
+        assert_eq!(sigs[1].name(), Some("second".into()));
+        assert_eq!(sigs[0].filename(), "");
+        assert_ne!(sigs[0].md5sum(), sigs[1].md5sum());
+    }
+
+    #[test]
+    fn compute_name_from_first() {
+        let params = ComputeParameters::builder()
+            .ksizes(vec![21])
+            .name_from_first(true)
+            .build();
+
+        let sigs = compute_from_readers(vec![("two.fa".into(), TWO_RECORDS)], &params).unwrap();
+
+        assert_eq!(sigs.len(), 1);
+        assert_eq!(sigs[0].name(), Some("first record".into()));
+        assert_eq!(sigs[0].filename(), "two.fa");
+    }
+
+    #[test]
+    fn compute_merge() {
+        let params = ComputeParameters::builder()
+            .ksizes(vec![31])
+            .merge(Some("merged".into()))
+            .build();
+
+        let inputs = [test_data("genome-s10.fa.gz"), test_data("genome-s11.fa.gz")];
+        let sigs = compute(&inputs, &params).unwrap();
+        assert_eq!(sigs.len(), 1);
+        assert_eq!(sigs[0].name(), Some("merged".into()));
+        assert_eq!(sigs[0].filename(), inputs[1].to_string_lossy());
+
+        // same as sketching both genomes in one file
+        let params = ComputeParameters::builder().ksizes(vec![31]).build();
+        let combined = compute(&[test_data("genome-s10+s11.fa.gz")], &params).unwrap();
+        assert_eq!(sigs[0].md5sum(), combined[0].md5sum());
+    }
+
+    #[test]
+    fn compute_input_is_protein() {
+        let params = ComputeParameters::builder()
+            .ksizes(vec![7])
+            .dna(false)
+            .protein(true)
+            .input_is_protein(true)
+            .build();
+
+        let sigs = compute(&[test_data("ecoli.faa")], &params).unwrap();
+        assert_eq!(sigs.len(), 1);
+
+        match &sigs[0].sketches()[0] {
+            Sketch::LargeMinHash(mh) => {
+                assert!(mh.is_protein());
+                assert!(mh.size() > 0);
+            }
+            _ => panic!("expected a MinHash sketch"),
+        }
+    }
+
+    #[test]
+    fn compute_check_sequence() {
+        let params = ComputeParameters::builder()
+            .ksizes(vec![31])
+            .check_sequence(true)
+            .build();
+
+        let res = compute(&[test_data("short.bad.fa")], &params);
+        assert!(matches!(res, Err(Error::InvalidDNA { .. })));
+
+        let params = ComputeParameters::builder()
+            .ksizes(vec![31])
+            .check_sequence(true)
+            .force(true)
+            .build();
+        assert!(compute(&[test_data("short.bad.fa")], &params).is_ok());
+    }
+
+    #[test]
+    fn compute_empty_input() {
+        let params = ComputeParameters::default();
+        let sigs = compute_from_readers(vec![("empty".into(), &b""[..])], &params).unwrap();
+        assert!(sigs.is_empty());
+    }
+}
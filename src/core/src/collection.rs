@@ -6,7 +6,11 @@ use camino::Utf8PathBuf as PathBuf;
 use crate::encodings::Idx;
 use crate::manifest::{Manifest, Record};
 use crate::prelude::*;
-use crate::storage::{FSStorage, InnerStorage, MemStorage, SigStore, ZipStorage};
+use crate::selection::Picklist;
+use crate::storage::{
+    CacheLimit, CachedStorage, FSStorage, InnerStorage, MemStorage, SigStore, StorageError,
+    TarStorage, ZipStorage, ZipStorageWriter, MANIFEST_PATH,
+};
 use crate::{Error, Result, ScaledType};
 
 #[cfg(feature = "parallel")]
@@ -93,18 +97,46 @@ impl Collection {
         Self { manifest, storage }
     }
 
+    /// Datasets and their records, skipping removed datasets.
     pub fn iter(&self) -> impl Iterator<Item = (Idx, &Record)> {
-        self.manifest.iter().enumerate().map(|(i, r)| (i as Idx, r))
+        self.manifest
+            .iter()
+            .enumerate()
+            .filter(|(_, r)| !r.removed())
+            .map(|(i, r)| (i as Idx, r))
     }
 
+    /// Datasets and their records, skipping removed datasets.
     #[cfg(feature = "parallel")]
-    pub fn par_iter(&self) -> impl IndexedParallelIterator<Item = (Idx, &Record)> {
+    pub fn par_iter(&self) -> impl ParallelIterator<Item = (Idx, &Record)> {
         self.manifest
             .par_iter()
             .enumerate()
+            .filter(|(_, r)| !r.removed())
             .map(|(i, r)| (i as Idx, r))
     }
 
+    /// Mark `datasets` as removed. Their ids stay reserved,
+    /// but `iter` and `par_iter` don't list them anymore.
+    #[cfg(all(feature = "branchwater", not(target_arch = "wasm32")))]
+    pub(crate) fn tombstone(&mut self, datasets: impl IntoIterator<Item = Idx>) {
+        for dataset in datasets {
+            self.manifest.tombstone(dataset as usize);
+        }
+    }
+
+    /// Datasets marked as removed.
+    #[cfg(all(feature = "branchwater", not(target_arch = "wasm32")))]
+    pub(crate) fn removed(&self) -> Vec<Idx> {
+        self.manifest
+            .iter()
+            .enumerate()
+            .filter(|(_, r)| r.removed())
+            .map(|(i, _)| i as Idx)
+            .collect()
+    }
+
+    /// Number of dataset ids, including removed datasets.
     pub fn len(&self) -> usize {
         self.manifest.len()
     }
@@ -121,10 +153,19 @@ impl Collection {
         &self.storage
     }
 
+    /// Cache signatures loaded from the storage, up to `limit`.
+    /// Statistics are available from `storage().cache_stats()`.
+    pub fn with_cache(mut self, limit: CacheLimit) -> Self {
+        self.storage = InnerStorage::new(CachedStorage::new(self.storage, limit));
+        self
+    }
+
     pub fn check_superset(&self, other: &Collection) -> Result<usize> {
-        self.iter()
-            .zip(other.iter())
-            .all(|((id1, rec1), (id2, rec2))| id1 == id2 && rec1 == rec2)
+        // compare all records, since removed datasets keep their ids
+        self.manifest
+            .iter()
+            .zip(other.manifest.iter())
+            .all(|(rec1, rec2)| rec1 == rec2)
             .then(|| self.len())
             // TODO: right error here
             .ok_or(Error::MismatchKSizes)
@@ -133,7 +174,70 @@ impl Collection {
     pub fn from_zipfile<P: AsRef<Path>>(zipfile: P) -> Result<Self> {
         let storage = ZipStorage::from_file(zipfile)?;
         // Load manifest from standard location in zipstorage
-        let manifest = Manifest::from_reader(storage.load("SOURMASH-MANIFEST.csv")?.as_slice())?;
+        let manifest = Manifest::from_reader(storage.load(MANIFEST_PATH)?.as_slice())?;
+        Ok(Self {
+            manifest,
+            storage: InnerStorage::new(storage),
+        })
+    }
+
+    /// Load a tar file (optionally compressed), using the manifest at the
+    /// standard location or building one for the signatures in it.
+    pub fn from_tarfile<P: AsRef<Path>>(tarfile: P) -> Result<Self> {
+        let storage = TarStorage::from_file(tarfile)?;
+        let filenames = storage.filenames();
+        Self::from_storage_files(InnerStorage::new(storage), &filenames)
+    }
+
+    /// Load a directory, using the manifest at the standard location or
+    /// building one for the signatures found (recursively) in it.
+    pub fn from_directory<P: AsRef<Path>>(dirname: P) -> Result<Self> {
+        let storage = FSStorage::new("", dirname.as_ref().as_str());
+        let filenames = storage.filenames()?;
+        Self::from_storage_files(InnerStorage::new(storage), &filenames)
+    }
+
+    fn from_storage_files(storage: InnerStorage, filenames: &[String]) -> Result<Self> {
+        let manifest = match storage.load(MANIFEST_PATH) {
+            Ok(raw) => Manifest::from_reader(&raw[..])?,
+            Err(Error::StorageError(StorageError::PathNotFoundError(_))) => {
+                let mut records = vec![];
+                for filename in filenames {
+                    if !(filename.ends_with(".sig") || filename.ends_with(".sig.gz")) {
+                        continue;
+                    }
+                    let sigs = Signature::from_reader(&storage.load(filename)?[..])?;
+                    records.extend(sigs.iter().flat_map(|sig| Record::from_sig(sig, filename)));
+                }
+                records.into()
+            }
+            Err(e) => return Err(e),
+        };
+
+        Ok(Self { manifest, storage })
+    }
+
+    /// Save all signatures in the collection into a new zip file, with a
+    /// manifest at the standard location.
+    ///
+    /// If `dedup` is set, signatures with the same md5sum are saved only once.
+    /// Returns the manifest of the new zip file.
+    pub fn to_zipfile<P: AsRef<Path>>(&self, zipfile: P, dedup: bool) -> Result<Manifest> {
+        let writer = ZipStorageWriter::new(zipfile, dedup)?;
+        for (_, record) in self.iter() {
+            let sig = self.sig_from_record(record)?;
+            writer.add_sig(sig.data()?)?;
+        }
+        writer.finish()
+    }
+
+    /// Load a zip file from a HTTP server, downloading only the manifest
+    /// (signatures are fetched when loaded). Data is cached in
+    /// `default_cache_dir()`.
+    #[cfg(all(feature = "http", not(target_arch = "wasm32")))]
+    pub fn from_url(url: &str) -> Result<Self> {
+        let storage = crate::storage::HttpZipStorage::from_url(url)?;
+        let manifest = Manifest::from_reader(storage.load(MANIFEST_PATH)?.as_slice())?;
         Ok(Self {
             manifest,
             storage: InnerStorage::new(storage),
@@ -199,24 +303,18 @@ impl Collection {
     }
 
     pub fn sig_for_dataset(&self, dataset_id: Idx) -> Result<SigStore> {
-        let match_path = if self.manifest.is_empty() {
-            ""
-        } else {
-            self.manifest[dataset_id as usize]
-                .internal_location()
-                .as_str()
-        };
-
-        let selection = Selection::from_record(&self.manifest[dataset_id as usize])?;
-        let sig = self.storage.load_sig(match_path)?.select(&selection)?;
-        assert_eq!(sig.signatures.len(), 1);
-        Ok(sig)
+        self.sig_from_record(&self.manifest[dataset_id as usize])
     }
 
+    /// Load the sketch for `record`, even if its file contains multiple
+    /// signatures.
     pub fn sig_from_record(&self, record: &Record) -> Result<SigStore> {
         let match_path = record.internal_location().as_str();
         let selection = Selection::from_record(record)?;
-        let sig = self.storage.load_sig(match_path)?.select(&selection)?;
+        let sig = self
+            .storage
+            .load_sig_with_md5(match_path, record.md5())?
+            .select(&selection)?;
         assert_eq!(sig.signatures.len(), 1);
         Ok(sig)
     }
@@ -244,9 +342,21 @@ impl Select for Collection {
     }
 }
 
+impl Collection {
+    /// Select datasets using `picklist`.
+    ///
+    /// Also returns the picklist values that didn't match any dataset.
+    pub fn select_picklist(mut self, picklist: &Picklist) -> Result<(Self, Vec<String>)> {
+        let (manifest, not_found) = self.manifest.select_picklist(picklist)?;
+        self.manifest = manifest;
+        Ok((self, not_found))
+    }
+}
+
 #[cfg(test)]
 mod test {
     use camino::Utf8PathBuf as PathBuf;
+    use std::collections::HashSet;
     use std::fs::File;
     use std::io::BufReader;
 
@@ -254,10 +364,10 @@ mod test {
 
     use crate::encodings::HashFunctions;
     use crate::manifest::Manifest;
-    use crate::prelude::Select;
-    use crate::selection::Selection;
+    use crate::prelude::{ReadData, Select};
+    use crate::selection::{PickStyle, Picklist, Selection};
     use crate::signature::Signature;
-    #[cfg(all(feature = "branchwater", not(target_arch = "wasm32")))]
+    use crate::storage::Storage;
     use crate::Result;
 
     #[test]
@@ -433,20 +543,22 @@ mod test {
     }
 
     #[test]
-    #[should_panic] // for now...
     fn sigstore_sig_from_record_2() {
         let mut filename = PathBuf::from(env!("CARGO_MANIFEST_DIR"));
         filename.push("../../tests/test-data/short.sig.gz");
         let v = [filename];
         let collection = Collection::from_paths(&v).expect("no sigs!?");
+        assert_eq!(collection.len(), 2);
 
-        // pull off first record
-        let v: Vec<_> = collection.iter().collect();
-        let (_idx, rec) = v.first().expect("no records in collection?!");
+        // two signatures in the same file
+        for (_idx, rec) in collection.iter() {
+            let sig = collection.sig_from_record(rec).expect("no sig!?");
+            assert_eq!(&sig.md5sum(), rec.md5());
+            assert_eq!(&sig.filename(), rec.filename());
+        }
 
-        // this will panic with "unimplemented" because there are two
-        // sketches and that is not supported.
-        let _first_sig = collection.sig_from_record(rec).expect("no sig!?");
+        let path = collection.manifest()[0].internal_location().as_str();
+        assert!(collection.storage().load_sig(path).is_err());
     }
 
     #[test]
@@ -472,6 +584,186 @@ mod test {
         }
     }
 
+    #[test]
+    fn sigstore_selection_picklist_zip() {
+        let mut filename = PathBuf::from(env!("CARGO_MANIFEST_DIR"));
+        filename.push("../../tests/test-data/prot/all.zip");
+
+        let mut picklist = Picklist::builder()
+            .coltype("ident".into())
+            .pickstyle(PickStyle::Include)
+            .build();
+        picklist
+            .init(["NC_011663.1", "GCA_001593935", "NC_000000.1"])
+            .unwrap();
+
+        let mut selection = Selection::default();
+        selection.set_ksize(31);
+        selection.set_picklist(picklist.clone());
+
+        let cl = Collection::from_zipfile(&filename)
+            .unwrap()
+            .select(&selection)
+            .unwrap();
+        assert_eq!(cl.len(), 1);
+        let (_, rec) = cl.iter().next().unwrap();
+        assert!(rec.name().starts_with("NC_011663.1"));
+
+        let (cl, not_found) = Collection::from_zipfile(&filename)
+            .unwrap()
+            .select_picklist(&picklist)
+            .unwrap();
+        assert_eq!(cl.len(), 4);
+        assert_eq!(not_found, vec!["NC_000000.1".to_string()]);
+    }
+
+    #[test]
+    fn collection_to_zipfile() -> Result<()> {
+        let mut filename = PathBuf::from(env!("CARGO_MANIFEST_DIR"));
+        filename.push("../../tests/test-data/47+63-multisig.sig");
+        let file = File::open(filename)?;
+        let sigs = Signature::from_reader(BufReader::new(file))?;
+
+        let cl = Collection::from_sigs(sigs)?;
+        let dir = tempfile::TempDir::new()?;
+        let zipfile = PathBuf::from_path_buf(dir.path().join("out.sig.zip")).unwrap();
+
+        let manifest = cl.to_zipfile(&zipfile, false)?;
+        assert_eq!(manifest.len(), cl.len());
+
+        let zcl = Collection::from_zipfile(&zipfile)?;
+        assert_eq!(zcl.len(), cl.len());
+        for ((_, rec), (_, zrec)) in cl.iter().zip(zcl.iter()) {
+            assert_eq!(rec.md5(), zrec.md5());
+            assert_eq!(rec.name(), zrec.name());
+            assert!(zrec.internal_location().as_str().starts_with("signatures/"));
+
+            let sig = cl.sig_from_record(rec)?;
+            let zsig = zcl.sig_from_record(zrec)?;
+            assert_eq!(sig.data()?, zsig.data()?);
+        }
+
+        // zip to zip
+        let zipfile2 = PathBuf::from_path_buf(dir.path().join("out2.sig.zip")).unwrap();
+        zcl.to_zipfile(&zipfile2, false)?;
+        assert_eq!(Collection::from_zipfile(&zipfile2)?.len(), cl.len());
+
+        Ok(())
+    }
+
+    #[test]
+    fn collection_to_zipfile_dedup() -> Result<()> {
+        let mut filename = PathBuf::from(env!("CARGO_MANIFEST_DIR"));
+        filename.push("../../tests/test-data/47+63-multisig.sig");
+        let file = File::open(filename)?;
+        let sigs = Signature::from_reader(BufReader::new(file))?;
+        let n_sigs = sigs.len();
+
+        let mut dups = sigs.clone();
+        dups.extend(sigs);
+        let cl = Collection::from_sigs(dups)?;
+        assert_eq!(cl.len(), 2 * n_sigs);
+
+        let dir = tempfile::TempDir::new()?;
+        let zipfile = PathBuf::from_path_buf(dir.path().join("dedup.sig.zip")).unwrap();
+        let manifest = cl.to_zipfile(&zipfile, true)?;
+        assert_eq!(manifest.len(), n_sigs);
+        assert_eq!(Collection::from_zipfile(&zipfile)?.len(), n_sigs);
+
+        // identical content is stored once, but all records are kept
+        let zipfile = PathBuf::from_path_buf(dir.path().join("dups.sig.zip")).unwrap();
+        let manifest = cl.to_zipfile(&zipfile, false)?;
+        assert_eq!(manifest.len(), 2 * n_sigs);
+        assert_eq!(
+            manifest.internal_locations().collect::<HashSet<_>>().len(),
+            n_sigs
+        );
+
+        Ok(())
+    }
+
+    fn check_same_sigs(cl: &Collection, expected: &Collection) -> Result<()> {
+        assert_eq!(cl.len(), expected.len());
+        let mut md5s: Vec<_> = cl.iter().map(|(_, r)| r.md5().clone()).collect();
+        let mut expected_md5s: Vec<_> = expected.iter().map(|(_, r)| r.md5().clone()).collect();
+        md5s.sort();
+        expected_md5s.sort();
+        assert_eq!(md5s, expected_md5s);
+
+        for (idx, rec) in cl.iter() {
+            assert_eq!(&cl.sig_for_dataset(idx)?.md5sum(), rec.md5());
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn collection_from_tarfile() -> Result<()> {
+        let mut basedir = PathBuf::from(env!("CARGO_MANIFEST_DIR"));
+        basedir.push("../../tests/test-data/prot/");
+        let expected = Collection::from_zipfile(basedir.join("protein.zip"))?;
+
+        // compressed, with a manifest under a top-level directory
+        let cl = Collection::from_tarfile(basedir.join("protein.tar.gz"))?;
+        assert_eq!(cl.manifest()[..], expected.manifest()[..]);
+        check_same_sigs(&cl, &expected)?;
+
+        // no manifest, and long paths in pax headers
+        let cl = Collection::from_tarfile(basedir.join("protein.tar"))?;
+        check_same_sigs(&cl, &expected)?;
+        for (_, rec) in cl.iter() {
+            assert!(rec.internal_location().as_str().len() > 200);
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    fn collection_from_directory() -> Result<()> {
+        let mut basedir = PathBuf::from(env!("CARGO_MANIFEST_DIR"));
+        basedir.push("../../tests/test-data/prot/");
+        let expected = Collection::from_zipfile(basedir.join("protein.zip"))?;
+
+        let cl = Collection::from_directory(basedir.join("protein"))?;
+        check_same_sigs(&cl, &expected)?;
+
+        // nested signatures, and other files are ignored
+        let dir = tempfile::TempDir::new()?;
+        let dirname = PathBuf::from_path_buf(dir.path().into()).unwrap();
+        std::fs::create_dir_all(dirname.join("a/b"))?;
+        std::fs::write(dirname.join("README"), "not a signature")?;
+        for (i, (_, rec)) in expected.iter().enumerate() {
+            let sig = expected.sig_from_record(rec)?;
+            let path = if i == 0 {
+                "a/b/first.sig"
+            } else {
+                "second.sig"
+            };
+            std::fs::write(dirname.join(path), serde_json::to_vec(&[sig.data()?])?)?;
+        }
+        let cl = Collection::from_directory(&dirname)?;
+        check_same_sigs(&cl, &expected)?;
+        assert_eq!(cl.manifest()[0].internal_location(), "a/b/first.sig");
+
+        // an existing manifest is used instead
+        let mut first = expected.manifest()[0].clone();
+        first.set_internal_location("a/b/first.sig".into());
+        let manifest = Manifest::from(vec![first]);
+        manifest.to_writer(File::create(dirname.join("SOURMASH-MANIFEST.csv"))?)?;
+        let cl = Collection::from_directory(&dirname)?;
+        assert_eq!(cl.len(), 1);
+        assert_eq!(cl.manifest()[..], manifest[..]);
+        cl.sig_for_dataset(0)?;
+
+        // and the storage can be opened from its spec
+        let storage = crate::storage::InnerStorage::from_spec(cl.storage().spec())?;
+        assert_eq!(
+            storage.load("a/b/first.sig")?,
+            std::fs::read(dirname.join("a/b/first.sig"))?
+        );
+
+        Ok(())
+    }
+
     #[test]
     fn sigstore_selection_moltype_sig() {
         // load test sigs
@@ -500,7 +792,7 @@ mod test {
     }
 
     #[test]
-    fn collection_from_collectionset() -> () {
+    fn collection_from_collectionset() {
         use crate::collection::CollectionSet;
 
         let base_path = PathBuf::from(env!("CARGO_MANIFEST_DIR"));
@@ -527,7 +819,7 @@ mod test {
 
     #[test]
     #[should_panic]
-    fn collection_from_collectionset_fail() -> () {
+    fn collection_from_collectionset_fail() {
         use crate::collection::CollectionSet;
 
         let base_path = PathBuf::from(env!("CARGO_MANIFEST_DIR"));
@@ -2,6 +2,7 @@ use serde::{Deserialize, Serialize};
 use std::collections::HashMap;
 use std::hash::{BuildHasher, BuildHasherDefault, Hash, Hasher};
 use std::str;
+use std::sync::{Arc, RwLock};
 
 use nohash_hasher::BuildNoHashHasher;
 use once_cell::sync::Lazy;
@@ -31,40 +32,101 @@ pub enum HashFunctions {
     Murmur64Protein,
     Murmur64Dayhoff,
     Murmur64Hp,
+    NtHash64Dna,
+    Murmur64Encoded(ProteinEncoding),
     Custom(String),
 }
 
 impl HashFunctions {
     pub fn dna(&self) -> bool {
-        *self == HashFunctions::Murmur64Dna
+        match self {
+            HashFunctions::Murmur64Dna | HashFunctions::NtHash64Dna => true,
+            HashFunctions::Custom(_) => self.custom().map_or(false, |f| !f.is_protein()),
+            _ => false,
+        }
     }
 
     pub fn protein(&self) -> bool {
-        *self == HashFunctions::Murmur64Protein
+        match self {
+            HashFunctions::Murmur64Protein => true,
+            HashFunctions::Murmur64Encoded(e) => e.alphabet == Alphabet::Protein,
+            HashFunctions::Custom(_) => self.custom().map_or(false, |f| f.is_protein()),
+            _ => false,
+        }
     }
 
     pub fn dayhoff(&self) -> bool {
-        *self == HashFunctions::Murmur64Dayhoff
+        match self {
+            HashFunctions::Murmur64Dayhoff => true,
+            HashFunctions::Murmur64Encoded(e) => e.alphabet == Alphabet::Dayhoff,
+            _ => false,
+        }
     }
 
     pub fn hp(&self) -> bool {
-        *self == HashFunctions::Murmur64Hp
+        match self {
+            HashFunctions::Murmur64Hp => true,
+            HashFunctions::Murmur64Encoded(e) => e.alphabet == Alphabet::Hp,
+            _ => false,
+        }
+    }
+
+    /// Hash function for amino acid k-mers using `alphabet`, translating DNA
+    /// input with the NCBI translation table `table`.
+    ///
+    /// The standard code (table 1) with the protein, dayhoff and hp alphabets
+    /// gives the usual `Murmur64Protein`, `Murmur64Dayhoff` and `Murmur64Hp`.
+    pub fn translated(alphabet: Alphabet, table: u8) -> Result<HashFunctions, Error> {
+        if !CODON_TABLES.contains_key(&table) {
+            return Err(Error::InvalidHashFunction {
+                function: format!("{}+table{}", alphabet, table),
+            });
+        }
+
+        Ok(match (alphabet, table) {
+            (Alphabet::Protein, 1) => HashFunctions::Murmur64Protein,
+            (Alphabet::Dayhoff, 1) => HashFunctions::Murmur64Dayhoff,
+            (Alphabet::Hp, 1) => HashFunctions::Murmur64Hp,
+            (alphabet, table) => {
+                HashFunctions::Murmur64Encoded(ProteinEncoding { alphabet, table })
+            }
+        })
+    }
+
+    /// How amino acids are encoded before hashing, for protein-based
+    /// hash functions.
+    pub fn protein_encoding(&self) -> Option<ProteinEncoding> {
+        let alphabet = match self {
+            HashFunctions::Murmur64Protein => Alphabet::Protein,
+            HashFunctions::Murmur64Dayhoff => Alphabet::Dayhoff,
+            HashFunctions::Murmur64Hp => Alphabet::Hp,
+            HashFunctions::Murmur64Encoded(e) => return Some(e.clone()),
+            HashFunctions::Custom(_) if self.protein() => Alphabet::Protein,
+            _ => return None,
+        };
+        Some(ProteinEncoding { alphabet, table: 1 })
+    }
+
+    /// The registered function for `HashFunctions::Custom`, if any.
+    pub fn custom(&self) -> Option<CustomHashFunction> {
+        match self {
+            HashFunctions::Custom(name) => HASH_FUNCTIONS.read().unwrap().get(name).cloned(),
+            _ => None,
+        }
     }
 }
 
 impl std::fmt::Display for HashFunctions {
     fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
-        write!(
-            f,
-            "{}",
-            match self {
-                HashFunctions::Murmur64Dna => "DNA",
-                HashFunctions::Murmur64Protein => "protein",
-                HashFunctions::Murmur64Dayhoff => "dayhoff",
-                HashFunctions::Murmur64Hp => "hp",
-                HashFunctions::Custom(v) => v,
-            }
-        )
+        match self {
+            HashFunctions::Murmur64Dna => write!(f, "DNA"),
+            HashFunctions::Murmur64Protein => write!(f, "protein"),
+            HashFunctions::Murmur64Dayhoff => write!(f, "dayhoff"),
+            HashFunctions::Murmur64Hp => write!(f, "hp"),
+            HashFunctions::NtHash64Dna => write!(f, "nthash"),
+            HashFunctions::Murmur64Encoded(e) => write!(f, "{}", e),
+            HashFunctions::Custom(v) => write!(f, "{}", v),
+        }
     }
 }
 
@@ -77,9 +139,97 @@ impl TryFrom<&str> for HashFunctions {
             "dayhoff" => Ok(HashFunctions::Murmur64Dayhoff),
             "hp" => Ok(HashFunctions::Murmur64Hp),
             "protein" => Ok(HashFunctions::Murmur64Protein),
-            v => unimplemented!("{v}"),
+            "nthash" => Ok(HashFunctions::NtHash64Dna),
+            _ if HASH_FUNCTIONS.read().unwrap().contains_key(moltype) => {
+                Ok(HashFunctions::Custom(moltype.into()))
+            }
+            _ => ProteinEncoding::parse(moltype)
+                .and_then(|(alphabet, table)| HashFunctions::translated(alphabet, table).ok())
+                .ok_or_else(|| Error::InvalidHashFunction {
+                    function: moltype.into(),
+                }),
+        }
+    }
+}
+
+type KmerHasher = dyn Fn(&[u8], u64) -> u64 + Send + Sync;
+
+/// A hash function that can be registered with `register_hash_function`
+/// and used in sketches as `HashFunctions::Custom(name)`.
+///
+/// The name is saved as the `molecule` of the sketch, so signatures using it
+/// can only be loaded after the function is registered again.
+///
+/// DNA hash functions receive the canonical k-mer (the smallest of the
+/// k-mer and its reverse complement), protein hash functions receive the
+/// amino acid k-mer, translated from DNA if needed.
+#[derive(Clone)]
+pub struct CustomHashFunction {
+    name: String,
+    is_protein: bool,
+    hasher: Arc<KmerHasher>,
+}
+
+impl CustomHashFunction {
+    pub fn new<F>(name: &str, is_protein: bool, hasher: F) -> Self
+    where
+        F: Fn(&[u8], u64) -> u64 + Send + Sync + 'static,
+    {
+        CustomHashFunction {
+            name: name.into(),
+            is_protein,
+            hasher: Arc::new(hasher),
         }
     }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    pub fn is_protein(&self) -> bool {
+        self.is_protein
+    }
+
+    #[inline]
+    pub fn hash(&self, kmer: &[u8], seed: u64) -> u64 {
+        (self.hasher)(kmer, seed)
+    }
+}
+
+impl std::fmt::Debug for CustomHashFunction {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        f.debug_struct("CustomHashFunction")
+            .field("name", &self.name)
+            .field("is_protein", &self.is_protein)
+            .finish()
+    }
+}
+
+static HASH_FUNCTIONS: Lazy<RwLock<HashMap<String, CustomHashFunction>>> =
+    Lazy::new(Default::default);
+
+/// Register a new hash function, returning the `HashFunctions` to use
+/// when building sketches with it.
+///
+/// Names can't shadow the built-in hash functions, and can't be registered
+/// twice.
+pub fn register_hash_function(function: CustomHashFunction) -> Result<HashFunctions, Error> {
+    if function.name.is_empty() || HashFunctions::try_from(function.name.as_str()).is_ok() {
+        return Err(Error::InvalidHashFunction {
+            function: function.name,
+        });
+    }
+
+    let mut registry = HASH_FUNCTIONS.write().unwrap();
+    if registry.contains_key(&function.name) {
+        return Err(Error::InvalidHashFunction {
+            function: function.name,
+        });
+    }
+
+    let name = function.name.clone();
+    registry.insert(name.clone(), function);
+    Ok(HashFunctions::Custom(name))
 }
 
 const COMPLEMENT: [u8; 256] = {
@@ -303,8 +453,140 @@ static HPTABLE: Lazy<HashMap<u8, u8>> = Lazy::new(|| {
     .collect()
 });
 
+// Differences from the standard code for the NCBI translation tables,
+// from https://www.ncbi.nlm.nih.gov/Taxonomy/Utils/wprintgc.cgi
+const TABLE_CHANGES: &[(u8, &[(&str, u8)])] = &[
+    (1, &[]),
+    // Vertebrate Mitochondrial
+    (
+        2,
+        &[("AGA", b'*'), ("AGG", b'*'), ("ATA", b'M'), ("TGA", b'W')],
+    ),
+    // Yeast Mitochondrial
+    (
+        3,
+        &[
+            ("ATA", b'M'),
+            ("CTT", b'T'),
+            ("CTC", b'T'),
+            ("CTA", b'T'),
+            ("CTG", b'T'),
+            ("TGA", b'W'),
+        ],
+    ),
+    // Mold, Protozoan, and Coelenterate Mitochondrial; Mycoplasma/Spiroplasma
+    (4, &[("TGA", b'W')]),
+    // Invertebrate Mitochondrial
+    (
+        5,
+        &[("AGA", b'S'), ("AGG", b'S'), ("ATA", b'M'), ("TGA", b'W')],
+    ),
+    // Ciliate, Dasycladacean and Hexamita Nuclear
+    (6, &[("TAA", b'Q'), ("TAG", b'Q')]),
+    // Echinoderm and Flatworm Mitochondrial
+    (
+        9,
+        &[("AAA", b'N'), ("AGA", b'S'), ("AGG", b'S'), ("TGA", b'W')],
+    ),
+    // Euplotid Nuclear
+    (10, &[("TGA", b'C')]),
+    // Bacterial, Archaeal and Plant Plastid
+    (11, &[]),
+    // Alternative Yeast Nuclear
+    (12, &[("CTG", b'S')]),
+    // Ascidian Mitochondrial
+    (
+        13,
+        &[("AGA", b'G'), ("AGG", b'G'), ("ATA", b'M'), ("TGA", b'W')],
+    ),
+    // Alternative Flatworm Mitochondrial
+    (
+        14,
+        &[
+            ("AAA", b'N'),
+            ("AGA", b'S'),
+            ("AGG", b'S'),
+            ("TAA", b'Y'),
+            ("TGA", b'W'),
+        ],
+    ),
+    // Chlorophycean Mitochondrial
+    (16, &[("TAG", b'L')]),
+    // Trematode Mitochondrial
+    (
+        21,
+        &[
+            ("TGA", b'W'),
+            ("ATA", b'M'),
+            ("AGA", b'S'),
+            ("AGG", b'S'),
+            ("AAA", b'N'),
+        ],
+    ),
+    // Scenedesmus obliquus Mitochondrial
+    (22, &[("TCA", b'*'), ("TAG", b'L')]),
+    // Thraustochytrium Mitochondrial
+    (23, &[("TTA", b'*')]),
+    // Rhabdopleuridae Mitochondrial
+    (24, &[("AGA", b'S'), ("AGG", b'K'), ("TGA", b'W')]),
+    // Candidate Division SR1 and Gracilibacteria
+    (25, &[("TGA", b'G')]),
+    // Pachysolen tannophilus Nuclear
+    (26, &[("CTG", b'A')]),
+];
+
+static CODON_TABLES: Lazy<HashMap<u8, HashMap<&'static str, u8>>> = Lazy::new(|| {
+    TABLE_CHANGES
+        .iter()
+        .map(|(id, changes)| {
+            let mut table = CODONTABLE.clone();
+            for (codon, aa) in changes.iter() {
+                table.insert(codon, *aa);
+            }
+
+            // codons ending in N are only defined if all the
+            // possible codons translate to the same amino acid.
+            let wobble: Vec<&'static str> = table
+                .keys()
+                .filter(|codon| codon.ends_with('N'))
+                .cloned()
+                .collect();
+            for codon in wobble {
+                let mut aas = ["A", "C", "G", "T"].iter().map(|nt| {
+                    let full = format!("{}{}", &codon[..2], nt);
+                    table.get(full.as_str()).copied()
+                });
+                let first = aas.next().flatten();
+                if aas.all(|aa| aa == first) {
+                    table.insert(codon, first.unwrap_or(b'X'));
+                } else {
+                    table.remove(codon);
+                }
+            }
+
+            (*id, table)
+        })
+        .collect()
+});
+
+/// NCBI translation tables supported by `translate_codon_with_table`.
+pub fn translation_tables() -> Vec<u8> {
+    TABLE_CHANGES.iter().map(|(id, _)| *id).collect()
+}
+
 #[inline]
 pub fn translate_codon(codon: &[u8]) -> Result<u8, Error> {
+    translate_codon_with_table(codon, 1)
+}
+
+#[inline]
+pub fn translate_codon_with_table(codon: &[u8], table: u8) -> Result<u8, Error> {
+    let codons = CODON_TABLES
+        .get(&table)
+        .ok_or_else(|| Error::InvalidHashFunction {
+            function: format!("table{}", table),
+        })?;
+
     if codon.len() == 1 {
         return Ok(b'X');
     }
@@ -312,14 +594,14 @@ pub fn translate_codon(codon: &[u8]) -> Result<u8, Error> {
     if codon.len() == 2 {
         let mut v = codon.to_vec();
         v.push(b'N');
-        match CODONTABLE.get(str::from_utf8(v.as_slice()).unwrap()) {
+        match codons.get(str::from_utf8(v.as_slice()).unwrap()) {
             Some(aa) => return Ok(*aa),
             None => return Ok(b'X'),
         }
     }
 
     if codon.len() == 3 {
-        match CODONTABLE.get(str::from_utf8(codon).unwrap()) {
+        match codons.get(str::from_utf8(codon).unwrap()) {
             Some(aa) => return Ok(*aa),
             None => return Ok(b'X'),
         }
@@ -367,6 +649,222 @@ pub fn to_aa(seq: &[u8], dayhoff: bool, hp: bool) -> Result<Vec<u8>, Error> {
     Ok(converted)
 }
 
+/// A reduced amino acid alphabet, where each group of amino acids is
+/// replaced by the first amino acid of the group before hashing.
+///
+/// Amino acids not in any group are replaced by `X`, stop codons are kept.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+#[cfg_attr(
+    feature = "rkyv",
+    derive(rkyv::Serialize, rkyv::Deserialize, rkyv::Archive)
+)]
+pub struct ReducedAlphabet {
+    name: String,
+    groups: Vec<String>,
+    lookup: Vec<u8>,
+}
+
+impl ReducedAlphabet {
+    pub fn new<S: AsRef<str>>(name: &str, groups: &[S]) -> Result<ReducedAlphabet, Error> {
+        let invalid = || Error::InvalidHashFunction {
+            function: format!(
+                "{}:{}",
+                name,
+                groups
+                    .iter()
+                    .map(|g| g.as_ref())
+                    .collect::<Vec<_>>()
+                    .join(",")
+            ),
+        };
+
+        if name.is_empty() || name.contains([':', '+', ',']) || groups.is_empty() {
+            return Err(invalid());
+        }
+
+        let mut lookup = vec![b'X'; 256];
+        lookup[b'*' as usize] = b'*';
+        let mut seen = [false; 256];
+
+        for group in groups {
+            let group = group.as_ref().as_bytes();
+            if group.is_empty() {
+                return Err(invalid());
+            }
+            for aa in group {
+                if !aa.is_ascii_uppercase() || seen[*aa as usize] {
+                    return Err(invalid());
+                }
+                seen[*aa as usize] = true;
+                lookup[*aa as usize] = group[0];
+            }
+        }
+
+        Ok(ReducedAlphabet {
+            name: name.into(),
+            groups: groups.iter().map(|g| g.as_ref().into()).collect(),
+            lookup,
+        })
+    }
+
+    /// Murphy et al. (2000), 10 groups.
+    pub fn murphy10() -> ReducedAlphabet {
+        Self::new(
+            "murphy10",
+            &["LVIM", "C", "A", "G", "ST", "P", "FYW", "EDNQ", "KR", "H"],
+        )
+        .unwrap()
+    }
+
+    /// GBMR4, from Peterson et al. (2009).
+    pub fn gbmr4() -> ReducedAlphabet {
+        Self::new("gbmr4", &["ADKERNTSQ", "YFLIVMCWH", "G", "P"]).unwrap()
+    }
+
+    /// SE-B(14), from Peterson et al. (2009).
+    pub fn seb14() -> ReducedAlphabet {
+        Self::new(
+            "seb14",
+            &[
+                "A", "C", "D", "EQ", "FY", "G", "H", "IV", "KR", "LM", "N", "P", "ST", "W",
+            ],
+        )
+        .unwrap()
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    pub fn groups(&self) -> &[String] {
+        &self.groups
+    }
+
+    #[inline]
+    pub fn reduce(&self, aa: u8) -> u8 {
+        self.lookup[aa as usize]
+    }
+}
+
+impl std::fmt::Display for ReducedAlphabet {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "{}:{}", self.name, self.groups.join(","))
+    }
+}
+
+/// Alphabet used for amino acid k-mers.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+#[cfg_attr(
+    feature = "rkyv",
+    derive(rkyv::Serialize, rkyv::Deserialize, rkyv::Archive)
+)]
+pub enum Alphabet {
+    Protein,
+    Dayhoff,
+    Hp,
+    Reduced(ReducedAlphabet),
+}
+
+impl Alphabet {
+    #[inline]
+    pub fn reduce(&self, aa: u8) -> u8 {
+        match self {
+            Alphabet::Protein => aa,
+            Alphabet::Dayhoff => aa_to_dayhoff(aa),
+            Alphabet::Hp => aa_to_hp(aa),
+            Alphabet::Reduced(r) => r.reduce(aa),
+        }
+    }
+}
+
+impl std::fmt::Display for Alphabet {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            Alphabet::Protein => write!(f, "protein"),
+            Alphabet::Dayhoff => write!(f, "dayhoff"),
+            Alphabet::Hp => write!(f, "hp"),
+            Alphabet::Reduced(r) => write!(f, "{}", r),
+        }
+    }
+}
+
+/// Translation table and alphabet for protein-based hash functions.
+///
+/// Saved as the sketch `molecule` as `<alphabet>[+table<N>]`, where reduced
+/// alphabets are written as `<name>:<group>,<group>,...`
+/// (e.g. `protein+table4` or `murphy10:LVIM,C,A,G,ST,P,FYW,EDNQ,KR,H`).
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+#[cfg_attr(
+    feature = "rkyv",
+    derive(rkyv::Serialize, rkyv::Deserialize, rkyv::Archive)
+)]
+pub struct ProteinEncoding {
+    alphabet: Alphabet,
+    table: u8,
+}
+
+impl ProteinEncoding {
+    pub fn alphabet(&self) -> &Alphabet {
+        &self.alphabet
+    }
+
+    pub fn table(&self) -> u8 {
+        self.table
+    }
+
+    /// Translate `seq` in the first frame, and convert to the alphabet.
+    pub fn translate(&self, seq: &[u8]) -> Result<Vec<u8>, Error> {
+        let mut converted: Vec<u8> = Vec::with_capacity(seq.len() / 3);
+
+        for chunk in seq.chunks(3) {
+            if chunk.len() < 3 {
+                break;
+            }
+
+            let residue = translate_codon_with_table(chunk, self.table)?;
+            converted.push(self.alphabet.reduce(residue));
+        }
+
+        Ok(converted)
+    }
+
+    fn parse(moltype: &str) -> Option<(Alphabet, u8)> {
+        let (alphabet, table) = match moltype.rsplit_once('+') {
+            Some((alphabet, table)) => (
+                alphabet,
+                table.to_lowercase().strip_prefix("table")?.parse().ok()?,
+            ),
+            None => (moltype, 1),
+        };
+
+        let alphabet = match alphabet.to_lowercase().as_ref() {
+            "protein" => Alphabet::Protein,
+            "dayhoff" => Alphabet::Dayhoff,
+            "hp" => Alphabet::Hp,
+            "murphy10" => Alphabet::Reduced(ReducedAlphabet::murphy10()),
+            "gbmr4" => Alphabet::Reduced(ReducedAlphabet::gbmr4()),
+            "seb14" => Alphabet::Reduced(ReducedAlphabet::seb14()),
+            _ => {
+                let (name, groups) = alphabet.split_once(':')?;
+                let groups: Vec<&str> = groups.split(',').collect();
+                Alphabet::Reduced(ReducedAlphabet::new(name, &groups).ok()?)
+            }
+        };
+
+        Some((alphabet, table))
+    }
+}
+
+impl std::fmt::Display for ProteinEncoding {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        if self.table == 1 {
+            write!(f, "{}", self.alphabet)
+        } else {
+            write!(f, "{}+table{}", self.alphabet, self.table)
+        }
+    }
+}
+
 pub const VALID: [bool; 256] = {
     let mut lookup = [false; 256];
     lookup[b'A' as usize] = true;
@@ -476,7 +974,7 @@ impl Colors {
         }
     }
 
-    pub fn indices(&self, color: &Color) -> Indices {
+    pub fn indices(&self, color: &Color) -> Indices<'_> {
         // TODO: what if color is not present?
         Indices {
             iter: self.colors.get(color).unwrap().0.iter(),
@@ -573,4 +1071,238 @@ mod test {
 
         assert_eq!(colors.len(), 2);
     }
+
+    #[test]
+    fn unknown_hash_function() {
+        assert!(matches!(
+            HashFunctions::try_from("not-registered"),
+            Err(Error::InvalidHashFunction { .. })
+        ));
+        assert_eq!(
+            HashFunctions::try_from("DNA").unwrap(),
+            HashFunctions::Murmur64Dna
+        );
+    }
+
+    #[test]
+    fn register_custom_hash_function() {
+        use crate::signature::SigsTrait;
+        use crate::sketch::minhash::KmerMinHash;
+
+        let xxh3 = register_hash_function(CustomHashFunction::new(
+            "xxh3-dna",
+            false,
+            twox_hash::xxh3::hash64_with_seed,
+        ))
+        .unwrap();
+        assert_eq!(xxh3, HashFunctions::Custom("xxh3-dna".into()));
+        assert_eq!(HashFunctions::try_from("xxh3-dna").unwrap(), xxh3);
+        assert!(xxh3.dna());
+        assert!(!xxh3.protein());
+
+        // no duplicates or shadowing built-in functions
+        assert!(
+            register_hash_function(CustomHashFunction::new("xxh3-dna", false, |_, _| 1)).is_err()
+        );
+        assert!(
+            register_hash_function(CustomHashFunction::new("dayhoff", true, |_, _| 1)).is_err()
+        );
+
+        let mut mh = KmerMinHash::new(0, 5, xxh3.clone(), 42, false, 10);
+        mh.add_sequence(b"ATGCGAT", false).unwrap();
+        let mut expected: Vec<_> = [&b"ATGCG"[..], b"TCGCA", b"ATCGC"]
+            .iter()
+            .map(|kmer| twox_hash::xxh3::hash64_with_seed(kmer, 42))
+            .collect();
+        expected.sort_unstable();
+        assert_eq!(mh.mins(), expected);
+
+        // the name is saved as the molecule
+        let json = serde_json::to_string(&mh).unwrap();
+        assert!(json.contains(r#""molecule":"xxh3-dna""#));
+        let loaded: KmerMinHash = serde_json::from_str(&json).unwrap();
+        assert_eq!(loaded.hash_function(), xxh3);
+        assert_eq!(loaded.mins(), mh.mins());
+
+        let murmur = KmerMinHash::new(0, 5, HashFunctions::Murmur64Dna, 42, false, 10);
+        assert!(matches!(
+            mh.check_compatible(&murmur),
+            Err(Error::MismatchHashFunction { .. })
+        ));
+
+        let unknown = json.replace("xxh3-dna", "xxh3-unknown");
+        assert!(serde_json::from_str::<KmerMinHash>(&unknown).is_err());
+
+        let mut sig = crate::signature::Signature::default();
+        sig.push(crate::sketch::Sketch::MinHash(mh.clone()));
+        assert_eq!(sig.hash_function(), "xxh3-dna");
+        let json = serde_json::to_string(&sig).unwrap();
+        assert!(json.contains(r#""hash_function":"xxh3-dna""#));
+
+        // no C equivalent in the FFI
+        #[cfg(not(all(target_arch = "wasm32", target_os = "unknown")))]
+        assert!(matches!(
+            crate::ffi::HashFunctions::try_from(xxh3),
+            Err(Error::InvalidHashFunction { .. })
+        ));
+    }
+
+    #[test]
+    fn custom_protein_hash_function() {
+        use crate::signature::SigsTrait;
+        use crate::sketch::minhash::KmerMinHash;
+
+        let hf = register_hash_function(CustomHashFunction::new(
+            "xxh3-protein",
+            true,
+            twox_hash::xxh3::hash64_with_seed,
+        ))
+        .unwrap();
+        assert!(hf.protein());
+
+        let mut mh = KmerMinHash::new(0, 9, hf.clone(), 42, false, 10);
+        assert!(mh.is_protein());
+        mh.add_protein(b"MRVLK").unwrap();
+        let mut expected: Vec<_> = [&b"MRV"[..], b"RVL", b"VLK"]
+            .iter()
+            .map(|kmer| twox_hash::xxh3::hash64_with_seed(kmer, 42))
+            .collect();
+        expected.sort_unstable();
+        assert_eq!(mh.mins(), expected);
+
+        // translated from DNA
+        let mut translated = KmerMinHash::new(0, 9, hf, 42, false, 100);
+        translated.add_sequence(b"ATGCGTGTTCTTAAA", false).unwrap();
+        for hash in expected {
+            assert!(translated.mins().contains(&hash));
+        }
+
+        let mut unregistered = KmerMinHash::new(
+            0,
+            9,
+            HashFunctions::Custom("not-registered".into()),
+            42,
+            false,
+            10,
+        );
+        assert!(matches!(
+            unregistered.add_protein(b"MRVLK"),
+            Err(Error::InvalidHashFunction { .. })
+        ));
+    }
+
+    #[test]
+    fn ncbi_translation_tables() {
+        assert_eq!(translate_codon(b"TGA").unwrap(), b'*');
+        assert_eq!(translate_codon_with_table(b"TGA", 1).unwrap(), b'*');
+        assert_eq!(translate_codon_with_table(b"TGA", 4).unwrap(), b'W');
+        assert_eq!(translate_codon_with_table(b"AGA", 2).unwrap(), b'*');
+        assert_eq!(translate_codon_with_table(b"ATA", 2).unwrap(), b'M');
+        assert_eq!(translate_codon_with_table(b"ATA", 11).unwrap(), b'I');
+
+        // wobble codons follow the table
+        assert_eq!(translate_codon_with_table(b"CT", 1).unwrap(), b'L');
+        assert_eq!(translate_codon_with_table(b"CT", 3).unwrap(), b'T');
+        assert_eq!(translate_codon_with_table(b"CT", 12).unwrap(), b'X');
+
+        assert!(translate_codon_with_table(b"TGA", 7).is_err());
+        assert!(translation_tables().contains(&4));
+    }
+
+    #[test]
+    fn reduced_alphabets() {
+        let murphy = ReducedAlphabet::murphy10();
+        assert_eq!(murphy.reduce(b'I'), b'L');
+        assert_eq!(murphy.reduce(b'W'), b'F');
+        assert_eq!(murphy.reduce(b'H'), b'H');
+        assert_eq!(murphy.reduce(b'*'), b'*');
+        assert_eq!(murphy.reduce(b'B'), b'X');
+
+        assert_eq!(ReducedAlphabet::gbmr4().reduce(b'Q'), b'A');
+        assert_eq!(ReducedAlphabet::seb14().reduce(b'M'), b'L');
+
+        let custom = ReducedAlphabet::new("small", &["AGST", "C"]).unwrap();
+        assert_eq!(custom.reduce(b'T'), b'A');
+
+        assert!(ReducedAlphabet::new("dup", &["AG", "GS"]).is_err());
+        assert!(ReducedAlphabet::new("lower", &["ag"]).is_err());
+        assert!(ReducedAlphabet::new("bad:name", &["AG"]).is_err());
+        assert!(ReducedAlphabet::new("empty", &[""]).is_err());
+    }
+
+    #[test]
+    fn encoded_hash_functions() {
+        assert_eq!(
+            HashFunctions::translated(Alphabet::Protein, 1).unwrap(),
+            HashFunctions::Murmur64Protein
+        );
+        assert_eq!(
+            HashFunctions::translated(Alphabet::Dayhoff, 11)
+                .unwrap()
+                .to_string(),
+            "dayhoff+table11"
+        );
+        assert!(HashFunctions::translated(Alphabet::Protein, 8).is_err());
+
+        let murphy =
+            HashFunctions::translated(Alphabet::Reduced(ReducedAlphabet::murphy10()), 4).unwrap();
+        assert!(!murphy.dna());
+        assert!(!murphy.protein());
+        assert_eq!(
+            murphy.to_string(),
+            "murphy10:LVIM,C,A,G,ST,P,FYW,EDNQ,KR,H+table4"
+        );
+
+        for hf in [
+            murphy,
+            HashFunctions::translated(Alphabet::Protein, 2).unwrap(),
+            HashFunctions::translated(
+                Alphabet::Reduced(ReducedAlphabet::new("small", &["AGST", "C"]).unwrap()),
+                1,
+            )
+            .unwrap(),
+        ] {
+            assert_eq!(
+                HashFunctions::try_from(hf.to_string().as_str()).unwrap(),
+                hf
+            );
+        }
+
+        assert_eq!(
+            HashFunctions::try_from("gbmr4").unwrap(),
+            HashFunctions::translated(Alphabet::Reduced(ReducedAlphabet::gbmr4()), 1).unwrap()
+        );
+        assert!(HashFunctions::try_from("protein+table99").is_err());
+        assert!(HashFunctions::try_from("protein+tabel4").is_err());
+    }
+
+    #[test]
+    fn sketch_with_encoding() {
+        use crate::signature::SigsTrait;
+        use crate::sketch::minhash::KmerMinHash;
+
+        let murphy =
+            HashFunctions::translated(Alphabet::Reduced(ReducedAlphabet::murphy10()), 1).unwrap();
+
+        // I -> L, W -> F
+        let mut mh = KmerMinHash::new(0, 9, murphy.clone(), 42, false, 10);
+        mh.add_protein(b"MIW").unwrap();
+        assert_eq!(mh.mins(), vec![crate::_hash_murmur(b"LLF", 42)]);
+
+        let json = serde_json::to_string(&mh).unwrap();
+        let loaded: KmerMinHash = serde_json::from_str(&json).unwrap();
+        assert_eq!(loaded.hash_function(), murphy);
+
+        // TGA is a stop codon in the standard code, tryptophan in table 4
+        let seq = b"ATGTGAAAA";
+        let mut standard = KmerMinHash::new(0, 9, HashFunctions::Murmur64Protein, 42, false, 10);
+        standard.add_sequence(seq, false).unwrap();
+        assert!(standard.mins().contains(&crate::_hash_murmur(b"M*K", 42)));
+
+        let table4 = HashFunctions::translated(Alphabet::Protein, 4).unwrap();
+        let mut mycoplasma = KmerMinHash::new(0, 9, table4, 42, false, 10);
+        mycoplasma.add_sequence(seq, false).unwrap();
+        assert!(mycoplasma.mins().contains(&crate::_hash_murmur(b"MWK", 42)));
+        assert!(!mycoplasma.mins().contains(&crate::_hash_murmur(b"M*K", 42)));
+    }
 }
@@ -40,6 +40,9 @@ pub enum SourmashError {
     #[error("Multiple sketches found, expected one")]
     MultipleSketchesFound,
 
+    #[error("different hash functions cannot be compared: {h1} != {h2}")]
+    MismatchHashFunction { h1: String, h2: String },
+
     #[error("Invalid hash function: {function:?}")]
     InvalidHashFunction { function: String },
 
@@ -58,6 +61,12 @@ pub enum SourmashError {
     #[error("Set error rate to a value smaller than 0.367696 and larger than 0.00203125")]
     HLLPrecisionBounds,
 
+    #[error("invalid picklist: {message}")]
+    InvalidPicklist { message: String },
+
+    #[error("no dataset found matching {key:?}")]
+    DatasetNotFound { key: String },
+
     #[error("error while calculating ANI confidence intervals: {message}")]
     ANIEstimationError { message: String },
 
@@ -91,8 +100,15 @@ pub enum SourmashError {
     #[error(transparent)]
     RocksDBError(#[from] rocksdb::Error),
 
+    #[cfg(feature = "revindex")]
+    #[error(transparent)]
+    IntegrityError(#[from] crate::index::revindex::IntegrityError),
+
     #[error(transparent)]
     ZipError(#[from] piz::result::ZipError),
+
+    #[error(transparent)]
+    NeedletailError(#[from] needletail::errors::ParseError),
 }
 
 #[derive(Debug, Error)]
@@ -123,6 +139,7 @@ pub enum SourmashErrorCode {
     NoMinHashFound = 1_10,
     EmptySignature = 1_11,
     MultipleSketchesFound = 1_12,
+    MismatchHashFunction = 1_13,
     // Input sequence errors
     InvalidDNA = 11_01,
     InvalidProt = 11_02,
@@ -131,6 +148,9 @@ pub enum SourmashErrorCode {
     // index-related errors
     ReadData = 12_01,
     Storage = 12_02,
+    InvalidPicklist = 12_03,
+    DatasetNotFound = 12_04,
+    IntegrityError = 12_05,
     // HLL errors
     HLLPrecisionBounds = 13_01,
     // ANI errors
@@ -144,6 +164,7 @@ pub enum SourmashErrorCode {
     CsvError = 100_006,
     RocksDBError = 100_007,
     ZipError = 100_008,
+    NeedletailError = 100_009,
 }
 
 #[cfg(not(all(target_arch = "wasm32", target_os = "unknown")))]
@@ -152,11 +173,9 @@ impl SourmashErrorCode {
         match error {
             SourmashError::Internal { .. } => SourmashErrorCode::Internal,
             SourmashError::Panic { .. } => SourmashErrorCode::Panic,
-            SourmashError::CannotUpsampleScaled { .. } => SourmashErrorCode::CannotUpsampleScaled,
+            SourmashError::CannotUpsampleScaled => SourmashErrorCode::CannotUpsampleScaled,
             SourmashError::MismatchNum { .. } => SourmashErrorCode::MismatchNum,
-            SourmashError::NeedsAbundanceTracking { .. } => {
-                SourmashErrorCode::NeedsAbundanceTracking
-            }
+            SourmashError::NeedsAbundanceTracking => SourmashErrorCode::NeedsAbundanceTracking,
             SourmashError::MismatchKSizes => SourmashErrorCode::MismatchKSizes,
             SourmashError::MismatchDNAProt => SourmashErrorCode::MismatchDNAProt,
             SourmashError::MismatchScaled => SourmashErrorCode::MismatchScaled,
@@ -166,13 +185,16 @@ impl SourmashErrorCode {
             SourmashError::NoMinHashFound => SourmashErrorCode::NoMinHashFound,
             SourmashError::EmptySignature => SourmashErrorCode::EmptySignature,
             SourmashError::MultipleSketchesFound => SourmashErrorCode::MultipleSketchesFound,
+            SourmashError::MismatchHashFunction { .. } => SourmashErrorCode::MismatchHashFunction,
             SourmashError::InvalidDNA { .. } => SourmashErrorCode::InvalidDNA,
             SourmashError::InvalidProt { .. } => SourmashErrorCode::InvalidProt,
             SourmashError::InvalidCodonLength { .. } => SourmashErrorCode::InvalidCodonLength,
             SourmashError::InvalidHashFunction { .. } => SourmashErrorCode::InvalidHashFunction,
             SourmashError::ReadDataError { .. } => SourmashErrorCode::ReadData,
             SourmashError::StorageError { .. } => SourmashErrorCode::Storage,
-            SourmashError::HLLPrecisionBounds { .. } => SourmashErrorCode::HLLPrecisionBounds,
+            SourmashError::InvalidPicklist { .. } => SourmashErrorCode::InvalidPicklist,
+            SourmashError::DatasetNotFound { .. } => SourmashErrorCode::DatasetNotFound,
+            SourmashError::HLLPrecisionBounds => SourmashErrorCode::HLLPrecisionBounds,
             SourmashError::ANIEstimationError { .. } => SourmashErrorCode::ANIEstimationError,
             SourmashError::SerdeError { .. } => SourmashErrorCode::SerdeError,
             SourmashError::IOError { .. } => SourmashErrorCode::Io,
@@ -184,7 +206,11 @@ impl SourmashErrorCode {
             #[cfg(feature = "branchwater")]
             SourmashError::RocksDBError { .. } => SourmashErrorCode::RocksDBError,
 
+            #[cfg(feature = "revindex")]
+            SourmashError::IntegrityError { .. } => SourmashErrorCode::IntegrityError,
+
             SourmashError::ZipError { .. } => SourmashErrorCode::ZipError,
+            SourmashError::NeedletailError { .. } => SourmashErrorCode::NeedletailError,
         }
     }
 }
@@ -1,5 +1,5 @@
 #[cfg(not(target_arch = "wasm32"))]
-#[cfg(feature = "branchwater")]
+#[cfg(feature = "revindex")]
 pub mod revindex;
 
 use crate::signature::Signature;
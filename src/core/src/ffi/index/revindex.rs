@@ -7,7 +7,8 @@ use crate::ffi::minhash::SourmashKmerMinHash;
 use crate::ffi::signature::SourmashSignature;
 use crate::ffi::utils::{ForeignObject, SourmashStr};
 use crate::index::revindex::mem_revindex::RevIndex;
-use crate::index::Index;
+use crate::index::revindex::RevIndexOps;
+use crate::index::{Index, SearchType};
 use crate::prelude::*;
 use crate::signature::{Signature, SigsTrait};
 use crate::sketch::minhash::KmerMinHash;
@@ -179,6 +180,67 @@ unsafe fn revindex_search(
 }
 }
 
+ffi_fn! {
+unsafe fn revindex_best_matches(
+    ptr: *const SourmashRevIndex,
+    sig_ptr: *const SourmashSignature,
+    k: usize,
+    threshold: f64,
+    do_containment: bool,
+    size: *mut usize,
+) -> Result<*const *const SourmashSearchResult> {
+    let revindex = SourmashRevIndex::as_rust(ptr);
+    let sig = SourmashSignature::as_rust(sig_ptr);
+
+    if sig.signatures.is_empty() {
+        *size = 0;
+        return Ok(std::ptr::null::<*const SourmashSearchResult>());
+    }
+
+    let Sketch::MinHash(mh) = &sig.signatures[0] else {
+        return Err(crate::Error::NoMinHashFound);
+    };
+
+    let search_type = if do_containment {
+        SearchType::Containment
+    } else {
+        SearchType::Jaccard
+    };
+
+    let template = revindex
+        .template()
+        .ok_or_else(|| crate::Error::Internal {
+            message: "revindex has no template to select matches with".into(),
+        })?;
+    let counter = revindex.counter_for_query(mh);
+    let results: Vec<(f64, Signature, String)> = revindex
+        .best_matches(counter, mh, search_type, k, threshold)?
+        .into_iter()
+        .map(|r| {
+            let match_sig = revindex.collection().sig_from_record(r.record())?;
+            let mut sig: Signature = match_sig.into();
+            let match_sketch = sig.select_sketch(template).cloned();
+            if let Some(sketch) = match_sketch {
+                sig.reset_sketches();
+                sig.push(sketch);
+            }
+            Ok((r.similarity(), sig, r.record().internal_location().to_string()))
+        })
+        .collect::<crate::Result<_>>()?;
+
+    // FIXME: use the ForeignObject trait, maybe define new method there...
+    let ptr_sigs: Vec<*const SourmashSearchResult> = results
+        .into_iter()
+        .map(|x| Box::into_raw(Box::new(x)) as *const SourmashSearchResult)
+        .collect();
+
+    let b = ptr_sigs.into_boxed_slice();
+    *size = b.len();
+
+    Ok(Box::into_raw(b) as *const *const SourmashSearchResult)
+}
+}
+
 ffi_fn! {
 unsafe fn revindex_gather(
     ptr: *const SourmashRevIndex,
@@ -206,12 +268,10 @@ unsafe fn revindex_gather(
     // TODO: proper threshold calculation
     let threshold: usize = (threshold * (mh.size() as f64)) as _;
 
-    let counter = revindex.counter_for_query(mh);
-    dbg!(&counter);
+    let (counter, query_colors, hash_to_color) = revindex.prepare_gather_counters(mh);
 
     let results: Vec<(f64, Signature, String)> = revindex
-        .gather(counter, threshold, mh)
-        .unwrap() // TODO: proper error handling
+        .gather(counter, query_colors, hash_to_color, threshold, mh, None)?
         .into_iter()
         .map(|r| {
             let filename = r.filename().to_owned();
@@ -236,7 +296,7 @@ unsafe fn revindex_gather(
 #[no_mangle]
 pub unsafe extern "C" fn revindex_scaled(ptr: *const SourmashRevIndex) -> ScaledType {
     let revindex = SourmashRevIndex::as_rust(ptr);
-    if let Sketch::MinHash(mh) = revindex.template() {
+    if let Some(Sketch::MinHash(mh)) = revindex.template() {
         mh.scaled()
     } else {
         unimplemented!()
@@ -364,12 +364,11 @@ pub unsafe extern "C" fn kmerminhash_max_hash(ptr: *const SourmashKmerMinHash) -
     mh.max_hash()
 }
 
-#[no_mangle]
-pub unsafe extern "C" fn kmerminhash_hash_function(
-    ptr: *const SourmashKmerMinHash,
-) -> HashFunctions {
+ffi_fn! {
+unsafe fn kmerminhash_hash_function(ptr: *const SourmashKmerMinHash) -> Result<u32> {
     let mh = SourmashKmerMinHash::as_rust(ptr);
-    mh.hash_function().into()
+    Ok(HashFunctions::try_from(mh.hash_function())? as u32)
+}
 }
 
 ffi_fn! {
@@ -36,33 +36,41 @@ pub enum HashFunctions {
     Murmur64Protein = 2,
     Murmur64Dayhoff = 3,
     Murmur64Hp = 4,
+    NtHash64Dna = 5,
 }
 
 impl From<HashFunctions> for crate::encodings::HashFunctions {
     fn from(v: HashFunctions) -> crate::encodings::HashFunctions {
         use crate::encodings::HashFunctions::{
-            Murmur64Dayhoff, Murmur64Dna, Murmur64Hp, Murmur64Protein,
+            Murmur64Dayhoff, Murmur64Dna, Murmur64Hp, Murmur64Protein, NtHash64Dna,
         };
         match v {
             HashFunctions::Murmur64Dna => Murmur64Dna,
             HashFunctions::Murmur64Protein => Murmur64Protein,
             HashFunctions::Murmur64Dayhoff => Murmur64Dayhoff,
             HashFunctions::Murmur64Hp => Murmur64Hp,
+            HashFunctions::NtHash64Dna => NtHash64Dna,
         }
     }
 }
 
-impl From<crate::encodings::HashFunctions> for HashFunctions {
-    fn from(v: crate::encodings::HashFunctions) -> HashFunctions {
+impl TryFrom<crate::encodings::HashFunctions> for HashFunctions {
+    type Error = crate::Error;
+
+    fn try_from(v: crate::encodings::HashFunctions) -> Result<HashFunctions, Self::Error> {
         use crate::encodings::HashFunctions::{
-            Murmur64Dayhoff, Murmur64Dna, Murmur64Hp, Murmur64Protein,
+            Murmur64Dayhoff, Murmur64Dna, Murmur64Hp, Murmur64Protein, NtHash64Dna,
         };
         match v {
-            Murmur64Dna => HashFunctions::Murmur64Dna,
-            Murmur64Protein => HashFunctions::Murmur64Protein,
-            Murmur64Dayhoff => HashFunctions::Murmur64Dayhoff,
-            Murmur64Hp => HashFunctions::Murmur64Hp,
-            _ => todo!("Not supported, probably custom"),
+            Murmur64Dna => Ok(HashFunctions::Murmur64Dna),
+            Murmur64Protein => Ok(HashFunctions::Murmur64Protein),
+            Murmur64Dayhoff => Ok(HashFunctions::Murmur64Dayhoff),
+            Murmur64Hp => Ok(HashFunctions::Murmur64Hp),
+            NtHash64Dna => Ok(HashFunctions::NtHash64Dna),
+            // custom and translated hash functions have no C equivalent
+            v => Err(crate::Error::InvalidHashFunction {
+                function: v.to_string(),
+            }),
         }
     }
 }
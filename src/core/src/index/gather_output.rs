@@ -0,0 +1,631 @@
+//! Gather results in the format used by `sourmash gather -o`.
+//!
+//! CSV output uses the same columns, column order and value formatting as the
+//! Python implementation, so files written here can be consumed by the same
+//! downstream tools. Newline-delimited JSON with the same fields is also
+//! supported.
+
+use std::io::{BufRead, Write};
+
+use getset::{CopyGetters, Getters};
+use serde::{Deserialize, Deserializer, Serialize};
+use typed_builder::TypedBuilder;
+
+use crate::index::GatherResult;
+use crate::signature::{Signature, SigsTrait};
+use crate::sketch::minhash::KmerMinHash;
+use crate::{Error, Result};
+
+/// Columns written by `sourmash gather`, in order.
+pub const GATHER_COLUMNS: [&str; 32] = [
+    "intersect_bp",
+    "f_orig_query",
+    "f_match",
+    "f_unique_to_query",
+    "f_unique_weighted",
+    "average_abund",
+    "median_abund",
+    "std_abund",
+    "filename",
+    "name",
+    "md5",
+    "f_match_orig",
+    "unique_intersect_bp",
+    "gather_result_rank",
+    "remaining_bp",
+    "query_filename",
+    "query_name",
+    "query_md5",
+    "query_bp",
+    "ksize",
+    "moltype",
+    "scaled",
+    "query_n_hashes",
+    "query_abundance",
+    "query_containment_ani",
+    "match_containment_ani",
+    "average_containment_ani",
+    "max_containment_ani",
+    "potential_false_negative",
+    "n_unique_weighted_found",
+    "sum_weighted_found",
+    "total_weighted_hashes",
+];
+
+/// Extra columns written when ANI confidence intervals are estimated.
+pub const ANI_CI_COLUMNS: [&str; 4] = [
+    "query_containment_ani_low",
+    "query_containment_ani_high",
+    "match_containment_ani_low",
+    "match_containment_ani_high",
+];
+
+/// Query metadata included in each row of gather output.
+#[derive(TypedBuilder, CopyGetters, Getters, Debug, Clone, PartialEq)]
+pub struct GatherQueryInfo {
+    #[getset(get = "pub")]
+    name: String,
+
+    #[getset(get = "pub")]
+    filename: String,
+
+    #[getset(get = "pub")]
+    md5: String,
+
+    #[getset(get_copy = "pub")]
+    n_hashes: u64,
+
+    #[getset(get_copy = "pub")]
+    scaled: u32,
+
+    /// k-mer size, in amino acids for protein sketches.
+    #[getset(get_copy = "pub")]
+    ksize: u32,
+
+    #[getset(get = "pub")]
+    moltype: String,
+
+    #[getset(get_copy = "pub")]
+    abundance: bool,
+}
+
+impl GatherQueryInfo {
+    /// Query information for `query`, the (selected) sketch used for gather.
+    pub fn from_minhash(name: &str, filename: &str, query: &KmerMinHash) -> Self {
+        let hash_function = query.hash_function();
+        let ksize = if hash_function.protein_encoding().is_some() {
+            query.ksize() as u32 / 3
+        } else {
+            query.ksize() as u32
+        };
+
+        Self {
+            name: name.into(),
+            filename: filename.into(),
+            md5: query.md5sum(),
+            n_hashes: query.size() as u64,
+            scaled: query.scaled(),
+            ksize,
+            moltype: hash_function.to_string(),
+            abundance: query.track_abundance(),
+        }
+    }
+
+    pub fn from_signature(query: &Signature) -> Result<Self> {
+        let mh = query.minhash().ok_or(Error::NoMinHashFound)?;
+        Ok(Self::from_minhash(
+            &query.name().unwrap_or_default(),
+            &query.filename(),
+            mh,
+        ))
+    }
+}
+
+/// One row of gather output.
+#[derive(CopyGetters, Getters, Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct GatherRow {
+    #[getset(get_copy = "pub")]
+    intersect_bp: u64,
+
+    #[getset(get_copy = "pub")]
+    f_orig_query: f64,
+
+    #[getset(get_copy = "pub")]
+    f_match: f64,
+
+    #[getset(get_copy = "pub")]
+    f_unique_to_query: f64,
+
+    #[getset(get_copy = "pub")]
+    f_unique_weighted: f64,
+
+    #[getset(get_copy = "pub")]
+    average_abund: Option<f64>,
+
+    #[getset(get_copy = "pub")]
+    median_abund: Option<f64>,
+
+    #[getset(get_copy = "pub")]
+    std_abund: Option<f64>,
+
+    #[getset(get = "pub")]
+    filename: String,
+
+    #[getset(get = "pub")]
+    name: String,
+
+    #[getset(get = "pub")]
+    md5: String,
+
+    #[getset(get_copy = "pub")]
+    f_match_orig: f64,
+
+    #[getset(get_copy = "pub")]
+    unique_intersect_bp: u64,
+
+    #[getset(get_copy = "pub")]
+    gather_result_rank: u32,
+
+    #[getset(get_copy = "pub")]
+    remaining_bp: u64,
+
+    #[getset(get = "pub")]
+    query_filename: String,
+
+    #[getset(get = "pub")]
+    query_name: String,
+
+    /// First 8 characters of the query md5sum.
+    #[getset(get = "pub")]
+    query_md5: String,
+
+    #[getset(get_copy = "pub")]
+    query_bp: u64,
+
+    #[getset(get_copy = "pub")]
+    ksize: u32,
+
+    #[getset(get = "pub")]
+    moltype: String,
+
+    #[getset(get_copy = "pub")]
+    scaled: u32,
+
+    #[getset(get_copy = "pub")]
+    query_n_hashes: u64,
+
+    #[getset(get_copy = "pub")]
+    #[serde(deserialize_with = "python_bool")]
+    query_abundance: bool,
+
+    #[getset(get_copy = "pub")]
+    query_containment_ani: f64,
+
+    #[getset(get_copy = "pub")]
+    match_containment_ani: f64,
+
+    #[getset(get_copy = "pub")]
+    average_containment_ani: f64,
+
+    #[getset(get_copy = "pub")]
+    max_containment_ani: f64,
+
+    #[getset(get_copy = "pub")]
+    #[serde(deserialize_with = "python_bool")]
+    potential_false_negative: bool,
+
+    #[getset(get_copy = "pub")]
+    n_unique_weighted_found: Option<u64>,
+
+    #[getset(get_copy = "pub")]
+    sum_weighted_found: u64,
+
+    #[getset(get_copy = "pub")]
+    total_weighted_hashes: u64,
+
+    #[getset(get_copy = "pub")]
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    query_containment_ani_low: Option<f64>,
+
+    #[getset(get_copy = "pub")]
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    query_containment_ani_high: Option<f64>,
+
+    #[getset(get_copy = "pub")]
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    match_containment_ani_low: Option<f64>,
+
+    #[getset(get_copy = "pub")]
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    match_containment_ani_high: Option<f64>,
+}
+
+impl GatherRow {
+    pub fn new(result: &GatherResult, query: &GatherQueryInfo) -> Self {
+        let match_scaled = result
+            .match_
+            .minhash()
+            .map_or(query.scaled, |mh| mh.scaled());
+
+        // abundance columns are left empty when the query has no abundances
+        let abund = |value| query.abundance.then_some(value);
+
+        Self {
+            intersect_bp: result.intersect_bp,
+            f_orig_query: result.f_orig_query,
+            f_match: result.f_match,
+            f_unique_to_query: result.f_unique_to_query,
+            f_unique_weighted: result.f_unique_weighted,
+            average_abund: abund(result.average_abund),
+            median_abund: abund(result.median_abund),
+            std_abund: abund(result.std_abund),
+            filename: result.filename.clone(),
+            name: result.name.clone(),
+            md5: result.md5.clone(),
+            f_match_orig: result.f_match_orig,
+            unique_intersect_bp: result.unique_intersect_bp,
+            gather_result_rank: result.gather_result_rank,
+            remaining_bp: result.remaining_bp,
+            query_filename: query.filename.clone(),
+            query_name: query.name.clone(),
+            query_md5: query.md5.chars().take(8).collect(),
+            query_bp: query.n_hashes * query.scaled as u64,
+            ksize: query.ksize,
+            moltype: query.moltype.clone(),
+            scaled: u32::max(query.scaled, match_scaled),
+            query_n_hashes: query.n_hashes,
+            query_abundance: query.abundance,
+            query_containment_ani: result.query_containment_ani,
+            match_containment_ani: result.match_containment_ani,
+            average_containment_ani: result.average_containment_ani,
+            max_containment_ani: result.max_containment_ani,
+            potential_false_negative: result.potential_false_negative,
+            n_unique_weighted_found: query.abundance.then_some(result.n_unique_weighted_found),
+            sum_weighted_found: result.sum_weighted_found,
+            total_weighted_hashes: result.total_weighted_hashes,
+            query_containment_ani_low: result.query_containment_ani_ci_low,
+            query_containment_ani_high: result.query_containment_ani_ci_high,
+            match_containment_ani_low: result.match_containment_ani_ci_low,
+            match_containment_ani_high: result.match_containment_ani_ci_high,
+        }
+    }
+
+    /// Whether ANI confidence intervals were calculated for this row.
+    pub fn has_ani_ci(&self) -> bool {
+        self.query_containment_ani_low.is_some()
+    }
+
+    fn to_csv_record(&self, with_ani_ci: bool) -> Vec<String> {
+        let float = |v: f64| format_float(v);
+        let opt_float = |v: Option<f64>| v.map(format_float).unwrap_or_default();
+
+        let mut record = vec![
+            self.intersect_bp.to_string(),
+            float(self.f_orig_query),
+            float(self.f_match),
+            float(self.f_unique_to_query),
+            float(self.f_unique_weighted),
+            opt_float(self.average_abund),
+            opt_float(self.median_abund),
+            opt_float(self.std_abund),
+            self.filename.clone(),
+            self.name.clone(),
+            self.md5.clone(),
+            float(self.f_match_orig),
+            self.unique_intersect_bp.to_string(),
+            self.gather_result_rank.to_string(),
+            self.remaining_bp.to_string(),
+            self.query_filename.clone(),
+            self.query_name.clone(),
+            self.query_md5.clone(),
+            self.query_bp.to_string(),
+            self.ksize.to_string(),
+            self.moltype.clone(),
+            self.scaled.to_string(),
+            self.query_n_hashes.to_string(),
+            format_bool(self.query_abundance),
+            float(self.query_containment_ani),
+            float(self.match_containment_ani),
+            float(self.average_containment_ani),
+            float(self.max_containment_ani),
+            format_bool(self.potential_false_negative),
+            self.n_unique_weighted_found
+                .map(|v| v.to_string())
+                .unwrap_or_default(),
+            self.sum_weighted_found.to_string(),
+            self.total_weighted_hashes.to_string(),
+        ];
+
+        if with_ani_ci {
+            record.extend([
+                opt_float(self.query_containment_ani_low),
+                opt_float(self.query_containment_ani_high),
+                opt_float(self.match_containment_ani_low),
+                opt_float(self.match_containment_ani_high),
+            ]);
+        }
+        record
+    }
+}
+
+/// Writes gather results as CSV, matching `sourmash gather -o`.
+pub struct GatherCsvWriter<W: Write> {
+    writer: csv::Writer<W>,
+    with_ani_ci: bool,
+}
+
+impl<W: Write> GatherCsvWriter<W> {
+    /// Write the header to `writer`, including the ANI confidence interval
+    /// columns if `with_ani_ci` is set.
+    pub fn new(writer: W, with_ani_ci: bool) -> Result<Self> {
+        let mut writer = csv::WriterBuilder::new()
+            .terminator(csv::Terminator::CRLF)
+            .from_writer(writer);
+
+        if with_ani_ci {
+            writer.write_record(GATHER_COLUMNS.iter().chain(ANI_CI_COLUMNS.iter()))?;
+        } else {
+            writer.write_record(GATHER_COLUMNS)?;
+        }
+
+        Ok(Self {
+            writer,
+            with_ani_ci,
+        })
+    }
+
+    pub fn write_row(&mut self, row: &GatherRow) -> Result<()> {
+        self.writer
+            .write_record(row.to_csv_record(self.with_ani_ci))?;
+        Ok(())
+    }
+
+    pub fn write_result(&mut self, result: &GatherResult, query: &GatherQueryInfo) -> Result<()> {
+        self.write_row(&GatherRow::new(result, query))
+    }
+
+    pub fn flush(&mut self) -> Result<()> {
+        self.writer.flush()?;
+        Ok(())
+    }
+
+    pub fn into_inner(self) -> Result<W> {
+        self.writer.into_inner().map_err(|e| e.into_error().into())
+    }
+}
+
+/// Read gather results from CSV written by `sourmash gather -o` or
+/// `GatherCsvWriter`.
+pub fn read_gather_csv<R: std::io::Read>(reader: R) -> Result<Vec<GatherRow>> {
+    let mut reader = csv::Reader::from_reader(reader);
+    let mut rows = vec![];
+    for row in reader.deserialize() {
+        rows.push(row?);
+    }
+    Ok(rows)
+}
+
+/// Write gather results as newline-delimited JSON, one row per line.
+pub fn write_gather_ndjson<'a, W: Write>(
+    mut writer: W,
+    rows: impl IntoIterator<Item = &'a GatherRow>,
+) -> Result<()> {
+    for row in rows {
+        serde_json::to_writer(&mut writer, row)?;
+        writer.write_all(b"\n")?;
+    }
+    Ok(())
+}
+
+/// Read gather results from newline-delimited JSON, skipping empty lines.
+///
+/// Floats are parsed with serde_json's default (fast) algorithm, and can
+/// differ from the written values in the last digit. Use CSV for exact
+/// round-trips.
+pub fn read_gather_ndjson<R: BufRead>(reader: R) -> Result<Vec<GatherRow>> {
+    let mut rows = vec![];
+    for line in reader.lines() {
+        let line = line?;
+        if line.trim().is_empty() {
+            continue;
+        }
+        rows.push(serde_json::from_str(&line)?);
+    }
+    Ok(rows)
+}
+
+/// Format `value` like Python's `repr(float)`.
+fn format_float(value: f64) -> String {
+    if value.is_nan() {
+        return "nan".into();
+    } else if value.is_infinite() {
+        return if value > 0.0 { "inf" } else { "-inf" }.into();
+    }
+
+    // shortest representation that round-trips, e.g. "1.25e-5"
+    let sci = format!("{:e}", value);
+    let (mantissa, exp) = sci.split_once('e').expect("missing exponent");
+    let exp: i32 = exp.parse().expect("invalid exponent");
+
+    if (-4..16).contains(&exp) {
+        let positional = value.to_string();
+        if positional.contains('.') {
+            positional
+        } else {
+            positional + ".0"
+        }
+    } else {
+        let sign = if exp < 0 { '-' } else { '+' };
+        format!("{}e{}{:02}", mantissa, sign, exp.abs())
+    }
+}
+
+fn format_bool(value: bool) -> String {
+    if value { "True" } else { "False" }.into()
+}
+
+/// Accept both Python-style ("True"/"False") and JSON booleans.
+fn python_bool<'de, D: Deserializer<'de>>(deserializer: D) -> std::result::Result<bool, D::Error> {
+    #[derive(Deserialize)]
+    #[serde(untagged)]
+    enum BoolOrString {
+        Bool(bool),
+        String(String),
+    }
+
+    match BoolOrString::deserialize(deserializer)? {
+        BoolOrString::Bool(b) => Ok(b),
+        BoolOrString::String(s) => match s.as_str() {
+            "True" | "true" => Ok(true),
+            "False" | "false" => Ok(false),
+            _ => Err(serde::de::Error::custom(format!("invalid boolean: {s}"))),
+        },
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::io::BufReader;
+
+    use camino::Utf8PathBuf as PathBuf;
+
+    use super::*;
+
+    use crate::collection::Collection;
+    use crate::index::linear::LinearIndex;
+    use crate::prelude::*;
+    use crate::selection::Selection;
+
+    #[test]
+    fn python_float_format() {
+        assert_eq!(format_float(1.0), "1.0");
+        assert_eq!(format_float(0.0), "0.0");
+        assert_eq!(format_float(-0.0), "-0.0");
+        assert_eq!(format_float(0.053456221198156684), "0.053456221198156684");
+        assert_eq!(format_float(1.6153846153846154), "1.6153846153846154");
+        assert_eq!(format_float(0.0001), "0.0001");
+        assert_eq!(format_float(0.00001), "1e-05");
+        assert_eq!(format_float(1.5e-7), "1.5e-07");
+        assert_eq!(format_float(123456789.0), "123456789.0");
+        assert_eq!(format_float(1e16), "1e+16");
+        assert_eq!(format_float(1.2345e100), "1.2345e+100");
+        assert_eq!(format_float(f64::NAN), "nan");
+    }
+
+    #[test]
+    fn gather_csv_python_roundtrip() -> Result<()> {
+        let mut basedir = PathBuf::from(env!("CARGO_MANIFEST_DIR"));
+        basedir.push("../../tests/test-data/tax/");
+
+        // with and without abundances
+        for csvfile in ["test1.gather.v450.csv", "lemonade-MAG3.x.gtdb.csv"] {
+            let original = std::fs::read(basedir.join(csvfile))?;
+
+            let rows = read_gather_csv(&original[..])?;
+            assert!(!rows.is_empty());
+
+            let mut writer = GatherCsvWriter::new(vec![], false)?;
+            for row in &rows {
+                writer.write_row(row)?;
+            }
+            let output = writer.into_inner()?;
+
+            assert_eq!(
+                String::from_utf8(output).unwrap(),
+                String::from_utf8(original).unwrap()
+            );
+        }
+
+        let rows = read_gather_csv(&std::fs::read(basedir.join("lemonade-MAG3.x.gtdb.csv"))?[..])?;
+        let row = &rows[0];
+        assert_eq!(row.intersect_bp(), 116000);
+        assert!(row.name().starts_with("GCF_006265245.1"));
+        assert_eq!(row.average_abund(), None);
+        assert_eq!(row.n_unique_weighted_found(), None);
+        assert!(!row.query_abundance());
+        assert!(!row.potential_false_negative());
+        assert!(!row.has_ani_ci());
+
+        Ok(())
+    }
+
+    /// Compare rows allowing for float parsing differences in the last digit.
+    fn assert_rows_close(a: &GatherRow, b: &GatherRow) {
+        let serde_json::Value::Object(a) = serde_json::to_value(a).unwrap() else {
+            panic!("row is not a JSON object");
+        };
+        let b = serde_json::to_value(b).unwrap();
+        for (field, value) in a {
+            match (value.as_f64(), b[&field].as_f64()) {
+                (Some(x), Some(y)) if !value.is_u64() => {
+                    assert!((x - y).abs() <= 1e-12 * x.abs().max(1.0), "{field}")
+                }
+                _ => assert_eq!(value, b[&field], "{field}"),
+            }
+        }
+    }
+
+    #[test]
+    fn gather_results_to_csv_and_ndjson() -> Result<()> {
+        let mut basedir = PathBuf::from(env!("CARGO_MANIFEST_DIR"));
+        basedir.push("../../tests/test-data/track_abund/");
+
+        let selection = Selection::builder().ksize(31).scaled(1000).build();
+        let collection =
+            Collection::from_zipfile(basedir.join("track_abund.zip"))?.select(&selection)?;
+        let linear = LinearIndex::from_collection(collection.try_into()?);
+
+        let query_sig = Signature::from_path(basedir.join("47.fa.sig"))?
+            .swap_remove(0)
+            .select(&selection)?;
+        let query = query_sig.minhash().unwrap().clone();
+        let query_info = GatherQueryInfo::from_signature(&query_sig)?;
+        assert_eq!(query_info.ksize(), 31);
+        assert_eq!(query_info.moltype(), "DNA");
+        assert!(query_info.abundance());
+
+        let counter = linear.counter_for_query(&query);
+        let results = linear.gather_with_options(counter, 0, &query, true, None)?;
+        let rows: Vec<_> = results
+            .iter()
+            .map(|r| GatherRow::new(r, &query_info))
+            .collect();
+
+        let row = &rows[0];
+        assert_eq!(row.query_md5(), &query.md5sum()[..8]);
+        assert_eq!(row.query_bp(), query.size() as u64 * 1000);
+        assert_eq!(row.average_abund(), Some(results[0].average_abund()));
+        assert!(row.query_abundance());
+        assert!(row.has_ani_ci());
+
+        // CSV
+        let mut writer = GatherCsvWriter::new(vec![], true)?;
+        for result in &results {
+            writer.write_result(result, &query_info)?;
+        }
+        let output = writer.into_inner()?;
+        let header = output.split(|c| *c == b'\n').next().unwrap();
+        assert_eq!(
+            header,
+            format!(
+                "{},{}\r",
+                GATHER_COLUMNS.join(","),
+                ANI_CI_COLUMNS.join(",")
+            )
+            .as_bytes()
+        );
+        assert_eq!(read_gather_csv(&output[..])?, rows);
+
+        // NDJSON
+        let mut output = vec![];
+        write_gather_ndjson(&mut output, &rows)?;
+        assert_eq!(output.iter().filter(|c| **c == b'\n').count(), rows.len());
+        let read = read_gather_ndjson(BufReader::new(&output[..]))?;
+        assert_eq!(read.len(), rows.len());
+        for (read, row) in read.iter().zip(&rows) {
+            assert_rows_close(read, row);
+        }
+
+        Ok(())
+    }
+}
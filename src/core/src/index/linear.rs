@@ -1,21 +1,24 @@
+use std::cmp::max;
 use std::collections::HashSet;
 use std::sync::atomic::{AtomicUsize, Ordering};
 
-use camino::Utf8PathBuf as PathBuf;
-use log::info;
+use log::{info, trace};
 
 #[cfg(feature = "parallel")]
 use rayon::prelude::*;
 
 use crate::collection::CollectionSet;
 use crate::encodings::Idx;
-use crate::index::{GatherResult, Index, Selection, SigCounter};
+use crate::index::{
+    best_from_counter, best_sigs_from_counter, calculate_gather_stats, prefetch_from_counter,
+    search_from_counter, GatherResult, Index, SearchResult, SearchType, Selection, SigCounter,
+};
 use crate::selection::Select;
 use crate::signature::SigsTrait;
-use crate::sketch::minhash::KmerMinHash;
+use crate::sketch::minhash::{KmerMinHash, KmerMinHashBTree};
 use crate::sketch::Sketch;
 use crate::storage::SigStore;
-use crate::Result;
+use crate::{Error, Result};
 
 /// Supports parallel search without a particular index.
 pub struct LinearIndex {
@@ -37,6 +40,11 @@ impl LinearIndex {
         self.collection.sig_for_dataset(dataset_id)
     }
 
+    #[cfg(feature = "revindex")]
+    pub(crate) fn collection_mut(&mut self) -> &mut CollectionSet {
+        &mut self.collection
+    }
+
     pub fn collection(&self) -> &CollectionSet {
         &self.collection
     }
@@ -120,7 +128,9 @@ impl LinearIndex {
     ) -> Result<Vec<String>> {
         let mut matches = vec![];
         if similarity {
-            unimplemented!("TODO: threshold correction")
+            return Err(Error::Internal {
+                message: "similarity thresholds need the query, use search_matches".into(),
+            });
         }
 
         for (dataset_id, size) in counter.most_common() {
@@ -138,153 +148,171 @@ impl LinearIndex {
         Ok(matches)
     }
 
+    /// Structured search results for all datasets in `counter` with a
+    /// `search_type` score of at least `threshold` against `query`.
+    pub fn search_matches(
+        &self,
+        counter: SigCounter,
+        query: &KmerMinHash,
+        search_type: SearchType,
+        threshold: f64,
+    ) -> Result<Vec<SearchResult>> {
+        search_from_counter(&self.collection, counter, query, search_type, threshold)
+    }
+
+    /// The `k` best matches for `query` in `counter`, see `best_from_counter`.
+    pub fn best_matches(
+        &self,
+        counter: SigCounter,
+        query: &KmerMinHash,
+        search_type: SearchType,
+        k: usize,
+        threshold: f64,
+    ) -> Result<Vec<SearchResult>> {
+        best_from_counter(&self.collection, counter, query, search_type, k, threshold)
+    }
+
+    /// Lazily yield every dataset in `counter` overlapping `query` by at least
+    /// `threshold_bp`, largest overlap first.
+    pub fn prefetch<'a>(
+        &'a self,
+        counter: SigCounter,
+        query: &'a KmerMinHash,
+        threshold_bp: usize,
+    ) -> impl Iterator<Item = Result<SearchResult>> + 'a {
+        prefetch_from_counter(&self.collection, counter, query, threshold_bp)
+    }
+
+    /// Gather statistics for `dataset_id` as the match of round `round`.
+    ///
+    /// `query` holds the hashes of `orig_query` left after the previous
+    /// rounds, and `sum_weighted_found` the abundance they already explained.
     pub fn gather_round(
         &self,
         dataset_id: Idx,
         match_size: usize,
+        orig_query: &KmerMinHash,
         query: &KmerMinHash,
         round: usize,
+        sum_weighted_found: u64,
     ) -> Result<GatherResult> {
-        let match_path = self
-            .collection
-            .record_for_dataset(dataset_id)?
-            .internal_location()
-            .into();
         let match_sig = self.collection.sig_for_dataset(dataset_id)?;
-        let result =
-            self.stats_for_match(match_sig, query, match_size, match_path, round as u32)?;
+        let match_mh = match_sig.minhash().ok_or(Error::NoMinHashFound)?;
+
+        let max_scaled = max(match_mh.scaled(), query.scaled());
+        let query = query.clone().downsample_scaled(max_scaled)?;
+
+        let (result, _) = calculate_gather_stats(
+            orig_query,
+            query,
+            match_sig,
+            match_size,
+            round as u32,
+            sum_weighted_found,
+            orig_query.sum_abunds(),
+            orig_query.track_abundance(),
+            false,
+            None,
+        )?;
         Ok(result)
     }
 
-    fn stats_for_match(
+    /// Gather: repeatedly find the best match for the query in `counter`,
+    /// removing the hashes it explains from the query before the next round.
+    ///
+    /// Results are the same as for the disk-based `RevIndex`.
+    pub fn gather(
         &self,
-        match_sig: SigStore,
+        counter: SigCounter,
+        threshold: usize,
         query: &KmerMinHash,
-        match_size: usize,
-        match_path: PathBuf,
-        gather_result_rank: u32,
-    ) -> Result<GatherResult> {
-        let template = self.template();
-
-        let mut match_mh = None;
-        if let Some(Sketch::MinHash(mh)) = match_sig.select_sketch(template) {
-            match_mh = Some(mh);
-        }
-        let match_mh = match_mh.expect("Couldn't find a compatible MinHash");
-
-        // Calculate stats
-        let f_orig_query = match_size as f64 / query.size() as f64;
-        let f_match = match_size as f64 / match_mh.size() as f64;
-        let filename = match_path.into_string();
-        let name = match_sig.name();
-        let unique_intersect_bp = (match_mh.scaled() as usize * match_size) as u64;
-
-        let (intersect_orig, _) = match_mh.intersection_size(query)?;
-        let intersect_bp: u64 = match_mh.scaled() as u64 * intersect_orig;
-
-        let f_unique_to_query = intersect_orig as f64 / query.size() as f64;
-        let match_ = match_sig;
-
-        // TODO: all of these
-        let f_unique_weighted = 0.;
-        let average_abund = 0.;
-        let median_abund = 0.;
-        let std_abund = 0.;
-        let md5 = "".into();
-        let f_match_orig = 0.;
-        let remaining_bp = 0;
-        let total_weighted_hashes = 0;
-        let n_unique_weighted_found = 0;
-        let query_containment_ani = 0.0;
-        let match_containment_ani = 0.0;
-        let max_containment_ani = 0.0;
-        let average_containment_ani = 0.0;
-        let query_containment_ani_ci_low = None;
-        let query_containment_ani_ci_high = None;
-        let match_containment_ani_ci_low = None;
-        let match_containment_ani_ci_high = None;
-        let sum_weighted_found = 0;
-
-        Ok(GatherResult {
-            intersect_bp,
-            f_orig_query,
-            f_match,
-            f_unique_to_query,
-            f_unique_weighted,
-            average_abund,
-            median_abund,
-            std_abund,
-            filename,
-            name,
-            md5,
-            match_,
-            f_match_orig,
-            unique_intersect_bp,
-            gather_result_rank,
-            remaining_bp,
-            sum_weighted_found,
-            total_weighted_hashes,
-            n_unique_weighted_found,
-            query_containment_ani,
-            query_containment_ani_ci_low,
-            query_containment_ani_ci_high,
-            match_containment_ani,
-            match_containment_ani_ci_low,
-            match_containment_ani_ci_high,
-            max_containment_ani,
-            average_containment_ani,
-        })
+    ) -> Result<Vec<GatherResult>> {
+        self.gather_with_options(counter, threshold, query, false, None)
     }
 
-    pub fn gather(
+    /// Same as `gather`, optionally calculating ANI confidence intervals
+    /// (`confidence` defaults to 0.95).
+    pub fn gather_with_options(
         &self,
         mut counter: SigCounter,
         threshold: usize,
-        query: &KmerMinHash,
-    ) -> std::result::Result<Vec<GatherResult>, Box<dyn std::error::Error>> {
+        orig_query: &KmerMinHash,
+        calc_ani_ci: bool,
+        confidence: Option<f64>,
+    ) -> Result<Vec<GatherResult>> {
         let mut match_size = usize::MAX;
         let mut matches = vec![];
-        let template = self.template();
+        let mut query = KmerMinHashBTree::from(orig_query.clone());
+        let mut sum_weighted_found = 0;
+        let total_weighted_hashes = orig_query.sum_abunds();
+        let calc_abund_stats = orig_query.track_abundance();
 
         while match_size > threshold && !counter.is_empty() {
-            let (dataset_id, size) = counter.most_common()[0];
-            if threshold == 0 && size == 0 {
+            trace!("counter len: {}", counter.len());
+
+            let (dataset_id, size) = counter.k_most_common_ordered(1)[0];
+            match_size = if size >= threshold { size } else { break };
+            // handle special case where threshold was set to 0
+            if match_size == 0 {
                 break;
             }
 
-            match_size = if size >= threshold {
-                size
-            } else {
-                break;
-            };
+            let match_sig = self.collection.sig_for_dataset(dataset_id)?;
+            let match_mh = match_sig.minhash().ok_or(Error::NoMinHashFound)?.clone();
+
+            // make downsampled minhashes
+            let max_scaled = max(match_mh.scaled(), query.scaled());
+            let match_mh = match_mh.downsample_scaled(max_scaled)?;
+            query = query.downsample_scaled(max_scaled)?;
+            let query_mh = KmerMinHash::from(query.clone());
+
+            let (result, isect) = calculate_gather_stats(
+                orig_query,
+                query_mh,
+                match_sig,
+                match_size,
+                matches.len() as u32,
+                sum_weighted_found,
+                total_weighted_hashes,
+                calc_abund_stats,
+                calc_ani_ci,
+                confidence,
+            )?;
+
+            sum_weighted_found = result.sum_weighted_found();
+            matches.push(result);
 
-            let result = self.gather_round(dataset_id, match_size, query, matches.len())?;
+            query.remove_many(match_mh.iter_mins().copied())?;
 
             // Prepare counter for finding the next match by decrementing
             // all hashes found in the current match in other datasets
-            // TODO: maybe par_iter?
-            let mut to_remove: HashSet<Idx> = Default::default();
-            to_remove.insert(dataset_id);
-
-            for (dataset, value) in counter.iter_mut() {
-                let dataset_sig = self.collection.sig_for_dataset(*dataset)?;
-                let mut match_mh = None;
-                if let Some(Sketch::MinHash(mh)) = dataset_sig.select_sketch(template) {
-                    match_mh = Some(mh);
-                }
-                let match_mh = match_mh.expect("Couldn't find a compatible MinHash");
-
-                let (intersection, _) = query.intersection_size(match_mh)?;
-                if intersection as usize > *value {
-                    to_remove.insert(*dataset);
-                } else {
-                    *value -= intersection as usize;
-                };
+            counter.remove(&dataset_id);
+            let isect: HashSet<u64> = isect.0.into_iter().collect();
+            let datasets: Vec<Idx> = counter.keys().copied().collect();
+
+            #[cfg(feature = "parallel")]
+            let datasets_iter = datasets.into_par_iter();
+
+            #[cfg(not(feature = "parallel"))]
+            let datasets_iter = datasets.into_iter();
+
+            let found: Vec<(Idx, usize)> = datasets_iter
+                .map(|dataset| {
+                    let dataset_sig = self.collection.sig_for_dataset(dataset)?;
+                    let dataset_mh = dataset_sig.minhash().ok_or(Error::NoMinHashFound)?;
+                    let n = dataset_mh
+                        .iter_mins()
+                        .filter(|hash| isect.contains(hash))
+                        .count();
+                    Ok((dataset, n))
+                })
+                .collect::<Result<_>>()?;
+
+            for (dataset, n) in found {
+                counter
+                    .entry(dataset)
+                    .and_modify(|e| *e = e.saturating_sub(n));
             }
-            to_remove.iter().for_each(|dataset_id| {
-                counter.remove(dataset_id);
-            });
-            matches.push(result);
         }
         Ok(matches)
     }
@@ -316,6 +344,31 @@ impl Select for LinearIndex {
 impl Index<'_> for LinearIndex {
     type Item = SigStore;
 
+    fn find_best(
+        &self,
+        sig: &Self::Item,
+        k: usize,
+        threshold: f64,
+        containment: bool,
+    ) -> Result<Vec<(f64, Self::Item)>> {
+        let Some(Sketch::MinHash(query)) = sig.select_sketch(&self.template) else {
+            return Err(Error::NoMinHashFound);
+        };
+        let search_type = if containment {
+            SearchType::Containment
+        } else {
+            SearchType::Jaccard
+        };
+
+        let counter = self.counter_for_query(query);
+        Ok(
+            best_sigs_from_counter(&self.collection, counter, query, search_type, k, threshold)?
+                .into_iter()
+                .map(|(result, match_sig)| (result.similarity(), match_sig))
+                .collect(),
+        )
+    }
+
     fn insert(&mut self, _node: Self::Item) -> Result<()> {
         unimplemented!()
     }
@@ -347,3 +400,354 @@ impl Index<'_> for LinearIndex {
         unimplemented!()
     }
 }
+
+#[cfg(test)]
+mod test {
+    use camino::Utf8PathBuf as PathBuf;
+
+    use super::*;
+
+    use crate::collection::Collection;
+    use crate::prelude::*;
+    use crate::signature::Signature;
+
+    fn load_query(path: &str, selection: &Selection) -> Result<KmerMinHash> {
+        let mut filename = PathBuf::from(env!("CARGO_MANIFEST_DIR"));
+        filename.push(path);
+        let sig = Signature::from_path(filename)?
+            .swap_remove(0)
+            .select(selection)?;
+        Ok(sig.minhash().unwrap().clone())
+    }
+
+    #[test]
+    fn linear_gather_stats() -> Result<()> {
+        let mut basedir = PathBuf::from(env!("CARGO_MANIFEST_DIR"));
+        basedir.push("../../tests/test-data/gather/");
+
+        let against: Vec<_> = [
+            "GCF_000016785.1_ASM1678v1_genomic.fna.gz.sig",
+            "GCF_000018945.1_ASM1894v1_genomic.fna.gz.sig",
+            "GCF_000008545.1_ASM854v1_genomic.fna.gz.sig",
+        ]
+        .iter()
+        .map(|sig| basedir.join(sig))
+        .collect();
+
+        let selection = Selection::builder().ksize(21).scaled(10000).build();
+        let collection = Collection::from_paths(&against)?.select(&selection)?;
+        let linear = LinearIndex::from_collection(collection.try_into()?);
+
+        let query = load_query("../../tests/test-data/gather/combined.sig", &selection)?;
+        let counter = linear.counter_for_query(&query);
+        let matches = linear.gather(counter, 0, &query)?;
+
+        // same values as revindex_load_and_gather_3
+        assert_eq!(matches.len(), 3);
+
+        let match_ = &matches[0];
+        assert!(match_.name().starts_with("NC_000853.1"));
+        assert_eq!(match_.f_match(), 1.0);
+        assert_eq!(round5(match_.f_unique_to_query()), round5(0.13096862));
+        assert_eq!(match_.unique_intersect_bp(), 1920000);
+        assert_eq!(match_.remaining_bp(), 12740000);
+        assert_eq!(round5(match_.query_containment_ani()), round5(0.90773763));
+        assert_eq!(match_.md5(), &match_.get_match().md5sum());
+        assert_eq!(match_.f_match_orig(), 1.0);
+        assert_eq!(match_.f_unique_weighted(), match_.f_unique_to_query());
+        assert_eq!(match_.average_abund(), 1.0);
+        assert!(match_.query_containment_ani_ci_low().is_none());
+
+        let match_ = &matches[1];
+        assert!(match_.name().starts_with("NC_011978.1"));
+        assert_eq!(match_.f_match(), 0.898936170212766);
+        assert_eq!(round5(match_.f_unique_to_query()), round5(0.115279));
+        assert_eq!(match_.unique_intersect_bp(), 1690000);
+        assert_eq!(match_.remaining_bp(), 11050000);
+        assert_eq!(match_.gather_result_rank(), 1);
+
+        let match_ = &matches[2];
+        assert!(match_.name().starts_with("NC_009486.1"));
+        assert_eq!(round5(match_.f_match()), round5(0.4842105));
+        assert_eq!(round5(match_.f_unique_to_query()), round5(0.0627557));
+        assert_eq!(match_.unique_intersect_bp(), 920000);
+        assert_eq!(match_.remaining_bp(), 10130000);
+        assert_eq!(round5(match_.query_containment_ani()), round5(0.90728512));
+
+        // with confidence intervals
+        let counter = linear.counter_for_query(&query);
+        let matches = linear.gather_with_options(counter, 0, &query, true, None)?;
+        assert_eq!(matches.len(), 3);
+        for match_ in &matches {
+            let low = match_.query_containment_ani_ci_low().unwrap();
+            let high = match_.query_containment_ani_ci_high().unwrap();
+            assert!(low <= high);
+            assert!(match_.match_containment_ani_ci_low().is_some());
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    fn linear_gather_abundance() -> Result<()> {
+        let mut zipfile = PathBuf::from(env!("CARGO_MANIFEST_DIR"));
+        zipfile.push("../../tests/test-data/track_abund/track_abund.zip");
+
+        let selection = Selection::builder().ksize(31).scaled(1000).build();
+        let collection = Collection::from_zipfile(zipfile)?.select(&selection)?;
+        let linear = LinearIndex::from_collection(collection.try_into()?);
+
+        let query = load_query("../../tests/test-data/track_abund/47.fa.sig", &selection)?;
+        assert!(query.track_abundance());
+
+        let counter = linear.counter_for_query(&query);
+        let matches = linear.gather(counter, 0, &query)?;
+        assert!(!matches.is_empty());
+
+        let total_weighted = query.sum_abunds();
+        let match_ = &matches[0];
+        assert_eq!(match_.f_unique_to_query(), 1.0);
+        assert_eq!(match_.total_weighted_hashes(), total_weighted);
+        assert_eq!(match_.n_unique_weighted_found(), total_weighted);
+        assert_eq!(match_.sum_weighted_found(), total_weighted);
+        assert_eq!(match_.f_unique_weighted(), 1.0);
+        assert!(match_.average_abund() > 1.0);
+        assert!(match_.median_abund() >= 1.0);
+
+        Ok(())
+    }
+
+    #[test]
+    fn linear_gather_round() -> Result<()> {
+        let mut basedir = PathBuf::from(env!("CARGO_MANIFEST_DIR"));
+        basedir.push("../../tests/test-data/gather-abund/");
+
+        let against: Vec<_> = ["genome-s10.fa.gz.sig", "genome-s11.fa.gz.sig"]
+            .iter()
+            .map(|sig| basedir.join(sig))
+            .collect();
+
+        let selection = Selection::builder().ksize(21).scaled(1000).build();
+        let collection = Collection::from_paths(&against)?.select(&selection)?;
+        let linear = LinearIndex::from_collection(collection.try_into()?);
+
+        // s10 at 10x coverage, s11 at 1x
+        let orig_query = load_query(
+            "../../tests/test-data/gather-abund/reads-s10x10-s11.sig",
+            &selection,
+        )?;
+        assert!(orig_query.track_abundance());
+        let counter = linear.counter_for_query(&orig_query);
+        let matches = linear.gather(counter, 0, &orig_query)?;
+        assert!(matches.len() > 1);
+
+        // replay each round from the hashes left by the previous ones
+        let mut query = orig_query.clone();
+        let mut sum_weighted_found = 0;
+        for (round, match_) in matches.iter().enumerate() {
+            let (dataset_id, _) = linear
+                .collection()
+                .iter()
+                .find(|(_, record)| record.md5() == match_.md5())
+                .unwrap();
+            let match_size = (match_.unique_intersect_bp() / 1000) as usize;
+
+            let result = linear.gather_round(
+                dataset_id,
+                match_size,
+                &orig_query,
+                &query,
+                round,
+                sum_weighted_found,
+            )?;
+            assert_eq!(&result, match_);
+
+            query.remove_many(match_.get_match().minhash().unwrap().iter_mins().copied())?;
+            sum_weighted_found = match_.sum_weighted_found();
+        }
+
+        Ok(())
+    }
+
+    fn round5(a: f64) -> f64 {
+        (a * 1e5).round() / 1e5
+    }
+
+    #[test]
+    fn linear_search_matches() -> Result<()> {
+        let mut basedir = PathBuf::from(env!("CARGO_MANIFEST_DIR"));
+        basedir.push("../../tests/test-data/gather/");
+
+        let against: Vec<_> = [
+            "GCF_000016785.1_ASM1678v1_genomic.fna.gz.sig",
+            "GCF_000018945.1_ASM1894v1_genomic.fna.gz.sig",
+            "GCF_000008545.1_ASM854v1_genomic.fna.gz.sig",
+        ]
+        .iter()
+        .map(|sig| basedir.join(sig))
+        .collect();
+
+        let selection = Selection::builder().ksize(21).scaled(10000).build();
+        let collection = Collection::from_paths(&against)?.select(&selection)?;
+        let linear = LinearIndex::from_collection(collection.try_into()?);
+
+        // search for one of the datasets: identical match first
+        let query = load_query(
+            "../../tests/test-data/gather/GCF_000016785.1_ASM1678v1_genomic.fna.gz.sig",
+            &selection,
+        )?;
+
+        let counter = linear.counter_for_query(&query);
+        let matches = linear.search_matches(counter, &query, SearchType::Jaccard, 0.0)?;
+        assert_eq!(matches.len(), 3);
+        let match_ = &matches[0];
+        assert!(match_.name().starts_with("NC_009486.1"));
+        assert_eq!(match_.similarity(), 1.0);
+        assert_eq!(match_.jaccard(), 1.0);
+        assert_eq!(match_.query_containment(), 1.0);
+        assert_eq!(match_.match_containment(), 1.0);
+        assert_eq!(match_.query_containment_ani(), 1.0);
+        assert_eq!(match_.intersect_bp(), query.size() as u64 * 10000);
+        assert_eq!(match_.md5(), &query.md5sum());
+        assert_eq!(match_.record().md5(), &query.md5sum());
+        assert!(matches[1].similarity() >= matches[2].similarity());
+
+        let counter = linear.counter_for_query(&query);
+        let matches = linear.search_matches(counter, &query, SearchType::Jaccard, 0.3)?;
+        assert_eq!(matches.len(), 2);
+        assert!(matches[1].name().starts_with("NC_000853.1"));
+        assert_eq!(round5(matches[1].jaccard()), round5(0.33101045));
+        assert_eq!(matches[1].query_containment(), 0.5);
+
+        // containment of a mixture: matches are contained in the query, but
+        // each only covers a fraction of it
+        let query = load_query("../../tests/test-data/gather/combined.sig", &selection)?;
+
+        let counter = linear.counter_for_query(&query);
+        let matches = linear.search_matches(counter, &query, SearchType::MaxContainment, 0.5)?;
+        assert_eq!(matches.len(), 3);
+        for match_ in &matches {
+            assert_eq!(match_.similarity(), 1.0);
+            assert_eq!(match_.match_containment(), 1.0);
+            assert_eq!(match_.max_containment_ani(), 1.0);
+        }
+
+        let counter = linear.counter_for_query(&query);
+        let matches = linear.search_matches(counter, &query, SearchType::Containment, 0.0)?;
+        assert_eq!(matches.len(), 3);
+        for match_ in &matches {
+            assert_eq!(match_.similarity(), match_.query_containment());
+            assert!(match_.query_containment() < match_.match_containment());
+            assert!(match_.jaccard() <= match_.query_containment());
+        }
+
+        let counter = linear.counter_for_query(&query);
+        let matches = linear.search_matches(counter, &query, SearchType::Containment, 0.2)?;
+        assert!(matches.is_empty());
+
+        let counter = linear.counter_for_query(&query);
+        assert!(linear.search(counter, true, 0).is_err());
+
+        Ok(())
+    }
+
+    #[test]
+    fn linear_prefetch() -> Result<()> {
+        let mut basedir = PathBuf::from(env!("CARGO_MANIFEST_DIR"));
+        basedir.push("../../tests/test-data/gather/");
+
+        let against: Vec<_> = [
+            "GCF_000016785.1_ASM1678v1_genomic.fna.gz.sig",
+            "GCF_000018945.1_ASM1894v1_genomic.fna.gz.sig",
+            "GCF_000008545.1_ASM854v1_genomic.fna.gz.sig",
+        ]
+        .iter()
+        .map(|sig| basedir.join(sig))
+        .collect();
+
+        let selection = Selection::builder().ksize(21).scaled(10000).build();
+        let collection = Collection::from_paths(&against)?.select(&selection)?;
+        let linear = LinearIndex::from_collection(collection.try_into()?);
+
+        let query = load_query("../../tests/test-data/gather/combined.sig", &selection)?;
+
+        let counter = linear.counter_for_query(&query);
+        let matches: Vec<_> = linear.prefetch(counter, &query, 0).collect::<Result<_>>()?;
+        assert_eq!(matches.len(), 3);
+        assert!(matches
+            .windows(2)
+            .all(|w| w[0].intersect_bp() >= w[1].intersect_bp()));
+
+        let match_ = &matches[0];
+        assert!(match_.name().starts_with("NC_000853.1"));
+        assert_eq!(match_.intersect_bp(), 1920000);
+        assert_eq!(match_.match_containment(), 1.0);
+        assert_eq!(match_.similarity(), match_.query_containment());
+
+        let counter = linear.counter_for_query(&query);
+        let matches: Vec<_> = linear
+            .prefetch(counter, &query, 1920000)
+            .collect::<Result<_>>()?;
+        assert_eq!(matches.len(), 1);
+
+        let counter = linear.counter_for_query(&query);
+        assert_eq!(linear.prefetch(counter, &query, 1920001).count(), 0);
+
+        Ok(())
+    }
+
+    #[test]
+    fn linear_best_matches() -> Result<()> {
+        let mut basedir = PathBuf::from(env!("CARGO_MANIFEST_DIR"));
+        basedir.push("../../tests/test-data/gather/");
+
+        let against: Vec<_> = [
+            "GCF_000016785.1_ASM1678v1_genomic.fna.gz.sig",
+            "GCF_000018945.1_ASM1894v1_genomic.fna.gz.sig",
+            "GCF_000008545.1_ASM854v1_genomic.fna.gz.sig",
+        ]
+        .iter()
+        .map(|sig| basedir.join(sig))
+        .collect();
+
+        let selection = Selection::builder().ksize(21).scaled(10000).build();
+        let collection = Collection::from_paths(&against)?.select(&selection)?;
+        let linear = LinearIndex::from_collection(collection.try_into()?);
+
+        let query = load_query(
+            "../../tests/test-data/gather/GCF_000016785.1_ASM1678v1_genomic.fna.gz.sig",
+            &selection,
+        )?;
+
+        let counter = linear.counter_for_query(&query);
+        let best = linear.best_matches(counter, &query, SearchType::Jaccard, 1, 0.0)?;
+        assert_eq!(best.len(), 1);
+        assert!(best[0].name().starts_with("NC_009486.1"));
+        assert_eq!(best[0].similarity(), 1.0);
+
+        // same as a threshold search, truncated to k
+        for query_path in [
+            "../../tests/test-data/gather/GCF_000016785.1_ASM1678v1_genomic.fna.gz.sig",
+            "../../tests/test-data/gather/combined.sig",
+        ] {
+            let query = load_query(query_path, &selection)?;
+            for search_type in [
+                SearchType::Jaccard,
+                SearchType::Containment,
+                SearchType::MaxContainment,
+            ] {
+                let counter = linear.counter_for_query(&query);
+                let all = linear.search_matches(counter, &query, search_type, 0.1)?;
+
+                for k in 0..=4 {
+                    let counter = linear.counter_for_query(&query);
+                    let best = linear.best_matches(counter, &query, search_type, k, 0.1)?;
+                    assert_eq!(best.len(), usize::min(k, all.len()));
+                    assert_eq!(best[..], all[..best.len()]);
+                }
+            }
+        }
+
+        Ok(())
+    }
+}
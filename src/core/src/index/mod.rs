@@ -3,14 +3,17 @@
 //! An index organizes signatures to allow for fast similarity search.
 //! Some indices also support containment searches.
 
+pub mod gather_output;
 pub mod linear;
 
-#[cfg(not(target_arch = "wasm32"))]
-#[cfg(feature = "branchwater")]
+#[cfg(feature = "revindex")]
 pub mod revindex;
 
 pub mod search;
 
+use std::borrow::Borrow;
+use std::cmp::max;
+use std::collections::HashMap;
 use std::path::Path;
 
 use getset::{CopyGetters, Getters, Setters};
@@ -19,16 +22,21 @@ use serde::{Deserialize, Serialize};
 use stats::{median, stddev};
 use typed_builder::TypedBuilder;
 
-use crate::ani_utils::{ani_ci_from_containment, ani_from_containment};
+use crate::ani_utils::{ani_ci_from_containment, ani_from_containment, potential_false_negative};
+use crate::collection::CollectionSet;
 use crate::encodings::Idx;
-use crate::index::search::{search_minhashes, search_minhashes_containment};
+use crate::index::search::{search_minhashes, search_minhashes_containment, BestMatches};
+use crate::manifest::Record;
 use crate::prelude::*;
 use crate::selection::Selection;
 use crate::signature::SigsTrait;
 use crate::sketch::minhash::KmerMinHash;
 use crate::storage::SigStore;
 use crate::Error::CannotUpsampleScaled;
-use crate::Result;
+use crate::{Error, Result};
+
+#[cfg(feature = "parallel")]
+use rayon::prelude::*;
 
 #[derive(TypedBuilder, CopyGetters, Getters, Setters, Serialize, Deserialize, Debug, PartialEq)]
 pub struct GatherResult {
@@ -116,6 +124,11 @@ pub struct GatherResult {
 
     #[getset(get_copy = "pub")]
     max_containment_ani: f64,
+
+    /// ANI is low enough that similar datasets may have been missed at this scaled.
+    #[getset(get_copy = "pub")]
+    #[serde(default)]
+    potential_false_negative: bool,
 }
 
 impl GatherResult {
@@ -124,6 +137,66 @@ impl GatherResult {
     }
 }
 
+/// Score used to rank matches in `search_from_counter`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum SearchType {
+    /// Jaccard similarity between query and match.
+    Jaccard,
+    /// Fraction of the query contained in the match.
+    Containment,
+    /// Containment of the smaller sketch in the larger one.
+    MaxContainment,
+}
+
+#[derive(TypedBuilder, CopyGetters, Getters, Serialize, Debug, Clone, PartialEq)]
+pub struct SearchResult {
+    /// Score for the `SearchType` used in the search.
+    #[getset(get_copy = "pub")]
+    similarity: f64,
+
+    #[getset(get_copy = "pub")]
+    jaccard: f64,
+
+    /// Fraction of the query found in the match.
+    #[getset(get_copy = "pub")]
+    query_containment: f64,
+
+    /// Fraction of the match found in the query.
+    #[getset(get_copy = "pub")]
+    match_containment: f64,
+
+    #[getset(get_copy = "pub")]
+    max_containment: f64,
+
+    #[getset(get_copy = "pub")]
+    intersect_bp: u64,
+
+    #[getset(get_copy = "pub")]
+    query_containment_ani: f64,
+
+    #[getset(get_copy = "pub")]
+    match_containment_ani: f64,
+
+    #[getset(get_copy = "pub")]
+    average_containment_ani: f64,
+
+    #[getset(get_copy = "pub")]
+    max_containment_ani: f64,
+
+    #[getset(get = "pub")]
+    name: String,
+
+    #[getset(get = "pub")]
+    filename: String,
+
+    #[getset(get = "pub")]
+    md5: String,
+
+    #[serde(skip)]
+    #[getset(get = "pub")]
+    record: Record,
+}
+
 type SigCounter = counter::Counter<Idx>;
 
 pub trait Index<'a> {
@@ -160,6 +233,30 @@ pub trait Index<'a> {
         }
     }
 
+    /// The `k` items most similar to (or containing the most of) `sig`, with
+    /// scores of at least `threshold`, best first.
+    ///
+    /// Ties are broken by position in the index. Items are returned by value,
+    /// since indexes backed by a collection only load them when needed.
+    fn find_best(
+        &self,
+        sig: &Self::Item,
+        k: usize,
+        threshold: f64,
+        containment: bool,
+    ) -> Result<Vec<(f64, Self::Item)>> {
+        let mut best = BestMatches::new(k, threshold);
+        for (position, node) in self.signatures().into_iter().enumerate() {
+            let score = if containment {
+                node.containment(sig)
+            } else {
+                node.similarity(sig)
+            };
+            best.insert(score, position, node);
+        }
+        Ok(best.into_sorted_vec())
+    }
+
     //fn gather(&self, sig: &Self::Item, threshold: f64) -> Result<Vec<&Self::Item>>;
 
     fn insert(&mut self, node: Self::Item) -> Result<()>;
@@ -291,6 +388,21 @@ pub fn calculate_gather_stats(
     let average_containment_ani = (query_containment_ani + match_containment_ani) / 2.0;
     let max_containment_ani = f64::max(query_containment_ani, match_containment_ani);
 
+    let scaled = match_mh.scaled();
+    let potential_false_negative = potential_false_negative(
+        query_containment_ani,
+        ksize,
+        scaled,
+        orig_query.n_unique_kmers(),
+        None,
+    ) || potential_false_negative(
+        match_containment_ani,
+        ksize,
+        scaled,
+        match_mh.n_unique_kmers(),
+        None,
+    );
+
     // set up non-abundance weighted values
     let mut f_unique_weighted = f_unique_to_query;
     let mut average_abund = 1.0;
@@ -298,7 +410,8 @@ pub fn calculate_gather_stats(
     let mut std_abund = 0.0;
     // should these default to the unweighted numbers?
     let mut n_unique_weighted_found = 0;
-    let mut sum_total_weighted_found = 0;
+    // without abundances every hash has weight 1
+    let mut sum_total_weighted_found = sum_weighted_found + isect_size as u64;
 
     // If abundance, calculate abund-related metrics (vs current query)
     if calc_abund_stats {
@@ -347,12 +460,237 @@ pub fn calculate_gather_stats(
         .match_containment_ani(match_containment_ani)
         .average_containment_ani(average_containment_ani)
         .max_containment_ani(max_containment_ani)
+        .potential_false_negative(potential_false_negative)
         .sum_weighted_found(sum_total_weighted_found)
         .total_weighted_hashes(total_weighted_hashes)
         .build();
     Ok((result, isect))
 }
 
+/// Compare `query` and `match_sig`, downsampling both to the larger scaled.
+///
+/// Abundances are ignored.
+pub fn calculate_search_stats(
+    query: &KmerMinHash,
+    match_sig: &Signature,
+    record: &Record,
+    search_type: SearchType,
+) -> Result<SearchResult> {
+    let match_mh = match_sig.minhash().ok_or(Error::NoMinHashFound)?;
+
+    let max_scaled = max(match_mh.scaled(), query.scaled());
+    let query = query.clone().downsample_scaled(max_scaled)?;
+    let match_mh = match_mh.clone().downsample_scaled(max_scaled)?;
+
+    let (common, union) = query.intersection_size(&match_mh)?;
+    let common = common as f64;
+    let jaccard = common / u64::max(1, union) as f64;
+    let query_containment = common / usize::max(1, query.size()) as f64;
+    let match_containment = common / usize::max(1, match_mh.size()) as f64;
+    let max_containment = f64::max(query_containment, match_containment);
+
+    let ksize = match_mh.ksize() as f64;
+    let query_containment_ani = ani_from_containment(query_containment, ksize);
+    let match_containment_ani = ani_from_containment(match_containment, ksize);
+
+    let similarity = match search_type {
+        SearchType::Jaccard => jaccard,
+        SearchType::Containment => query_containment,
+        SearchType::MaxContainment => max_containment,
+    };
+
+    Ok(SearchResult::builder()
+        .similarity(similarity)
+        .jaccard(jaccard)
+        .query_containment(query_containment)
+        .match_containment(match_containment)
+        .max_containment(max_containment)
+        .intersect_bp(common as u64 * max_scaled as u64)
+        .query_containment_ani(query_containment_ani)
+        .match_containment_ani(match_containment_ani)
+        .average_containment_ani((query_containment_ani + match_containment_ani) / 2.0)
+        .max_containment_ani(f64::max(query_containment_ani, match_containment_ani))
+        .name(match_sig.name_str())
+        .filename(match_sig.filename())
+        .md5(match_sig.md5sum())
+        .record(record.clone())
+        .build())
+}
+
+/// Find all datasets in `counter` (as built by `counter_for_query`) with a
+/// score of at least `threshold` against `query`, sorted by decreasing score
+/// (ties in dataset order).
+///
+/// Candidates are preselected using the number of shared hashes in the
+/// counter, and only those are loaded to calculate `SearchResult`s.
+pub fn search_from_counter(
+    collection: &CollectionSet,
+    counter: SigCounter,
+    query: &KmerMinHash,
+    search_type: SearchType,
+    threshold: f64,
+) -> Result<Vec<SearchResult>> {
+    // Both jaccard and containment are at most (shared hashes / query size),
+    // with sizes taken at the coarser scaled of query and collection.
+    let min_shared = match (search_type, collection.min_max_scaled()) {
+        (SearchType::MaxContainment, _) | (_, None) => 1,
+        (_, Some((_, max_scaled))) => {
+            let scaled = u32::max(*max_scaled, query.scaled());
+            let query_size = query.clone().downsample_scaled(scaled)?.size();
+            // floor: keep candidates lost to rounding, they are checked below
+            usize::max(1, (threshold * query_size as f64).floor() as usize)
+        }
+    };
+
+    let candidates: Vec<Idx> = counter
+        .into_iter()
+        .filter_map(|(dataset_id, shared)| (shared >= min_shared).then_some(dataset_id))
+        .collect();
+
+    #[cfg(feature = "parallel")]
+    let candidates_iter = candidates.into_par_iter();
+
+    #[cfg(not(feature = "parallel"))]
+    let candidates_iter = candidates.into_iter();
+
+    let results: Vec<Option<(Idx, SearchResult)>> = candidates_iter
+        .map(|dataset_id| {
+            let record = collection.record_for_dataset(dataset_id)?;
+            let match_sig = collection.sig_for_dataset(dataset_id)?;
+            let result = calculate_search_stats(query, &match_sig, record, search_type)?;
+            Ok((result.similarity() >= threshold).then_some((dataset_id, result)))
+        })
+        .collect::<Result<_>>()?;
+
+    let mut results: Vec<(Idx, SearchResult)> = results.into_iter().flatten().collect();
+    results.sort_by(|(a_id, a), (b_id, b)| {
+        b.similarity
+            .total_cmp(&a.similarity)
+            .then_with(|| a_id.cmp(b_id))
+    });
+    Ok(results.into_iter().map(|(_, result)| result).collect())
+}
+
+/// The `k` best matches for `query` in `counter` (as built by
+/// `counter_for_query`) with a score of at least `threshold`, best first.
+///
+/// Candidates are visited by decreasing number of shared hashes, and only
+/// loaded if their best possible score can still beat the current `k`-th
+/// match. Ties are broken by dataset order in the collection.
+pub fn best_from_counter(
+    collection: &CollectionSet,
+    counter: SigCounter,
+    query: &KmerMinHash,
+    search_type: SearchType,
+    k: usize,
+    threshold: f64,
+) -> Result<Vec<SearchResult>> {
+    Ok(
+        best_sigs_from_counter(collection, counter, query, search_type, k, threshold)?
+            .into_iter()
+            .map(|(result, _)| result)
+            .collect(),
+    )
+}
+
+/// Same as `best_from_counter`, also returning the matched signatures.
+pub(crate) fn best_sigs_from_counter(
+    collection: &CollectionSet,
+    counter: SigCounter,
+    query: &KmerMinHash,
+    search_type: SearchType,
+    k: usize,
+    threshold: f64,
+) -> Result<Vec<(SearchResult, SigStore)>> {
+    let mut best = BestMatches::new(k, threshold);
+    // query size at each scaled used in comparisons
+    let mut query_sizes: HashMap<u32, usize> = HashMap::default();
+
+    for (dataset_id, shared) in counter.k_most_common_ordered(counter.len()) {
+        let record = collection.record_for_dataset(dataset_id)?;
+
+        // Both jaccard and containment are at most (shared / query size).
+        // Hashes in the counter are at the coarser scaled of query and match.
+        let upper_bound = match search_type {
+            SearchType::MaxContainment => 1.0,
+            _ => {
+                let scaled = u32::max(*record.scaled(), query.scaled());
+                let query_size = match query_sizes.get(&scaled) {
+                    Some(size) => *size,
+                    None => {
+                        let size = query.clone().downsample_scaled(scaled)?.size();
+                        query_sizes.insert(scaled, size);
+                        size
+                    }
+                };
+                shared as f64 / usize::max(1, query_size) as f64
+            }
+        };
+        if upper_bound < best.threshold() {
+            continue;
+        }
+
+        let match_sig = collection.sig_for_dataset(dataset_id)?;
+        let result = calculate_search_stats(query, &match_sig, record, search_type)?;
+        best.insert(
+            result.similarity(),
+            dataset_id as usize,
+            (result, match_sig),
+        );
+    }
+
+    Ok(best
+        .into_sorted_vec()
+        .into_iter()
+        .map(|(_, matched)| matched)
+        .collect())
+}
+
+/// All datasets in `counter` (as built by `counter_for_query`) sharing at
+/// least `threshold_bp` with `query`, ordered by decreasing overlap.
+///
+/// Matches are loaded lazily as the iterator advances, with `similarity`
+/// set to the containment of the query in the match.
+pub fn prefetch_from_counter<'a, Q>(
+    collection: &'a CollectionSet,
+    counter: SigCounter,
+    query: Q,
+    threshold_bp: usize,
+) -> impl Iterator<Item = Result<SearchResult>> + 'a
+where
+    Q: Borrow<KmerMinHash> + 'a,
+{
+    let query_mh = query.borrow();
+    let scaled = collection
+        .min_max_scaled()
+        .map_or(query_mh.scaled(), |(_, max_scaled)| {
+            u32::max(*max_scaled, query_mh.scaled())
+        }) as usize;
+    let min_shared = usize::max(1, (threshold_bp + scaled - 1) / scaled);
+
+    counter
+        .k_most_common_ordered(counter.len())
+        .into_iter()
+        .take_while(move |(_, shared)| *shared >= min_shared)
+        .filter_map(move |(dataset_id, _)| {
+            let result = collection
+                .record_for_dataset(dataset_id)
+                .and_then(|record| {
+                    let match_sig = collection.sig_for_dataset(dataset_id)?;
+                    calculate_search_stats(
+                        query.borrow(),
+                        &match_sig,
+                        record,
+                        SearchType::Containment,
+                    )
+                });
+            match result {
+                Ok(r) if r.intersect_bp() < threshold_bp as u64 => None,
+                r => Some(r),
+            }
+        })
+}
+
 #[cfg(test)]
 mod test_calculate_gather_stats {
     use super::*;
@@ -413,7 +751,7 @@ mod test_calculate_gather_stats {
             match_size,
             gather_result_rank,
             0,
-            total_weighted_hashes.try_into().unwrap(),
+            total_weighted_hashes,
             calc_abund_stats,
             calc_ani_ci,
             None,
@@ -451,4 +789,40 @@ mod test_calculate_gather_stats {
         assert_eq!(result.n_unique_weighted_found, 7);
         assert_eq!(result.sum_weighted_found, 7);
     }
+
+    #[test]
+    fn test_calculate_gather_stats_flat() {
+        let scaled = 10;
+        let mut match_mh = KmerMinHash::new(scaled, 31, HashFunctions::Murmur64Dna, 42, false, 0);
+        match_mh.add_many(&[1, 3, 5, 8, 11]).unwrap();
+        let mut match_sig = Signature::default();
+        match_sig.push(Sketch::MinHash(match_mh));
+
+        let mut orig_query = KmerMinHash::new(scaled, 31, HashFunctions::Murmur64Dna, 42, false, 0);
+        orig_query.add_many(&[1, 3, 5, 6, 8, 10]).unwrap();
+        // hashes found in previous rounds of gather
+        let mut query = orig_query.clone();
+        query.remove_many([6]).unwrap();
+        let total_weighted_hashes = orig_query.size() as u64;
+
+        let (result, _isect) = calculate_gather_stats(
+            &orig_query,
+            query,
+            match_sig.into(),
+            4,
+            1,
+            1,
+            total_weighted_hashes,
+            false,
+            false,
+            None,
+        )
+        .unwrap();
+
+        // without abundances every hash has weight 1
+        assert_eq!(result.total_weighted_hashes, 6);
+        assert_eq!(result.sum_weighted_found, 1 + 4);
+        assert_eq!(result.f_unique_weighted, result.f_unique_to_query);
+        assert_eq!(result.n_unique_weighted_found, 0);
+    }
 }
@@ -0,0 +1,619 @@
+use std::collections::{HashMap, HashSet};
+use std::path::Path;
+use std::sync::{Arc, RwLock};
+
+use byteorder::{LittleEndian, ReadBytesExt, WriteBytesExt};
+use histogram::Histogram;
+use log::{error, info};
+use rocksdb::WriteBatch;
+
+use crate::collection::CollectionSet;
+use crate::encodings::{Color, Idx};
+use crate::index::revindex::disk_revindex::{
+    index_datasets, internalize_storage, load_collection_from_rocksdb, load_processed,
+    load_template, map_hashes_colors, save_collection, verify_metadata,
+};
+use crate::index::revindex::{
+    self as module, checked_template, compute_color, template_from_collection, Datasets, DbStats,
+    HashToColor, IntegrityCheck, IntegrityError, QueryColors, RevIndexOps, BATCH_SIZE, MANIFEST,
+    PROCESSED, REMOVED, STORAGE_SPEC, TEMPLATE, VERSION,
+};
+use crate::index::{GatherResult, SigCounter};
+use crate::prelude::*;
+use crate::sketch::minhash::KmerMinHash;
+use crate::sketch::Sketch;
+use crate::storage::rocksdb::{
+    color_cf_descriptors, db_options, COLORS, COLOR_CFS, DB, HASHES, METADATA, PENDING, STORAGE,
+};
+use crate::HashIntoType;
+use crate::{Error, Result};
+
+/// A RevIndex storing hashes to colors and colors to datasets separately.
+///
+/// Many hashes are shared by the same set of datasets, so each set is stored
+/// only once in the `colors` column family. New datasets are first merged
+/// into the `pending` column family, and then moved into colors by
+/// `merge_pending`.
+#[derive(Clone)]
+pub struct ColorRevIndex {
+    db: Arc<DB>,
+    collection: Arc<CollectionSet>,
+    processed: Arc<RwLock<Datasets>>,
+    template: Option<Sketch>,
+}
+
+fn color_to_bytes(color: Color) -> [u8; 8] {
+    let mut color_bytes = [0u8; 8];
+    (&mut color_bytes[..])
+        .write_u64::<LittleEndian>(color)
+        .expect("error writing bytes");
+    color_bytes
+}
+
+fn hash_to_bytes(hash: u64) -> Vec<u8> {
+    let mut v = vec![0_u8; 8];
+    (&mut v[..])
+        .write_u64::<LittleEndian>(hash)
+        .expect("error writing bytes");
+    v
+}
+
+impl ColorRevIndex {
+    pub fn create(path: &Path, collection: CollectionSet) -> Result<module::RevIndex> {
+        let mut opts = db_options();
+        opts.create_if_missing(true);
+        opts.create_missing_column_families(true);
+
+        let db = Arc::new(DB::open_cf_descriptors(
+            &opts,
+            path,
+            color_cf_descriptors(),
+        )?);
+
+        let collection = Arc::new(collection);
+        let processed = Arc::new(RwLock::new(load_processed(&db, &collection, true)?));
+        let template = checked_template(load_template(&db)?, &collection)?;
+
+        let index = Self {
+            db,
+            collection,
+            processed: processed.clone(),
+            template,
+        };
+
+        let processed_sigs =
+            index_datasets(&index.db, &index.collection, &processed, PENDING, None)?;
+        index.merge_pending()?;
+
+        save_collection(&index.db, &index.collection, index.template.as_ref())?;
+
+        info!("Compact SSTs");
+        index.compact();
+        info!("Done! Processed {} reference sigs", processed_sigs);
+
+        Ok(module::RevIndex::Color(index))
+    }
+
+    /// Open a colored RevIndex.
+    ///
+    /// Pending datasets left over from an interrupted build are merged
+    /// when opening for writing; read-only access fails if there are any.
+    pub fn open<P: AsRef<Path>>(
+        path: P,
+        read_only: bool,
+        storage_spec: Option<&str>,
+    ) -> Result<module::RevIndex> {
+        let mut opts = db_options();
+        opts.create_if_missing(true);
+        opts.create_missing_column_families(true);
+
+        let cfs = color_cf_descriptors();
+
+        let db = if read_only {
+            Arc::new(DB::open_cf_descriptors_read_only(
+                &opts,
+                path.as_ref(),
+                cfs,
+                false,
+            )?)
+        } else {
+            Arc::new(DB::open_cf_descriptors(&opts, path.as_ref(), cfs)?)
+        };
+
+        let collection = Arc::new(load_collection_from_rocksdb(db.clone(), storage_spec)?);
+        let processed = Arc::new(RwLock::new(load_processed(&db, &collection, false)?));
+        let template = match load_template(&db)? {
+            Some(template) => Some(template),
+            // indices built before templates were stored
+            None => template_from_collection(&collection)?,
+        };
+
+        let index = Self {
+            db,
+            collection,
+            processed,
+            template,
+        };
+
+        if index.has_pending() {
+            if read_only {
+                return Err(Error::Internal {
+                    message: "colored RevIndex has unmerged colors, open it for writing first"
+                        .into(),
+                });
+            }
+            index.merge_pending()?;
+        }
+
+        Ok(module::RevIndex::Color(index))
+    }
+
+    fn has_pending(&self) -> bool {
+        let cf_pending = self.db.cf_handle(PENDING).unwrap();
+        self.db
+            .iterator_cf(&cf_pending, rocksdb::IteratorMode::Start)
+            .next()
+            .is_some()
+    }
+
+    /// Move datasets from the `pending` column family into colors,
+    /// returning how many hashes were updated.
+    ///
+    /// Colors left without any hash pointing to them are removed afterwards.
+    fn merge_pending(&self) -> Result<usize> {
+        let cf_pending = self.db.cf_handle(PENDING).unwrap();
+        let cf_hashes = self.db.cf_handle(HASHES).unwrap();
+        let cf_colors = self.db.cf_handle(COLORS).unwrap();
+
+        info!("Merging pending colors");
+        let mut merged = 0;
+        let mut batch = WriteBatch::default();
+        for result in self
+            .db
+            .iterator_cf(&cf_pending, rocksdb::IteratorMode::Start)
+        {
+            let (key, value) = result?;
+            let hash = (&key[..]).read_u64::<LittleEndian>()?;
+            let mut datasets =
+                Datasets::from_slice(&value).ok_or(IntegrityError::CorruptedHash(hash))?;
+
+            if let Some(old_color) = self.db.get_pinned_cf(&cf_hashes, &key)? {
+                if let Some(old_datasets) = self.db.get_pinned_cf(&cf_colors, &old_color)? {
+                    let old_color = (&old_color[..]).read_u64::<LittleEndian>()?;
+                    datasets.union(
+                        Datasets::from_slice(&old_datasets)
+                            .ok_or(IntegrityError::CorruptedColor(old_color))?,
+                    );
+                }
+            }
+
+            let color_bytes = color_to_bytes(compute_color(&datasets));
+            let datasets_bytes = datasets
+                .as_bytes()
+                .ok_or(IntegrityError::CorruptedHash(hash))?;
+            batch.put_cf(&cf_colors, color_bytes, datasets_bytes);
+            batch.put_cf(&cf_hashes, &key, color_bytes);
+            batch.delete_cf(&cf_pending, &key);
+            merged += 1;
+
+            if batch.len() >= BATCH_SIZE {
+                self.db.write(std::mem::take(&mut batch))?;
+            }
+        }
+        self.db.write(batch)?;
+        info!("Merged {} pending hashes", merged);
+
+        if merged > 0 {
+            self.remove_unused_colors()?;
+        }
+
+        Ok(merged)
+    }
+
+    /// Delete colors not referenced by any hash, returning how many were removed.
+    fn remove_unused_colors(&self) -> Result<usize> {
+        let cf_hashes = self.db.cf_handle(HASHES).unwrap();
+        let cf_colors = self.db.cf_handle(COLORS).unwrap();
+
+        let mut used: HashSet<Color> = HashSet::new();
+        for result in self
+            .db
+            .iterator_cf(&cf_hashes, rocksdb::IteratorMode::Start)
+        {
+            let (_, value) = result?;
+            used.insert((&value[..]).read_u64::<LittleEndian>()?);
+        }
+
+        let mut removed = 0;
+        let mut batch = WriteBatch::default();
+        for result in self
+            .db
+            .iterator_cf(&cf_colors, rocksdb::IteratorMode::Start)
+        {
+            let (key, _) = result?;
+            if !used.contains(&(&key[..]).read_u64::<LittleEndian>()?) {
+                batch.delete_cf(&cf_colors, &key);
+                removed += 1;
+            }
+
+            if batch.len() >= BATCH_SIZE {
+                self.db.write(std::mem::take(&mut batch))?;
+            }
+        }
+        self.db.write(batch)?;
+        info!("Removed {} unused colors", removed);
+
+        Ok(removed)
+    }
+
+    /// Datasets for each of `colors` found in the colors table.
+    fn datasets_for_colors<'a, I>(&self, colors: I) -> Result<HashMap<Color, Datasets>>
+    where
+        I: IntoIterator<Item = &'a Color>,
+    {
+        let cf_colors = self.db.cf_handle(COLORS).unwrap();
+        let colors: HashSet<Color> = colors.into_iter().copied().collect();
+        let colors: Vec<Color> = colors.into_iter().collect();
+        let keys = colors.iter().map(|c| (&cf_colors, color_to_bytes(*c)));
+
+        colors
+            .iter()
+            .zip(self.db.multi_get_cf(keys))
+            .filter_map(|(color, r)| match r {
+                Ok(Some(raw)) => Some(
+                    Datasets::from_slice(&raw)
+                        .map(|datasets| (*color, datasets))
+                        .ok_or_else(|| IntegrityError::CorruptedColor(*color).into()),
+                ),
+                Ok(None) => None,
+                Err(e) => Some(Err(e.into())),
+            })
+            .collect()
+    }
+
+    fn colors_for_query(&self, query: &KmerMinHash) -> Vec<(u64, Color)> {
+        let cf_hashes = self.db.cf_handle(HASHES).unwrap();
+        let hashes_iter = query
+            .iter_mins()
+            .map(|hash| (&cf_hashes, hash_to_bytes(*hash)));
+
+        query
+            .iter_mins()
+            .zip(self.db.multi_get_cf(hashes_iter))
+            .filter_map(|(hash, r)| {
+                r.ok().unwrap_or(None).map(|raw| {
+                    let color = (&raw[..]).read_u64::<LittleEndian>().unwrap();
+                    (*hash, color)
+                })
+            })
+            .collect()
+    }
+
+    /// Import the hashes, metadata and storage of a plain RevIndex.
+    pub(super) fn import_plain(&self, plain_db: &DB, collection: &CollectionSet) -> Result<()> {
+        self.collection.check_superset(collection)?;
+
+        info!("start converting colors");
+        let cf_plain = plain_db.cf_handle(HASHES).unwrap();
+        let cf_pending = self.db.cf_handle(PENDING).unwrap();
+        let mut batch = WriteBatch::default();
+        for result in plain_db.iterator_cf(&cf_plain, rocksdb::IteratorMode::Start) {
+            let (key, value) = result?;
+            batch.merge_cf(&cf_pending, &key, &value);
+
+            if batch.len() >= BATCH_SIZE {
+                self.db.write(std::mem::take(&mut batch))?;
+            }
+        }
+        self.db.write(batch)?;
+        self.merge_pending()?;
+        info!("finished converting colors");
+
+        info!("copying sigs to output");
+        let cf_plain = plain_db.cf_handle(STORAGE).unwrap();
+        let cf_storage = self.db.cf_handle(STORAGE).unwrap();
+        for result in plain_db.iterator_cf(&cf_plain, rocksdb::IteratorMode::Start) {
+            let (key, value) = result?;
+            self.db.put_cf(&cf_storage, &key, &value)?;
+        }
+        info!("finished copying sigs to output");
+
+        let cf_plain = plain_db.cf_handle(METADATA).unwrap();
+        let cf_metadata = self.db.cf_handle(METADATA).unwrap();
+        for key in [
+            VERSION,
+            MANIFEST,
+            STORAGE_SPEC,
+            PROCESSED,
+            REMOVED,
+            TEMPLATE,
+        ] {
+            if let Some(value) = plain_db.get_pinned_cf(&cf_plain, key)? {
+                self.db.put_cf(&cf_metadata, key, &value)?;
+            }
+        }
+
+        Ok(())
+    }
+}
+
+impl RevIndexOps for ColorRevIndex {
+    fn counter_for_query(&self, query: &KmerMinHash) -> SigCounter {
+        info!("Collecting colors");
+        let mut color_counts: HashMap<Color, usize> = HashMap::new();
+        for (_, color) in self.colors_for_query(query) {
+            *color_counts.entry(color).or_default() += 1;
+        }
+
+        let datasets = self
+            .datasets_for_colors(color_counts.keys())
+            .unwrap_or_else(|e| {
+                error!("Error loading colors: {}", e);
+                HashMap::new()
+            });
+
+        // colors missing from the colors table are skipped
+        let mut counter = SigCounter::new();
+        for (color, count) in color_counts {
+            if let Some(color_datasets) = datasets.get(&color) {
+                for dataset in color_datasets.clone() {
+                    *counter.entry(dataset).or_default() += count;
+                }
+            }
+        }
+        counter
+    }
+
+    fn datasets_for_hashes(&self, hashes: &[HashIntoType]) -> Result<Vec<Datasets>> {
+        let cf_hashes = self.db.cf_handle(HASHES).unwrap();
+        let hashes_iter = hashes.iter().map(|hash| (&cf_hashes, hash_to_bytes(*hash)));
+
+        let colors = self
+            .db
+            .multi_get_cf(hashes_iter)
+            .into_iter()
+            .map(|r| {
+                Ok(r?.map(|raw| {
+                    (&raw[..])
+                        .read_u64::<LittleEndian>()
+                        .expect("error reading color")
+                }))
+            })
+            .collect::<Result<Vec<Option<Color>>>>()?;
+        let datasets = self.datasets_for_colors(colors.iter().flatten())?;
+
+        hashes
+            .iter()
+            .zip(colors)
+            .map(|(hash, color)| match color {
+                Some(color) => datasets
+                    .get(&color)
+                    .cloned()
+                    .ok_or_else(|| IntegrityError::MissingColor { hash: *hash, color }.into()),
+                None => Ok(Datasets::default()),
+            })
+            .collect()
+    }
+
+    fn prepare_gather_counters(
+        &self,
+        query: &KmerMinHash,
+    ) -> (SigCounter, QueryColors, HashToColor) {
+        info!("Building hash_to_colors and query_colors");
+        let colors = self.colors_for_query(query);
+        let query_colors: QueryColors = self
+            .datasets_for_colors(colors.iter().map(|(_, color)| color))
+            .unwrap_or_else(|e| {
+                error!("Error loading colors: {}", e);
+                HashMap::new()
+            });
+
+        // hashes pointing to colors missing from the colors table are skipped
+        let hash_to_color: HashToColor = colors
+            .into_iter()
+            .filter(|(_, color)| query_colors.contains_key(color))
+            .collect();
+
+        let mut counter: SigCounter = Default::default();
+        for color in hash_to_color.0.values() {
+            counter.update(query_colors[color].clone());
+        }
+
+        (counter, query_colors, hash_to_color)
+    }
+
+    fn gather(
+        &self,
+        counter: SigCounter,
+        query_colors: QueryColors,
+        hash_to_color: HashToColor,
+        threshold: usize,
+        orig_query: &KmerMinHash,
+        _selection: Option<Selection>,
+    ) -> Result<Vec<GatherResult>> {
+        let orig_query = self.validate_query(orig_query)?;
+        module::gather_from_counters(
+            &self.collection,
+            counter,
+            query_colors,
+            hash_to_color,
+            threshold,
+            &orig_query,
+            None,
+        )
+    }
+
+    fn update(mut self, mut collection: CollectionSet) -> Result<module::RevIndex> {
+        self.collection.check_superset(&collection)?;
+        collection.tombstone(self.collection.removed());
+        self.template = checked_template(self.template.take(), &collection)?;
+        info!("sigs in the original index: {}", self.collection.len());
+
+        self.collection = Arc::new(collection);
+        info!(
+            "sigs in the new index once finished: {}",
+            self.collection.len()
+        );
+
+        let processed = self.processed.clone();
+        info!(
+            "sigs left to process: {}",
+            self.collection.len() - processed.read().unwrap().len()
+        );
+
+        // process the remainder
+        let processed_sigs = index_datasets(&self.db, &self.collection, &processed, PENDING, None)?;
+        self.merge_pending()?;
+
+        save_collection(&self.db, &self.collection, self.template.as_ref())?;
+
+        info!("Compact SSTs");
+        self.compact();
+
+        info!("Processed additional {} reference sigs", processed_sigs);
+
+        Ok(module::RevIndex::Color(self))
+    }
+
+    fn check(&self, quick: bool) -> DbStats {
+        let cf_hashes = self.db.cf_handle(HASHES).unwrap();
+        let cf_colors = self.db.cf_handle(COLORS).unwrap();
+
+        let mut kcount = 0;
+        let mut vcount = 0;
+        let mut vcounts = Histogram::new(12, 64).expect("Error initializing histogram");
+        let mut datasets: Datasets = Default::default();
+        let mut color_sizes: HashMap<Color, u64> = HashMap::new();
+
+        if !quick {
+            for result in self
+                .db
+                .iterator_cf(&cf_colors, rocksdb::IteratorMode::Start)
+            {
+                let (key, value) = result.unwrap();
+                let color = (&key[..]).read_u64::<LittleEndian>().unwrap();
+                let v = Datasets::from_slice(&value).expect("Error with value");
+                color_sizes.insert(color, v.len() as u64);
+                vcount += value.len();
+                datasets.union(v);
+            }
+        }
+
+        for result in self
+            .db
+            .iterator_cf(&cf_hashes, rocksdb::IteratorMode::Start)
+        {
+            let (key, value) = result.unwrap();
+            kcount += key.len();
+
+            if !quick {
+                let color = (&value[..]).read_u64::<LittleEndian>().unwrap();
+                vcounts.increment(color_sizes[&color]).unwrap();
+            }
+            vcount += value.len();
+        }
+
+        DbStats {
+            total_datasets: datasets.len(),
+            total_keys: kcount / 8,
+            kcount,
+            vcount,
+            vcounts,
+        }
+    }
+
+    fn compact(&self) {
+        for cf_name in COLOR_CFS {
+            let cf = self.db.cf_handle(cf_name).unwrap();
+            self.db.compact_range_cf(&cf, None::<&[u8]>, None::<&[u8]>)
+        }
+    }
+
+    fn flush(&self) -> Result<()> {
+        self.db.flush_wal(true)?;
+
+        for cf_name in [HASHES, COLORS, METADATA] {
+            let cf = self.db.cf_handle(cf_name).unwrap();
+            self.db.flush_cf(&cf)?;
+        }
+
+        Ok(())
+    }
+
+    fn collection(&self) -> &CollectionSet {
+        &self.collection
+    }
+
+    fn template(&self) -> Option<&Sketch> {
+        self.template.as_ref()
+    }
+
+    fn internalize_storage(&mut self) -> Result<()> {
+        internalize_storage(&self.db, &mut self.collection)
+    }
+
+    fn verify(&self) -> Result<Vec<IntegrityError>> {
+        let mut problems = vec![];
+        let (processed, removed) = verify_metadata(&self.db, &mut problems)?;
+
+        let mut check = IntegrityCheck::new(&self.collection, removed, None);
+        for problem in problems {
+            check.add_problem(problem);
+        }
+
+        let cf_pending = self.db.cf_handle(PENDING).unwrap();
+        let pending = self
+            .db
+            .iterator_cf(&cf_pending, rocksdb::IteratorMode::Start)
+            .count();
+        if pending > 0 {
+            check.add_problem(IntegrityError::UnmergedColors(pending));
+        }
+
+        info!("Checking hashes");
+        let cf_hashes = self.db.cf_handle(HASHES).unwrap();
+        let cf_colors = self.db.cf_handle(COLORS).unwrap();
+        let mut colors: HashMap<Color, Option<Datasets>> = HashMap::new();
+        for result in self
+            .db
+            .iterator_cf(&cf_hashes, rocksdb::IteratorMode::Start)
+        {
+            let (key, value) = result?;
+            let hash = (&key[..]).read_u64::<LittleEndian>()?;
+            let color = (&value[..]).read_u64::<LittleEndian>()?;
+
+            if let std::collections::hash_map::Entry::Vacant(e) = colors.entry(color) {
+                let datasets = self
+                    .db
+                    .get_pinned_cf(&cf_colors, color_to_bytes(color))?
+                    .and_then(|raw| Datasets::from_slice(&raw));
+                e.insert(datasets);
+            }
+
+            match &colors[&color] {
+                Some(datasets) => check.add_hash(hash, datasets.clone()),
+                None => check.add_problem(IntegrityError::MissingColor { hash, color }),
+            }
+        }
+
+        info!("Checking datasets");
+        Ok(check.finish(processed))
+    }
+
+    fn repair(&mut self, datasets: &[Idx]) -> Result<()> {
+        for dataset in datasets {
+            info!("Repairing dataset {}", dataset);
+            map_hashes_colors(&self.db, &self.collection, *dataset, PENDING, None)?;
+            self.processed.write().unwrap().extend([*dataset]);
+        }
+        self.merge_pending()?;
+        save_collection(&self.db, &self.collection, self.template.as_ref())?;
+        self.flush()
+    }
+
+    fn convert(&self, _output_db: module::RevIndex) -> Result<()> {
+        Err(Error::Internal {
+            message: "a colored RevIndex can't be converted".into(),
+        })
+    }
+}
@@ -1,45 +1,42 @@
-use std::cmp::max;
-use std::hash::{BuildHasher, BuildHasherDefault, Hash, Hasher};
+use std::collections::{BTreeSet, HashSet};
 use std::path::Path;
 use std::sync::atomic::{AtomicUsize, Ordering};
 use std::sync::{Arc, RwLock};
 
-use byteorder::{LittleEndian, WriteBytesExt};
-use log::{info, trace};
+use byteorder::{LittleEndian, ReadBytesExt, WriteBytesExt};
+use log::info;
 use rayon::prelude::*;
-use rocksdb::MergeOperands;
+use rocksdb::{MergeOperands, WriteBatch};
 
 use crate::collection::{Collection, CollectionSet};
-use crate::encodings::{Color, Idx};
+use crate::encodings::Idx;
 use crate::index::revindex::{
-    self as module, stats_for_cf, Datasets, DbStats, HashToColor, QueryColors, RevIndexOps,
-    MANIFEST, PROCESSED, STORAGE_SPEC, VERSION,
+    self as module, checked_template, compute_color, datasets_for_keys, stats_for_cf,
+    template_from_collection, Datasets, DbStats, HashToColor, IntegrityCheck, IntegrityError,
+    QueryColors, RevIndexOps, Shard, ShardBy, BATCH_SIZE, MANIFEST, PROCESSED, REMOVED, SHARD,
+    STORAGE_SPEC, TEMPLATE, VERSION,
 };
-use crate::index::{calculate_gather_stats, GatherResult, SigCounter};
-use crate::manifest::Manifest;
+use crate::index::{GatherResult, SigCounter};
+use crate::manifest::{Manifest, Record};
 use crate::prelude::*;
-use crate::sketch::minhash::{KmerMinHash, KmerMinHashBTree};
+use crate::sketch::minhash::KmerMinHash;
 use crate::sketch::Sketch;
 use crate::storage::{
     rocksdb::{cf_descriptors, db_options, ALL_CFS, DB, HASHES, METADATA},
     InnerStorage, RocksDBStorage, Storage,
 };
-use crate::Result;
+use crate::HashIntoType;
+use crate::{Error, Result};
 
 const DB_VERSION: u8 = 1;
 
-fn compute_color(idxs: &Datasets) -> Color {
-    let s = BuildHasherDefault::<twox_hash::Xxh3Hash128>::default();
-    let mut hasher = s.build_hasher();
-    idxs.hash(&mut hasher);
-    hasher.finish()
-}
-
 #[derive(Clone)]
 pub struct RevIndex {
     db: Arc<DB>,
     collection: Arc<CollectionSet>,
     processed: Arc<RwLock<Datasets>>,
+    template: Option<Sketch>,
+    shard: Option<Shard>,
 }
 
 pub(crate) fn merge_datasets(
@@ -78,47 +75,33 @@ impl RevIndex {
 
         let db = Arc::new(DB::open_cf_descriptors(&opts, path, cfs).unwrap());
 
-        let processed_sigs = AtomicUsize::new(0);
-
         let collection = Arc::new(collection);
-        let processed = Arc::new(RwLock::new(Self::load_processed(
-            db.clone(),
-            collection.clone(),
-            true,
-        )?));
+        let processed = Arc::new(RwLock::new(load_processed(&db, &collection, true)?));
+        let template = checked_template(load_template(&db)?, &collection)?;
+        let shard = load_shard(&db)?;
 
         let index = Self {
             db,
             collection,
             processed: processed.clone(),
+            template,
+            shard,
         };
 
-        index.collection.par_iter().for_each(|(dataset_id, _)| {
-            // check if this dataset_id was processed already
-            // call map_hashes_colors only if not already processed
-            if !processed.read().unwrap().contains(&dataset_id) {
-                let i = processed_sigs.fetch_add(1, Ordering::SeqCst);
-                if i % 1000 == 0 {
-                    info!("Processed {} reference sigs", i);
-                }
-
-                index.map_hashes_colors(dataset_id as Idx);
-
-                // if cached in a new field in the RevIndex,
-                // then update the cache too
+        let processed_sigs = index_datasets(
+            &index.db,
+            &index.collection,
+            &processed,
+            HASHES,
+            index.shard.as_ref(),
+        )?;
 
-                processed.write().unwrap().extend([dataset_id]);
-            }
-        });
-
-        index.save_collection().expect("Error saving collection");
+        save_collection(&index.db, &index.collection, index.template.as_ref())
+            .expect("Error saving collection");
 
         info!("Compact SSTs");
         index.compact();
-        info!(
-            "Done! Processed {} reference sigs",
-            processed_sigs.into_inner()
-        );
+        info!("Done! Processed {} reference sigs", processed_sigs);
 
         Ok(module::RevIndex::Plain(index))
     }
@@ -146,132 +129,543 @@ impl RevIndex {
             Arc::new(DB::open_cf_descriptors(&opts, path.as_ref(), cfs)?)
         };
 
-        let collection = Arc::new(Self::load_collection_from_rocksdb(
-            db.clone(),
-            storage_spec,
-        )?);
-
-        let processed = Arc::new(RwLock::new(Self::load_processed(
-            db.clone(),
-            collection.clone(),
-            false,
-        )?));
+        let collection = Arc::new(load_collection_from_rocksdb(db.clone(), storage_spec)?);
+        let processed = Arc::new(RwLock::new(load_processed(&db, &collection, false)?));
+        let template = match load_template(&db)? {
+            Some(template) => Some(template),
+            // indices built before templates were stored
+            None => template_from_collection(&collection)?,
+        };
+        let shard = load_shard(&db)?;
 
         Ok(module::RevIndex::Plain(Self {
             db,
             collection,
             processed,
+            template,
+            shard,
         }))
     }
 
-    fn load_processed(
-        db: Arc<DB>,
-        collection: Arc<CollectionSet>,
-        assume_empty: bool,
-    ) -> Result<Datasets> {
-        let cf_metadata = db.cf_handle(METADATA).unwrap();
-        if let Some(rdr) = db.get_pinned_cf(&cf_metadata, PROCESSED)? {
-            // convert rdr to Datasets
-            Datasets::from_slice(&rdr)
-                .ok_or_else(|| todo!("throw error from deserializing Datasets"))
-        } else if assume_empty {
-            Ok(Datasets::default())
+    /// Merge `indices` into a new index at `path`, renumbering datasets in
+    /// order. Signatures are copied into the new index, unless all indices
+    /// share the same external storage.
+    pub(super) fn merge(indices: &[&Self], path: &Path) -> Result<module::RevIndex> {
+        if indices.iter().any(|index| index.shard.is_some()) {
+            return Err(Error::Internal {
+                message: "shards can't be merged, combine their gather counters instead".into(),
+            });
+        }
+
+        let mut template = None;
+        for index in indices {
+            template = checked_template(template, &index.collection)?;
+        }
+
+        let specs: HashSet<String> = indices
+            .iter()
+            .map(|index| index.collection.storage().spec())
+            .collect();
+        let shared_storage = specs.len() == 1
+            && specs
+                .iter()
+                .all(|spec| !spec.starts_with("rocksdb") && !spec.starts_with("memory"));
+
+        let db = create_db(path)?;
+        let internal_storage = RocksDBStorage::from_db(db.clone());
+        let cf_hashes = db.cf_handle(HASHES).unwrap();
+
+        let mut records: Vec<Record> = vec![];
+        let mut processed = Datasets::default();
+        let mut removed = Datasets::default();
+        for (i, index) in indices.iter().enumerate() {
+            let offset = records.len() as Idx;
+            let shift = |datasets: Datasets| -> Datasets {
+                let ids: Vec<Idx> = datasets.into_iter().map(|d| d + offset).collect();
+                Datasets::new(&ids)
+            };
+            info!(
+                "Merging {} datasets from index {}",
+                index.collection.len(),
+                i
+            );
+
+            // removed datasets are kept too, to preserve the id offsets
+            for record in index.collection.manifest().iter() {
+                let mut record = record.clone();
+                if !shared_storage {
+                    let location = record.internal_location().as_str();
+                    let sig_data = index.collection.storage().load(location)?;
+                    let location =
+                        internal_storage.save(&format!("{}/{}", i, location), &sig_data)?;
+                    record.set_internal_location(location.into());
+                }
+                records.push(record);
+            }
+
+            let mut batch = WriteBatch::default();
+            let cf_input = index.db.cf_handle(HASHES).unwrap();
+            for result in index
+                .db
+                .iterator_cf(&cf_input, rocksdb::IteratorMode::Start)
+            {
+                let (key, value) = result?;
+                let datasets = Datasets::from_slice(&value).ok_or_else(|| {
+                    IntegrityError::CorruptedHash((&key[..]).read_u64::<LittleEndian>().unwrap())
+                })?;
+                batch.merge_cf(&cf_hashes, &key, shift(datasets).as_bytes().unwrap());
+
+                if batch.len() >= BATCH_SIZE {
+                    db.write(std::mem::take(&mut batch))?;
+                }
+            }
+            db.write(batch)?;
+
+            processed.union(shift(index.processed.read().unwrap().clone()));
+            removed.union(shift(load_removed(&index.db)?));
+        }
+
+        let storage = if shared_storage {
+            indices[0].collection.storage().clone()
         } else {
-            let all_datasets: Vec<_> = (0..collection.manifest().len()).map(|v| v as Idx).collect();
-            Ok(Datasets::new(&all_datasets))
+            InnerStorage::new(internal_storage)
+        };
+        let mut collection: CollectionSet =
+            Collection::new(Manifest::from(records), storage).try_into()?;
+        collection.tombstone(removed.clone());
+
+        save_collection(&db, &collection, template.as_ref())?;
+        save_datasets(&db, &processed, &removed, None)?;
+
+        let index = Self {
+            db,
+            collection: Arc::new(collection),
+            processed: Arc::new(RwLock::new(processed)),
+            template,
+            shard: None,
+        };
+        index.compact();
+        Ok(module::RevIndex::Plain(index))
+    }
+
+    /// Split this index into one shard per path in `paths`.
+    pub(super) fn shard<P: AsRef<Path>>(
+        &self,
+        paths: &[P],
+        by: ShardBy,
+    ) -> Result<Vec<module::RevIndex>> {
+        if self.shard.is_some() {
+            return Err(Error::Internal {
+                message: "this index is already a shard".into(),
+            });
+        }
+
+        let max_hash = match &self.template {
+            Some(Sketch::MinHash(mh)) if mh.max_hash() != 0 => mh.max_hash(),
+            _ => u64::MAX,
+        };
+        let shards = Shard::split(by, paths.len(), max_hash, self.collection.len());
+        let dbs = paths
+            .iter()
+            .map(|path| create_db(path.as_ref()))
+            .collect::<Result<Vec<_>>>()?;
+
+        info!("Splitting hashes into {} shards", shards.len());
+        let mut batches: Vec<WriteBatch> = dbs.iter().map(|_| WriteBatch::default()).collect();
+        let cf_hashes = self.db.cf_handle(HASHES).unwrap();
+        for result in self
+            .db
+            .iterator_cf(&cf_hashes, rocksdb::IteratorMode::Start)
+        {
+            let (key, value) = result?;
+            let hash = (&key[..]).read_u64::<LittleEndian>()?;
+            let datasets =
+                Datasets::from_slice(&value).ok_or(IntegrityError::CorruptedHash(hash))?;
+
+            for ((shard, db), batch) in shards.iter().zip(&dbs).zip(batches.iter_mut()) {
+                if !shard.contains_hash(hash) {
+                    continue;
+                }
+                let ids: Vec<Idx> = datasets
+                    .clone()
+                    .into_iter()
+                    .filter(|d| shard.contains_dataset(*d))
+                    .collect();
+                if ids.is_empty() {
+                    continue;
+                }
+
+                let cf_shard = db.cf_handle(HASHES).unwrap();
+                batch.put_cf(&cf_shard, &key, Datasets::new(&ids).as_bytes().unwrap());
+                if batch.len() >= BATCH_SIZE {
+                    db.write(std::mem::take(batch))?;
+                }
+            }
         }
+
+        let internal = self.collection.storage().spec().starts_with("rocksdb");
+        let removed = load_removed(&self.db)?;
+        let processed = self.processed.read().unwrap().clone();
+
+        shards
+            .into_iter()
+            .zip(dbs)
+            .zip(batches)
+            .map(|((shard, db), batch)| {
+                db.write(batch)?;
+
+                let mut collection = self.collection.clone();
+                if internal {
+                    // gather can run on any shard, so all signatures are needed
+                    let storage = RocksDBStorage::from_db(db.clone());
+                    for location in self.collection.manifest().internal_locations() {
+                        storage.save(location, &self.collection.storage().load(location)?)?;
+                    }
+                    collection = Arc::new(
+                        Collection::new(
+                            self.collection.manifest().clone(),
+                            InnerStorage::new(storage),
+                        )
+                        .try_into()?,
+                    );
+                }
+
+                let ids: Vec<Idx> = processed
+                    .clone()
+                    .into_iter()
+                    .filter(|d| shard.contains_dataset(*d))
+                    .collect();
+                let processed = Datasets::new(&ids);
+
+                save_collection(&db, &collection, self.template.as_ref())?;
+                save_datasets(&db, &processed, &removed, Some(&shard))?;
+
+                let index = Self {
+                    db,
+                    collection,
+                    processed: Arc::new(RwLock::new(processed)),
+                    template: self.template.clone(),
+                    shard: Some(shard),
+                };
+                index.compact();
+                Ok(module::RevIndex::Plain(index))
+            })
+            .collect()
     }
+}
 
-    fn load_collection_from_rocksdb(
-        db: Arc<DB>,
-        storage_spec: Option<&str>,
-    ) -> Result<CollectionSet> {
-        let cf_metadata = db.cf_handle(METADATA).unwrap();
+/// Open a new database at `path`, with the column families of a plain index.
+fn create_db(path: &Path) -> Result<Arc<DB>> {
+    let mut opts = db_options();
+    opts.create_if_missing(true);
+    opts.create_missing_column_families(true);
+
+    Ok(Arc::new(DB::open_cf_descriptors(
+        &opts,
+        path,
+        cf_descriptors(),
+    )?))
+}
 
-        let rdr = db.get_cf(&cf_metadata, VERSION)?.unwrap();
-        assert_eq!(rdr[0], DB_VERSION);
+/// Write the processed and removed datasets and the shard of a new index.
+fn save_datasets(
+    db: &DB,
+    processed: &Datasets,
+    removed: &Datasets,
+    shard: Option<&Shard>,
+) -> Result<()> {
+    let cf_metadata = db.cf_handle(METADATA).unwrap();
+    db.put_cf(&cf_metadata, PROCESSED, processed.as_bytes().unwrap())?;
+    if removed.len() > 0 {
+        db.put_cf(&cf_metadata, REMOVED, removed.as_bytes().unwrap())?;
+    }
+    if let Some(shard) = shard {
+        db.put_cf(&cf_metadata, SHARD, serde_json::to_vec(shard)?)?;
+    }
+    Ok(())
+}
+
+/// Part of the collection indexed in `db`, if it is a shard.
+pub(super) fn load_shard(db: &DB) -> Result<Option<Shard>> {
+    let cf_metadata = db.cf_handle(METADATA).unwrap();
+    match db.get_pinned_cf(&cf_metadata, SHARD)? {
+        Some(rdr) => {
+            Ok(Some(serde_json::from_slice(&rdr).map_err(|_| {
+                IntegrityError::CorruptedMetadata(SHARD.into())
+            })?))
+        }
+        None => Ok(None),
+    }
+}
 
-        let rdr = db.get_cf(&cf_metadata, MANIFEST)?.unwrap();
-        let manifest = Manifest::from_reader(&rdr[..])?;
+/// Check the metadata entries of `db`, returning the processed datasets
+/// (if they can be loaded) and the removed ones.
+pub(super) fn verify_metadata(
+    db: &DB,
+    problems: &mut Vec<IntegrityError>,
+) -> Result<(Option<Datasets>, Datasets)> {
+    let cf_metadata = db.cf_handle(METADATA).unwrap();
+
+    for key in [VERSION, MANIFEST, STORAGE_SPEC] {
+        if db.get_pinned_cf(&cf_metadata, key)?.is_none() {
+            problems.push(IntegrityError::MissingMetadata(key.into()));
+        }
+    }
 
-        let spec = match storage_spec {
-            Some(spec) => spec.into(),
-            None => {
-                let db_spec = db.get_cf(&cf_metadata, STORAGE_SPEC)?;
-                String::from_utf8(db_spec.unwrap()).map_err(|e| e.utf8_error())?
+    let processed = match db.get_pinned_cf(&cf_metadata, PROCESSED)? {
+        Some(rdr) => {
+            let processed = Datasets::from_slice(&rdr);
+            if processed.is_none() {
+                problems.push(IntegrityError::CorruptedMetadata(PROCESSED.into()));
             }
-        };
+            processed
+        }
+        None => {
+            problems.push(IntegrityError::MissingMetadata(PROCESSED.into()));
+            None
+        }
+    };
 
-        let storage = if spec == "rocksdb://" {
-            InnerStorage::new(RocksDBStorage::from_db(db.clone()))
-        } else {
-            InnerStorage::from_spec(spec)?
-        };
+    let removed = match db.get_pinned_cf(&cf_metadata, REMOVED)? {
+        Some(rdr) => Datasets::from_slice(&rdr).unwrap_or_else(|| {
+            problems.push(IntegrityError::CorruptedMetadata(REMOVED.into()));
+            Datasets::default()
+        }),
+        None => Datasets::default(),
+    };
+
+    Ok((processed, removed))
+}
+
+/// Datasets removed from `db`, kept as tombstones in the manifest.
+pub(super) fn load_removed(db: &DB) -> Result<Datasets> {
+    let cf_metadata = db.cf_handle(METADATA).unwrap();
+    Ok(db
+        .get_pinned_cf(&cf_metadata, REMOVED)?
+        .and_then(|rdr| Datasets::from_slice(&rdr))
+        .unwrap_or_default())
+}
 
-        Collection::new(manifest, storage).try_into()
+/// Datasets already indexed in `db`.
+pub(super) fn load_processed(
+    db: &DB,
+    collection: &CollectionSet,
+    assume_empty: bool,
+) -> Result<Datasets> {
+    let cf_metadata = db.cf_handle(METADATA).unwrap();
+    if let Some(rdr) = db.get_pinned_cf(&cf_metadata, PROCESSED)? {
+        // convert rdr to Datasets
+        Datasets::from_slice(&rdr)
+            .ok_or_else(|| IntegrityError::CorruptedMetadata(PROCESSED.into()).into())
+    } else if assume_empty {
+        Ok(Datasets::default())
+    } else {
+        let all_datasets: Vec<_> = (0..collection.manifest().len()).map(|v| v as Idx).collect();
+        Ok(Datasets::new(&all_datasets))
     }
+}
 
-    fn save_collection(&self) -> Result<()> {
-        let cf_metadata = self.db.cf_handle(METADATA).unwrap();
+pub(super) fn load_collection_from_rocksdb(
+    db: Arc<DB>,
+    storage_spec: Option<&str>,
+) -> Result<CollectionSet> {
+    let cf_metadata = db.cf_handle(METADATA).unwrap();
+
+    let rdr = db
+        .get_cf(&cf_metadata, VERSION)?
+        .ok_or_else(|| IntegrityError::MissingMetadata(VERSION.into()))?;
+    assert_eq!(rdr[0], DB_VERSION);
+
+    let rdr = db
+        .get_cf(&cf_metadata, MANIFEST)?
+        .ok_or_else(|| IntegrityError::MissingMetadata(MANIFEST.into()))?;
+    let manifest = Manifest::from_reader(&rdr[..])?;
+
+    let spec = match storage_spec {
+        Some(spec) => spec.into(),
+        None => {
+            let db_spec = db
+                .get_cf(&cf_metadata, STORAGE_SPEC)?
+                .ok_or_else(|| IntegrityError::MissingMetadata(STORAGE_SPEC.into()))?;
+            String::from_utf8(db_spec).map_err(|e| e.utf8_error())?
+        }
+    };
 
-        // save DB version
-        // TODO: probably should go together with a more general
-        //       saving procedure used in create/update
-        self.db.put_cf(&cf_metadata, VERSION, [DB_VERSION])?;
+    let storage = if spec == "rocksdb://" {
+        InnerStorage::new(RocksDBStorage::from_db(db.clone()))
+    } else {
+        InnerStorage::from_spec(spec)?
+    };
 
-        // write manifest
-        let mut wtr = vec![];
-        {
-            self.collection.manifest().to_writer(&mut wtr)?;
+    let mut collection = Collection::new(manifest, storage);
+    collection.tombstone(load_removed(&db)?);
+    collection.try_into()
+}
+
+/// Template stored in `db`, if any.
+pub(super) fn load_template(db: &DB) -> Result<Option<Sketch>> {
+    let cf_metadata = db.cf_handle(METADATA).unwrap();
+    match db.get_pinned_cf(&cf_metadata, TEMPLATE)? {
+        Some(rdr) => {
+            Ok(Some(serde_json::from_slice(&rdr).map_err(|_| {
+                IntegrityError::CorruptedMetadata(TEMPLATE.into())
+            })?))
         }
-        self.db.put_cf(&cf_metadata, MANIFEST, &wtr[..])?;
+        None => Ok(None),
+    }
+}
 
-        // write storage spec
-        let spec = self.collection.storage().spec();
+pub(super) fn save_collection(
+    db: &DB,
+    collection: &CollectionSet,
+    template: Option<&Sketch>,
+) -> Result<()> {
+    let cf_metadata = db.cf_handle(METADATA).unwrap();
+
+    // save DB version
+    // TODO: probably should go together with a more general
+    //       saving procedure used in create/update
+    db.put_cf(&cf_metadata, VERSION, [DB_VERSION])?;
+
+    // write manifest
+    let mut wtr = vec![];
+    {
+        collection.manifest().to_writer(&mut wtr)?;
+    }
+    db.put_cf(&cf_metadata, MANIFEST, &wtr[..])?;
 
-        // TODO: check if spec if memstorage, would probably have to
-        // save into rocksdb in that case!
+    // write storage spec
+    let mut spec = collection.storage().spec();
+    if spec == format!("rocksdb://{}", db.path().display()) {
+        // signatures are stored in this db, loaded from it on open
+        spec = "rocksdb://".into();
+    }
 
-        self.db.put_cf(&cf_metadata, STORAGE_SPEC, spec)?;
+    // TODO: check if spec if memstorage, would probably have to
+    // save into rocksdb in that case!
 
-        Ok(())
+    db.put_cf(&cf_metadata, STORAGE_SPEC, spec)?;
+
+    // write template
+    if let Some(template) = template {
+        db.put_cf(&cf_metadata, TEMPLATE, serde_json::to_vec(template)?)?;
     }
 
-    fn map_hashes_colors(&self, dataset_id: Idx) {
-        let search_sig = self
-            .collection
-            .sig_for_dataset(dataset_id)
-            .expect("Couldn't find a compatible Signature");
-        let search_mh = &search_sig.sketches()[0];
+    Ok(())
+}
 
-        let colors = Datasets::new(&[dataset_id]).as_bytes().unwrap();
+/// Merge the hashes of `dataset_id` (in `shard`, if any) into the `cf_name`
+/// column family, and mark the dataset as processed.
+pub(super) fn map_hashes_colors(
+    db: &DB,
+    collection: &CollectionSet,
+    dataset_id: Idx,
+    cf_name: &str,
+    shard: Option<&Shard>,
+) -> Result<()> {
+    let search_sig = collection.sig_for_dataset(dataset_id)?;
+
+    let colors = Datasets::new(&[dataset_id]).as_bytes().unwrap();
+
+    let cf_hashes = db.cf_handle(cf_name).unwrap();
+
+    let hashes = match search_sig.sketches().first() {
+        Some(Sketch::MinHash(mh)) => mh.mins(),
+        Some(Sketch::LargeMinHash(mh)) => mh.mins(),
+        _ => return Err(Error::NoMinHashFound),
+    };
+
+    let mut hash_bytes = [0u8; 8];
+    for hash in hashes
+        .into_iter()
+        .filter(|hash| shard.map_or(true, |s| s.contains_hash(*hash)))
+    {
+        (&mut hash_bytes[..])
+            .write_u64::<LittleEndian>(hash)
+            .expect("error writing bytes");
+        db.merge_cf(&cf_hashes, &hash_bytes[..], colors.as_slice())?;
+    }
 
-        let cf_hashes = self.db.cf_handle(HASHES).unwrap();
+    // finished processing this dataset,
+    // do a merge_cf in the PROCESSED key in metadata
+    // to account for that.
+    let cf_metadata = db.cf_handle(METADATA).unwrap();
+    db.merge_cf(&cf_metadata, PROCESSED, colors.as_slice())?;
 
-        let hashes = match search_mh {
-            Sketch::MinHash(mh) => mh.mins(),
-            Sketch::LargeMinHash(mh) => mh.mins(),
-            _ => unimplemented!(),
-        };
+    Ok(())
+}
 
-        let mut hash_bytes = [0u8; 8];
-        for hash in hashes {
-            (&mut hash_bytes[..])
-                .write_u64::<LittleEndian>(hash)
-                .expect("error writing bytes");
-            self.db
-                .merge_cf(&cf_hashes, &hash_bytes[..], colors.as_slice())
-                .expect("error merging");
+/// Index all datasets in `collection` (and `shard`, if any) not yet in
+/// `processed`, returning how many were added.
+pub(super) fn index_datasets(
+    db: &DB,
+    collection: &CollectionSet,
+    processed: &RwLock<Datasets>,
+    cf_name: &str,
+    shard: Option<&Shard>,
+) -> Result<usize> {
+    let processed_sigs = AtomicUsize::new(0);
+
+    collection.par_iter().try_for_each(|(dataset_id, _)| {
+        // check if this dataset_id was processed already
+        // call map_hashes_colors only if not already processed
+        if !processed.read().unwrap().contains(&dataset_id)
+            && shard.map_or(true, |s| s.contains_dataset(dataset_id))
+        {
+            let i = processed_sigs.fetch_add(1, Ordering::SeqCst);
+            if i % 1000 == 0 {
+                info!("Processed {} reference sigs", i);
+            }
+
+            map_hashes_colors(db, collection, dataset_id as Idx, cf_name, shard)?;
+
+            // if cached in a new field in the RevIndex,
+            // then update the cache too
+
+            processed.write().unwrap().extend([dataset_id]);
         }
+        Ok::<(), Error>(())
+    })?;
 
-        // finished processing this dataset,
-        // do a merge_cf in the PROCESSED key in metadata
-        // to account for that.
-        let cf_metadata = self.db.cf_handle(METADATA).unwrap();
-        self.db
-            .merge_cf(&cf_metadata, PROCESSED, colors.as_slice())
-            .expect("error merging");
+    Ok(processed_sigs.into_inner())
+}
+
+/// Copy all signatures into the rocksdb storage of `db`,
+/// and point `collection` to it.
+pub(super) fn internalize_storage(db: &Arc<DB>, collection: &mut Arc<CollectionSet>) -> Result<()> {
+    // check if collection is already internal, if so return
+    if collection.storage().spec() == "rocksdb://" {
+        return Ok(());
+    }
+
+    // build new rocksdb storage from db
+    let new_storage = RocksDBStorage::from_db(db.clone());
+
+    // use manifest to copy from current storage to new one
+    collection
+        .par_iter()
+        .try_for_each(|(_, record)| -> Result<()> {
+            let path = record.internal_location().as_str();
+            let sig_data = collection.storage().load(path).unwrap();
+            new_storage.save(path, &sig_data)?;
+            Ok(())
+        })?;
+
+    // Replace storage for collection.
+    // Using unchecked version because we just used the manifest
+    // above to make sure the storage is still consistent
+    unsafe {
+        if let Some(v) = Arc::get_mut(collection) {
+            v.set_storage_unchecked(InnerStorage::new(new_storage))
+        }
     }
+
+    // write storage spec
+    let cf_metadata = db.cf_handle(METADATA).unwrap();
+    let spec = "rocksdb://";
+    db.put_cf(&cf_metadata, STORAGE_SPEC, spec)?;
+
+    Ok(())
 }
 
 impl RevIndexOps for RevIndex {
@@ -298,6 +692,27 @@ impl RevIndexOps for RevIndex {
             .collect()
     }
 
+    fn datasets_for_hashes(&self, hashes: &[HashIntoType]) -> Result<Vec<Datasets>> {
+        let cf_hashes = self.db.cf_handle(HASHES).unwrap();
+        let hashes_iter = hashes.iter().map(|hash| {
+            let mut v = vec![0_u8; 8];
+            (&mut v[..])
+                .write_u64::<LittleEndian>(*hash)
+                .expect("error writing bytes");
+            (&cf_hashes, v)
+        });
+
+        hashes
+            .iter()
+            .zip(self.db.multi_get_cf(hashes_iter))
+            .map(|(hash, r)| match r? {
+                Some(raw_datasets) => Datasets::from_slice(&raw_datasets)
+                    .ok_or_else(|| IntegrityError::CorruptedHash(*hash).into()),
+                None => Ok(Datasets::default()),
+            })
+            .collect()
+    }
+
     fn prepare_gather_counters(
         &self,
         query: &KmerMinHash,
@@ -340,142 +755,31 @@ impl RevIndexOps for RevIndex {
         (counter, query_colors, hash_to_colors)
     }
 
-    fn matches_from_counter(&self, counter: SigCounter, threshold: usize) -> Vec<(String, usize)> {
-        info!("get matches from counter");
-        counter
-            .most_common()
-            .into_iter()
-            .filter_map(|(dataset_id, size)| {
-                if size >= threshold {
-                    let row = &self
-                        .collection
-                        .record_for_dataset(dataset_id)
-                        .expect("dataset not found");
-
-                    let name = [row.name(), row.filename(), row.md5()]
-                        .into_iter()
-                        .skip_while(|v| v.is_empty())
-                        .next()
-                        .unwrap(); // guaranteed to succeed because `md5` always exists
-
-                    Some((name.into(), size))
-                } else {
-                    None
-                }
-            })
-            .collect()
-    }
-
     fn gather(
         &self,
-        mut counter: SigCounter,
+        counter: SigCounter,
         query_colors: QueryColors,
         hash_to_color: HashToColor,
         threshold: usize,
         orig_query: &KmerMinHash,
-        selection: Option<Selection>,
+        _selection: Option<Selection>,
     ) -> Result<Vec<GatherResult>> {
-        let mut match_size = usize::MAX;
-        let mut matches = vec![];
-        let mut query = KmerMinHashBTree::from(orig_query.clone());
-        let mut sum_weighted_found = 0;
-        let _selection = selection.unwrap_or_else(|| self.collection.selection());
-        let total_weighted_hashes = orig_query.sum_abunds();
-
-        // or set this with user --track-abundance?
-        let calc_abund_stats = orig_query.track_abundance();
-
-        // todo: let user pass these options in
-        let calc_ani_ci = false;
-        let ani_confidence_interval_fraction = None;
-
-        while match_size > threshold && !counter.is_empty() {
-            trace!("counter len: {}", counter.len());
-            trace!("match size: {}", match_size);
-
-            let (dataset_id, size) = counter.k_most_common_ordered(1)[0];
-            match_size = if size >= threshold { size } else { break };
-            // handle special case where threshold was set to 0
-            if match_size == 0 {
-                break;
-            }
-
-            let match_sig = self.collection.sig_for_dataset(dataset_id)?;
-            let match_mh = match_sig.minhash().unwrap().clone();
-
-            // make downsampled minhashes
-            let max_scaled = max(match_mh.scaled(), query.scaled());
-
-            let match_mh = match_mh
-                .downsample_scaled(max_scaled)
-                .expect("cannot downsample match");
-
-            // repeatedly downsample query, then extract to KmerMinHash
-            // => calculate_gather_stats
-            query = query
-                .downsample_scaled(max_scaled)
-                .expect("cannot downsample query");
-            let query_mh = KmerMinHash::from(query.clone());
-
-            // just calculate essentials here
-            let gather_result_rank = matches.len() as u32;
-
-            // grab the specific intersection:
-            // Calculate stats
-            let (gather_result, isect) = calculate_gather_stats(
-                orig_query,
-                query_mh,
-                match_sig,
-                match_size,
-                gather_result_rank,
-                sum_weighted_found,
-                total_weighted_hashes,
-                calc_abund_stats,
-                calc_ani_ci,
-                ani_confidence_interval_fraction,
-            )
-            .expect("could not calculate gather stats");
-
-            // use intersection from calc_gather_stats to make a KmerMinHash.
-            let mut isect_mh = match_mh.clone();
-            isect_mh.clear();
-            isect_mh.add_many(&isect.0)?;
-
-            // keep track of the sum weighted found
-            sum_weighted_found = gather_result.sum_weighted_found();
-            matches.push(gather_result);
-
-            trace!("Preparing counter for next round");
-            // Prepare counter for finding the next match by decrementing
-            // all hashes found in the current match in other datasets
-            // TODO: not used at the moment, so just skip.
-            query.remove_many(match_mh.iter_mins().copied())?; // is there a better way?
-
-            // TODO: Use HashesToColors here instead. If not initialized,
-            //       build it.
-            isect
-                .0
-                .iter()
-                .filter_map(|hash| hash_to_color.get(hash))
-                .flat_map(|color| {
-                    // TODO: remove this clone
-                    query_colors.get(color).unwrap().clone().into_iter()
-                })
-                .for_each(|dataset| {
-                    // TODO: collect the flat_map into a Counter, and remove more
-                    //       than one at a time...
-                    counter.entry(dataset).and_modify(|e| *e -= 1);
-                });
-
-            counter.remove(&dataset_id);
-        }
-        Ok(matches)
+        let orig_query = self.validate_query(orig_query)?;
+        module::gather_from_counters(
+            &self.collection,
+            counter,
+            query_colors,
+            hash_to_color,
+            threshold,
+            &orig_query,
+            None,
+        )
     }
 
-    fn update(mut self, collection: CollectionSet) -> Result<module::RevIndex> {
-        // TODO: verify new collection manifest is a superset of current one,
-        //       and the initial chunk is the same
+    fn update(mut self, mut collection: CollectionSet) -> Result<module::RevIndex> {
         self.collection.check_superset(&collection)?;
+        collection.tombstone(self.collection.removed());
+        self.template = checked_template(self.template.take(), &collection)?;
         info!("sigs in the original index: {}", self.collection.len());
 
         self.collection = Arc::new(collection);
@@ -491,35 +795,21 @@ impl RevIndexOps for RevIndex {
         );
 
         // process the remainder
-        let processed_sigs = AtomicUsize::new(0);
-
-        self.collection.par_iter().for_each(|(dataset_id, _)| {
-            // check if this dataset_id was processed already
-            // call map_hashes_colors only if not already processed
-            if !processed.read().unwrap().contains(&dataset_id) {
-                let i = processed_sigs.fetch_add(1, Ordering::SeqCst);
-                if i % 1000 == 0 {
-                    info!("Processed {} reference sigs", i);
-                }
-
-                self.map_hashes_colors(dataset_id as Idx);
-
-                // if cached in a new field in the RevIndex,
-                // then update the cache too
-
-                processed.write().unwrap().extend([dataset_id]);
-            }
-        });
+        let processed_sigs = index_datasets(
+            &self.db,
+            &self.collection,
+            &processed,
+            HASHES,
+            self.shard.as_ref(),
+        )?;
 
-        self.save_collection().expect("Error saving collection");
+        save_collection(&self.db, &self.collection, self.template.as_ref())
+            .expect("Error saving collection");
 
         info!("Compact SSTs");
         self.compact();
 
-        info!(
-            "Processed additional {} reference sigs",
-            processed_sigs.into_inner()
-        );
+        info!("Processed additional {} reference sigs", processed_sigs);
 
         Ok(module::RevIndex::Plain(self))
     }
@@ -550,81 +840,118 @@ impl RevIndexOps for RevIndex {
         &self.collection
     }
 
+    fn template(&self) -> Option<&Sketch> {
+        self.template.as_ref()
+    }
+
     fn internalize_storage(&mut self) -> Result<()> {
-        // check if collection is already internal, if so return
-        if self.collection.storage().spec() == "rocksdb://" {
-            return Ok(());
+        internalize_storage(&self.db, &mut self.collection)
+    }
+
+    fn verify(&self) -> Result<Vec<IntegrityError>> {
+        let mut problems = vec![];
+        let (processed, removed) = verify_metadata(&self.db, &mut problems)?;
+
+        let mut check = IntegrityCheck::new(&self.collection, removed, self.shard.as_ref());
+        for problem in problems {
+            check.add_problem(problem);
         }
 
-        // build new rocksdb storage from db
-        let new_storage = RocksDBStorage::from_db(self.db.clone());
-
-        // use manifest to copy from current storage to new one
-        self.collection()
-            .par_iter()
-            .try_for_each(|(_, record)| -> Result<()> {
-                let path = record.internal_location().as_str();
-                let sig_data = self.collection.storage().load(path).unwrap();
-                new_storage.save(path, &sig_data)?;
-                Ok(())
-            })?;
-
-        // Replace storage for collection.
-        // Using unchecked version because we just used the manifest
-        // above to make sure the storage is still consistent
-        unsafe {
-            if let Some(v) = Arc::get_mut(&mut self.collection) {
-                v.set_storage_unchecked(InnerStorage::new(new_storage))
+        info!("Checking hashes");
+        let cf_hashes = self.db.cf_handle(HASHES).unwrap();
+        for result in self
+            .db
+            .iterator_cf(&cf_hashes, rocksdb::IteratorMode::Start)
+        {
+            let (key, value) = result?;
+            let hash = (&key[..]).read_u64::<LittleEndian>()?;
+            match Datasets::from_slice(&value) {
+                Some(datasets) => check.add_hash(hash, datasets),
+                None => check.add_problem(IntegrityError::CorruptedHash(hash)),
             }
         }
 
-        // write storage spec
-        let cf_metadata = self.db.cf_handle(METADATA).unwrap();
-        let spec = "rocksdb://";
-        self.db.put_cf(&cf_metadata, STORAGE_SPEC, spec)?;
-
-        Ok(())
+        info!("Checking datasets");
+        Ok(check.finish(processed))
     }
 
-    fn convert(&self, _output_db: module::RevIndex) -> Result<()> {
-        todo!()
-        /*
-        if let RevIndex::Color(db) = output_db {
-            let other_db = db.db;
-
-            let cf_hashes = self.db.cf_handle(HASHES).unwrap();
+    fn repair(&mut self, datasets: &[Idx]) -> Result<()> {
+        for dataset in datasets {
+            info!("Repairing dataset {}", dataset);
+            map_hashes_colors(
+                &self.db,
+                &self.collection,
+                *dataset,
+                HASHES,
+                self.shard.as_ref(),
+            )?;
+            self.processed.write().unwrap().extend([*dataset]);
+        }
+        save_collection(&self.db, &self.collection, self.template.as_ref())?;
+        self.flush()
+    }
 
-            info!("start converting colors");
-            let mut color_bytes = [0u8; 8];
-            let iter = self
-                .db
-                .iterator_cf(&cf_hashes, rocksdb::IteratorMode::Start);
-            for (key, value) in iter {
-                let datasets = Datasets::from_slice(&value).unwrap();
-                let new_idx: Vec<_> = datasets.into_iter().collect();
-                let new_color = Colors::update(other_db.clone(), None, new_idx.as_slice()).unwrap();
-
-                (&mut color_bytes[..])
-                    .write_u64::<LittleEndian>(new_color)
-                    .expect("error writing bytes");
-                other_db
-                    .put_cf(&cf_hashes, &key[..], &color_bytes[..])
-                    .unwrap();
+    fn remove_datasets(&mut self, keys: &[&str]) -> Result<Vec<Idx>> {
+        let ids = datasets_for_keys(&self.collection, keys, &load_removed(&self.db)?)?;
+        let to_remove = Datasets::new(&ids);
+
+        // only hashes present in the removed datasets need pruning
+        let mut hashes = BTreeSet::new();
+        for id in &ids {
+            let sig = self.collection.sig_for_dataset(*id)?;
+            match sig.sketches().first() {
+                Some(Sketch::MinHash(mh)) => hashes.extend(mh.iter_mins().copied()),
+                Some(Sketch::LargeMinHash(mh)) => hashes.extend(mh.mins()),
+                _ => return Err(Error::NoMinHashFound),
             }
-            info!("finished converting colors");
+        }
+        info!(
+            "Pruning {} hashes from {} datasets",
+            hashes.len(),
+            ids.len()
+        );
 
-            info!("copying sigs to output");
-            let cf_sigs = self.db.cf_handle(SIGS).unwrap();
-            let iter = self.db.iterator_cf(&cf_sigs, rocksdb::IteratorMode::Start);
-            for (key, value) in iter {
-                other_db.put_cf(&cf_sigs, &key[..], &value[..]).unwrap();
+        let cf_hashes = self.db.cf_handle(HASHES).unwrap();
+        let mut batch = WriteBatch::default();
+        let mut hash_bytes = [0u8; 8];
+        for hash in hashes {
+            (&mut hash_bytes[..])
+                .write_u64::<LittleEndian>(hash)
+                .expect("error writing bytes");
+            if let Some(raw) = self.db.get_pinned_cf(&cf_hashes, hash_bytes)? {
+                let mut datasets =
+                    Datasets::from_slice(&raw).ok_or(IntegrityError::CorruptedHash(hash))?;
+                datasets.remove_all(&to_remove);
+                if datasets.len() == 0 {
+                    batch.delete_cf(&cf_hashes, hash_bytes);
+                } else {
+                    batch.put_cf(&cf_hashes, hash_bytes, datasets.as_bytes().unwrap());
+                }
             }
-            info!("finished copying sigs to output");
+        }
+        self.db.write(batch)?;
 
-            Ok(())
+        let cf_metadata = self.db.cf_handle(METADATA).unwrap();
+        self.db
+            .merge_cf(&cf_metadata, REMOVED, to_remove.as_bytes().unwrap())?;
+        Arc::make_mut(&mut self.collection).tombstone(ids.iter().copied());
+
+        Ok(ids)
+    }
+
+    fn convert(&self, output_db: module::RevIndex) -> Result<()> {
+        if self.shard.is_some() {
+            return Err(Error::Internal {
+                message: "shards can't be converted to a colored RevIndex".into(),
+            });
+        }
+
+        if let module::RevIndex::Color(db) = output_db {
+            db.import_plain(&self.db, &self.collection)
         } else {
-            todo!()
+            Err(Error::Internal {
+                message: "a plain RevIndex can only be converted to a colored one".into(),
+            })
         }
-        */
     }
 }
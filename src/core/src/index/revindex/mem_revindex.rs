@@ -2,26 +2,34 @@ use std::sync::atomic::{AtomicUsize, Ordering};
 
 use camino::Utf8Path as Path;
 use camino::Utf8PathBuf as PathBuf;
+use histogram::Histogram;
 use log::{debug, info};
+use once_cell::sync::OnceCell;
 
 #[cfg(feature = "parallel")]
 use rayon::prelude::*;
 
-use crate::collection::Collection;
+use crate::collection::{Collection, CollectionSet};
 use crate::encodings::{Colors, Idx};
 use crate::index::linear::LinearIndex;
-use crate::index::revindex::HashToColor;
-use crate::index::{GatherResult, Index, SigCounter};
+use crate::index::revindex::{
+    self as module, Datasets, DbStats, HashToColor, QueryColors, RevIndexOps,
+};
+use crate::index::{best_sigs_from_counter, GatherResult, Index, SearchType, SigCounter};
 use crate::prelude::*;
 use crate::signature::{Signature, SigsTrait};
 use crate::sketch::minhash::KmerMinHash;
 use crate::sketch::Sketch;
-use crate::Result;
+use crate::storage::{InnerStorage, MemStorage};
+use crate::{Error, HashIntoType, Result};
 
+/// Reverse index kept in memory, for small collections and short-lived jobs.
 pub struct RevIndex {
     linear: LinearIndex,
     hash_to_color: HashToColor,
     colors: Colors,
+    // loaded on first use by `signature_refs`
+    signatures: OnceCell<Vec<Signature>>,
 }
 
 impl LinearIndex {
@@ -30,58 +38,81 @@ impl LinearIndex {
         threshold: usize,
         merged_query: Option<KmerMinHash>,
         queries: Option<&[KmerMinHash]>,
-    ) -> RevIndex {
+    ) -> Result<RevIndex> {
+        let (hash_to_color, colors) = self.map_datasets(
+            0..self.collection().len() as Idx,
+            threshold,
+            merged_query,
+            queries,
+        )?;
+
+        Ok(RevIndex {
+            hash_to_color,
+            colors,
+            linear: self,
+            signatures: OnceCell::new(),
+        })
+    }
+
+    fn map_datasets(
+        &self,
+        dataset_ids: std::ops::Range<Idx>,
+        threshold: usize,
+        merged_query: Option<KmerMinHash>,
+        queries: Option<&[KmerMinHash]>,
+    ) -> Result<(HashToColor, Colors)> {
         let processed_sigs = AtomicUsize::new(0);
 
         #[cfg(feature = "parallel")]
-        let sig_iter = self.collection().par_iter();
+        let sig_iter = dataset_ids.into_par_iter();
 
         #[cfg(not(feature = "parallel"))]
-        let sig_iter = self.collection().iter();
+        let sig_iter = dataset_ids.into_iter();
 
-        let filtered_sigs = sig_iter.enumerate().filter_map(|(dataset_id, _)| {
+        let filtered_sigs = sig_iter.filter_map(|dataset_id| {
             let i = processed_sigs.fetch_add(1, Ordering::SeqCst);
             if i % 1000 == 0 {
                 info!("Processed {} reference sigs", i);
             }
 
-            let search_sig = self
-                .collection()
-                .sig_for_dataset(dataset_id as Idx)
-                .expect("Error loading sig")
-                .into();
+            let search_sig = match self.collection().sig_for_dataset(dataset_id) {
+                Ok(sig) => sig.into(),
+                Err(e) => return Some(Err(e)),
+            };
 
             RevIndex::map_hashes_colors(
-                dataset_id as Idx,
+                dataset_id,
                 &search_sig,
                 queries,
                 &merged_query,
                 threshold,
                 self.template(),
             )
+            .transpose()
         });
 
         #[cfg(feature = "parallel")]
-        let (hash_to_color, colors) = filtered_sigs.reduce(
+        let (hash_to_color, colors) = filtered_sigs.try_reduce(
             || (HashToColor::new(), Colors::default()),
-            HashToColor::reduce_hashes_colors,
-        );
+            |a, b| Ok(HashToColor::reduce_hashes_colors(a, b)),
+        )?;
 
         #[cfg(not(feature = "parallel"))]
-        let (hash_to_color, colors) = filtered_sigs.fold(
-            (HashToColor::new(), Colors::default()),
-            HashToColor::reduce_hashes_colors,
-        );
+        let (hash_to_color, colors) = filtered_sigs
+            .try_fold((HashToColor::new(), Colors::default()), |a, b| {
+                Ok::<_, Error>(HashToColor::reduce_hashes_colors(a, b?))
+            })?;
 
-        RevIndex {
-            hash_to_color,
-            colors,
-            linear: self,
-        }
+        Ok((hash_to_color, colors))
     }
 }
 
 impl RevIndex {
+    /// Index all hashes of all datasets in `collection`.
+    pub fn from_collection(collection: CollectionSet) -> Result<Self> {
+        LinearIndex::from_collection(collection).index(0, None, None)
+    }
+
     pub fn new(
         search_sigs: &[PathBuf],
         selection: &Selection,
@@ -95,7 +126,7 @@ impl RevIndex {
         let collection = Collection::from_paths(search_sigs)?.select(selection)?;
         let linear = LinearIndex::from_collection(collection.try_into()?);
 
-        Ok(linear.index(threshold, merged_query, queries))
+        linear.index(threshold, merged_query, queries)
     }
 
     pub fn from_zipfile<P: AsRef<Path>>(
@@ -111,7 +142,7 @@ impl RevIndex {
         let collection = Collection::from_zipfile(zipfile)?.select(selection)?;
         let linear = LinearIndex::from_collection(collection.try_into()?);
 
-        Ok(linear.index(threshold, merged_query, queries))
+        linear.index(threshold, merged_query, queries)
     }
 
     fn merge_queries(qs: &[KmerMinHash], threshold: usize) -> Option<KmerMinHash> {
@@ -138,9 +169,7 @@ impl RevIndex {
         let collection = Collection::from_sigs(search_sigs)?.select(selection)?;
         let linear = LinearIndex::from_collection(collection.try_into()?);
 
-        let idx = linear.index(threshold, merged_query, queries);
-
-        Ok(idx)
+        linear.index(threshold, merged_query, queries)
     }
 
     fn map_hashes_colors(
@@ -150,25 +179,22 @@ impl RevIndex {
         merged_query: &Option<KmerMinHash>,
         threshold: usize,
         template: &Sketch,
-    ) -> Option<(HashToColor, Colors)> {
-        let mut search_mh = None;
-        if let Some(Sketch::MinHash(mh)) = search_sig.select_sketch(template) {
-            search_mh = Some(mh);
-        }
-
-        let search_mh = search_mh.expect("Couldn't find a compatible MinHash");
+    ) -> Result<Option<(HashToColor, Colors)>> {
+        let Some(Sketch::MinHash(search_mh)) = search_sig.select_sketch(template) else {
+            return Err(Error::NoMinHashFound);
+        };
         let mut hash_to_color = HashToColor::new();
         let mut colors = Colors::default();
 
         if let Some(qs) = queries {
             if let Some(ref merged) = merged_query {
-                let (matched_hashes, intersection) = merged.intersection(search_mh).unwrap();
+                let (matched_hashes, intersection) = merged.intersection(search_mh)?;
                 if !matched_hashes.is_empty() || intersection > threshold as u64 {
                     hash_to_color.add_to(&mut colors, dataset_id, matched_hashes);
                 }
             } else {
                 for query in qs {
-                    let (matched_hashes, intersection) = query.intersection(search_mh).unwrap();
+                    let (matched_hashes, intersection) = query.intersection(search_mh)?;
                     if !matched_hashes.is_empty() || intersection > threshold as u64 {
                         hash_to_color.add_to(&mut colors, dataset_id, matched_hashes);
                     }
@@ -183,9 +209,9 @@ impl RevIndex {
         };
 
         if hash_to_color.is_empty() {
-            None
+            Ok(None)
         } else {
-            Some((hash_to_color, colors))
+            Ok(Some((hash_to_color, colors)))
         }
     }
 
@@ -198,44 +224,6 @@ impl RevIndex {
         self.linear.search(counter, similarity, threshold)
     }
 
-    pub fn gather(
-        &self,
-        mut counter: SigCounter,
-        threshold: usize,
-        query: &KmerMinHash,
-    ) -> Result<Vec<GatherResult>> {
-        let mut match_size = usize::MAX;
-        let mut matches = vec![];
-
-        while match_size > threshold && !counter.is_empty() {
-            let (dataset_id, size) = counter.most_common()[0];
-            match_size = if size >= threshold { size } else { break };
-            let result = self
-                .linear
-                .gather_round(dataset_id, match_size, query, matches.len())?;
-            if let Some(Sketch::MinHash(match_mh)) =
-                result.match_.select_sketch(self.linear.template())
-            {
-                // Prepare counter for finding the next match by decrementing
-                // all hashes found in the current match in other datasets
-                for hash in match_mh.iter_mins() {
-                    if let Some(color) = self.hash_to_color.get(hash) {
-                        counter.subtract(self.colors.indices(color).cloned());
-                    }
-                }
-                counter.remove(&dataset_id);
-                matches.push(result);
-            } else {
-                unimplemented!()
-            }
-        }
-        Ok(matches)
-    }
-
-    pub fn template(&self) -> Sketch {
-        self.linear.template().clone()
-    }
-
     // TODO: mh should be a sketch, or even a sig...
     pub(crate) fn find_signatures(
         &self,
@@ -288,8 +276,10 @@ impl RevIndex {
         }
         Ok(results)
     }
+}
 
-    pub fn counter_for_query(&self, query: &KmerMinHash) -> SigCounter {
+impl RevIndexOps for RevIndex {
+    fn counter_for_query(&self, query: &KmerMinHash) -> SigCounter {
         query
             .iter_mins()
             .filter_map(|hash| self.hash_to_color.get(hash))
@@ -297,21 +287,202 @@ impl RevIndex {
             .cloned()
             .collect()
     }
+
+    fn datasets_for_hashes(&self, hashes: &[HashIntoType]) -> Result<Vec<Datasets>> {
+        Ok(hashes
+            .iter()
+            .map(|hash| match self.hash_to_color.get(hash) {
+                Some(color) => {
+                    let idxs: Vec<Idx> = self.colors.indices(color).cloned().collect();
+                    Datasets::new(&idxs)
+                }
+                None => Datasets::default(),
+            })
+            .collect())
+    }
+
+    fn prepare_gather_counters(
+        &self,
+        query: &KmerMinHash,
+    ) -> (SigCounter, QueryColors, HashToColor) {
+        let mut query_colors: QueryColors = Default::default();
+        let mut counter: SigCounter = Default::default();
+
+        let hash_to_color = query
+            .iter_mins()
+            .filter_map(|hash| {
+                self.hash_to_color.get(hash).map(|color| {
+                    let datasets = query_colors.entry(*color).or_insert_with(|| {
+                        let idxs: Vec<Idx> = self.colors.indices(color).cloned().collect();
+                        Datasets::new(&idxs)
+                    });
+                    counter.update(datasets.clone());
+                    (*hash, *color)
+                })
+            })
+            .collect();
+
+        (counter, query_colors, hash_to_color)
+    }
+
+    fn update(mut self, collection: CollectionSet) -> Result<module::RevIndex> {
+        self.collection().check_superset(&collection)?;
+        module::check_collection(self.linear.template(), &collection)?;
+        let start = self.collection().len() as Idx;
+        info!("sigs in the original index: {}", start);
+
+        let linear = LinearIndex::from_collection(collection);
+        info!(
+            "sigs in the new index once finished: {}",
+            linear.collection().len()
+        );
+
+        let new_datasets = start..linear.collection().len() as Idx;
+        let (hash_to_color, colors) = linear.map_datasets(new_datasets, 0, None, None)?;
+        (self.hash_to_color, self.colors) = HashToColor::reduce_hashes_colors(
+            (self.hash_to_color, self.colors),
+            (hash_to_color, colors),
+        );
+        self.linear = linear;
+        self.signatures = OnceCell::new();
+
+        Ok(module::RevIndex::Mem(self))
+    }
+
+    fn compact(&self) {}
+
+    fn flush(&self) -> Result<()> {
+        Ok(())
+    }
+
+    fn convert(&self, _output_db: module::RevIndex) -> Result<()> {
+        Err(Error::Internal {
+            message: "in-memory indices can't be converted".into(),
+        })
+    }
+
+    fn check(&self, quick: bool) -> DbStats {
+        let mut vcounts = Histogram::new(12, 64).expect("Error initializing histogram");
+        let mut datasets: Datasets = Default::default();
+
+        if !quick {
+            for color in self.hash_to_color.0.values() {
+                let idxs: Vec<Idx> = self.colors.indices(color).cloned().collect();
+                vcounts.increment(idxs.len() as u64).unwrap();
+                datasets.union(Datasets::new(&idxs));
+            }
+        }
+
+        // hashes and colors are both 8 bytes
+        let total_keys = self.hash_to_color.len();
+        DbStats {
+            total_datasets: datasets.len(),
+            total_keys,
+            kcount: total_keys * 8,
+            vcount: total_keys * 8,
+            vcounts,
+        }
+    }
+
+    fn gather(
+        &self,
+        counter: SigCounter,
+        query_colors: QueryColors,
+        hash_to_color: HashToColor,
+        threshold: usize,
+        orig_query: &KmerMinHash,
+        _selection: Option<Selection>,
+    ) -> Result<Vec<GatherResult>> {
+        let orig_query = self.validate_query(orig_query)?;
+        module::gather_from_counters(
+            self.collection(),
+            counter,
+            query_colors,
+            hash_to_color,
+            threshold,
+            &orig_query,
+            None,
+        )
+    }
+
+    fn collection(&self) -> &CollectionSet {
+        self.linear.collection()
+    }
+
+    fn template(&self) -> Option<&Sketch> {
+        Some(self.linear.template())
+    }
+
+    fn internalize_storage(&mut self) -> Result<()> {
+        if self.collection().storage().spec() == "memory://" {
+            return Ok(());
+        }
+
+        // copy whole files, since they can contain multiple signatures
+        let new_storage = MemStorage::new();
+        for (_, record) in self.collection().iter() {
+            let path = record.internal_location().as_str();
+            let content = self.collection().storage().load(path)?;
+            new_storage.save(path, &content)?;
+        }
+
+        // Using unchecked version because we just used the manifest
+        // above to make sure the storage is still consistent
+        unsafe {
+            self.linear
+                .collection_mut()
+                .set_storage_unchecked(InnerStorage::new(new_storage))
+        }
+
+        Ok(())
+    }
 }
 
 impl Index<'_> for RevIndex {
     type Item = Signature;
 
+    fn find_best(
+        &self,
+        sig: &Self::Item,
+        k: usize,
+        threshold: f64,
+        containment: bool,
+    ) -> Result<Vec<(f64, Self::Item)>> {
+        let Some(Sketch::MinHash(query)) = sig.select_sketch(self.linear.template()) else {
+            return Err(Error::NoMinHashFound);
+        };
+        let search_type = if containment {
+            SearchType::Containment
+        } else {
+            SearchType::Jaccard
+        };
+
+        let counter = self.counter_for_query(query);
+        Ok(
+            best_sigs_from_counter(self.collection(), counter, query, search_type, k, threshold)?
+                .into_iter()
+                .map(|(result, match_sig)| (result.similarity(), match_sig.into()))
+                .collect(),
+        )
+    }
+
     fn insert(&mut self, _node: Self::Item) -> Result<()> {
-        unimplemented!()
+        Err(Error::Internal {
+            message: "in-memory indices can't be modified, use update with a superset collection"
+                .into(),
+        })
     }
 
     fn save<P: AsRef<std::path::Path>>(&self, _path: P) -> Result<()> {
-        unimplemented!()
+        Err(Error::Internal {
+            message: "in-memory indices can't be saved, use a disk index instead".into(),
+        })
     }
 
     fn load<P: AsRef<std::path::Path>>(_path: P) -> Result<()> {
-        unimplemented!()
+        Err(Error::Internal {
+            message: "in-memory indices can't be loaded, use a disk index instead".into(),
+        })
     }
 
     fn len(&self) -> usize {
@@ -327,7 +498,10 @@ impl Index<'_> for RevIndex {
     }
 
     fn signature_refs(&self) -> Vec<&Self::Item> {
-        unimplemented!()
+        self.signatures
+            .get_or_init(|| self.signatures())
+            .iter()
+            .collect()
     }
 }
 
@@ -336,7 +510,7 @@ mod test {
     use super::*;
 
     use crate::index::revindex::prepare_query;
-    use crate::Result;
+    use crate::index::SearchType;
 
     #[test]
     fn revindex_new() -> Result<()> {
@@ -448,14 +622,227 @@ mod test {
         let results_linear = index.linear.search(counter_lin, false, 0).unwrap();
         assert_eq!(results_rev, results_linear);
 
-        let counter_rev = index.counter_for_query(&query_mh);
+        let (counter_rev, query_colors, hash_to_color) = index.prepare_gather_counters(&query_mh);
         let counter_lin = index.linear.counter_for_query(&query_mh);
 
-        let results_rev = index.gather(counter_rev, 0, &query_mh).unwrap();
+        let results_rev = index
+            .gather(counter_rev, query_colors, hash_to_color, 0, &query_mh, None)
+            .unwrap();
         let results_linear = index.linear.gather(counter_lin, 0, &query_mh).unwrap();
         assert_eq!(results_rev.len(), 1);
         assert_eq!(results_rev, results_linear);
 
+        let counter = index.counter_for_query(&query_mh);
+        let best = index.best_matches(counter, &query_mh, SearchType::Containment, 1, 0.0)?;
+        assert_eq!(best.len(), 1);
+        assert_eq!(best[0].md5(), results_rev[0].md5());
+        assert_eq!(best[0].similarity(), 1.0);
+
+        Ok(())
+    }
+
+    #[test]
+    fn revindex_mem_update() -> Result<()> {
+        let mut basedir = PathBuf::from(env!("CARGO_MANIFEST_DIR"));
+        basedir.push("../../tests/test-data/gather/");
+
+        let against: Vec<_> = [
+            "GCF_000006945.2_ASM694v2_genomic.fna.gz.sig",
+            "GCF_000007545.1_ASM754v1_genomic.fna.gz.sig",
+            "GCF_000008105.1_ASM810v1_genomic.fna.gz.sig",
+        ]
+        .iter()
+        .map(|sig| basedir.join(sig))
+        .collect();
+
+        let selection = Selection::builder().ksize(21).scaled(10000).build();
+        let collection = Collection::from_paths(&against)?.select(&selection)?;
+        let full = RevIndex::from_collection(collection.clone().try_into()?)?;
+
+        let partial = Collection::from_paths(&against[..2])?.select(&selection)?;
+        let index = RevIndex::from_collection(partial.try_into()?)?;
+        assert_eq!(index.collection().len(), 2);
+
+        let index = index.update(collection.try_into()?)?;
+        assert_eq!(index.collection().len(), 3);
+
+        let stats = index.check(false);
+        assert_eq!(stats.total_datasets, 3);
+        assert_eq!(stats.total_keys, full.hash_to_color.len());
+
+        let query_sig = Signature::from_path(basedir.join("combined.sig"))?
+            .swap_remove(0)
+            .select(&selection)?;
+        let query = prepare_query(query_sig, &selection).unwrap();
+
+        let (counter, query_colors, hash_to_color) = index.prepare_gather_counters(&query);
+        let matches = index.gather(counter, query_colors, hash_to_color, 0, &query, None)?;
+
+        let (counter, query_colors, hash_to_color) = full.prepare_gather_counters(&query);
+        let full_matches = full.gather(counter, query_colors, hash_to_color, 0, &query, None)?;
+
+        assert_eq!(matches.len(), 3);
+        assert_eq!(matches, full_matches);
+
+        Ok(())
+    }
+
+    #[test]
+    fn revindex_find_best() -> Result<()> {
+        let mut basedir = PathBuf::from(env!("CARGO_MANIFEST_DIR"));
+        basedir.push("../../tests/test-data/gather/");
+
+        let against: Vec<_> = [
+            "GCF_000016785.1_ASM1678v1_genomic.fna.gz.sig",
+            "GCF_000018945.1_ASM1894v1_genomic.fna.gz.sig",
+            "GCF_000008545.1_ASM854v1_genomic.fna.gz.sig",
+        ]
+        .iter()
+        .map(|sig| basedir.join(sig))
+        .collect();
+
+        let selection = Selection::builder().ksize(21).scaled(10000).build();
+        let collection = Collection::from_paths(&against)?.select(&selection)?;
+        let linear = LinearIndex::from_collection(collection.clone().try_into()?);
+        let index = RevIndex::from_collection(collection.try_into()?)?;
+
+        // the first query has sketches for other ksizes, find_best selects the right one
+        for query_path in [&against[0], &basedir.join("combined.sig")] {
+            let query = Signature::from_path(query_path)?.swap_remove(0);
+
+            for containment in [false, true] {
+                let best = index.find_best(&query, 2, 0.0, containment)?;
+                let linear_best = linear.find_best(&query.clone().into(), 2, 0.0, containment)?;
+                assert!(!best.is_empty());
+                assert_eq!(best.len(), linear_best.len());
+
+                for ((score, sig), (linear_score, linear_sig)) in best.iter().zip(&linear_best) {
+                    assert_eq!(score, linear_score);
+                    assert_eq!(sig.md5sum(), linear_sig.md5sum());
+                }
+            }
+        }
+
+        let query = Signature::from_path(&against[0])?.swap_remove(0);
+        assert_eq!(query.size(), 3);
+        let best = index.find_best(&query, 1, 0.0, false)?;
+        assert_eq!(best.len(), 1);
+        assert_eq!(best[0].0, 1.0);
+        assert_eq!(best[0].1.name_str(), query.name_str());
+
+        // no compatible sketch in the query
+        let query = Signature::from_path(&against[0])?
+            .swap_remove(0)
+            .select(&Selection::builder().ksize(51).build())?;
+        assert!(matches!(
+            index.find_best(&query, 1, 0.0, false),
+            Err(Error::NoMinHashFound)
+        ));
+        assert!(matches!(
+            linear.find_best(&query.into(), 1, 0.0, false),
+            Err(Error::NoMinHashFound)
+        ));
+
+        Ok(())
+    }
+
+    #[test]
+    fn revindex_index_trait() -> Result<()> {
+        let selection = Selection::builder().ksize(31).scaled(10000).build();
+        let search_sigs = [
+            "../../tests/test-data/gather/GCF_000006945.2_ASM694v2_genomic.fna.gz.sig".into(),
+            "../../tests/test-data/gather/GCF_000007545.1_ASM754v1_genomic.fna.gz.sig".into(),
+            "../../tests/test-data/gather/GCF_000008105.1_ASM810v1_genomic.fna.gz.sig".into(),
+        ];
+        let mut index = RevIndex::new(&search_sigs, &selection, 0, None, false)?;
+
+        let sigs = index.signature_refs();
+        assert_eq!(sigs.len(), 3);
+        let query = sigs[1].clone();
+        let matches = Index::search(&index, &query, 0.5, false)?;
+        assert_eq!(matches.len(), 1);
+        assert_eq!(matches[0].md5sum(), query.md5sum());
+
+        assert!(index.insert(query).is_err());
+        assert!(index.save("unused").is_err());
+        assert!(RevIndex::load("unused").is_err());
+
+        Ok(())
+    }
+
+    #[test]
+    fn revindex_missing_sig_file() -> Result<()> {
+        let tmpdir = tempfile::TempDir::new()?;
+        let basedir =
+            PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("../../tests/test-data/gather/");
+
+        let mut paths = vec![];
+        for name in [
+            "GCF_000006945.2_ASM694v2_genomic.fna.gz.sig",
+            "GCF_000007545.1_ASM754v1_genomic.fna.gz.sig",
+        ] {
+            let path = PathBuf::from_path_buf(tmpdir.path().join(name)).unwrap();
+            std::fs::copy(basedir.join(name), &path)?;
+            paths.push(path);
+        }
+
+        let selection = Selection::builder().ksize(31).scaled(10000).build();
+        let collection: CollectionSet = Collection::from_paths(&paths)?
+            .select(&selection)?
+            .try_into()?;
+        std::fs::remove_file(&paths[1])?;
+
+        assert!(RevIndex::from_collection(collection).is_err());
+
+        Ok(())
+    }
+
+    #[test]
+    fn revindex_multisig_file() -> Result<()> {
+        let selection = Selection::builder().ksize(31).scaled(10000).build();
+        let search_sigs = ["../../tests/test-data/47+63-multisig.sig".into()];
+        let mut index = RevIndex::new(&search_sigs, &selection, 0, None, false)?;
+        assert_eq!(index.collection().len(), 6);
+
+        index.internalize_storage()?;
+        assert_eq!(index.collection().storage().spec(), "memory://");
+
+        // each signature in the file is its own best match
+        for (idx, record) in index.collection().iter() {
+            let sig = index.collection().sig_for_dataset(idx)?;
+            assert_eq!(&sig.name_str(), record.name());
+
+            let query = prepare_query(sig.into(), &selection).unwrap();
+            let counter = index.counter_for_query(&query);
+            let best = index.best_matches(counter, &query, SearchType::Jaccard, 1, 0.0)?;
+            assert_eq!(best[0].name(), record.name());
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    fn revindex_mem_internalize_storage() -> Result<()> {
+        let selection = Selection::builder()
+            .ksize(19)
+            .scaled(100)
+            .moltype(crate::encodings::HashFunctions::Murmur64Protein)
+            .build();
+        let mut index = RevIndex::from_zipfile(
+            "../../tests/test-data/prot/protein.zip",
+            &selection,
+            0,
+            None,
+            false,
+        )?;
+
+        index.internalize_storage()?;
+        assert_eq!(index.collection().storage().spec(), "memory://");
+
+        for (idx, _) in index.collection().iter() {
+            index.collection().sig_for_dataset(idx)?;
+        }
+
         Ok(())
     }
 }
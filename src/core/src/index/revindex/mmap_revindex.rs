@@ -0,0 +1,723 @@
+use std::collections::hash_map::Entry;
+use std::collections::HashMap;
+use std::fs::{self, File};
+use std::io::{BufWriter, Write};
+use std::ops::Range;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+use byteorder::{LittleEndian, ReadBytesExt, WriteBytesExt};
+use camino::Utf8PathBuf;
+use histogram::Histogram;
+use itertools::{EitherOrBoth, Itertools};
+use log::info;
+use memmap2::Mmap;
+use rayon::prelude::*;
+use serde::{Deserialize, Serialize};
+
+use crate::collection::{Collection, CollectionSet};
+use crate::encodings::{Color, Idx};
+use crate::index::revindex::{
+    self as module, checked_template, compute_color, template_from_collection, Datasets, DbStats,
+    HashToColor, IntegrityCheck, IntegrityError, QueryColors, RevIndexOps,
+};
+use crate::index::{GatherResult, SigCounter};
+use crate::manifest::Manifest;
+use crate::prelude::*;
+use crate::sketch::minhash::KmerMinHash;
+use crate::sketch::Sketch;
+use crate::storage::{InnerStorage, ZipStorage};
+use crate::HashIntoType;
+use crate::{Error, Result};
+
+const INDEX_VERSION: u8 = 1;
+
+// Files in the index directory
+const METADATA_FILE: &str = "revindex.json";
+const MANIFEST_FILE: &str = "manifest.csv";
+const HASHES_FILE: &str = "hashes.bin";
+const COLORS_FILE: &str = "colors.bin";
+const SIGNATURES_FILE: &str = "signatures.zip";
+
+// Each entry in the hashes file is a hash and a color, both u64
+const ENTRY_SIZE: usize = 16;
+
+#[derive(Serialize, Deserialize)]
+struct Metadata {
+    version: u8,
+    /// `None` if the signatures are stored in the index directory
+    storage_spec: Option<String>,
+    template: Option<Sketch>,
+}
+
+/// A RevIndex stored in memory-mapped files, without RocksDB.
+///
+/// Hashes are kept sorted together with a color, the offset of their
+/// datasets in the colors file. Files are only replaced (never modified in
+/// place), so read-only indices can be shared between processes.
+pub struct MmapRevIndex {
+    path: PathBuf,
+    hashes: Option<Mmap>,
+    colors: Option<Mmap>,
+    collection: Arc<CollectionSet>,
+    template: Option<Sketch>,
+    read_only: bool,
+}
+
+impl MmapRevIndex {
+    /// Is there an index in this format at `path`?
+    pub(super) fn is_index(path: &Path) -> bool {
+        path.join(METADATA_FILE).is_file()
+    }
+
+    pub fn create(path: &Path, collection: CollectionSet) -> Result<module::RevIndex> {
+        fs::create_dir_all(path)?;
+        let template = checked_template(None, &collection)?;
+
+        let entries = collect_hashes(&collection, 0..collection.len() as Idx)?;
+        write_tables(path, entries.into_iter().map(Ok))?;
+
+        let mut index = Self {
+            path: path.into(),
+            hashes: None,
+            colors: None,
+            collection: Arc::new(collection),
+            template,
+            read_only: false,
+        };
+        index.save_metadata()?;
+        index.map_tables()?;
+        info!("Done! Indexed {} reference sigs", index.collection.len());
+
+        Ok(module::RevIndex::Mmap(index))
+    }
+
+    pub fn open(
+        path: &Path,
+        read_only: bool,
+        storage_spec: Option<&str>,
+    ) -> Result<module::RevIndex> {
+        let metadata: Metadata = serde_json::from_reader(File::open(path.join(METADATA_FILE))?)
+            .map_err(|_| IntegrityError::CorruptedMetadata(METADATA_FILE.into()))?;
+        if metadata.version != INDEX_VERSION {
+            return Err(IntegrityError::CorruptedMetadata(METADATA_FILE.into()).into());
+        }
+
+        let manifest = Manifest::from_reader(File::open(path.join(MANIFEST_FILE))?)?;
+        let storage = match storage_spec.map(String::from).or(metadata.storage_spec) {
+            Some(spec) => InnerStorage::from_spec(spec)?,
+            None => InnerStorage::new(ZipStorage::from_file(signatures_path(path)?)?),
+        };
+        let collection: CollectionSet = Collection::new(manifest, storage).try_into()?;
+        let template = match metadata.template {
+            Some(template) => Some(template),
+            None => template_from_collection(&collection)?,
+        };
+
+        let mut index = Self {
+            path: path.into(),
+            hashes: None,
+            colors: None,
+            collection: Arc::new(collection),
+            template,
+            read_only,
+        };
+        index.map_tables()?;
+
+        Ok(module::RevIndex::Mmap(index))
+    }
+
+    fn map_tables(&mut self) -> Result<()> {
+        // drop the old mappings before mapping the new files
+        self.hashes = None;
+        self.colors = None;
+
+        self.hashes = map_file(&self.path.join(HASHES_FILE))?;
+        if self.hashes.as_ref().map_or(0, |m| m.len()) % ENTRY_SIZE != 0 {
+            return Err(IntegrityError::CorruptedMetadata(HASHES_FILE.into()).into());
+        }
+        self.colors = map_file(&self.path.join(COLORS_FILE))?;
+        Ok(())
+    }
+
+    fn save_metadata(&self) -> Result<()> {
+        let mut manifest = vec![];
+        self.collection.manifest().to_writer(&mut manifest)?;
+        replace_file(&self.path.join(MANIFEST_FILE), &manifest)?;
+
+        let spec = self.collection.storage().spec();
+        let internal = format!("zip://{}", signatures_path(&self.path)?);
+        let metadata = Metadata {
+            version: INDEX_VERSION,
+            storage_spec: if spec == internal { None } else { Some(spec) },
+            template: self.template.clone(),
+        };
+        replace_file(
+            &self.path.join(METADATA_FILE),
+            &serde_json::to_vec(&metadata)?,
+        )
+    }
+
+    fn check_writable(&self) -> Result<()> {
+        if self.read_only {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::PermissionDenied,
+                format!("index at {} was opened read-only", self.path.display()),
+            )
+            .into());
+        }
+        Ok(())
+    }
+
+    fn len(&self) -> usize {
+        self.hashes.as_ref().map_or(0, |m| m.len() / ENTRY_SIZE)
+    }
+
+    fn entry(&self, i: usize) -> (HashIntoType, Color) {
+        let hashes = self.hashes.as_ref().expect("index has no hashes");
+        let mut raw = &hashes[i * ENTRY_SIZE..(i + 1) * ENTRY_SIZE];
+        let hash = raw.read_u64::<LittleEndian>().unwrap();
+        let color = raw.read_u64::<LittleEndian>().unwrap();
+        (hash, color)
+    }
+
+    fn entries(&self) -> impl Iterator<Item = (HashIntoType, Color)> + '_ {
+        (0..self.len()).map(|i| self.entry(i))
+    }
+
+    /// Binary search for the color of `hash`.
+    fn color_for_hash(&self, hash: HashIntoType) -> Option<Color> {
+        let (mut low, mut high) = (0, self.len());
+        while low < high {
+            let mid = low + (high - low) / 2;
+            let (mid_hash, color) = self.entry(mid);
+            match mid_hash.cmp(&hash) {
+                std::cmp::Ordering::Less => low = mid + 1,
+                std::cmp::Ordering::Greater => high = mid,
+                std::cmp::Ordering::Equal => return Some(color),
+            }
+        }
+        None
+    }
+
+    /// Datasets stored at offset `color` of the colors file.
+    fn datasets_for_color(&self, color: Color) -> Option<Datasets> {
+        let colors = self.colors.as_ref()?;
+        let start = usize::try_from(color).ok()?.checked_add(4)?;
+        let mut raw_len = colors.get(start - 4..start)?;
+        let end = start.checked_add(raw_len.read_u32::<LittleEndian>().ok()? as usize)?;
+        Datasets::from_slice(colors.get(start..end)?)
+    }
+
+    /// Colors for the hashes in `query`. Hashes pointing to a missing color
+    /// are skipped, `verify` reports them.
+    fn query_colors(&self, query: &KmerMinHash) -> (QueryColors, HashToColor) {
+        let mut query_colors = QueryColors::new();
+        let hash_to_color = query
+            .iter_mins()
+            .filter_map(|hash| {
+                let color = self.color_for_hash(*hash)?;
+                if let Entry::Vacant(e) = query_colors.entry(color) {
+                    e.insert(self.datasets_for_color(color)?);
+                }
+                Some((*hash, color))
+            })
+            .collect();
+        (query_colors, hash_to_color)
+    }
+
+    /// Rewrite the hash tables with the datasets in `new_datasets` added.
+    fn add_datasets(&mut self, new_datasets: Range<Idx>) -> Result<()> {
+        let new_entries = collect_hashes(&self.collection, new_datasets)?;
+        let merged = self
+            .entries()
+            .merge_join_by(new_entries, |(hash, _), (new_hash, _)| hash.cmp(new_hash))
+            .map(|entry| match entry {
+                EitherOrBoth::Left((hash, color)) => Ok((hash, self.stored_datasets(hash, color)?)),
+                EitherOrBoth::Right(new_entry) => Ok(new_entry),
+                EitherOrBoth::Both((hash, color), (_, new_datasets)) => {
+                    let mut datasets = self.stored_datasets(hash, color)?;
+                    datasets.union(new_datasets);
+                    Ok((hash, datasets))
+                }
+            });
+        write_tables(&self.path, merged)?;
+        self.map_tables()
+    }
+
+    fn stored_datasets(&self, hash: HashIntoType, color: Color) -> Result<Datasets> {
+        self.datasets_for_color(color)
+            .ok_or_else(|| IntegrityError::MissingColor { hash, color }.into())
+    }
+}
+
+/// Hashes of `datasets` in `collection`, sorted and grouped by hash.
+fn collect_hashes(
+    collection: &CollectionSet,
+    datasets: Range<Idx>,
+) -> Result<Vec<(HashIntoType, Datasets)>> {
+    info!("Collecting hashes from {} datasets", datasets.len());
+    let mut pairs: Vec<(HashIntoType, Idx)> = datasets
+        .into_par_iter()
+        .map(|dataset_id| {
+            let sig = collection.sig_for_dataset(dataset_id)?;
+            let hashes = match sig.sketches().first() {
+                Some(Sketch::MinHash(mh)) => mh.mins(),
+                Some(Sketch::LargeMinHash(mh)) => mh.mins(),
+                _ => return Err(Error::NoMinHashFound),
+            };
+            Ok(hashes.into_iter().map(|hash| (hash, dataset_id)).collect())
+        })
+        .collect::<Result<Vec<Vec<_>>>>()?
+        .into_iter()
+        .flatten()
+        .collect();
+    pairs.par_sort_unstable();
+
+    let mut entries = vec![];
+    let mut ids = vec![];
+    for (i, (hash, dataset_id)) in pairs.iter().enumerate() {
+        ids.push(*dataset_id);
+        if pairs.get(i + 1).map_or(true, |(next, _)| next != hash) {
+            entries.push((*hash, Datasets::new(&ids)));
+            ids.clear();
+        }
+    }
+    Ok(entries)
+}
+
+/// Write sorted `entries` into new hashes and colors files in `path`,
+/// storing each distinct set of datasets once.
+fn write_tables<I>(path: &Path, entries: I) -> Result<()>
+where
+    I: IntoIterator<Item = Result<(HashIntoType, Datasets)>>,
+{
+    let hashes_tmp = path.join(format!("{}.tmp", HASHES_FILE));
+    let colors_tmp = path.join(format!("{}.tmp", COLORS_FILE));
+    let mut hashes = BufWriter::new(File::create(&hashes_tmp)?);
+    let mut colors = BufWriter::new(File::create(&colors_tmp)?);
+
+    let mut offsets: HashMap<Color, u64> = HashMap::new();
+    let mut colors_len = 0;
+    for entry in entries {
+        let (hash, datasets) = entry?;
+        let offset = match offsets.entry(compute_color(&datasets)) {
+            Entry::Occupied(e) => *e.get(),
+            Entry::Vacant(e) => {
+                let raw = datasets
+                    .as_bytes()
+                    .ok_or(IntegrityError::CorruptedHash(hash))?;
+                colors.write_u32::<LittleEndian>(raw.len() as u32)?;
+                colors.write_all(&raw)?;
+                let offset = colors_len;
+                colors_len += 4 + raw.len() as u64;
+                *e.insert(offset)
+            }
+        };
+        hashes.write_u64::<LittleEndian>(hash)?;
+        hashes.write_u64::<LittleEndian>(offset)?;
+    }
+    hashes
+        .into_inner()
+        .map_err(|e| e.into_error())?
+        .sync_all()?;
+    colors
+        .into_inner()
+        .map_err(|e| e.into_error())?
+        .sync_all()?;
+
+    fs::rename(hashes_tmp, path.join(HASHES_FILE))?;
+    fs::rename(colors_tmp, path.join(COLORS_FILE))?;
+    Ok(())
+}
+
+/// Write `content` to a temporary file and move it over `path`.
+fn replace_file(path: &Path, content: &[u8]) -> Result<()> {
+    let tmp = path.with_extension("tmp");
+    fs::write(&tmp, content)?;
+    fs::rename(tmp, path)?;
+    Ok(())
+}
+
+fn map_file(path: &Path) -> Result<Option<Mmap>> {
+    let file = File::open(path)?;
+    if file.metadata()?.len() == 0 {
+        // empty files can't be mapped
+        return Ok(None);
+    }
+    // Safety: index files are replaced by renaming, never modified in place
+    Ok(Some(unsafe { Mmap::map(&file)? }))
+}
+
+fn signatures_path(path: &Path) -> Result<Utf8PathBuf> {
+    Utf8PathBuf::try_from(path.join(SIGNATURES_FILE))
+        .map_err(|e| std::io::Error::new(std::io::ErrorKind::InvalidInput, e.to_string()).into())
+}
+
+impl RevIndexOps for MmapRevIndex {
+    fn counter_for_query(&self, query: &KmerMinHash) -> SigCounter {
+        let (query_colors, hash_to_color) = self.query_colors(query);
+        let mut counter = SigCounter::new();
+        for color in hash_to_color.0.values() {
+            counter.update(query_colors[color].clone());
+        }
+        counter
+    }
+
+    fn datasets_for_hashes(&self, hashes: &[HashIntoType]) -> Result<Vec<Datasets>> {
+        hashes
+            .iter()
+            .map(|hash| match self.color_for_hash(*hash) {
+                Some(color) => self.stored_datasets(*hash, color),
+                None => Ok(Datasets::default()),
+            })
+            .collect()
+    }
+
+    fn prepare_gather_counters(
+        &self,
+        query: &KmerMinHash,
+    ) -> (SigCounter, QueryColors, HashToColor) {
+        let (query_colors, hash_to_color) = self.query_colors(query);
+        let mut counter = SigCounter::new();
+        for color in hash_to_color.0.values() {
+            counter.update(query_colors[color].clone());
+        }
+        (counter, query_colors, hash_to_color)
+    }
+
+    fn update(mut self, collection: CollectionSet) -> Result<module::RevIndex> {
+        self.check_writable()?;
+        self.collection.check_superset(&collection)?;
+        self.template = checked_template(self.template.take(), &collection)?;
+        let start = self.collection.len() as Idx;
+        info!("sigs in the original index: {}", start);
+
+        self.collection = Arc::new(collection);
+        info!(
+            "sigs in the new index once finished: {}",
+            self.collection.len()
+        );
+
+        self.add_datasets(start..self.collection.len() as Idx)?;
+        self.save_metadata()?;
+
+        Ok(module::RevIndex::Mmap(self))
+    }
+
+    fn compact(&self) {}
+
+    fn flush(&self) -> Result<()> {
+        Ok(())
+    }
+
+    fn convert(&self, _output_db: module::RevIndex) -> Result<()> {
+        Err(Error::Internal {
+            message: "memory-mapped indices can't be converted".into(),
+        })
+    }
+
+    fn check(&self, quick: bool) -> DbStats {
+        let mut vcounts = Histogram::new(12, 64).expect("Error initializing histogram");
+        let mut datasets: Datasets = Default::default();
+
+        if !quick {
+            for (_, color) in self.entries() {
+                if let Some(idxs) = self.datasets_for_color(color) {
+                    vcounts.increment(idxs.len() as u64).unwrap();
+                    datasets.union(idxs);
+                }
+            }
+        }
+
+        // hashes and colors are both 8 bytes
+        let total_keys = self.len();
+        DbStats {
+            total_datasets: datasets.len(),
+            total_keys,
+            kcount: total_keys * 8,
+            vcount: total_keys * 8,
+            vcounts,
+        }
+    }
+
+    fn gather(
+        &self,
+        counter: SigCounter,
+        query_colors: QueryColors,
+        hash_to_color: HashToColor,
+        threshold: usize,
+        orig_query: &KmerMinHash,
+        _selection: Option<Selection>,
+    ) -> Result<Vec<GatherResult>> {
+        let orig_query = self.validate_query(orig_query)?;
+        module::gather_from_counters(
+            &self.collection,
+            counter,
+            query_colors,
+            hash_to_color,
+            threshold,
+            &orig_query,
+            None,
+        )
+    }
+
+    fn collection(&self) -> &CollectionSet {
+        &self.collection
+    }
+
+    fn template(&self) -> Option<&Sketch> {
+        self.template.as_ref()
+    }
+
+    /// Copy all signatures into a zip file in the index directory.
+    fn internalize_storage(&mut self) -> Result<()> {
+        self.check_writable()?;
+        let zip_path = signatures_path(&self.path)?;
+        if self.collection.storage().spec() == format!("zip://{}", zip_path) {
+            return Ok(());
+        }
+
+        let manifest = self.collection.to_zipfile(&zip_path, false)?;
+        let storage = InnerStorage::new(ZipStorage::from_file(&zip_path)?);
+        self.collection = Arc::new(Collection::new(manifest, storage).try_into()?);
+        self.save_metadata()
+    }
+
+    fn verify(&self) -> Result<Vec<IntegrityError>> {
+        let mut check = IntegrityCheck::new(&self.collection, Datasets::default(), None);
+
+        info!("Checking hashes");
+        let mut previous = None;
+        for (hash, color) in self.entries() {
+            // lookups rely on the hashes being sorted
+            if previous.map_or(false, |p| p >= hash) {
+                check.add_problem(IntegrityError::CorruptedHash(hash));
+            }
+            previous = Some(hash);
+
+            match self.datasets_for_color(color) {
+                Some(datasets) => check.add_hash(hash, datasets),
+                None => check.add_problem(IntegrityError::MissingColor { hash, color }),
+            }
+        }
+
+        info!("Checking datasets");
+        Ok(check.finish(None))
+    }
+
+    /// Rebuild the hash tables, since they can't be fixed in place.
+    fn repair(&mut self, _datasets: &[Idx]) -> Result<()> {
+        self.check_writable()?;
+        let entries = collect_hashes(&self.collection, 0..self.collection.len() as Idx)?;
+        write_tables(&self.path, entries.into_iter().map(Ok))?;
+        self.map_tables()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    use camino::Utf8PathBuf;
+    use tempfile::TempDir;
+
+    use crate::index::revindex::mem_revindex;
+    use crate::index::revindex::{prepare_query, Backend, RevIndex};
+    use crate::signature::Signature;
+
+    fn gather_data() -> Result<(Vec<Utf8PathBuf>, KmerMinHash, Selection)> {
+        let mut basedir = Utf8PathBuf::from(env!("CARGO_MANIFEST_DIR"));
+        basedir.push("../../tests/test-data/gather/");
+
+        let against: Vec<_> = [
+            "GCF_000006945.2_ASM694v2_genomic.fna.gz.sig",
+            "GCF_000007545.1_ASM754v1_genomic.fna.gz.sig",
+            "GCF_000008105.1_ASM810v1_genomic.fna.gz.sig",
+            "GCF_000008545.1_ASM854v1_genomic.fna.gz.sig",
+            "GCF_000009085.1_ASM908v1_genomic.fna.gz.sig",
+            "GCF_000016785.1_ASM1678v1_genomic.fna.gz.sig",
+        ]
+        .iter()
+        .map(|sig| basedir.join(sig))
+        .collect();
+
+        let selection = Selection::builder().ksize(21).scaled(10000).build();
+        let query_sig = Signature::from_path(basedir.join("combined.sig"))?
+            .swap_remove(0)
+            .select(&selection)?;
+        let query = prepare_query(query_sig, &selection).unwrap();
+
+        Ok((against, query, selection))
+    }
+
+    fn collection(paths: &[Utf8PathBuf], selection: &Selection) -> Result<CollectionSet> {
+        Collection::from_paths(paths)?.select(selection)?.try_into()
+    }
+
+    fn summary(index: &RevIndex, query: &KmerMinHash) -> Result<Vec<(String, u64)>> {
+        let (counter, query_colors, hash_to_color) = index.prepare_gather_counters(query);
+        Ok(index
+            .gather(counter, query_colors, hash_to_color, 0, query, None)?
+            .into_iter()
+            .map(|m| (m.name().clone(), m.intersect_bp()))
+            .collect())
+    }
+
+    #[test]
+    fn mmap_revindex_same_as_mem() -> Result<()> {
+        let (against, query, selection) = gather_data()?;
+        let collection = collection(&against, &selection)?;
+
+        let mem = RevIndex::Mem(mem_revindex::RevIndex::from_collection(collection.clone())?);
+        let expected = summary(&mem, &query)?;
+        assert_eq!(expected.len(), 6);
+
+        let output = TempDir::new()?;
+        let index = RevIndex::create_with_backend(output.path(), collection, Backend::Mmap)?;
+        assert_eq!(summary(&index, &query)?, expected);
+        assert_eq!(
+            index.counter_for_query(&query),
+            mem.counter_for_query(&query)
+        );
+        assert_eq!(
+            index.lookup_hashes(&query.mins())?,
+            mem.lookup_hashes(&query.mins())?
+        );
+        assert_eq!(index.verify()?, vec![]);
+
+        let stats = index.check(false);
+        assert_eq!(*stats.total_datasets(), 6);
+        assert_eq!(stats.total_keys(), mem.check(false).total_keys());
+        drop(index);
+
+        let index = RevIndex::open(output.path(), true, None)?;
+        assert!(matches!(index, RevIndex::Mmap(_)));
+        assert_eq!(index.collection().len(), 6);
+        assert_eq!(summary(&index, &query)?, expected);
+
+        Ok(())
+    }
+
+    #[test]
+    fn mmap_revindex_update() -> Result<()> {
+        let (against, query, selection) = gather_data()?;
+
+        let output = TempDir::new()?;
+        let empty = Collection::from_sigs(vec![])?.try_into()?;
+        let index = RevIndex::create_with_backend(output.path(), empty, Backend::Mmap)?;
+        assert!(index.template().is_none());
+        assert_eq!(index.check(false).total_keys(), &0);
+
+        let index = index.update(collection(&against[..3], &selection)?)?;
+        let index = index.update(collection(&against, &selection)?)?;
+        assert_eq!(index.verify()?, vec![]);
+        drop(index);
+
+        let full_output = TempDir::new()?;
+        let full = RevIndex::create_with_backend(
+            full_output.path(),
+            collection(&against, &selection)?,
+            Backend::Mmap,
+        )?;
+
+        let index = RevIndex::open(output.path(), true, None)?;
+        assert_eq!(summary(&index, &query)?, summary(&full, &query)?);
+
+        // read-only indices can't be modified
+        assert!(index.update(collection(&against, &selection)?).is_err());
+
+        Ok(())
+    }
+
+    #[test]
+    fn mmap_revindex_internalize_storage() -> Result<()> {
+        let (against, query, selection) = gather_data()?;
+
+        let output = TempDir::new()?;
+        let mut index = RevIndex::create_with_backend(
+            output.path(),
+            collection(&against, &selection)?,
+            Backend::Mmap,
+        )?;
+        let expected = summary(&index, &query)?;
+
+        index.internalize_storage()?;
+        assert!(index
+            .collection()
+            .storage()
+            .spec()
+            .ends_with(SIGNATURES_FILE));
+        drop(index);
+
+        let index = RevIndex::open(output.path(), true, None)?;
+        assert!(index
+            .collection()
+            .storage()
+            .spec()
+            .ends_with(SIGNATURES_FILE));
+        assert_eq!(summary(&index, &query)?, expected);
+
+        Ok(())
+    }
+
+    #[test]
+    fn mmap_revindex_verify_and_repair() -> Result<()> {
+        let (against, query, selection) = gather_data()?;
+
+        let output = TempDir::new()?;
+        let index = RevIndex::create_with_backend(
+            output.path(),
+            collection(&against, &selection)?,
+            Backend::Mmap,
+        )?;
+        let expected = summary(&index, &query)?;
+        drop(index);
+
+        // drop the last hash
+        let hashes_path = output.path().join(HASHES_FILE);
+        let mut hashes = fs::read(&hashes_path)?;
+        let (last_hash, last_color) = {
+            let mut raw = &hashes[hashes.len() - ENTRY_SIZE..];
+            (
+                raw.read_u64::<LittleEndian>()?,
+                raw.read_u64::<LittleEndian>()?,
+            )
+        };
+        hashes.truncate(hashes.len() - ENTRY_SIZE);
+        // and point the first hash to a missing color
+        let first_hash = (&hashes[..8]).read_u64::<LittleEndian>()?;
+        (&mut hashes[8..16]).write_u64::<LittleEndian>(u64::MAX)?;
+        fs::write(&hashes_path, &hashes)?;
+
+        let mut index = RevIndex::open(output.path(), false, None)?;
+        let problems = index.verify()?;
+        assert!(problems.contains(&IntegrityError::MissingColor {
+            hash: first_hash,
+            color: u64::MAX
+        }));
+        // queries skip the missing color
+        assert!(!index.counter_for_query(&query).is_empty());
+        summary(&index, &query)?;
+        let truncated = match &index {
+            RevIndex::Mmap(index) => index.datasets_for_color(last_color).unwrap(),
+            _ => unreachable!(),
+        };
+        for dataset in truncated {
+            assert!(problems.iter().any(|p| matches!(
+                p,
+                IntegrityError::HashCountMismatch { dataset: d, .. } if *d == dataset
+            )));
+        }
+        assert_eq!(
+            index.lookup_hash(last_hash)?,
+            Vec::<&crate::manifest::Record>::new()
+        );
+
+        index.repair(&[])?;
+        assert_eq!(index.verify()?, vec![]);
+        assert_eq!(summary(&index, &query)?, expected);
+
+        Ok(())
+    }
+}
@@ -1,34 +1,66 @@
+#[cfg(all(feature = "branchwater", not(target_arch = "wasm32")))]
+pub mod color_revindex;
+#[cfg(all(feature = "branchwater", not(target_arch = "wasm32")))]
 pub mod disk_revindex;
 pub mod mem_revindex;
+pub mod mmap_revindex;
 
+use std::cmp::{max, Reverse};
 use std::collections::HashMap;
-use std::hash::{Hash, Hasher};
+use std::hash::{BuildHasher, BuildHasherDefault, Hash, Hasher};
+use std::ops::{Range, RangeInclusive};
 use std::path::Path;
+#[cfg(all(feature = "branchwater", not(target_arch = "wasm32")))]
 use std::sync::Arc;
 
 use byteorder::{LittleEndian, WriteBytesExt};
+use cfg_if::cfg_if;
 use enum_dispatch::enum_dispatch;
 use getset::{Getters, Setters};
+use log::{info, trace};
 use nohash_hasher::BuildNoHashHasher;
+use rayon::prelude::*;
 use roaring::RoaringBitmap;
 use serde::{Deserialize, Serialize};
 
+#[cfg(not(all(feature = "branchwater", not(target_arch = "wasm32"))))]
+use crate::storage::StorageError;
+
 use crate::collection::CollectionSet;
-use crate::encodings::{Color, Colors, Idx};
-use crate::index::{GatherResult, SigCounter};
+use crate::encodings::{Color, Colors, HashFunctions, Idx};
+use crate::index::{
+    best_from_counter, calculate_gather_stats, prefetch_from_counter, search_from_counter,
+    GatherResult, SearchResult, SearchType, SigCounter,
+};
+use crate::manifest::Record;
 use crate::prelude::*;
-use crate::signature::Signature;
-use crate::sketch::minhash::KmerMinHash;
+use crate::signature::{Signature, SigsTrait};
+use crate::sketch::minhash::{KmerMinHash, KmerMinHashBTree};
 use crate::sketch::Sketch;
+#[cfg(all(feature = "branchwater", not(target_arch = "wasm32")))]
 use crate::storage::rocksdb::{db_options, COLORS, DB};
 use crate::HashIntoType;
-use crate::Result;
+use crate::{Error, Result};
 
 // DB metadata saved in the METADATA column family
+#[cfg(all(feature = "branchwater", not(target_arch = "wasm32")))]
 const MANIFEST: &str = "manifest";
+#[cfg(all(feature = "branchwater", not(target_arch = "wasm32")))]
 const STORAGE_SPEC: &str = "storage_spec";
+#[cfg(all(feature = "branchwater", not(target_arch = "wasm32")))]
 const VERSION: &str = "version";
+#[cfg(all(feature = "branchwater", not(target_arch = "wasm32")))]
 const PROCESSED: &str = "processed";
+#[cfg(all(feature = "branchwater", not(target_arch = "wasm32")))]
+const REMOVED: &str = "removed";
+#[cfg(all(feature = "branchwater", not(target_arch = "wasm32")))]
+const TEMPLATE: &str = "template";
+#[cfg(all(feature = "branchwater", not(target_arch = "wasm32")))]
+const SHARD: &str = "shard";
+
+// Number of operations to accumulate before writing a batch
+#[cfg(all(feature = "branchwater", not(target_arch = "wasm32")))]
+const BATCH_SIZE: usize = 100_000;
 
 type QueryColors = HashMap<Color, Datasets>;
 type HashToColorT = HashMap<HashIntoType, Color, BuildNoHashHasher<HashIntoType>>;
@@ -36,21 +68,115 @@ type HashToColorT = HashMap<HashIntoType, Color, BuildNoHashHasher<HashIntoType>
 pub struct HashToColor(HashToColorT);
 
 #[enum_dispatch(RevIndexOps)]
+#[allow(clippy::large_enum_variant)]
 pub enum RevIndex {
-    //Color(color_revindex::ColorRevIndex),
+    #[cfg(all(feature = "branchwater", not(target_arch = "wasm32")))]
+    Color(color_revindex::ColorRevIndex),
+    #[cfg(all(feature = "branchwater", not(target_arch = "wasm32")))]
     Plain(disk_revindex::RevIndex),
-    //Mem(mem_revindex::RevIndex),
+    Mem(mem_revindex::RevIndex),
+    Mmap(mmap_revindex::MmapRevIndex),
 }
 
 #[enum_dispatch]
 pub trait RevIndexOps {
-    /* TODO: need the repair_cf variant, not available in rocksdb-rust yet
-        pub fn repair(index: &Path, colors: bool);
-    */
-
     fn counter_for_query(&self, query: &KmerMinHash) -> SigCounter;
 
-    fn matches_from_counter(&self, counter: SigCounter, threshold: usize) -> Vec<(String, usize)>;
+    /// Datasets containing each of `hashes`, in the same order.
+    fn datasets_for_hashes(&self, hashes: &[HashIntoType]) -> Result<Vec<Datasets>>;
+
+    /// Records for the datasets containing `hash`, sorted by dataset.
+    fn lookup_hash(&self, hash: HashIntoType) -> Result<Vec<&Record>> {
+        let mut datasets: Vec<Idx> = self
+            .datasets_for_hashes(&[hash])?
+            .into_iter()
+            .flatten()
+            .collect();
+        datasets.sort_unstable();
+        datasets
+            .into_iter()
+            .map(|dataset_id| self.collection().record_for_dataset(dataset_id))
+            .collect()
+    }
+
+    /// Presence of each of `hashes` in the datasets containing any of them.
+    fn lookup_hashes(&self, hashes: &[HashIntoType]) -> Result<HashPresence> {
+        let datasets = self.datasets_for_hashes(hashes)?;
+        Ok(HashPresence::new(hashes, datasets))
+    }
+
+    fn matches_from_counter(&self, counter: SigCounter, threshold: usize) -> Vec<(String, usize)> {
+        info!("get matches from counter");
+        counter
+            .most_common()
+            .into_iter()
+            .filter_map(|(dataset_id, size)| {
+                if size >= threshold {
+                    let row = &self
+                        .collection()
+                        .record_for_dataset(dataset_id)
+                        .expect("dataset not found");
+
+                    let name = [row.name(), row.filename(), row.md5()]
+                        .into_iter()
+                        .find(|v| !v.is_empty())
+                        .unwrap(); // guaranteed to succeed because `md5` always exists
+
+                    Some((name.into(), size))
+                } else {
+                    None
+                }
+            })
+            .collect()
+    }
+
+    fn search_matches(
+        &self,
+        counter: SigCounter,
+        query: &KmerMinHash,
+        search_type: SearchType,
+        threshold: f64,
+    ) -> Result<Vec<SearchResult>> {
+        let query = self.validate_query(query)?;
+        search_from_counter(self.collection(), counter, &query, search_type, threshold)
+    }
+
+    fn best_matches(
+        &self,
+        counter: SigCounter,
+        query: &KmerMinHash,
+        search_type: SearchType,
+        k: usize,
+        threshold: f64,
+    ) -> Result<Vec<SearchResult>> {
+        let query = self.validate_query(query)?;
+        best_from_counter(
+            self.collection(),
+            counter,
+            &query,
+            search_type,
+            k,
+            threshold,
+        )
+    }
+
+    fn prefetch<'a>(
+        &'a self,
+        counter: SigCounter,
+        query: &'a KmerMinHash,
+        threshold_bp: usize,
+    ) -> Box<dyn Iterator<Item = Result<SearchResult>> + 'a> {
+        let query = match self.validate_query(query) {
+            Ok(query) => query,
+            Err(e) => return Box::new(std::iter::once(Err(e))),
+        };
+        Box::new(prefetch_from_counter(
+            self.collection(),
+            counter,
+            query,
+            threshold_bp,
+        ))
+    }
 
     fn prepare_gather_counters(
         &self,
@@ -79,9 +205,83 @@ pub trait RevIndexOps {
         selection: Option<Selection>,
     ) -> Result<Vec<GatherResult>>;
 
+    /// Like `gather`, but picking at each round the match that explains the
+    /// most query abundance, and stopping once the best match would explain
+    /// less than `threshold_f_weighted` of it.
+    fn gather_weighted(
+        &self,
+        counter: SigCounter,
+        query_colors: QueryColors,
+        hash_to_color: HashToColor,
+        threshold: usize,
+        threshold_f_weighted: f64,
+        orig_query: &KmerMinHash,
+    ) -> Result<Vec<GatherResult>> {
+        let orig_query = self.validate_query(orig_query)?;
+        gather_from_counters(
+            self.collection(),
+            counter,
+            query_colors,
+            hash_to_color,
+            threshold,
+            &orig_query,
+            Some(threshold_f_weighted),
+        )
+    }
+
     fn collection(&self) -> &CollectionSet;
 
+    /// Sketch parameters (ksize, moltype, scaled, seed, abundance) the index
+    /// was built with, or `None` if it has no datasets yet.
+    fn template(&self) -> Option<&Sketch>;
+
+    /// Check `query` against the index template, downsampling it if the
+    /// index was built with a larger scaled.
+    fn validate_query(&self, query: &KmerMinHash) -> Result<KmerMinHash> {
+        match self.template() {
+            Some(template) => check_query(template, query),
+            None => Ok(query.clone()),
+        }
+    }
+
     fn internalize_storage(&mut self) -> Result<()>;
+
+    /// Cross-check the manifest, storage, processed datasets and hashes,
+    /// returning all problems found.
+    fn verify(&self) -> Result<Vec<IntegrityError>> {
+        Err(Error::Internal {
+            message: "this RevIndex doesn't support verification".into(),
+        })
+    }
+
+    /// Index `datasets` again, restoring missing hashes and marking them
+    /// as processed.
+    fn repair(&mut self, _datasets: &[Idx]) -> Result<()> {
+        Err(Error::Internal {
+            message: "this RevIndex doesn't support repairs".into(),
+        })
+    }
+
+    /// Remove datasets with md5 or name in `keys`, returning their ids.
+    ///
+    /// Removed datasets stay in the manifest as tombstones, so the ids of
+    /// all other datasets are unchanged.
+    fn remove_datasets(&mut self, _keys: &[&str]) -> Result<Vec<Idx>> {
+        Err(Error::Internal {
+            message: "this RevIndex doesn't support removing datasets".into(),
+        })
+    }
+
+    /// Remove datasets matching `keys` and index the new datasets in
+    /// `collection`, which must extend the current collection.
+    fn replace_datasets(self, keys: &[&str], collection: CollectionSet) -> Result<RevIndex>
+    where
+        Self: Sized,
+    {
+        let mut index = self;
+        index.remove_datasets(keys)?;
+        index.update(collection)
+    }
 }
 
 impl HashToColor {
@@ -161,41 +361,620 @@ impl FromIterator<(HashIntoType, Color)> for HashToColor {
     }
 }
 
-impl RevIndex {
-    /* TODO: need the repair_cf variant, not available in rocksdb-rust yet
-        pub fn repair(index: &Path, colors: bool) {
-            if colors {
-                color_revindex::repair(index);
-            } else {
-                disk_revindex::repair(index);
-            }
-        }
-    */
+/// On-disk formats for `RevIndex::create_with_backend`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Backend {
+    /// RocksDB, with hashes mapped to datasets or to colors
+    #[cfg(all(feature = "branchwater", not(target_arch = "wasm32")))]
+    RocksDB { colors: bool },
+    /// Sorted hash tables in memory-mapped files, without RocksDB
+    Mmap,
+}
 
+impl RevIndex {
+    #[cfg(all(feature = "branchwater", not(target_arch = "wasm32")))]
     pub fn create<P: AsRef<Path>>(
         index: P,
         collection: CollectionSet,
         colors: bool,
     ) -> Result<Self> {
         if colors {
-            todo!() //color_revindex::ColorRevIndex::create(index)
+            color_revindex::ColorRevIndex::create(index.as_ref(), collection)
         } else {
             disk_revindex::RevIndex::create(index.as_ref(), collection)
         }
     }
 
+    pub fn create_with_backend<P: AsRef<Path>>(
+        index: P,
+        collection: CollectionSet,
+        backend: Backend,
+    ) -> Result<Self> {
+        match backend {
+            #[cfg(all(feature = "branchwater", not(target_arch = "wasm32")))]
+            Backend::RocksDB { colors } => Self::create(index, collection, colors),
+            Backend::Mmap => mmap_revindex::MmapRevIndex::create(index.as_ref(), collection),
+        }
+    }
+
+    /// Open an existing index, detecting its backend.
     pub fn open<P: AsRef<Path>>(index: P, read_only: bool, spec: Option<&str>) -> Result<Self> {
-        let opts = db_options();
-        let cfs = DB::list_cf(&opts, index.as_ref())?;
+        if mmap_revindex::MmapRevIndex::is_index(index.as_ref()) {
+            return mmap_revindex::MmapRevIndex::open(index.as_ref(), read_only, spec);
+        }
 
-        if cfs.into_iter().any(|c| c == COLORS) {
-            // TODO: ColorRevIndex can't be read-only for now,
-            //       due to pending unmerged colors
-            todo!() //color_revindex::ColorRevIndex::open(index, false)
-        } else {
-            disk_revindex::RevIndex::open(index, read_only, spec)
+        cfg_if! {
+            if #[cfg(all(feature = "branchwater", not(target_arch = "wasm32")))] {
+                let opts = db_options();
+                let cfs = DB::list_cf(&opts, index.as_ref())?;
+
+                if cfs.into_iter().any(|c| c == COLORS) {
+                    color_revindex::ColorRevIndex::open(index, read_only, spec)
+                } else {
+                    disk_revindex::RevIndex::open(index, read_only, spec)
+                }
+            } else {
+                Err(StorageError::MissingFeature(
+                    "branchwater".into(),
+                    index.as_ref().display().to_string(),
+                )
+                .into())
+            }
+        }
+    }
+}
+
+#[cfg(all(feature = "branchwater", not(target_arch = "wasm32")))]
+impl RevIndex {
+    /// Merge plain indices into a new index at `path`. Datasets are
+    /// renumbered in the order of `indices`.
+    pub fn merge<P: AsRef<Path>>(indices: &[&RevIndex], path: P) -> Result<Self> {
+        let plain = indices
+            .iter()
+            .map(|index| match index {
+                RevIndex::Plain(index) => Ok(index),
+                _ => Err(Error::Internal {
+                    message: "only plain RevIndexes can be merged".into(),
+                }),
+            })
+            .collect::<Result<Vec<_>>>()?;
+        disk_revindex::RevIndex::merge(&plain, path.as_ref())
+    }
+
+    /// Split a plain index into one shard per path in `paths`.
+    pub fn shard<P: AsRef<Path>>(&self, paths: &[P], by: ShardBy) -> Result<Vec<Self>> {
+        match self {
+            RevIndex::Plain(index) => index.shard(paths, by),
+            _ => Err(Error::Internal {
+                message: "only plain RevIndexes can be sharded".into(),
+            }),
+        }
+    }
+}
+
+/// How to split an index with `RevIndex::shard`.
+#[cfg(all(feature = "branchwater", not(target_arch = "wasm32")))]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ShardBy {
+    /// Equal ranges of hash values, each shard keeping all datasets
+    Hashes,
+    /// Contiguous ranges of datasets
+    Datasets,
+}
+
+/// Part of an index kept by a shard. Shards keep the whole collection, so
+/// dataset ids are the same across shards, and the gather counters of all
+/// shards can be combined with `combine_gather_counters`.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Shard {
+    Hashes(RangeInclusive<HashIntoType>),
+    Datasets(Range<Idx>),
+}
+
+impl Shard {
+    #[cfg(all(feature = "branchwater", not(target_arch = "wasm32")))]
+    fn split(by: ShardBy, n: usize, max_hash: HashIntoType, n_datasets: usize) -> Vec<Self> {
+        // boundaries are computed in u128 to avoid overflows near u64::MAX
+        let bound = |total: u128, i: usize| (total * i as u128 / n as u128) as u64;
+        (0..n)
+            .map(|i| match by {
+                ShardBy::Hashes => {
+                    let total = max_hash as u128 + 1;
+                    Shard::Hashes(bound(total, i)..=bound(total, i + 1).wrapping_sub(1))
+                }
+                ShardBy::Datasets => {
+                    let total = n_datasets as u128;
+                    Shard::Datasets(bound(total, i) as Idx..bound(total, i + 1) as Idx)
+                }
+            })
+            .collect()
+    }
+
+    pub fn contains_hash(&self, hash: HashIntoType) -> bool {
+        match self {
+            Shard::Hashes(range) => range.contains(&hash),
+            Shard::Datasets(_) => true,
+        }
+    }
+
+    pub fn contains_dataset(&self, dataset: Idx) -> bool {
+        match self {
+            Shard::Hashes(_) => true,
+            Shard::Datasets(range) => range.contains(&dataset),
+        }
+    }
+}
+
+pub(crate) fn compute_color(idxs: &Datasets) -> Color {
+    let s = BuildHasherDefault::<twox_hash::Xxh3Hash128>::default();
+    let mut hasher = s.build_hasher();
+    idxs.hash(&mut hasher);
+    hasher.finish()
+}
+
+/// Combine the gather counters prepared by each shard of an index,
+/// for use with `RevIndexOps::gather` on any of the shards.
+pub fn combine_gather_counters<I>(parts: I) -> (SigCounter, QueryColors, HashToColor)
+where
+    I: IntoIterator<Item = (SigCounter, QueryColors, HashToColor)>,
+{
+    let mut hash_to_datasets: HashMap<HashIntoType, Datasets> = HashMap::new();
+    for (_, query_colors, hash_to_color) in parts {
+        for (hash, color) in hash_to_color.0 {
+            hash_to_datasets
+                .entry(hash)
+                .or_default()
+                .union(query_colors[&color].clone());
+        }
+    }
+
+    let mut counter = SigCounter::new();
+    let mut query_colors = QueryColors::new();
+    let mut hash_to_color = HashToColor::new();
+    for (hash, datasets) in hash_to_datasets {
+        let color = compute_color(&datasets);
+        counter.update(datasets.clone());
+        query_colors.entry(color).or_insert(datasets);
+        hash_to_color.0.insert(hash, color);
+    }
+
+    (counter, query_colors, hash_to_color)
+}
+
+/// Gather for counters built by `RevIndexOps::prepare_gather_counters`,
+/// shared by all RevIndex variants. Matches are picked by number of shared
+/// hashes, or by the query abundance they explain if `threshold_f_weighted`
+/// is set.
+pub(crate) fn gather_from_counters(
+    collection: &CollectionSet,
+    mut counter: SigCounter,
+    query_colors: QueryColors,
+    hash_to_color: HashToColor,
+    threshold: usize,
+    orig_query: &KmerMinHash,
+    threshold_f_weighted: Option<f64>,
+) -> Result<Vec<GatherResult>> {
+    let mut match_size = usize::MAX;
+    let mut matches = vec![];
+    let mut query = KmerMinHashBTree::from(orig_query.clone());
+    let mut sum_weighted_found = 0;
+    let total_weighted_hashes = orig_query.sum_abunds();
+
+    // query abundance shared with each dataset
+    let abunds: HashMap<HashIntoType, u64> = orig_query.to_vec_abunds().into_iter().collect();
+    let abund = |hash: &HashIntoType| {
+        abunds.get(hash).copied().ok_or_else(|| Error::Internal {
+            message: format!("hash {} is not in the gather query", hash),
+        })
+    };
+    let mut weighted_counter: HashMap<Idx, u64> = HashMap::new();
+    if threshold_f_weighted.is_some() {
+        for (hash, color) in hash_to_color.0.iter() {
+            let Some(datasets) = query_colors.get(color) else {
+                continue;
+            };
+            let abund = abund(hash)?;
+            for dataset in datasets.clone() {
+                *weighted_counter.entry(dataset).or_default() += abund;
+            }
+        }
+    }
+
+    // or set this with user --track-abundance?
+    let calc_abund_stats = orig_query.track_abundance();
+
+    // todo: let user pass these options in
+    let calc_ani_ci = false;
+    let ani_confidence_interval_fraction = None;
+
+    while match_size > threshold && !counter.is_empty() {
+        trace!("counter len: {}", counter.len());
+        trace!("match size: {}", match_size);
+
+        let (dataset_id, size) = match threshold_f_weighted {
+            None => counter.k_most_common_ordered(1)[0],
+            Some(threshold_f_weighted) => {
+                // ties are broken by dataset, like in k_most_common_ordered
+                let best = counter
+                    .iter()
+                    .filter(|(_, size)| **size >= threshold && **size > 0)
+                    .map(|(dataset, size)| {
+                        let weight = weighted_counter.get(dataset).copied().unwrap_or(0);
+                        (weight, Reverse(*dataset), *size)
+                    })
+                    .max();
+                match best {
+                    Some((weight, Reverse(dataset), size))
+                        if weight as f64 / total_weighted_hashes as f64 >= threshold_f_weighted =>
+                    {
+                        (dataset, size)
+                    }
+                    _ => break,
+                }
+            }
+        };
+        match_size = if size >= threshold { size } else { break };
+        // handle special case where threshold was set to 0
+        if match_size == 0 {
+            break;
+        }
+
+        let match_sig = collection.sig_for_dataset(dataset_id)?;
+        let match_mh = match_sig.minhash().ok_or(Error::NoMinHashFound)?.clone();
+
+        // make downsampled minhashes
+        let max_scaled = max(match_mh.scaled(), query.scaled());
+
+        let match_mh = match_mh.downsample_scaled(max_scaled)?;
+
+        // repeatedly downsample query, then extract to KmerMinHash
+        // => calculate_gather_stats
+        query = query.downsample_scaled(max_scaled)?;
+        let query_mh = KmerMinHash::from(query.clone());
+
+        // just calculate essentials here
+        let gather_result_rank = matches.len() as u32;
+
+        // grab the specific intersection:
+        // Calculate stats
+        let (gather_result, isect) = calculate_gather_stats(
+            orig_query,
+            query_mh,
+            match_sig,
+            match_size,
+            gather_result_rank,
+            sum_weighted_found,
+            total_weighted_hashes,
+            calc_abund_stats,
+            calc_ani_ci,
+            ani_confidence_interval_fraction,
+        )?;
+
+        // use intersection from calc_gather_stats to make a KmerMinHash.
+        let mut isect_mh = match_mh.clone();
+        isect_mh.clear();
+        isect_mh.add_many(&isect.0)?;
+
+        // keep track of the sum weighted found
+        sum_weighted_found = gather_result.sum_weighted_found();
+        matches.push(gather_result);
+
+        trace!("Preparing counter for next round");
+        // Prepare counter for finding the next match by decrementing
+        // all hashes found in the current match in other datasets
+        // TODO: not used at the moment, so just skip.
+        query.remove_many(match_mh.iter_mins().copied())?; // is there a better way?
+
+        // TODO: Use HashesToColors here instead. If not initialized,
+        //       build it.
+        for hash in &isect.0 {
+            let Some(color) = hash_to_color.get(hash) else {
+                continue;
+            };
+            let Some(datasets) = query_colors.get(color) else {
+                continue;
+            };
+            // TODO: remove this clone
+            for dataset in datasets.clone() {
+                // TODO: collect into a Counter, and remove more
+                //       than one at a time...
+                counter.entry(dataset).and_modify(|e| *e -= 1);
+                if let Some(weight) = weighted_counter.get_mut(&dataset) {
+                    *weight -= abund(hash)?;
+                }
+            }
         }
+
+        counter.remove(&dataset_id);
+        weighted_counter.remove(&dataset_id);
+    }
+    Ok(matches)
+}
+
+/// Problems found by `RevIndexOps::verify`.
+#[non_exhaustive]
+#[derive(Debug, Clone, PartialEq, Eq, thiserror::Error)]
+pub enum IntegrityError {
+    #[error("metadata key {0:?} is missing")]
+    MissingMetadata(String),
+
+    #[error("metadata key {0:?} can't be deserialized")]
+    CorruptedMetadata(String),
+
+    #[error("signature for dataset {dataset} can't be loaded from {location}")]
+    MissingSignature { dataset: Idx, location: String },
+
+    #[error("signature for dataset {0} has no MinHash sketch")]
+    MissingMinHash(Idx),
+
+    #[error("dataset {0} is not marked as processed")]
+    NotProcessed(Idx),
+
+    #[error("processed dataset {0} is not in the manifest")]
+    UnknownProcessed(Idx),
+
+    #[error("datasets for hash {0} can't be deserialized")]
+    CorruptedHash(HashIntoType),
+
+    #[error("hash {hash} points to dataset {dataset}, not in the manifest")]
+    DanglingDataset { hash: HashIntoType, dataset: Idx },
+
+    #[error("hash {hash} points to removed dataset {dataset}")]
+    RemovedDataset { hash: HashIntoType, dataset: Idx },
+
+    #[error("datasets for color {0} can't be deserialized")]
+    CorruptedColor(Color),
+
+    #[error("hash {hash} points to color {color}, not in the colors table")]
+    MissingColor { hash: HashIntoType, color: Color },
+
+    #[error("{0} hashes are waiting to be merged into colors")]
+    UnmergedColors(usize),
+
+    #[error("dataset {dataset} has {expected} hashes, but {found} are indexed")]
+    HashCountMismatch {
+        dataset: Idx,
+        expected: usize,
+        found: usize,
+    },
+}
+
+impl IntegrityError {
+    /// The dataset affected by this problem, if it can be fixed by
+    /// `RevIndexOps::repair`.
+    pub fn dataset(&self) -> Option<Idx> {
+        match self {
+            Self::NotProcessed(dataset) | Self::HashCountMismatch { dataset, .. } => Some(*dataset),
+            _ => None,
+        }
+    }
+}
+
+/// Accumulates the datasets for each hash to cross-check them
+/// against the collection.
+struct IntegrityCheck<'a> {
+    collection: &'a CollectionSet,
+    removed: Datasets,
+    shard: Option<&'a Shard>,
+    hash_counts: Vec<usize>,
+    problems: Vec<IntegrityError>,
+}
+
+impl<'a> IntegrityCheck<'a> {
+    fn new(collection: &'a CollectionSet, removed: Datasets, shard: Option<&'a Shard>) -> Self {
+        Self {
+            collection,
+            removed,
+            shard,
+            hash_counts: vec![0; collection.len()],
+            problems: vec![],
+        }
+    }
+
+    fn add_problem(&mut self, problem: IntegrityError) {
+        self.problems.push(problem);
+    }
+
+    fn add_hash(&mut self, hash: HashIntoType, datasets: Datasets) {
+        for dataset in datasets {
+            if dataset as usize >= self.hash_counts.len() {
+                self.add_problem(IntegrityError::DanglingDataset { hash, dataset });
+            } else if self.removed.contains(&dataset) {
+                self.add_problem(IntegrityError::RemovedDataset { hash, dataset });
+            } else {
+                self.hash_counts[dataset as usize] += 1;
+            }
+        }
+    }
+
+    fn finish(mut self, processed: Option<Datasets>) -> Vec<IntegrityError> {
+        if let Some(processed) = &processed {
+            for dataset in processed.clone() {
+                if dataset as usize >= self.collection.len() {
+                    self.add_problem(IntegrityError::UnknownProcessed(dataset));
+                }
+            }
+        }
+
+        let dataset_problems: Vec<_> = self
+            .collection
+            .par_iter()
+            .filter(|(dataset, _)| !self.removed.contains(dataset))
+            .flat_map_iter(|(dataset, record)| {
+                let mut problems = vec![];
+                let found = self.hash_counts[dataset as usize];
+
+                // datasets outside a dataset shard are not indexed at all
+                if !self.shard.map_or(true, |s| s.contains_dataset(dataset)) {
+                    if found != 0 {
+                        problems.push(IntegrityError::HashCountMismatch {
+                            dataset,
+                            expected: 0,
+                            found,
+                        });
+                    }
+                    return problems;
+                }
+
+                if let Some(processed) = &processed {
+                    if !processed.contains(&dataset) {
+                        problems.push(IntegrityError::NotProcessed(dataset));
+                    }
+                }
+
+                let in_shard =
+                    |hash: &HashIntoType| self.shard.map_or(true, |s| s.contains_hash(*hash));
+                let expected = match self.collection.sig_for_dataset(dataset) {
+                    Ok(sig) => match sig.sketches().first() {
+                        Some(Sketch::MinHash(mh)) => mh.iter_mins().filter(|h| in_shard(h)).count(),
+                        Some(Sketch::LargeMinHash(mh)) => {
+                            mh.mins().iter().filter(|h| in_shard(h)).count()
+                        }
+                        _ => {
+                            problems.push(IntegrityError::MissingMinHash(dataset));
+                            return problems;
+                        }
+                    },
+                    Err(_) => {
+                        problems.push(IntegrityError::MissingSignature {
+                            dataset,
+                            location: record.internal_location().to_string(),
+                        });
+                        return problems;
+                    }
+                };
+
+                if expected != found {
+                    problems.push(IntegrityError::HashCountMismatch {
+                        dataset,
+                        expected,
+                        found,
+                    });
+                }
+                problems
+            })
+            .collect();
+
+        self.problems.extend(dataset_problems);
+        self.problems
+    }
+}
+
+/// Template for the datasets in `collection`, with the parameters of the
+/// first sketch and no hashes.
+fn template_from_collection(collection: &CollectionSet) -> Result<Option<Sketch>> {
+    if collection.is_empty() {
+        return Ok(None);
+    }
+
+    let sig = collection.sig_for_dataset(0)?;
+    let mut template = match sig.sketches().swap_remove(0) {
+        Sketch::MinHash(mh) => mh,
+        Sketch::LargeMinHash(mh) => mh.into(),
+        _ => return Err(Error::NoMinHashFound),
+    };
+    template.clear();
+
+    Ok(Some(Sketch::MinHash(template)))
+}
+
+/// Check `query` against `template`, downsampling it if the template
+/// has a larger scaled.
+pub fn check_query(template: &Sketch, query: &KmerMinHash) -> Result<KmerMinHash> {
+    let template = match template {
+        Sketch::MinHash(mh) => mh,
+        _ => return Err(Error::NoMinHashFound),
+    };
+
+    if query.ksize() != template.ksize() {
+        return Err(Error::MismatchKSizes);
+    }
+    if query.hash_function() != template.hash_function() {
+        if matches!(query.hash_function(), HashFunctions::Custom(_))
+            || matches!(template.hash_function(), HashFunctions::Custom(_))
+        {
+            return Err(Error::MismatchHashFunction {
+                h1: template.hash_function().to_string(),
+                h2: query.hash_function().to_string(),
+            });
+        }
+        return Err(Error::MismatchDNAProt);
+    }
+    if query.seed() != template.seed() {
+        return Err(Error::MismatchSeed);
     }
+    if query.num() != template.num() {
+        return Err(Error::MismatchNum {
+            n1: template.num(),
+            n2: query.num(),
+        });
+    }
+
+    if query.scaled() < template.scaled() {
+        query.clone().downsample_scaled(template.scaled())
+    } else {
+        Ok(query.clone())
+    }
+}
+
+/// Check that all datasets in `collection` match `template`, or build a
+/// new template from `collection` if there isn't one yet.
+fn checked_template(
+    template: Option<Sketch>,
+    collection: &CollectionSet,
+) -> Result<Option<Sketch>> {
+    match template {
+        Some(template) => {
+            check_collection(&template, collection)?;
+            Ok(Some(template))
+        }
+        None => template_from_collection(collection),
+    }
+}
+
+/// Check that all datasets in `collection` match the sketch parameters
+/// in `template`.
+fn check_collection(template: &Sketch, collection: &CollectionSet) -> Result<()> {
+    let mut sig = Signature::default();
+    sig.push(template.clone());
+    let template = Record::from_sig(&sig, "").swap_remove(0);
+
+    collection.iter().try_for_each(|(_, record)| {
+        template.check_compatible(record)?;
+        if record.scaled() != template.scaled() {
+            return Err(Error::MismatchScaled);
+        }
+        Ok(())
+    })
+}
+
+/// Ids of datasets with md5 or name in `keys`, skipping `removed` ones.
+#[cfg(all(feature = "branchwater", not(target_arch = "wasm32")))]
+fn datasets_for_keys(
+    collection: &CollectionSet,
+    keys: &[&str],
+    removed: &Datasets,
+) -> Result<Vec<Idx>> {
+    let mut ids = vec![];
+    for key in keys {
+        let start = ids.len();
+        ids.extend(
+            collection
+                .iter()
+                .filter(|(id, record)| {
+                    !removed.contains(id) && (record.md5() == key || record.name() == key)
+                })
+                .map(|(id, _)| id),
+        );
+        if ids.len() == start {
+            return Err(Error::DatasetNotFound {
+                key: key.to_string(),
+            });
+        }
+    }
+    ids.sort_unstable();
+    ids.dedup();
+    Ok(ids)
 }
 
 pub fn prepare_query(search_sig: Signature, selection: &Selection) -> Option<KmerMinHash> {
@@ -235,6 +1014,14 @@ impl Hash for Datasets {
     }
 }
 
+impl PartialEq for Datasets {
+    fn eq(&self, other: &Self) -> bool {
+        self.len() == other.len() && self.clone().into_iter().all(|d| other.contains(&d))
+    }
+}
+
+impl Eq for Datasets {}
+
 impl IntoIterator for Datasets {
     type Item = Idx;
     type IntoIter = Box<dyn Iterator<Item = Self::Item>>;
@@ -300,7 +1087,7 @@ impl Datasets {
             Some(Self::Empty)
         } else {
             // Many
-            Some(Self::Many(RoaringBitmap::deserialize_from(slice).unwrap()))
+            RoaringBitmap::deserialize_from(slice).ok().map(Self::Many)
         }
     }
 
@@ -344,6 +1131,29 @@ impl Datasets {
         }
     }
 
+    #[cfg(all(feature = "branchwater", not(target_arch = "wasm32")))]
+    fn remove_all(&mut self, other: &Datasets) {
+        match self {
+            Datasets::Empty => (),
+            Datasets::Unique(v) => {
+                if other.contains(v) {
+                    *self = Datasets::Empty
+                }
+            }
+            Datasets::Many(ref mut v) => {
+                for idx in other.clone() {
+                    v.remove(idx);
+                }
+                // keep the same representation as Datasets::new
+                match v.len() {
+                    0 => *self = Datasets::Empty,
+                    1 => *self = Datasets::Unique(v.min().unwrap()),
+                    _ => (),
+                }
+            }
+        }
+    }
+
     fn len(&self) -> usize {
         match self {
             Self::Empty => 0,
@@ -361,6 +1171,53 @@ impl Datasets {
     }
 }
 
+/// Result of a batch hash lookup: the datasets containing each hash, and
+/// all datasets containing at least one of the hashes.
+#[derive(Getters, Debug, Clone, PartialEq, Eq)]
+pub struct HashPresence {
+    #[getset(get = "pub")]
+    hashes: Vec<HashIntoType>,
+
+    #[getset(get = "pub")]
+    datasets: Vec<Idx>,
+
+    /// Datasets containing each hash, in the same order as `hashes`.
+    #[getset(get = "pub")]
+    found: Vec<Datasets>,
+}
+
+impl HashPresence {
+    fn new(hashes: &[HashIntoType], found: Vec<Datasets>) -> Self {
+        let mut datasets: Vec<Idx> = found.iter().cloned().flatten().collect();
+        datasets.sort_unstable();
+        datasets.dedup();
+
+        Self {
+            hashes: hashes.to_vec(),
+            datasets,
+            found,
+        }
+    }
+
+    /// Is the `i`-th hash present in `dataset_id`?
+    pub fn contains(&self, i: usize, dataset_id: Idx) -> bool {
+        self.found[i].contains(&dataset_id)
+    }
+
+    /// Number of datasets containing each hash.
+    pub fn counts(&self) -> Vec<usize> {
+        self.found.iter().map(|datasets| datasets.len()).collect()
+    }
+
+    /// Records for `datasets`.
+    pub fn records<'a>(&self, collection: &'a CollectionSet) -> Result<Vec<&'a Record>> {
+        self.datasets
+            .iter()
+            .map(|dataset_id| collection.record_for_dataset(*dataset_id))
+            .collect()
+    }
+}
+
 #[derive(Getters, Setters, Debug)]
 pub struct DbStats {
     #[getset(get = "pub")]
@@ -379,6 +1236,7 @@ pub struct DbStats {
     vcounts: histogram::Histogram,
 }
 
+#[cfg(all(feature = "branchwater", not(target_arch = "wasm32")))]
 fn stats_for_cf(db: Arc<DB>, cf_name: &str, deep_check: bool, quick: bool) -> DbStats {
     use byteorder::ReadBytesExt;
     use histogram::Histogram;
@@ -418,6 +1276,7 @@ fn stats_for_cf(db: Arc<DB>, cf_name: &str, deep_check: bool, quick: bool) -> Db
 }
 
 #[cfg(test)]
+#[cfg(all(feature = "branchwater", not(target_arch = "wasm32")))]
 mod test {
     use camino::Utf8PathBuf as PathBuf;
     use tempfile::TempDir;
@@ -515,29 +1374,481 @@ mod test {
     }
 
     #[test]
-    fn revindex_load_and_gather() -> Result<()> {
+    fn revindex_remove_and_replace() -> Result<()> {
         let mut basedir = PathBuf::from(env!("CARGO_MANIFEST_DIR"));
         basedir.push("../../tests/test-data/scaled/");
 
         let siglist: Vec<_> = (10..=12)
-            .map(|i| {
-                let mut filename = basedir.clone();
-                filename.push(format!("genome-s{}.fa.gz.sig", i));
-                filename
-            })
+            .map(|i| basedir.join(format!("genome-s{}.fa.gz.sig", i)))
             .collect();
 
         let selection = Selection::builder().ksize(31).scaled(10000).build();
         let output = TempDir::new()?;
 
-        let mut query = None;
-        let query_sig = Signature::from_path(&siglist[0])?
+        let query_sig = Signature::from_path(&siglist[1])?
             .swap_remove(0)
             .select(&selection)?;
-        if let Some(q) = prepare_query(query_sig, &selection) {
-            query = Some(q);
-        }
-        let query = query.unwrap();
+        let query = prepare_query(query_sig, &selection).unwrap();
+
+        let collection = Collection::from_paths(&siglist)?.select(&selection)?;
+        let md5 = collection.record_for_dataset(1)?.md5().clone();
+        let mut index = RevIndex::create(output.path(), collection.try_into()?, false)?;
+
+        let counter = index.counter_for_query(&query);
+        assert_eq!(counter.get(&1), Some(&45));
+
+        assert!(matches!(
+            index.remove_datasets(&["not-a-dataset"]),
+            Err(crate::Error::DatasetNotFound { .. })
+        ));
+        assert_eq!(index.remove_datasets(&[md5.as_str()])?, [1]);
+        // already removed
+        assert!(index.remove_datasets(&[md5.as_str()]).is_err());
+
+        let counter = index.counter_for_query(&query);
+        assert_eq!(counter.get(&1), None);
+        let ids: Vec<_> = index.collection().iter().map(|(id, _)| id).collect();
+        assert_eq!(ids, [0, 2]);
+        assert!(index.collection().record_for_dataset(1)?.removed());
+        drop(index);
+
+        // tombstones survive reopening, and ids are stable
+        let index = RevIndex::open(output.path(), false, None)?;
+        assert_eq!(index.collection().len(), 3);
+        let ids: Vec<_> = index.collection().iter().map(|(id, _)| id).collect();
+        assert_eq!(ids, [0, 2]);
+        assert_eq!(*index.check(false).total_datasets(), 2);
+        assert!(index.counter_for_query(&query).get(&1).is_none());
+
+        // replace with a new copy of the same dataset, appended at the end
+        let output = TempDir::new()?;
+        let collection = Collection::from_paths(&siglist)?.select(&selection)?;
+        let index = RevIndex::create(output.path(), collection.try_into()?, false)?;
+
+        let mut new_siglist = siglist.clone();
+        new_siglist.push(siglist[1].clone());
+        let new_collection = Collection::from_paths(&new_siglist)?.select(&selection)?;
+        let index = index.replace_datasets(&[md5.as_str()], new_collection.try_into()?)?;
+
+        let counter = index.counter_for_query(&query);
+        assert_eq!(counter.get(&1), None);
+        assert_eq!(counter.get(&3), Some(&45));
+        let ids: Vec<_> = index.collection().iter().map(|(id, _)| id).collect();
+        assert_eq!(ids, [0, 2, 3]);
+        assert_eq!(*index.check(false).total_datasets(), 3);
+
+        Ok(())
+    }
+
+    #[test]
+    fn revindex_verify_and_repair() -> Result<()> {
+        use byteorder::{LittleEndian, WriteBytesExt};
+
+        use super::{Datasets, IntegrityError, MANIFEST, PROCESSED};
+        use crate::storage::rocksdb::{cf_descriptors, db_options, DB, HASHES, METADATA};
+
+        let mut basedir = PathBuf::from(env!("CARGO_MANIFEST_DIR"));
+        basedir.push("../../tests/test-data/scaled/");
+
+        let siglist: Vec<_> = (10..=12)
+            .map(|i| basedir.join(format!("genome-s{}.fa.gz.sig", i)))
+            .collect();
+
+        let selection = Selection::builder().ksize(31).scaled(10000).build();
+        let output = TempDir::new()?;
+        let collection = Collection::from_paths(&siglist)?.select(&selection)?;
+
+        let index = RevIndex::create(output.path(), collection.clone().try_into()?, false)?;
+        assert_eq!(index.verify()?, []);
+        drop(index);
+
+        let hashes_for = |i: usize| -> Result<Vec<u64>> {
+            let sig = Signature::from_path(&siglist[i])?
+                .swap_remove(0)
+                .select(&selection)?;
+            Ok(prepare_query(sig, &selection).unwrap().mins())
+        };
+
+        // Drop `dataset` from the first `n` of its hashes.
+        let unindex = |db: &DB, dataset: u32, n: usize| -> Result<()> {
+            let cf_hashes = db.cf_handle(HASHES).unwrap();
+            for hash in hashes_for(dataset as usize)?.into_iter().take(n) {
+                let mut key = vec![];
+                key.write_u64::<LittleEndian>(hash)?;
+                let mut datasets = Datasets::from_slice(&db.get_cf(&cf_hashes, &key)?.unwrap())
+                    .expect("valid datasets");
+                datasets.remove_all(&Datasets::new(&[dataset]));
+                if datasets.len() == 0 {
+                    db.delete_cf(&cf_hashes, &key)?;
+                } else {
+                    db.put_cf(&cf_hashes, &key, datasets.as_bytes().unwrap())?;
+                }
+            }
+            Ok(())
+        };
+
+        // simulate a crash while indexing the last dataset
+        {
+            let db = DB::open_cf_descriptors(&db_options(), output.path(), cf_descriptors())?;
+            unindex(&db, 2, usize::MAX)?;
+            let cf_metadata = db.cf_handle(METADATA).unwrap();
+            db.delete_cf(&cf_metadata, MANIFEST)?;
+            db.put_cf(
+                &cf_metadata,
+                PROCESSED,
+                Datasets::new(&[0, 1]).as_bytes().unwrap(),
+            )?;
+        }
+
+        assert!(matches!(
+            RevIndex::open(output.path(), false, None),
+            Err(crate::Error::IntegrityError(IntegrityError::MissingMetadata(key))) if key == MANIFEST
+        ));
+
+        // resume indexing
+        let index = RevIndex::create(output.path(), collection.try_into()?, false)?;
+        assert_eq!(index.verify()?, []);
+        drop(index);
+
+        // lose some hashes for a processed dataset
+        {
+            let db = DB::open_cf_descriptors(&db_options(), output.path(), cf_descriptors())?;
+            unindex(&db, 1, 5)?;
+        }
+
+        let mut index = RevIndex::open(output.path(), false, None)?;
+        let problems = index.verify()?;
+        assert_eq!(
+            problems,
+            [IntegrityError::HashCountMismatch {
+                dataset: 1,
+                expected: hashes_for(1)?.len(),
+                found: hashes_for(1)?.len() - 5,
+            }]
+        );
+
+        let datasets: Vec<_> = problems.iter().filter_map(|p| p.dataset()).collect();
+        index.repair(&datasets)?;
+        assert_eq!(index.verify()?, []);
+
+        Ok(())
+    }
+
+    #[test]
+    fn revindex_template() -> Result<()> {
+        use crate::encodings::HashFunctions;
+        use crate::index::SearchType;
+        use crate::signature::SigsTrait;
+        use crate::sketch::minhash::KmerMinHash;
+        use crate::sketch::Sketch;
+
+        let mut basedir = PathBuf::from(env!("CARGO_MANIFEST_DIR"));
+        basedir.push("../../tests/test-data/scaled/");
+
+        let siglist: Vec<_> = (10..=12)
+            .map(|i| basedir.join(format!("genome-s{}.fa.gz.sig", i)))
+            .collect();
+
+        let selection = Selection::builder().ksize(31).scaled(10000).build();
+        let collection = Collection::from_paths(&siglist)?.select(&selection)?;
+
+        for colors in [false, true] {
+            let output = TempDir::new()?;
+
+            // templates are built from the first dataset, if there is one
+            let empty = Collection::from_sigs(vec![])?;
+            let index = RevIndex::create(output.path(), empty.try_into()?, colors)?;
+            assert!(index.template().is_none());
+            let index = index.update(collection.clone().try_into()?)?;
+            drop(index);
+
+            let index = RevIndex::open(output.path(), true, None)?;
+            if let Some(Sketch::MinHash(template)) = index.template() {
+                assert_eq!(template.ksize(), 31);
+                assert_eq!(template.scaled(), 10000);
+                assert_eq!(template.hash_function(), HashFunctions::Murmur64Dna);
+                assert_eq!(template.size(), 0);
+            } else {
+                panic!("missing template");
+            }
+
+            let query = KmerMinHash::new(10000, 21, HashFunctions::Murmur64Dna, 42, false, 0);
+            let (counter, query_colors, hash_to_color) = index.prepare_gather_counters(&query);
+            assert!(matches!(
+                index.gather(counter, query_colors, hash_to_color, 0, &query, None),
+                Err(crate::Error::MismatchKSizes)
+            ));
+
+            let query = KmerMinHash::new(10000, 31, HashFunctions::Murmur64Protein, 42, false, 0);
+            let counter = index.counter_for_query(&query);
+            assert!(matches!(
+                index.search_matches(counter, &query, SearchType::Jaccard, 0.1),
+                Err(crate::Error::MismatchDNAProt)
+            ));
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    fn check_query_against_template() -> Result<()> {
+        use crate::encodings::HashFunctions;
+        use crate::sketch::minhash::KmerMinHash;
+        use crate::sketch::Sketch;
+
+        let mut filename = PathBuf::from(env!("CARGO_MANIFEST_DIR"));
+        filename.push("../../tests/test-data/scaled/genome-s10.fa.gz.sig");
+
+        let selection = Selection::builder().ksize(31).scaled(10000).build();
+        let query_sig = Signature::from_path(filename)?
+            .swap_remove(0)
+            .select(&selection)?;
+        let query = prepare_query(query_sig, &selection).unwrap();
+
+        let template = |scaled, seed, num| {
+            Sketch::MinHash(KmerMinHash::new(
+                scaled,
+                31,
+                HashFunctions::Murmur64Dna,
+                seed,
+                false,
+                num,
+            ))
+        };
+
+        // same scaled: unchanged
+        let checked = super::check_query(&template(10000, 42, 0), &query)?;
+        assert_eq!(checked.mins(), query.mins());
+
+        // larger scaled in the index: downsampled
+        let checked = super::check_query(&template(20000, 42, 0), &query)?;
+        assert_eq!(checked.scaled(), 20000);
+        assert_eq!(
+            checked.mins(),
+            query.clone().downsample_scaled(20000)?.mins()
+        );
+
+        // smaller scaled in the index: still compatible
+        let checked = super::check_query(&template(1000, 42, 0), &query)?;
+        assert_eq!(checked.scaled(), 10000);
+
+        assert!(matches!(
+            super::check_query(&template(10000, 43, 0), &query),
+            Err(crate::Error::MismatchSeed)
+        ));
+        assert!(matches!(
+            super::check_query(&template(0, 42, 500), &query),
+            Err(crate::Error::MismatchNum { n1: 500, n2: 0 })
+        ));
+
+        Ok(())
+    }
+
+    #[test]
+    fn revindex_lookup_hashes() -> Result<()> {
+        use crate::collection::CollectionSet;
+        use crate::encodings::Idx;
+        use crate::sketch::minhash::KmerMinHash;
+
+        let mut basedir = PathBuf::from(env!("CARGO_MANIFEST_DIR"));
+        basedir.push("../../tests/test-data/gather/");
+
+        let against: Vec<_> = [
+            "GCF_000006945.2_ASM694v2_genomic.fna.gz.sig",
+            "GCF_000007545.1_ASM754v1_genomic.fna.gz.sig",
+            "GCF_000008105.1_ASM810v1_genomic.fna.gz.sig",
+            "GCF_000008545.1_ASM854v1_genomic.fna.gz.sig",
+        ]
+        .iter()
+        .map(|sig| basedir.join(sig))
+        .collect();
+
+        let selection = Selection::builder().ksize(21).scaled(10000).build();
+        let collection: CollectionSet = Collection::from_paths(&against)?
+            .select(&selection)?
+            .try_into()?;
+
+        let mut sketches: Vec<KmerMinHash> = vec![];
+        for (dataset_id, _) in collection.iter() {
+            sketches.push(collection.sig_for_dataset(dataset_id)?.try_into()?);
+        }
+
+        let query_sig = Signature::from_path(basedir.join("combined.sig"))?
+            .swap_remove(0)
+            .select(&selection)?;
+        let query = prepare_query(query_sig, &selection).unwrap();
+        // include a hash not present in any dataset
+        let mut hashes = query.mins();
+        hashes.push(0);
+
+        let output = TempDir::new()?;
+        let color_output = TempDir::new()?;
+        let indices = [
+            RevIndex::create(output.path(), collection.clone(), false)?,
+            RevIndex::create(color_output.path(), collection.clone(), true)?,
+            RevIndex::Mem(super::mem_revindex::RevIndex::from_collection(
+                collection.clone(),
+            )?),
+        ];
+
+        for index in indices {
+            let presence = index.lookup_hashes(&hashes)?;
+            assert_eq!(presence.hashes(), &hashes);
+            assert_eq!(presence.datasets(), &[0, 1, 2, 3]);
+            assert_eq!(presence.found().len(), hashes.len());
+            assert_eq!(presence.counts().last(), Some(&0));
+            assert_eq!(presence.records(index.collection())?.len(), 4);
+
+            for (i, hash) in hashes.iter().enumerate() {
+                let records = index.lookup_hash(*hash)?;
+                let mut expected = vec![];
+                for (dataset_id, sketch) in sketches.iter().enumerate() {
+                    let present = sketch.mins().binary_search(hash).is_ok();
+                    assert_eq!(presence.contains(i, dataset_id as Idx), present);
+                    if present {
+                        expected.push(index.collection().record_for_dataset(dataset_id as Idx)?);
+                    }
+                }
+                assert_eq!(records, expected);
+                assert_eq!(presence.counts()[i], expected.len());
+            }
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    fn revindex_merge_and_shard() -> Result<()> {
+        use super::{combine_gather_counters, ShardBy};
+
+        let mut basedir = PathBuf::from(env!("CARGO_MANIFEST_DIR"));
+        basedir.push("../../tests/test-data/gather/");
+
+        let against: Vec<_> = [
+            "GCF_000006945.2_ASM694v2_genomic.fna.gz.sig",
+            "GCF_000007545.1_ASM754v1_genomic.fna.gz.sig",
+            "GCF_000008105.1_ASM810v1_genomic.fna.gz.sig",
+            "GCF_000008545.1_ASM854v1_genomic.fna.gz.sig",
+        ]
+        .iter()
+        .map(|sig| basedir.join(sig))
+        .collect();
+
+        let selection = Selection::builder().ksize(21).scaled(10000).build();
+        let query_sig = Signature::from_path(basedir.join("combined.sig"))?
+            .swap_remove(0)
+            .select(&selection)?;
+        let query = prepare_query(query_sig, &selection).unwrap();
+
+        let summary = |index: &RevIndex| -> Result<Vec<(String, u64)>> {
+            let (counter, query_colors, hash_to_color) = index.prepare_gather_counters(&query);
+            Ok(index
+                .gather(counter, query_colors, hash_to_color, 0, &query, None)?
+                .into_iter()
+                .map(|m| (m.name().clone(), m.intersect_bp()))
+                .collect())
+        };
+
+        let full_output = TempDir::new()?;
+        let collection = Collection::from_paths(&against)?.select(&selection)?;
+        let mut full = RevIndex::create(full_output.path(), collection.try_into()?, false)?;
+        let expected = summary(&full)?;
+        assert_eq!(expected.len(), 4);
+
+        // merge two halves, one of them with internal storage
+        let a_output = TempDir::new()?;
+        let collection = Collection::from_paths(&against[..2])?.select(&selection)?;
+        let mut a = RevIndex::create(a_output.path(), collection.try_into()?, false)?;
+        a.internalize_storage()?;
+
+        let b_output = TempDir::new()?;
+        let collection = Collection::from_paths(&against[2..])?.select(&selection)?;
+        let b = RevIndex::create(b_output.path(), collection.try_into()?, false)?;
+
+        let merged_output = TempDir::new()?;
+        let merged = RevIndex::merge(&[&a, &b], merged_output.path())?;
+        assert_eq!(merged.collection().len(), 4);
+        assert_eq!(merged.verify()?, vec![]);
+        assert_eq!(summary(&merged)?, expected);
+        drop(merged);
+
+        let merged = RevIndex::open(merged_output.path(), true, None)?;
+        assert_eq!(merged.verify()?, vec![]);
+        assert_eq!(summary(&merged)?, expected);
+
+        // shards of an index with internal storage can be used on their own
+        full.internalize_storage()?;
+        for by in [ShardBy::Hashes, ShardBy::Datasets] {
+            let outputs = [TempDir::new()?, TempDir::new()?, TempDir::new()?];
+            let paths: Vec<_> = outputs.iter().map(|o| o.path()).collect();
+            let shards = full.shard(&paths, by)?;
+            assert_eq!(shards.len(), 3);
+            drop(shards);
+
+            let shards = paths
+                .iter()
+                .map(|path| RevIndex::open(path, true, None))
+                .collect::<Result<Vec<_>>>()?;
+
+            let mut hashes = 0;
+            for shard in &shards {
+                assert_eq!(shard.verify()?, vec![]);
+                assert_eq!(shard.collection().len(), 4);
+                hashes += shard.counter_for_query(&query).values().sum::<usize>();
+            }
+            assert_eq!(
+                hashes,
+                full.counter_for_query(&query).values().sum::<usize>()
+            );
+
+            let (counter, query_colors, hash_to_color) = combine_gather_counters(
+                shards
+                    .iter()
+                    .map(|shard| shard.prepare_gather_counters(&query)),
+            );
+            let matches: Vec<_> = shards[1]
+                .gather(counter, query_colors, hash_to_color, 0, &query, None)?
+                .into_iter()
+                .map(|m| (m.name().clone(), m.intersect_bp()))
+                .collect();
+            assert_eq!(matches, expected);
+
+            assert!(shards[0].shard(&[TempDir::new()?.path()], by).is_err());
+            assert!(RevIndex::merge(&[&shards[0], &shards[1]], TempDir::new()?.path()).is_err());
+
+            let color_output = TempDir::new()?;
+            let empty = Collection::from_sigs(vec![])?;
+            let color_index = RevIndex::create(color_output.path(), empty.try_into()?, true)?;
+            assert!(shards[0].convert(color_index).is_err());
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    fn revindex_load_and_gather() -> Result<()> {
+        let mut basedir = PathBuf::from(env!("CARGO_MANIFEST_DIR"));
+        basedir.push("../../tests/test-data/scaled/");
+
+        let siglist: Vec<_> = (10..=12)
+            .map(|i| {
+                let mut filename = basedir.clone();
+                filename.push(format!("genome-s{}.fa.gz.sig", i));
+                filename
+            })
+            .collect();
+
+        let selection = Selection::builder().ksize(31).scaled(10000).build();
+        let output = TempDir::new()?;
+
+        let mut query = None;
+        let query_sig = Signature::from_path(&siglist[0])?
+            .swap_remove(0)
+            .select(&selection)?;
+        if let Some(q) = prepare_query(query_sig, &selection) {
+            query = Some(q);
+        }
+        let query = query.unwrap();
 
         {
             let collection = Collection::from_paths(&siglist)?.select(&selection)?;
@@ -685,7 +1996,7 @@ mod test {
         assert_eq!(round5(match_.f_match()), round5(0.125799573560768));
         assert_eq!(
             round5(match_.f_unique_to_query()),
-            round5(0.04024556616643930)
+            round5(0.0402455661664393)
         );
 
         let match_ = &matches[8];
@@ -705,7 +2016,7 @@ mod test {
         assert_eq!(round5(match_.f_match()), round5(0.0725995316159251));
         assert_eq!(
             round5(match_.f_unique_to_query()),
-            round5(0.021145975443383400)
+            round5(0.0211459754433834)
         );
 
         let match_ = &matches[10];
@@ -715,7 +2026,7 @@ mod test {
         assert_eq!(round5(match_.f_match()), round5(0.0148619957537155));
         assert_eq!(
             round5(match_.f_unique_to_query()),
-            round5(0.0047748976807639800)
+            round5(0.00477489768076398)
         );
 
         Ok(())
@@ -728,7 +2039,7 @@ mod test {
         let mut basedir = PathBuf::from(env!("CARGO_MANIFEST_DIR"));
         basedir.push("../../tests/test-data/gather/");
 
-        let against = vec![
+        let against = [
             "GCF_000016785.1_ASM1678v1_genomic.fna.gz.sig",
             "GCF_000018945.1_ASM1894v1_genomic.fna.gz.sig",
             "GCF_000008545.1_ASM854v1_genomic.fna.gz.sig",
@@ -813,6 +2124,529 @@ mod test {
         Ok(())
     }
 
+    #[test]
+    fn revindex_gather_same_as_linear() -> Result<()> {
+        use crate::index::linear::LinearIndex;
+
+        let mut basedir = PathBuf::from(env!("CARGO_MANIFEST_DIR"));
+        basedir.push("../../tests/test-data/");
+
+        let against: Vec<_> = [
+            "GCF_000006945.2_ASM694v2_genomic.fna.gz.sig",
+            "GCF_000007545.1_ASM754v1_genomic.fna.gz.sig",
+            "GCF_000008105.1_ASM810v1_genomic.fna.gz.sig",
+            "GCF_000008545.1_ASM854v1_genomic.fna.gz.sig",
+            "GCF_000009085.1_ASM908v1_genomic.fna.gz.sig",
+            "GCF_000016785.1_ASM1678v1_genomic.fna.gz.sig",
+            "GCF_000018945.1_ASM1894v1_genomic.fna.gz.sig",
+        ]
+        .iter()
+        .map(|sig| basedir.join("gather").join(sig))
+        .collect();
+
+        let cases = [
+            (
+                Collection::from_paths(&against)?,
+                basedir.join("gather/combined.sig"),
+                Selection::builder().ksize(21).scaled(10000).build(),
+            ),
+            (
+                Collection::from_zipfile(basedir.join("track_abund/track_abund.zip"))?,
+                basedir.join("track_abund/47.fa.sig"),
+                Selection::builder().ksize(31).scaled(1000).build(),
+            ),
+        ];
+
+        for (collection, query_path, selection) in cases {
+            let collection = collection.select(&selection)?;
+            let query_sig = Signature::from_path(query_path)?
+                .swap_remove(0)
+                .select(&selection)?;
+            let query = prepare_query(query_sig, &selection).unwrap();
+
+            let output = TempDir::new()?;
+            let index = RevIndex::create(output.path(), collection.clone().try_into()?, false)?;
+            let linear = LinearIndex::from_collection(collection.clone().try_into()?);
+
+            let (counter, query_colors, hash_to_color) = index.prepare_gather_counters(&query);
+            let matches = index.gather(
+                counter,
+                query_colors,
+                hash_to_color,
+                0,
+                &query,
+                Some(selection),
+            )?;
+
+            let mem_index = RevIndex::Mem(super::mem_revindex::RevIndex::from_collection(
+                collection.clone().try_into()?,
+            )?);
+            let (counter, query_colors, hash_to_color) = mem_index.prepare_gather_counters(&query);
+            let mem_matches =
+                mem_index.gather(counter, query_colors, hash_to_color, 0, &query, None)?;
+
+            let color_output = TempDir::new()?;
+            let color_index =
+                RevIndex::create(color_output.path(), collection.clone().try_into()?, true)?;
+            let (counter, query_colors, hash_to_color) =
+                color_index.prepare_gather_counters(&query);
+            let color_matches =
+                color_index.gather(counter, query_colors, hash_to_color, 0, &query, None)?;
+            assert_eq!(
+                color_index.counter_for_query(&query),
+                index.counter_for_query(&query)
+            );
+
+            let counter = linear.counter_for_query(&query);
+            let linear_matches = linear.gather(counter, 0, &query)?;
+
+            assert!(!matches.is_empty());
+            assert_eq!(matches, linear_matches);
+            assert_eq!(mem_matches, linear_matches);
+            assert_eq!(color_matches, linear_matches);
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    fn revindex_gather_weighted() -> Result<()> {
+        use std::cmp::max;
+        use std::collections::HashMap;
+
+        let mut basedir = PathBuf::from(env!("CARGO_MANIFEST_DIR"));
+        basedir.push("../../tests/test-data/gather-abund/");
+
+        let selection = Selection::builder().ksize(21).scaled(1000).build();
+        let mut sigs = vec![];
+        for name in ["genome-s10", "genome-s11", "genome-s12"] {
+            let path = basedir.join(format!("{name}.fa.gz.sig"));
+            sigs.extend(Signature::from_path(path)?);
+        }
+        let collection = Collection::from_sigs(sigs)?.select(&selection)?;
+        // s10 at 10x coverage, s11 at 1x
+        let query_sig = Signature::from_path(basedir.join("reads-s10x10-s11.sig"))?
+            .swap_remove(0)
+            .select(&selection)?;
+        let query = prepare_query(query_sig, &selection).unwrap();
+        assert!(query.track_abundance());
+
+        // query abundance shared with each dataset
+        let abunds: HashMap<u64, u64> = query.to_vec_abunds().into_iter().collect();
+        let mut best_weight = 0;
+        for (dataset_id, _) in collection.iter() {
+            let sig = collection.sig_for_dataset(dataset_id)?;
+            let weight: u64 = sig
+                .minhash()
+                .unwrap()
+                .iter_mins()
+                .filter_map(|hash| abunds.get(hash))
+                .sum();
+            best_weight = max(best_weight, weight);
+        }
+
+        let output = TempDir::new()?;
+        let indices = [
+            RevIndex::create(output.path(), collection.clone().try_into()?, false)?,
+            RevIndex::Mem(super::mem_revindex::RevIndex::from_collection(
+                collection.clone().try_into()?,
+            )?),
+        ];
+
+        for index in indices {
+            let (counter, query_colors, hash_to_color) = index.prepare_gather_counters(&query);
+            let unweighted = index.gather(counter, query_colors, hash_to_color, 0, &query, None)?;
+            let (counter, query_colors, hash_to_color) = index.prepare_gather_counters(&query);
+            let weighted =
+                index.gather_weighted(counter, query_colors, hash_to_color, 0, 0.0, &query)?;
+
+            // greedy selection by weighted overlap
+            assert_eq!(weighted[0].n_unique_weighted_found(), best_weight);
+            for pair in weighted.windows(2) {
+                assert!(pair[0].f_unique_weighted() >= pair[1].f_unique_weighted());
+            }
+            // everything in the index is still found
+            assert_eq!(
+                weighted.last().unwrap().sum_weighted_found(),
+                unweighted.last().unwrap().sum_weighted_found()
+            );
+
+            // stop once the best match explains too little of the query
+            let threshold_f_weighted = weighted[0].f_unique_weighted() / 2.0;
+            let expected = weighted
+                .iter()
+                .take_while(|m| m.f_unique_weighted() >= threshold_f_weighted)
+                .count();
+            assert!(expected < weighted.len());
+            let (counter, query_colors, hash_to_color) = index.prepare_gather_counters(&query);
+            let stopped = index.gather_weighted(
+                counter,
+                query_colors,
+                hash_to_color,
+                0,
+                threshold_f_weighted,
+                &query,
+            )?;
+            assert_eq!(stopped.len(), expected);
+        }
+
+        // a query with a smaller scaled is downsampled to the index first
+        let coarse = Selection::builder().ksize(21).scaled(2000).build();
+        let index = RevIndex::Mem(super::mem_revindex::RevIndex::from_collection(
+            collection.select(&coarse)?.try_into()?,
+        )?);
+        let downsampled = query.clone().downsample_scaled(2000)?;
+        let (counter, query_colors, hash_to_color) = index.prepare_gather_counters(&downsampled);
+        let expected =
+            index.gather_weighted(counter, query_colors, hash_to_color, 0, 0.1, &downsampled)?;
+        let (counter, query_colors, hash_to_color) = index.prepare_gather_counters(&query);
+        let weighted =
+            index.gather_weighted(counter, query_colors, hash_to_color, 0, 0.1, &query)?;
+        assert!(!expected.is_empty());
+        assert_eq!(weighted, expected);
+
+        // counters built for another query are an error, not a panic
+        let (counter, query_colors, hash_to_color) = index.prepare_gather_counters(&downsampled);
+        let mut partial = downsampled.clone();
+        partial.remove_many(hash_to_color.0.keys().take(10).copied())?;
+        assert!(matches!(
+            index.gather_weighted(counter, query_colors, hash_to_color, 0, 0.1, &partial),
+            Err(crate::Error::Internal { .. })
+        ));
+
+        Ok(())
+    }
+
+    #[test]
+    fn revindex_convert_to_color() -> Result<()> {
+        let mut basedir = PathBuf::from(env!("CARGO_MANIFEST_DIR"));
+        basedir.push("../../tests/test-data/gather/");
+
+        let against: Vec<_> = [
+            "GCF_000006945.2_ASM694v2_genomic.fna.gz.sig",
+            "GCF_000007545.1_ASM754v1_genomic.fna.gz.sig",
+            "GCF_000008105.1_ASM810v1_genomic.fna.gz.sig",
+            "GCF_000008545.1_ASM854v1_genomic.fna.gz.sig",
+            "GCF_000009085.1_ASM908v1_genomic.fna.gz.sig",
+            "GCF_000016785.1_ASM1678v1_genomic.fna.gz.sig",
+            "GCF_000018945.1_ASM1894v1_genomic.fna.gz.sig",
+        ]
+        .iter()
+        .map(|sig| basedir.join(sig))
+        .collect();
+
+        let selection = Selection::builder().ksize(21).scaled(10000).build();
+        let collection = Collection::from_paths(&against)?.select(&selection)?;
+        let query_sig = Signature::from_path(basedir.join("combined.sig"))?
+            .swap_remove(0)
+            .select(&selection)?;
+        let query = prepare_query(query_sig, &selection).unwrap();
+
+        let output = TempDir::new()?;
+        let mut index = RevIndex::create(output.path(), collection.try_into()?, false)?;
+        index.internalize_storage()?;
+
+        let color_output = TempDir::new()?;
+        {
+            let empty = Collection::from_sigs(vec![])?;
+            let color_index = RevIndex::create(color_output.path(), empty.try_into()?, true)?;
+            index.convert(color_index)?;
+        }
+
+        let color_index = RevIndex::open(color_output.path(), true, None)?;
+        assert!(matches!(color_index, RevIndex::Color(_)));
+        assert_eq!(color_index.collection().len(), index.collection().len());
+        assert_eq!(
+            color_index.collection().storage().spec(),
+            format!("rocksdb://{}", color_output.path().display())
+        );
+
+        let stats = index.check(false);
+        let color_stats = color_index.check(false);
+        assert_eq!(color_stats.total_keys(), stats.total_keys());
+        assert_eq!(color_stats.total_datasets(), stats.total_datasets());
+
+        let (counter, query_colors, hash_to_color) = index.prepare_gather_counters(&query);
+        let matches = index.gather(counter, query_colors, hash_to_color, 0, &query, None)?;
+
+        let (counter, query_colors, hash_to_color) = color_index.prepare_gather_counters(&query);
+        let color_matches =
+            color_index.gather(counter, query_colors, hash_to_color, 0, &query, None)?;
+
+        assert_eq!(matches.len(), 7);
+        assert_eq!(color_matches, matches);
+
+        Ok(())
+    }
+
+    #[test]
+    fn revindex_convert_after_remove() -> Result<()> {
+        let mut basedir = PathBuf::from(env!("CARGO_MANIFEST_DIR"));
+        basedir.push("../../tests/test-data/scaled/");
+
+        let siglist: Vec<_> = (10..=12)
+            .map(|i| basedir.join(format!("genome-s{}.fa.gz.sig", i)))
+            .collect();
+
+        let selection = Selection::builder().ksize(31).scaled(10000).build();
+        let query_sig = Signature::from_path(&siglist[1])?
+            .swap_remove(0)
+            .select(&selection)?;
+        let query = prepare_query(query_sig, &selection).unwrap();
+
+        let collection = Collection::from_paths(&siglist)?.select(&selection)?;
+        let md5 = collection.record_for_dataset(1)?.md5().clone();
+
+        let output = TempDir::new()?;
+        let mut index = RevIndex::create(output.path(), collection.try_into()?, false)?;
+        index.remove_datasets(&[md5.as_str()])?;
+
+        let color_output = TempDir::new()?;
+        {
+            let empty = Collection::from_sigs(vec![])?;
+            let color_index = RevIndex::create(color_output.path(), empty.try_into()?, true)?;
+            index.convert(color_index)?;
+        }
+
+        // the tombstone is carried over with the rest of the metadata
+        let color_index = RevIndex::open(color_output.path(), true, None)?;
+        assert_eq!(color_index.verify()?, []);
+        assert_eq!(*color_index.check(false).total_datasets(), 2);
+        assert!(color_index.counter_for_query(&query).get(&1).is_none());
+
+        Ok(())
+    }
+
+    #[test]
+    fn revindex_color_update() -> Result<()> {
+        let mut basedir = PathBuf::from(env!("CARGO_MANIFEST_DIR"));
+        basedir.push("../../tests/test-data/scaled/");
+
+        let siglist: Vec<_> = (10..=12)
+            .map(|i| basedir.join(format!("genome-s{}.fa.gz.sig", i)))
+            .collect();
+
+        let selection = Selection::builder().ksize(31).scaled(10000).build();
+        let output = TempDir::new()?;
+
+        {
+            let collection = Collection::from_paths(&siglist[..2])?.select(&selection)?;
+            RevIndex::create(output.path(), collection.try_into()?, true)?;
+        }
+
+        let query_sig = Signature::from_path(&siglist[2])?
+            .swap_remove(0)
+            .select(&selection)?;
+        let query = prepare_query(query_sig, &selection).unwrap();
+
+        let new_collection = Collection::from_paths(&siglist)?.select(&selection)?;
+        let index =
+            RevIndex::open(output.path(), false, None)?.update(new_collection.try_into()?)?;
+        assert!(matches!(index, RevIndex::Color(_)));
+        assert_eq!(index.verify()?, []);
+
+        let counter = index.counter_for_query(&query);
+        let matches = index.matches_from_counter(counter, 0);
+
+        assert!(matches[0].0.ends_with("/genome-s12.fa.gz"));
+        assert_eq!(matches[0].1, 45);
+        assert_eq!(*index.check(false).total_datasets(), 3);
+
+        Ok(())
+    }
+
+    #[test]
+    fn revindex_color_update_removes_unused_colors() -> Result<()> {
+        let mut basedir = PathBuf::from(env!("CARGO_MANIFEST_DIR"));
+        basedir.push("../../tests/test-data/scaled/");
+
+        // genome-s10+s11 shares all its hashes with the first two
+        let siglist: Vec<_> = [
+            "genome-s10.fa.gz.sig",
+            "genome-s11.fa.gz.sig",
+            "genome-s10+s11.fa.gz.sig",
+        ]
+        .iter()
+        .map(|sig| basedir.join(sig))
+        .collect();
+
+        let selection = Selection::builder().ksize(31).scaled(10000).build();
+        let output = TempDir::new()?;
+
+        {
+            let collection = Collection::from_paths(&siglist[..2])?.select(&selection)?;
+            RevIndex::create(output.path(), collection.try_into()?, true)?;
+        }
+
+        let collection = Collection::from_paths(&siglist)?.select(&selection)?;
+        let index =
+            RevIndex::open(output.path(), false, None)?.update(collection.clone().try_into()?)?;
+        assert_eq!(index.verify()?, []);
+
+        let fresh_output = TempDir::new()?;
+        let fresh = RevIndex::create(fresh_output.path(), collection.try_into()?, true)?;
+
+        let (stats, fresh_stats) = (index.check(false), fresh.check(false));
+        assert_eq!(stats.total_keys(), fresh_stats.total_keys());
+        assert_eq!(stats.vcount(), fresh_stats.vcount());
+
+        Ok(())
+    }
+
+    #[test]
+    fn revindex_color_missing_and_corrupted_colors() -> Result<()> {
+        use super::IntegrityError;
+        use crate::storage::rocksdb::{color_cf_descriptors, db_options, COLORS, DB};
+
+        let mut basedir = PathBuf::from(env!("CARGO_MANIFEST_DIR"));
+        basedir.push("../../tests/test-data/scaled/");
+
+        let siglist: Vec<_> = ["genome-s10.fa.gz.sig", "genome-s11.fa.gz.sig"]
+            .iter()
+            .map(|sig| basedir.join(sig))
+            .collect();
+
+        let selection = Selection::builder().ksize(31).scaled(10000).build();
+        let output = TempDir::new()?;
+        let collection = Collection::from_paths(&siglist)?.select(&selection)?;
+        let index = RevIndex::create(output.path(), collection.try_into()?, true)?;
+
+        let query_sig = Signature::from_path(basedir.join("genome-s10+s11.fa.gz.sig"))?
+            .swap_remove(0)
+            .select(&selection)?;
+        let query = prepare_query(query_sig, &selection).unwrap();
+        let full_counter = index.counter_for_query(&query);
+        drop(index);
+
+        // Overwrite (or delete) the first color in the colors table.
+        let set_first_color = |value: Option<&[u8]>| -> Result<()> {
+            let db = DB::open_cf_descriptors(&db_options(), output.path(), color_cf_descriptors())?;
+            let cf_colors = db.cf_handle(COLORS).unwrap();
+            let (key, _) = db
+                .iterator_cf(&cf_colors, rocksdb::IteratorMode::Start)
+                .next()
+                .unwrap()?;
+            match value {
+                Some(value) => db.put_cf(&cf_colors, &key, value)?,
+                None => db.delete_cf(&cf_colors, &key)?,
+            }
+            Ok(())
+        };
+
+        // hashes with a missing color are skipped when counting and gathering
+        set_first_color(None)?;
+        let index = RevIndex::open(output.path(), true, None)?;
+        let counter = index.counter_for_query(&query);
+        assert!(counter.total::<usize>() < full_counter.total());
+
+        let (counter, query_colors, hash_to_color) = index.prepare_gather_counters(&query);
+        assert!(counter.total::<usize>() < full_counter.total());
+        index.gather(counter, query_colors, hash_to_color, 0, &query, None)?;
+
+        assert!(matches!(
+            index.lookup_hashes(&query.mins()),
+            Err(crate::Error::IntegrityError(
+                IntegrityError::MissingColor { .. }
+            ))
+        ));
+        drop(index);
+
+        // corrupted colors are errors instead of panics
+        set_first_color(Some(b"bad"))?;
+        let index = RevIndex::open(output.path(), true, None)?;
+        assert!(matches!(
+            index.lookup_hashes(&query.mins()),
+            Err(crate::Error::IntegrityError(
+                IntegrityError::CorruptedColor(_)
+            ))
+        ));
+        assert!(index.counter_for_query(&query).is_empty());
+
+        Ok(())
+    }
+
+    #[test]
+    fn revindex_search_and_prefetch_same_as_linear() -> Result<()> {
+        use crate::index::linear::LinearIndex;
+        use crate::index::SearchType;
+
+        let mut basedir = PathBuf::from(env!("CARGO_MANIFEST_DIR"));
+        basedir.push("../../tests/test-data/gather/");
+
+        let against: Vec<_> = [
+            "GCF_000006945.2_ASM694v2_genomic.fna.gz.sig",
+            "GCF_000007545.1_ASM754v1_genomic.fna.gz.sig",
+            "GCF_000008105.1_ASM810v1_genomic.fna.gz.sig",
+            "GCF_000008545.1_ASM854v1_genomic.fna.gz.sig",
+            "GCF_000009085.1_ASM908v1_genomic.fna.gz.sig",
+            "GCF_000016785.1_ASM1678v1_genomic.fna.gz.sig",
+            "GCF_000018945.1_ASM1894v1_genomic.fna.gz.sig",
+        ]
+        .iter()
+        .map(|sig| basedir.join(sig))
+        .collect();
+
+        let selection = Selection::builder().ksize(21).scaled(10000).build();
+        let collection = Collection::from_paths(&against)?.select(&selection)?;
+        let query_sig = Signature::from_path(basedir.join("combined.sig"))?
+            .swap_remove(0)
+            .select(&selection)?;
+        let query = prepare_query(query_sig, &selection).unwrap();
+
+        let output = TempDir::new()?;
+        let index = RevIndex::create(output.path(), collection.clone().try_into()?, false)?;
+        let linear = LinearIndex::from_collection(collection.try_into()?);
+
+        for (search_type, threshold) in [
+            (SearchType::Jaccard, 0.0),
+            (SearchType::Jaccard, 0.05),
+            (SearchType::Containment, 0.1),
+            (SearchType::MaxContainment, 0.5),
+        ] {
+            let counter = index.counter_for_query(&query);
+            let matches = index.search_matches(counter, &query, search_type, threshold)?;
+
+            let counter = linear.counter_for_query(&query);
+            let linear_matches = linear.search_matches(counter, &query, search_type, threshold)?;
+
+            assert!(!matches.is_empty());
+            assert!(matches.iter().all(|m| m.similarity() >= threshold));
+            assert_eq!(matches, linear_matches);
+
+            for k in [1, 3] {
+                let counter = index.counter_for_query(&query);
+                let best = index.best_matches(counter, &query, search_type, k, threshold)?;
+
+                let counter = linear.counter_for_query(&query);
+                let linear_best =
+                    linear.best_matches(counter, &query, search_type, k, threshold)?;
+
+                assert_eq!(best.len(), usize::min(k, matches.len()));
+                assert_eq!(best, linear_best);
+                assert_eq!(best[..], matches[..best.len()]);
+            }
+        }
+
+        for threshold_bp in [0, 500_000, 2_000_000] {
+            let counter = index.counter_for_query(&query);
+            let matches: Vec<_> = index
+                .prefetch(counter, &query, threshold_bp)
+                .collect::<Result<_>>()?;
+
+            let counter = linear.counter_for_query(&query);
+            let linear_matches: Vec<_> = linear
+                .prefetch(counter, &query, threshold_bp)
+                .collect::<Result<_>>()?;
+
+            assert!(matches
+                .iter()
+                .all(|m| m.intersect_bp() >= threshold_bp as u64));
+            assert_eq!(matches, linear_matches);
+        }
+
+        Ok(())
+    }
+
     #[test]
     fn revindex_move() -> Result<()> {
         let basedir = PathBuf::from(env!("CARGO_MANIFEST_DIR"));
@@ -996,7 +2830,7 @@ mod test {
 
         let spec;
         {
-            let rdb_storage = RocksDBStorage::from_path(output.as_os_str().to_str().unwrap());
+            let rdb_storage = RocksDBStorage::from_path(output.as_os_str().to_str().unwrap())?;
             spec = rdb_storage.spec();
             collection.iter().for_each(|(_, r)| {
                 assert_eq!(
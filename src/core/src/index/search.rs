@@ -1,3 +1,6 @@
+use std::cmp::Reverse;
+use std::collections::BinaryHeap;
+
 use crate::index::Comparable;
 
 pub fn search_minhashes<L>(node: &dyn Comparable<L>, query: &L, threshold: f64) -> bool {
@@ -12,23 +15,152 @@ pub fn search_minhashes_containment<L>(
     node.containment(query) > threshold
 }
 
-pub fn search_minhashes_find_best<L>() -> fn(&dyn Comparable<L>, &L, f64) -> bool {
-    /* TODO: implement the proper function, as a closure that modifies `best_so_far`
-    let mut _best_so_far = 0.;
+/// Keeps the `k` best scoring items seen so far.
+///
+/// Once `k` items were found the threshold rises to the score of the worst of
+/// them, so callers can skip candidates that can't make it into the results.
+/// Ties are broken by `position`, preferring items seen earlier in the index.
+pub struct BestMatches<T> {
+    k: usize,
+    min_score: f64,
+    best: BinaryHeap<Reverse<Scored<T>>>,
+}
+
+struct Scored<T> {
+    score: f64,
+    position: usize,
+    item: T,
+}
+
+impl<T> Scored<T> {
+    fn rank(&self) -> (f64, Reverse<usize>) {
+        (self.score, Reverse(self.position))
+    }
+}
+
+impl<T> PartialEq for Scored<T> {
+    fn eq(&self, other: &Self) -> bool {
+        self.cmp(other) == std::cmp::Ordering::Equal
+    }
+}
+
+impl<T> Eq for Scored<T> {}
 
-    move |node, query, threshold| {
-        let sim = node.similarity(query);
-        if sim > best_so_far {
-            best_so_far = sim;
-            true
+impl<T> PartialOrd for Scored<T> {
+    fn partial_cmp(&self, other: &Self) -> Option<std::cmp::Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl<T> Ord for Scored<T> {
+    fn cmp(&self, other: &Self) -> std::cmp::Ordering {
+        let (score, position) = self.rank();
+        let (other_score, other_position) = other.rank();
+        score
+            .total_cmp(&other_score)
+            .then_with(|| position.cmp(&other_position))
+    }
+}
+
+impl<T> BestMatches<T> {
+    /// Keep up to `k` items scoring at least `min_score`.
+    pub fn new(k: usize, min_score: f64) -> Self {
+        Self {
+            k,
+            min_score,
+            best: BinaryHeap::with_capacity(k + 1),
+        }
+    }
+
+    /// Minimum score for a new item to be kept.
+    pub fn threshold(&self) -> f64 {
+        if self.best.len() < self.k {
+            self.min_score
         } else {
-            if sim > threshold {
-                true
-            } else {
-                false
+            self.best
+                .peek()
+                .map_or(f64::INFINITY, |Reverse(worst)| worst.score)
+        }
+    }
+
+    /// Offer `item`, returning whether it is currently one of the best `k`.
+    pub fn insert(&mut self, score: f64, position: usize, item: T) -> bool {
+        if self.k == 0 || score < self.min_score || score.is_nan() {
+            return false;
+        }
+
+        let candidate = Scored {
+            score,
+            position,
+            item,
+        };
+
+        if self.best.len() == self.k {
+            match self.best.peek() {
+                Some(Reverse(worst)) if candidate > *worst => {
+                    self.best.pop();
+                }
+                _ => return false,
             }
         }
+
+        self.best.push(Reverse(candidate));
+        true
+    }
+
+    pub fn len(&self) -> usize {
+        self.best.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.best.is_empty()
+    }
+
+    /// Best items and their scores, best first.
+    pub fn into_sorted_vec(self) -> Vec<(f64, T)> {
+        // `Reverse` makes the ascending sort go from best to worst
+        self.best
+            .into_sorted_vec()
+            .into_iter()
+            .map(|Reverse(scored)| (scored.score, scored.item))
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn best_matches_keeps_top_k() {
+        let mut best = BestMatches::new(2, 0.1);
+        assert_eq!(best.threshold(), 0.1);
+
+        assert!(!best.insert(0.05, 0, "below min"));
+        assert!(best.insert(0.5, 1, "a"));
+        assert!(best.insert(0.2, 2, "b"));
+        assert_eq!(best.threshold(), 0.2);
+
+        assert!(best.insert(0.7, 3, "c"));
+        assert_eq!(best.threshold(), 0.5);
+        assert!(!best.insert(0.3, 4, "d"));
+
+        // ties prefer earlier positions
+        assert!(!best.insert(0.5, 5, "later tie"));
+        assert!(best.insert(0.5, 0, "earlier tie"));
+
+        assert_eq!(best.len(), 2);
+        assert_eq!(
+            best.into_sorted_vec(),
+            vec![(0.7, "c"), (0.5, "earlier tie")]
+        );
+    }
+
+    #[test]
+    fn best_matches_empty() {
+        let mut best = BestMatches::new(0, 0.0);
+        assert!(!best.insert(1.0, 0, ()));
+        assert!(best.is_empty());
+        assert!(best.into_sorted_vec().is_empty());
     }
-    */
-    unimplemented!();
 }
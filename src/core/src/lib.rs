@@ -32,6 +32,7 @@ pub mod collection;
 pub mod encodings;
 pub mod index;
 pub mod manifest;
+pub mod nthash;
 pub mod selection;
 pub mod signature;
 pub mod sketch;
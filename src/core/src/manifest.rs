@@ -11,8 +11,11 @@ use rayon::prelude::*;
 use serde::de;
 use serde::{Deserialize, Serialize};
 
+use log::warn;
+
 use crate::encodings::HashFunctions;
 use crate::prelude::*;
+use crate::selection::{PickStyle, Picklist};
 use crate::signature::SigsTrait;
 use crate::sketch::Sketch;
 use crate::{Result, ScaledType};
@@ -52,6 +55,12 @@ pub struct Record {
 
     #[getset(get = "pub", set = "pub")]
     filename: String,
+
+    /// Tombstone for a dataset removed from an index.
+    /// The record keeps its position, so dataset ids stay stable.
+    #[getset(get_copy = "pub")]
+    #[serde(skip)]
+    removed: bool,
 }
 
 fn intbool<S>(x: &bool, s: S) -> std::result::Result<S::Ok, S::Error>
@@ -121,10 +130,9 @@ impl Record {
 
                 let md5short = md5[0..8].into();
 
-                ksize = match hash_function {
-                    HashFunctions::Murmur64Protein | HashFunctions::Murmur64Dayhoff | HashFunctions::Murmur64Hp => ksize / 3,
-                    _ => ksize,
-                };
+                if hash_function.protein_encoding().is_some() {
+                    ksize /= 3;
+                }
 
                 Self {
                     internal_location: path.into(),
@@ -138,13 +146,19 @@ impl Record {
                     n_hashes,
                     num,
                     scaled,
+                    removed: false,
                 }
             })
             .collect()
     }
 
     pub fn moltype(&self) -> HashFunctions {
-        self.moltype.as_str().try_into().unwrap()
+        // hash functions that are not registered in this process can still be
+        // selected by name
+        self.moltype
+            .as_str()
+            .try_into()
+            .unwrap_or_else(|_| HashFunctions::Custom(self.moltype.clone()))
     }
 
     pub fn check_compatible(&self, other: &Record) -> Result<()> {
@@ -243,6 +257,14 @@ impl Manifest {
         self.records.iter()
     }
 
+    /// Mark the record at `position` as removed, keeping its slot.
+    #[cfg(all(feature = "branchwater", not(target_arch = "wasm32")))]
+    pub(crate) fn tombstone(&mut self, position: usize) {
+        if let Some(record) = self.records.get_mut(position) {
+            record.removed = true;
+        }
+    }
+
     pub fn intersect_manifest(&self, other: &Manifest) -> Self {
         // extract tuples from other mf:
         let pairs: HashSet<_> = other.iter().collect();
@@ -301,7 +323,48 @@ impl Select for Manifest {
             valid
         });
 
-        Ok(Manifest { records })
+        let mut manifest = Manifest { records };
+        if let Some(picklist) = selection.picklist() {
+            let (selected, not_found) = manifest.select_picklist(&picklist)?;
+            if !not_found.is_empty() {
+                warn!(
+                    "{} picklist values not found in '{}'",
+                    not_found.len(),
+                    picklist.pickfile()
+                );
+            }
+            manifest = selected;
+        }
+
+        Ok(manifest)
+    }
+}
+
+impl Manifest {
+    /// Select records using `picklist`.
+    ///
+    /// Also returns the picklist values that didn't match any record.
+    pub fn select_picklist(self, picklist: &Picklist) -> Result<(Self, Vec<String>)> {
+        let pickset = picklist.pickset()?;
+        let include = matches!(picklist.pickstyle(), PickStyle::Include);
+
+        let mut found = HashSet::new();
+        let mut records = Vec::with_capacity(self.records.len());
+        for record in self.records {
+            let value = picklist.record_value(&record)?;
+            let in_pickset = pickset.contains(&value);
+            if in_pickset {
+                found.insert(value);
+            }
+            if in_pickset == include {
+                records.push(record);
+            }
+        }
+
+        let mut not_found: Vec<String> = pickset.difference(&found).cloned().collect();
+        not_found.sort();
+
+        Ok((Manifest { records }, not_found))
     }
 }
 
@@ -367,16 +430,15 @@ mod test {
     use super::Manifest;
     use crate::collection::Collection;
     use crate::encodings::HashFunctions;
-    use crate::selection::{Select, Selection};
+    use crate::selection::{PickStyle, Picklist, Select, Selection};
+    use crate::Error;
 
     #[test]
     fn manifest_from_pathlist() {
         let temp_dir = TempDir::new().unwrap();
         let utf8_output = PathBuf::from_path_buf(temp_dir.path().to_path_buf())
             .expect("Path should be valid UTF-8");
-        let mut filename = utf8_output.join("sig-pathlist.txt");
-        //convert to camino utf8pathbuf
-        filename = PathBuf::from(filename);
+        let filename = utf8_output.join("sig-pathlist.txt");
         // build sig filenames
         let base_path = PathBuf::from(env!("CARGO_MANIFEST_DIR"));
         let test_sigs = vec![
@@ -413,9 +475,7 @@ mod test {
         let temp_dir = TempDir::new().unwrap();
         let utf8_output = PathBuf::from_path_buf(temp_dir.path().to_path_buf())
             .expect("Path should be valid UTF-8");
-        let mut filename = utf8_output.join("sig-pathlist.txt");
-        //convert to camino utf8pathbuf
-        filename = PathBuf::from(filename);
+        let filename = utf8_output.join("sig-pathlist.txt");
 
         let mut pathfile = File::create(&filename).unwrap();
         writeln!(pathfile, "Valid line").unwrap();
@@ -474,9 +534,9 @@ mod test {
         for record in m2.iter() {
             eprintln!("{:?}", record.name());
             if record.name().contains("OS185") {
-                assert_eq!(record.with_abundance(), false)
+                assert!(!record.with_abundance())
             } else {
-                assert_eq!(record.with_abundance(), true)
+                assert!(record.with_abundance())
             }
         }
     }
@@ -613,4 +673,153 @@ mod test {
         let new_mf = manifest.intersect_manifest(&manifest2);
         assert_eq!(new_mf.len(), 1);
     }
+
+    fn prot_manifest() -> Manifest {
+        let mut filename = PathBuf::from(env!("CARGO_MANIFEST_DIR"));
+        filename.push("../../tests/test-data/prot/all.zip");
+        Collection::from_zipfile(&filename)
+            .unwrap()
+            .manifest()
+            .clone()
+    }
+
+    fn write_pickfile(temp_dir: &TempDir, column: &str, values: &[&str]) -> String {
+        let filename = temp_dir.path().join("picklist.csv");
+        let mut wtr = csv::Writer::from_path(&filename).unwrap();
+        wtr.write_record([column, "other"]).unwrap();
+        for value in values {
+            wtr.write_record([value, "x"]).unwrap();
+        }
+        wtr.flush().unwrap();
+        filename.to_str().unwrap().into()
+    }
+
+    #[test]
+    fn manifest_select_picklist() {
+        let temp_dir = TempDir::new().unwrap();
+
+        let cases: &[(&str, &[&str], usize)] = &[
+            ("name", &["GCA_001593925", "missing"], 3),
+            ("md5", &["09a08691ce52952152f0e866a59f6261"], 1),
+            (
+                "md5prefix8",
+                &["09a08691ce52952152f0e866a59f6261", "38729c63"],
+                2,
+            ),
+            ("md5short", &["fbca5e52"], 1),
+            ("ident", &["NC_009665.1 some description", "NC_009665"], 1),
+            ("identprefix", &["NC_009665.2", "NC_011663.1"], 2),
+        ];
+
+        for (coltype, values, expected) in cases {
+            let pickfile = write_pickfile(&temp_dir, "col", values);
+            let picklist = Picklist::builder()
+                .coltype(coltype.to_string())
+                .pickfile(pickfile)
+                .column_name("col".into())
+                .pickstyle(PickStyle::Include)
+                .build();
+
+            let (selected, _) = prot_manifest().select_picklist(&picklist).unwrap();
+            assert_eq!(selected.len(), *expected, "coltype {}", coltype);
+
+            let mut picklist = picklist.clone();
+            picklist.set_pickstyle(PickStyle::Exclude);
+            let (excluded, _) = prot_manifest().select_picklist(&picklist).unwrap();
+            assert_eq!(excluded.len(), 8 - expected, "coltype {}", coltype);
+        }
+    }
+
+    #[test]
+    fn manifest_select_picklist_not_found() {
+        let temp_dir = TempDir::new().unwrap();
+        let pickfile = write_pickfile(
+            &temp_dir,
+            "name",
+            &["GCA_001593925", "", "missing", "missing"],
+        );
+
+        let mut picklist = Picklist::builder()
+            .coltype("name".into())
+            .pickfile(pickfile)
+            .column_name("name".into())
+            .pickstyle(PickStyle::Include)
+            .build();
+
+        let (n_empty, duplicates) = picklist.load().unwrap();
+        assert_eq!(n_empty, 1);
+        assert_eq!(duplicates.len(), 1);
+
+        let (selected, not_found) = prot_manifest().select_picklist(&picklist).unwrap();
+        assert_eq!(selected.len(), 3);
+        assert_eq!(not_found, vec!["missing".to_string()]);
+
+        // picklists are also applied with other selection parameters
+        let mut selection = Selection::default();
+        selection.set_moltype(HashFunctions::Murmur64Protein);
+        selection.set_picklist(picklist);
+        let selected = prot_manifest().select(&selection).unwrap();
+        assert_eq!(selected.len(), 1);
+
+        // values can also be provided directly
+        let mut picklist = Picklist::builder()
+            .coltype("md5short".into())
+            .pickstyle(PickStyle::Include)
+            .build();
+        picklist.init(["fbca5e5211e4d58427997fd5c8343e9a"]).unwrap();
+        let (selected, not_found) = prot_manifest().select_picklist(&picklist).unwrap();
+        assert_eq!(selected.len(), 1);
+        assert!(not_found.is_empty());
+    }
+
+    #[test]
+    fn manifest_select_picklist_loaded_once() {
+        let temp_dir = TempDir::new().unwrap();
+        let pickfile = write_pickfile(&temp_dir, "name", &["GCA_001593925"]);
+
+        let picklist = Picklist::builder()
+            .coltype("name".into())
+            .pickfile(pickfile.clone())
+            .column_name("name".into())
+            .pickstyle(PickStyle::Include)
+            .build();
+        let mut selection = Selection::default();
+        selection.set_picklist(picklist);
+
+        let selected = prot_manifest().select(&selection).unwrap();
+        assert_eq!(selected.len(), 3);
+
+        // the pickfile is not read again
+        std::fs::remove_file(&pickfile).unwrap();
+        let selected = prot_manifest().select(&selection).unwrap();
+        assert_eq!(selected.len(), 3);
+    }
+
+    #[test]
+    fn manifest_select_picklist_errors() {
+        let temp_dir = TempDir::new().unwrap();
+        let pickfile = write_pickfile(&temp_dir, "name", &["GCA_001593925"]);
+
+        let picklist = Picklist::builder()
+            .coltype("name".into())
+            .pickfile(pickfile.clone())
+            .column_name("no-such-column".into())
+            .pickstyle(PickStyle::Include)
+            .build();
+        assert!(matches!(
+            prot_manifest().select_picklist(&picklist),
+            Err(Error::InvalidPicklist { .. })
+        ));
+
+        let picklist = Picklist::builder()
+            .coltype("accession".into())
+            .pickfile(pickfile)
+            .column_name("name".into())
+            .pickstyle(PickStyle::Include)
+            .build();
+        assert!(matches!(
+            prot_manifest().select_picklist(&picklist),
+            Err(Error::InvalidPicklist { .. })
+        ));
+    }
 }
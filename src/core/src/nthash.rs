@@ -0,0 +1,279 @@
+//! # Rolling hash for DNA k-mers
+//!
+//! A canonical ntHash-style rolling hash. Each new k-mer hash is derived
+//! from the previous one in constant time, and the sequence is read in place
+//! (no uppercasing or reverse complement buffers), so a single pass over a
+//! sequence can produce hashes for several ksizes at once.
+//!
+//! Hashes are not compatible with the murmur64 DNA hashes, and sketches
+//! using them are marked with `HashFunctions::NtHash64Dna`.
+
+use crate::Error;
+use crate::HashIntoType;
+
+const SEED_A: u64 = 0x3c8b_fbb3_95c6_0474;
+const SEED_C: u64 = 0x3193_c185_62a0_2b4c;
+const SEED_G: u64 = 0x2032_3ed0_8257_2324;
+const SEED_T: u64 = 0x2955_49f5_4be2_4456;
+
+const H: [u64; 256] = {
+    let mut lookup = [0; 256];
+    lookup[b'A' as usize] = SEED_A;
+    lookup[b'C' as usize] = SEED_C;
+    lookup[b'G' as usize] = SEED_G;
+    lookup[b'T' as usize] = SEED_T;
+    lookup[b'a' as usize] = SEED_A;
+    lookup[b'c' as usize] = SEED_C;
+    lookup[b'g' as usize] = SEED_G;
+    lookup[b't' as usize] = SEED_T;
+    lookup
+};
+
+const RC: [u64; 256] = {
+    let mut lookup = [0; 256];
+    lookup[b'A' as usize] = SEED_T;
+    lookup[b'C' as usize] = SEED_G;
+    lookup[b'G' as usize] = SEED_C;
+    lookup[b'T' as usize] = SEED_A;
+    lookup[b'a' as usize] = SEED_T;
+    lookup[b'c' as usize] = SEED_G;
+    lookup[b'g' as usize] = SEED_C;
+    lookup[b't' as usize] = SEED_A;
+    lookup
+};
+
+#[inline]
+fn h(c: u8) -> u64 {
+    H[c as usize]
+}
+
+#[inline]
+fn rc(c: u8) -> u64 {
+    RC[c as usize]
+}
+
+#[inline]
+fn valid(c: u8) -> bool {
+    H[c as usize] != 0
+}
+
+/// Mix the canonical value with the seed (splitmix64 finalizer),
+/// so different seeds produce unrelated hashes.
+#[inline]
+fn finalize(canonical: u64, seed: u64) -> HashIntoType {
+    let mut x = canonical ^ seed.wrapping_mul(0x9e37_79b9_7f4a_7c15);
+    x = (x ^ (x >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+    x = (x ^ (x >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+    x ^ (x >> 31)
+}
+
+/// Hash a single k-mer. Gives the same value as `NtHashIterator` for
+/// the same k-mer, and is the same for a k-mer and its reverse complement.
+///
+/// The k-mer must only contain `ACGT` (in any case).
+pub fn nthash(kmer: &[u8], seed: u64) -> HashIntoType {
+    let k = kmer.len();
+    let (fwd, rev) = kmer
+        .iter()
+        .enumerate()
+        .fold((0u64, 0u64), |(fwd, rev), (j, &c)| {
+            (
+                fwd ^ h(c).rotate_left((k - 1 - j) as u32),
+                rev ^ rc(c).rotate_left(j as u32),
+            )
+        });
+    finalize(fwd.min(rev), seed)
+}
+
+/// Iterator over the rolling hashes of all k-mers in a sequence, for
+/// one or more ksizes.
+///
+/// Yields `(ksize, hash)` pairs in sequence order; for each position,
+/// hashes for the k-mers ending there are returned in the order `ksizes`
+/// were given.
+///
+/// K-mers with characters other than `ACGT` are an error, unless `force`
+/// is set, in which case they are skipped.
+pub struct NtHashIterator<'a> {
+    seq: &'a [u8],
+    ksizes: Vec<usize>,
+    seed: u64,
+    force: bool,
+
+    pos: usize,
+    run: usize,
+    k_idx: usize,
+    fwd: Vec<u64>,
+    rev: Vec<u64>,
+}
+
+impl<'a> NtHashIterator<'a> {
+    pub fn new(seq: &'a [u8], ksizes: &[usize], seed: u64, force: bool) -> Self {
+        let mut ks: Vec<usize> = vec![];
+        for &k in ksizes {
+            if k > 0 && !ks.contains(&k) {
+                ks.push(k);
+            }
+        }
+
+        NtHashIterator {
+            seq,
+            fwd: vec![0; ks.len()],
+            rev: vec![0; ks.len()],
+            k_idx: ks.len(),
+            ksizes: ks,
+            seed,
+            force,
+            pos: 0,
+            run: 0,
+        }
+    }
+
+    fn invalid_kmer(&self) -> Error {
+        // report the first k-mer (of the smallest ksize) containing the
+        // invalid character, same as the murmur64 path does.
+        let k = self.ksizes.iter().min().copied().unwrap_or(1);
+        let start = (self.pos + 1).saturating_sub(k);
+        let end = (start + k).min(self.seq.len());
+        Error::InvalidDNA {
+            message: String::from_utf8_lossy(&self.seq[start..end]).into_owned(),
+        }
+    }
+}
+
+impl<'a> Iterator for NtHashIterator<'a> {
+    type Item = Result<(usize, HashIntoType), Error>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            // k-mers ending at the last position read
+            while self.k_idx < self.ksizes.len() {
+                let i = self.k_idx;
+                self.k_idx += 1;
+
+                let k = self.ksizes[i];
+                if self.run >= k {
+                    let hash = finalize(self.fwd[i].min(self.rev[i]), self.seed);
+                    return Some(Ok((k, hash)));
+                }
+            }
+
+            if self.pos >= self.seq.len() {
+                return None;
+            }
+
+            let c = self.seq[self.pos];
+            if !valid(c) {
+                let kmin = self.ksizes.iter().min().copied().unwrap_or(0);
+                if !self.force && kmin > 0 && self.seq.len() >= kmin {
+                    let err = self.invalid_kmer();
+                    self.pos = self.seq.len();
+                    return Some(Err(err));
+                }
+                self.run = 0;
+                self.pos += 1;
+                continue;
+            }
+
+            for (i, &k) in self.ksizes.iter().enumerate() {
+                if self.run >= k {
+                    let out = self.seq[self.pos - k];
+                    self.fwd[i] = self.fwd[i].rotate_left(1) ^ h(out).rotate_left(k as u32) ^ h(c);
+                    self.rev[i] = self.rev[i].rotate_right(1)
+                        ^ rc(out).rotate_right(1)
+                        ^ rc(c).rotate_left((k - 1) as u32);
+                } else {
+                    if self.run == 0 {
+                        self.fwd[i] = 0;
+                        self.rev[i] = 0;
+                    }
+                    self.fwd[i] = self.fwd[i].rotate_left(1) ^ h(c);
+                    self.rev[i] ^= rc(c).rotate_left(self.run as u32);
+                }
+            }
+
+            self.run += 1;
+            self.pos += 1;
+            self.k_idx = 0;
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    use crate::encodings::revcomp;
+
+    #[test]
+    fn rolling_matches_direct() {
+        let seq = b"ACGTCGATCGATCGGGCTAGCTACGATCAGCATCGACTGACTACGACTTTT";
+
+        for k in [1, 3, 21, 31] {
+            let rolling: Vec<_> = NtHashIterator::new(seq, &[k], 42, false)
+                .map(|h| h.unwrap().1)
+                .collect();
+            let direct: Vec<_> = seq.windows(k).map(|kmer| nthash(kmer, 42)).collect();
+            assert_eq!(rolling, direct);
+        }
+    }
+
+    #[test]
+    fn multiple_ksizes() {
+        let seq = b"ACGTCGATCGATCGGGCTAGCTACGATCAGCATCGACTGACTACGACTTTT";
+
+        let hashes: Vec<_> = NtHashIterator::new(seq, &[5, 11], 42, false)
+            .map(|h| h.unwrap())
+            .collect();
+
+        for k in [5, 11] {
+            let per_k: Vec<_> = hashes
+                .iter()
+                .filter(|(ksize, _)| *ksize == k)
+                .map(|(_, h)| *h)
+                .collect();
+            let direct: Vec<_> = seq.windows(k).map(|kmer| nthash(kmer, 42)).collect();
+            assert_eq!(per_k, direct);
+        }
+    }
+
+    #[test]
+    fn canonical() {
+        let seq = b"ACGTCGATCGATCGGGCTAGCTACGATCAGCATCGACTGACTACGACTTTT";
+        let rc = revcomp(seq);
+
+        let mut fw: Vec<_> = NtHashIterator::new(seq, &[21], 42, false)
+            .map(|h| h.unwrap().1)
+            .collect();
+        let mut rv: Vec<_> = NtHashIterator::new(&rc, &[21], 42, false)
+            .map(|h| h.unwrap().1)
+            .collect();
+        fw.sort_unstable();
+        rv.sort_unstable();
+        assert_eq!(fw, rv);
+
+        let lower = seq.to_ascii_lowercase();
+        assert_eq!(nthash(&lower[..21], 42), nthash(&seq[..21], 42));
+        assert_ne!(nthash(&seq[..21], 42), nthash(&seq[..21], 43));
+    }
+
+    #[test]
+    fn invalid_characters() {
+        let seq = b"ACGTNACGTACG";
+
+        let mut it = NtHashIterator::new(seq, &[3], 42, false);
+        assert!(it.next().unwrap().is_ok());
+        assert!(it.next().unwrap().is_ok());
+        assert!(matches!(it.next(), Some(Err(Error::InvalidDNA { .. }))));
+        assert!(it.next().is_none());
+
+        let hashes: Vec<_> = NtHashIterator::new(seq, &[3], 42, true)
+            .map(|h| h.unwrap().1)
+            .collect();
+        let expected: Vec<_> = [&seq[..4], &seq[5..]]
+            .iter()
+            .flat_map(|part| part.windows(3).map(|kmer| nthash(kmer, 42)))
+            .collect();
+        assert_eq!(hashes, expected);
+    }
+}
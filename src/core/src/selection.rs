@@ -1,9 +1,13 @@
+use std::collections::HashSet;
+use std::sync::Arc;
+
 use getset::{CopyGetters, Getters, Setters};
+use once_cell::sync::OnceCell;
 use typed_builder::TypedBuilder;
 
 use crate::encodings::HashFunctions;
 use crate::manifest::Record;
-use crate::{Result, ScaledType};
+use crate::{Error, Result, ScaledType};
 
 #[derive(Default, Debug, TypedBuilder, Clone)]
 pub struct Selection {
@@ -46,6 +50,11 @@ pub struct Picklist {
     #[getset(get = "pub", set = "pub")]
     #[builder]
     pickstyle: PickStyle,
+
+    // loaded on first use, and shared by clones (like the ones returned by
+    // `Selection::picklist`) so the pickfile is only read once
+    #[builder(default, setter(skip))]
+    pickset: Arc<OnceCell<HashSet<String>>>,
 }
 
 #[derive(Clone, Default, Debug)]
@@ -56,6 +65,112 @@ pub enum PickStyle {
     Exclude = 2,
 }
 
+impl Picklist {
+    /// Column types supported for matching against manifest records.
+    pub const COLTYPES: [&'static str; 6] = [
+        "md5",
+        "md5prefix8",
+        "md5short",
+        "name",
+        "ident",
+        "identprefix",
+    ];
+
+    /// Use `values` instead of loading them from the pickfile.
+    pub fn init<I, S>(&mut self, values: I) -> Result<()>
+    where
+        I: IntoIterator<Item = S>,
+        S: AsRef<str>,
+    {
+        let pickset = values
+            .into_iter()
+            .map(|v| self.preprocess(v.as_ref()))
+            .collect::<Result<_>>()?;
+        self.pickset = Arc::new(OnceCell::with_value(pickset));
+        Ok(())
+    }
+
+    /// Load values from the `column_name` column of the pickfile CSV.
+    ///
+    /// Returns the number of rows with empty values, and the values
+    /// that showed up more than once.
+    pub fn load(&mut self) -> Result<(usize, HashSet<String>)> {
+        let (pickset, n_empty, duplicates) = self.read_pickfile()?;
+        self.pickset = Arc::new(OnceCell::with_value(pickset));
+        Ok((n_empty, duplicates))
+    }
+
+    fn read_pickfile(&self) -> Result<(HashSet<String>, usize, HashSet<String>)> {
+        let (rdr, _format) = niffler::from_path(&self.pickfile)?;
+        let mut rdr = csv::Reader::from_reader(rdr);
+
+        let column = rdr
+            .headers()?
+            .iter()
+            .position(|h| h == self.column_name)
+            .ok_or_else(|| Error::InvalidPicklist {
+                message: format!(
+                    "column '{}' not in pickfile '{}'",
+                    self.column_name, self.pickfile
+                ),
+            })?;
+
+        let mut pickset = HashSet::new();
+        let mut n_empty = 0;
+        let mut duplicates = HashSet::new();
+
+        for row in rdr.records() {
+            let row = row?;
+            let value = row.get(column).unwrap_or_default();
+            if value.is_empty() {
+                n_empty += 1;
+                continue;
+            }
+
+            let value = self.preprocess(value)?;
+            if pickset.contains(&value) {
+                duplicates.insert(value);
+            } else {
+                pickset.insert(value);
+            }
+        }
+
+        Ok((pickset, n_empty, duplicates))
+    }
+
+    /// Values to pick, loading them from the pickfile on first use.
+    pub fn pickset(&self) -> Result<&HashSet<String>> {
+        self.pickset.get_or_try_init(|| Ok(self.read_pickfile()?.0))
+    }
+
+    /// The value of `record` to check against the picklist values.
+    pub fn record_value(&self, record: &Record) -> Result<String> {
+        match self.coltype.as_str() {
+            "md5" => Ok(record.md5().clone()),
+            "md5prefix8" | "md5short" => self.preprocess(record.md5()),
+            "name" | "ident" | "identprefix" => self.preprocess(record.name()),
+            _ => Err(self.invalid_coltype()),
+        }
+    }
+
+    fn preprocess(&self, value: &str) -> Result<String> {
+        let ident = || value.split(' ').next().unwrap_or_default();
+        Ok(match self.coltype.as_str() {
+            "md5" | "name" => value.into(),
+            "md5prefix8" | "md5short" => value.chars().take(8).collect(),
+            "ident" => ident().into(),
+            "identprefix" => ident().split('.').next().unwrap_or_default().into(),
+            _ => return Err(self.invalid_coltype()),
+        })
+    }
+
+    fn invalid_coltype(&self) -> Error {
+        Error::InvalidPicklist {
+            message: format!("invalid picklist column type '{}'", self.coltype),
+        }
+    }
+}
+
 pub trait Select {
     fn select(self, selection: &Selection) -> Result<Self>
     where
@@ -15,7 +15,8 @@ use rayon::prelude::*;
 use serde::{Deserialize, Serialize};
 use typed_builder::TypedBuilder;
 
-use crate::encodings::{aa_to_dayhoff, aa_to_hp, revcomp, to_aa, HashFunctions, VALID};
+use crate::encodings::{revcomp, CustomHashFunction, HashFunctions, ProteinEncoding, VALID};
+use crate::nthash::{nthash, NtHashIterator};
 use crate::prelude::*;
 use crate::sketch::minhash::KmerMinHash;
 use crate::sketch::Sketch;
@@ -36,6 +37,17 @@ pub trait SigsTrait {
     fn add_hash(&mut self, hash: HashIntoType);
 
     fn add_sequence(&mut self, seq: &[u8], force: bool) -> Result<(), Error> {
+        if self.hash_function() == HashFunctions::NtHash64Dna {
+            for hash_value in NtHashIterator::new(seq, &[self.ksize()], self.seed(), force) {
+                match hash_value {
+                    Ok((_, 0)) => continue,
+                    Ok((_, x)) => self.add_hash(x),
+                    Err(err) => return Err(err),
+                }
+            }
+            return Ok(());
+        }
+
         let ready_hashes = SeqToHashes::new(
             seq,
             self.ksize(),
@@ -163,6 +175,14 @@ impl SigsTrait for Sketch {
     }
 }
 
+#[inline]
+fn hash_kmer(custom: Option<&CustomHashFunction>, kmer: &[u8], seed: u64) -> u64 {
+    match custom {
+        Some(f) => f.hash(kmer, seed),
+        None => crate::_hash_murmur(kmer, seed),
+    }
+}
+
 // Iterator for converting sequence to hashes
 pub struct SeqToHashes {
     sequence: Vec<u8>,
@@ -172,6 +192,8 @@ pub struct SeqToHashes {
     force: bool,
     is_protein: bool,
     hash_function: HashFunctions,
+    custom: Option<CustomHashFunction>,
+    encoding: Option<ProteinEncoding>,
     seed: u64,
     hashes_buffer: Vec<u64>,
 
@@ -217,6 +239,8 @@ impl SeqToHashes {
             max_index: _max_index,
             force,
             is_protein,
+            custom: hash_function.custom(),
+            encoding: hash_function.protein_encoding(),
             hash_function,
             seed,
             hashes_buffer: Vec::with_capacity(1000),
@@ -248,6 +272,16 @@ impl Iterator for SeqToHashes {
 
     fn next(&mut self) -> Option<Self::Item> {
         if (self.kmer_index < self.max_index) || !self.hashes_buffer.is_empty() {
+            if let (HashFunctions::Custom(name), None) = (&self.hash_function, &self.custom) {
+                // not registered, nothing to hash with
+                let err = Error::InvalidHashFunction {
+                    function: name.clone(),
+                };
+                self.kmer_index = self.max_index;
+                self.hashes_buffer.clear();
+                return Some(Err(err));
+            }
+
             // Processing DNA or Translated DNA
             if !self.is_protein {
                 // Setting the parameters only in the first iteration
@@ -301,12 +335,25 @@ impl Iterator for SeqToHashes {
 
                     let krc = &self.dna_rc[self.dna_len - self.dna_ksize - self.kmer_index
                         ..self.dna_len - self.kmer_index];
-                    let hash = crate::_hash_murmur(std::cmp::min(kmer, krc), self.seed);
+                    let hash = if self.hash_function == HashFunctions::NtHash64Dna {
+                        nthash(kmer, self.seed)
+                    } else {
+                        hash_kmer(self.custom.as_ref(), std::cmp::min(kmer, krc), self.seed)
+                    };
                     self.kmer_index += 1;
                     Some(Ok(hash))
                 } else if self.hashes_buffer.is_empty() && self.translate_iter_step == 0 {
                     // Processing protein by translating DNA
                     // TODO: Implement iterator over frames instead of hashes_buffer.
+                    let encoding = match self.encoding.clone() {
+                        Some(encoding) => encoding,
+                        None => {
+                            self.kmer_index = self.max_index;
+                            return Some(Err(Error::InvalidHashFunction {
+                                function: format!("{}", self.hash_function),
+                            }));
+                        }
+                    };
 
                     for frame_number in 0..3 {
                         let substr: Vec<u8> = self
@@ -317,15 +364,10 @@ impl Iterator for SeqToHashes {
                             .take(self.sequence.len() - frame_number)
                             .collect();
 
-                        let aa = to_aa(
-                            &substr,
-                            self.hash_function.dayhoff(),
-                            self.hash_function.hp(),
-                        )
-                        .unwrap();
+                        let aa = encoding.translate(&substr).unwrap();
 
                         aa.windows(self.k_size).for_each(|n| {
-                            let hash = crate::_hash_murmur(n, self.seed);
+                            let hash = hash_kmer(self.custom.as_ref(), n, self.seed);
                             self.hashes_buffer.push(hash);
                         });
 
@@ -336,15 +378,10 @@ impl Iterator for SeqToHashes {
                             .skip(frame_number)
                             .take(self.dna_rc.len() - frame_number)
                             .collect();
-                        let aa_rc = to_aa(
-                            &rc_substr,
-                            self.hash_function.dayhoff(),
-                            self.hash_function.hp(),
-                        )
-                        .unwrap();
+                        let aa_rc = encoding.translate(&rc_substr).unwrap();
 
                         aa_rc.windows(self.k_size).for_each(|n| {
-                            let hash = crate::_hash_murmur(n, self.seed);
+                            let hash = hash_kmer(self.custom.as_ref(), n, self.seed);
                             self.hashes_buffer.push(hash);
                         });
                     }
@@ -365,24 +402,25 @@ impl Iterator for SeqToHashes {
 
                 if self.hash_function.protein() {
                     let aa_kmer = &self.sequence[self.kmer_index..self.kmer_index + self.k_size];
-                    let hash = crate::_hash_murmur(aa_kmer, self.seed);
+                    let hash = hash_kmer(self.custom.as_ref(), aa_kmer, self.seed);
                     self.kmer_index += 1;
                     Some(Ok(hash))
                 } else {
                     if !self.prot_configured {
-                        self.aa_seq = match &self.hash_function {
-                            HashFunctions::Murmur64Dayhoff => {
-                                self.sequence.iter().cloned().map(aa_to_dayhoff).collect()
-                            }
-                            HashFunctions::Murmur64Hp => {
-                                self.sequence.iter().cloned().map(aa_to_hp).collect()
-                            }
-                            invalid => {
+                        self.aa_seq = match &self.encoding {
+                            Some(encoding) => self
+                                .sequence
+                                .iter()
+                                .map(|aa| encoding.alphabet().reduce(*aa))
+                                .collect(),
+                            None => {
+                                self.kmer_index = self.max_index;
                                 return Some(Err(Error::InvalidHashFunction {
-                                    function: format!("{}", invalid),
+                                    function: format!("{}", self.hash_function),
                                 }));
                             }
                         };
+                        self.prot_configured = true;
                     }
 
                     let aa_kmer = &self.aa_seq[self.kmer_index..self.kmer_index + self.k_size];
@@ -483,6 +521,12 @@ impl Signature {
     }
 
     pub fn push(&mut self, sketch: Sketch) {
+        // ntHash and custom hash functions are saved by name instead of "0.murmur64"
+        match sketch.hash_function() {
+            HashFunctions::Custom(name) => self.hash_function = name,
+            f @ HashFunctions::NtHash64Dna => self.hash_function = f.to_string(),
+            _ => {}
+        }
         self.signatures.push(sketch);
     }
 
@@ -658,15 +702,32 @@ impl Signature {
     }
 
     pub fn add_sequence(&mut self, seq: &[u8], force: bool) -> Result<(), Error> {
+        // rolling hash sketches are filled in a single pass over the sequence
+        // for all their ksizes, instead of one pass per sketch.
+        let (mut rolling, others): (Vec<_>, Vec<_>) = self
+            .signatures
+            .iter_mut()
+            .partition(|sketch| sketch.hash_function() == HashFunctions::NtHash64Dna);
+
+        while !rolling.is_empty() {
+            let seed = rolling[0].seed();
+            let (same_seed, rest): (Vec<_>, Vec<_>) = rolling
+                .into_iter()
+                .partition(|sketch| sketch.seed() == seed);
+            rolling = rest;
+
+            add_rolling_hashes(same_seed, seq, seed, force)?;
+        }
+
         cfg_if! {
         if #[cfg(feature = "parallel")] {
-            self.signatures
-                .par_iter_mut()
+            others
+                .into_par_iter()
                 .try_for_each(|sketch| {
                     sketch.add_sequence(seq, force) }
                 )?;
         } else {
-            for sketch in self.signatures.iter_mut(){
+            for sketch in others {
                 sketch.add_sequence(seq, force)?;
             }
         }
@@ -782,6 +843,28 @@ impl Clone for Iter<'_> {
     }
 }
 
+fn add_rolling_hashes(
+    mut sketches: Vec<&mut Sketch>,
+    seq: &[u8],
+    seed: u64,
+    force: bool,
+) -> Result<(), Error> {
+    let ksizes: Vec<usize> = sketches.iter().map(|sketch| sketch.ksize()).collect();
+
+    for hash_value in NtHashIterator::new(seq, &ksizes, seed, force) {
+        let (ksize, hash) = hash_value?;
+        if hash == 0 {
+            continue;
+        }
+        sketches
+            .iter_mut()
+            .filter(|sketch| sketch.ksize() == ksize)
+            .for_each(|sketch| sketch.add_hash(hash));
+    }
+
+    Ok(())
+}
+
 impl ToWriter for Signature {
     fn to_writer<W>(&self, writer: &mut W) -> Result<(), Error>
     where
@@ -808,11 +891,10 @@ impl Select for Signature {
             let mut valid = true;
             valid = if let Some(ksize) = selection.ksize() {
                 let k = s.ksize() as u32;
-                let adjusted_ksize = match s.hash_function() {
-                    HashFunctions::Murmur64Protein
-                    | HashFunctions::Murmur64Dayhoff
-                    | HashFunctions::Murmur64Hp => ksize * 3,
-                    _ => ksize,
+                let adjusted_ksize = if s.hash_function().protein_encoding().is_some() {
+                    ksize * 3
+                } else {
+                    ksize
                 };
                 k == adjusted_ksize
             } else {
@@ -927,9 +1009,11 @@ mod test {
     use needletail::parse_fastx_reader;
 
     use crate::cmd::ComputeParameters;
+    use crate::encodings::HashFunctions;
     use crate::signature::SigsTrait;
+    use crate::sketch::minhash::KmerMinHash;
 
-    use super::Signature;
+    use super::{SeqToHashes, Signature};
 
     use crate::prelude::Select;
     use crate::selection::Selection;
@@ -963,7 +1047,7 @@ mod test {
 
         assert_eq!(sigs.len(), 4);
 
-        let sig = sigs.get(0).unwrap();
+        let sig = sigs.first().unwrap();
         assert_eq!(sig.class, "sourmash_signature");
         assert_eq!(sig.email, "");
         if let Some(ref filename) = sig.filename {
@@ -1086,7 +1170,7 @@ mod test {
 
         assert_eq!(sigs.len(), 1);
 
-        let sig = sigs.get(0).unwrap();
+        let sig = sigs.first().unwrap();
         let mh = sig.minhash().unwrap();
         assert_eq!(mh.scaled(), 1000);
     }
@@ -1102,7 +1186,7 @@ mod test {
 
         assert_eq!(sigs.len(), 1);
 
-        let sig = sigs.get(0).unwrap();
+        let sig = sigs.first().unwrap();
         let mhdirect = sig.minhash().unwrap();
         let sketch = sig.get_sketch().unwrap();
         if let Sketch::MinHash(mh) = sketch {
@@ -1110,7 +1194,7 @@ mod test {
             assert_eq!(mhdirect, mh); // should be the same
         } else {
             // error
-            assert!(false);
+            panic!("expected a MinHash sketch");
         }
     }
 
@@ -1126,7 +1210,7 @@ mod test {
 
         assert_eq!(sigs.len(), 1);
 
-        let sig = sigs.get(0).unwrap();
+        let sig = sigs.first().unwrap();
         let mut mhdirect = sig.minhash().unwrap().clone();
         // change slightly and push into new_sig
         mhdirect.add_sequence(b"ATGGA", false).unwrap();
@@ -1151,7 +1235,7 @@ mod test {
 
         assert_eq!(sigs.len(), 1);
 
-        let sig = sigs.get(0).unwrap();
+        let sig = sigs.first().unwrap();
         let mut mhdirect = sig.minhash().unwrap().clone();
         // change slightly and push into new_sig
         mhdirect.add_sequence(b"ATGGA", false).unwrap();
@@ -1269,6 +1353,43 @@ mod test {
         assert!(mh.is_none());
     }
 
+    #[test]
+    fn selection_encoded_protein() {
+        use crate::encodings::Alphabet;
+        use crate::manifest::Record;
+
+        // protein k-mers translated with table 4 use the same ksize convention
+        // as plain protein sketches
+        let prot_ksize = 7;
+        let mut sig = Signature::default();
+        for hash_function in [
+            HashFunctions::Murmur64Protein,
+            HashFunctions::translated(Alphabet::Protein, 4).unwrap(),
+        ] {
+            let mut mh = KmerMinHash::new(0, prot_ksize * 3, hash_function, 42, false, 0);
+            mh.add_protein(b"MRVLKFGGTSVANAERFLRVADILESNARQGQVATVLSAPAKITNHLVAMIEK")
+                .unwrap();
+            sig.push(Sketch::MinHash(mh));
+        }
+
+        let records = Record::from_sig(&sig, "");
+        assert_eq!(records.len(), 2);
+        assert_eq!(records[0].ksize(), prot_ksize);
+        assert_eq!(records[1].ksize(), prot_ksize);
+
+        let mut selection = Selection::default();
+        selection.set_ksize(prot_ksize);
+        let selected_sig = sig.clone().select(&selection).unwrap();
+        assert_eq!(selected_sig.size(), 2);
+        for sketch in selected_sig.iter() {
+            assert_eq!(sketch.ksize(), prot_ksize as usize * 3);
+        }
+
+        selection.set_ksize(prot_ksize * 3);
+        let selected_sig = sig.select(&selection).unwrap();
+        assert_eq!(selected_sig.size(), 0);
+    }
+
     #[test]
     fn selection_scaled_too_low() {
         let mut filename = PathBuf::from(env!("CARGO_MANIFEST_DIR"));
@@ -1287,4 +1408,76 @@ mod test {
             assert_eq!(modified_sig.size(), 0);
         }
     }
+
+    #[test]
+    fn nthash_sketches() {
+        let seq = b"ATGCGATCGATCGGGCTAGCTACGATCAGCATCGACTGACTACGACTNNNACGATCGAACGATCGA";
+
+        let mut sig =
+            Signature::from_params(&ComputeParameters::builder().ksizes(vec![5, 7]).build());
+        for sketch in sig.signatures.iter_mut() {
+            if let Sketch::LargeMinHash(mh) = sketch {
+                mh.set_hash_function(HashFunctions::NtHash64Dna).unwrap();
+            }
+        }
+        sig.add_sequence(seq, true).unwrap();
+
+        // one pass for all ksizes gives the same hashes as the
+        // per k-mer iterator
+        for sketch in &sig.signatures {
+            let mut expected: Vec<u64> = SeqToHashes::new(
+                seq,
+                sketch.ksize(),
+                true,
+                false,
+                HashFunctions::NtHash64Dna,
+                42,
+            )
+            .map(|h| h.unwrap())
+            .filter(|h| *h != 0)
+            .collect();
+            expected.sort_unstable();
+            expected.dedup();
+            expected.truncate(500);
+
+            assert!(!expected.is_empty());
+            assert_eq!(sketch.to_vec(), expected);
+        }
+
+        let mut mh = KmerMinHash::new(0, 5, HashFunctions::NtHash64Dna, 42, false, 500);
+        mh.add_sequence(seq, true).unwrap();
+        assert_eq!(mh.to_vec(), sig.signatures[0].to_vec());
+        assert!(mh.add_sequence(seq, false).is_err());
+
+        // murmur64 hashes are unchanged
+        let mut murmur = KmerMinHash::new(0, 5, HashFunctions::Murmur64Dna, 42, false, 500);
+        murmur.add_sequence(seq, true).unwrap();
+        assert_ne!(murmur.to_vec(), mh.to_vec());
+        assert!(murmur.to_vec().contains(&crate::_hash_murmur(b"ATCGC", 42)));
+    }
+
+    #[test]
+    fn nthash_signature_roundtrip() {
+        let seq = b"ATGCGATCGATCGGGCTAGCTACGATCAGCATCGACTGACTACGACTNNNACGATCGAACGATCGA";
+
+        let mut mh = KmerMinHash::new(0, 5, HashFunctions::NtHash64Dna, 42, false, 500);
+        mh.add_sequence(seq, true).unwrap();
+        let mut sig = Signature::default();
+        sig.push(Sketch::MinHash(mh));
+        assert_eq!(sig.hash_function(), "nthash");
+
+        let mut buffer = vec![];
+        serde_json::to_writer(&mut buffer, &[&sig]).unwrap();
+        let json: serde_json::Value = serde_json::from_slice(&buffer).unwrap();
+        assert_eq!(json[0]["hash_function"], "nthash");
+        assert_eq!(json[0]["signatures"][0]["molecule"], "nthash");
+
+        let loaded = Signature::from_reader(&buffer[..]).unwrap().swap_remove(0);
+        assert_eq!(loaded.hash_function(), "nthash");
+        assert_eq!(
+            loaded.sketches()[0].hash_function(),
+            HashFunctions::NtHash64Dna
+        );
+        assert_eq!(loaded, sig);
+    }
 }
@@ -18,7 +18,7 @@ use crate::prelude::ToWriter;
 use crate::signature::SigsTrait;
 use crate::sketch::hyperloglog::HyperLogLog;
 use crate::Error;
-use crate::{ScaledType, _hash_murmur};
+use crate::{_hash_murmur, ScaledType};
 
 pub fn max_hash_for_scaled(scaled: ScaledType) -> u64 {
     match scaled {
@@ -150,13 +150,8 @@ impl<'de> Deserialize<'de> for KmerMinHash {
         let tmpsig = TempSig::deserialize(deserializer)?;
 
         let num = if tmpsig.max_hash != 0 { 0 } else { tmpsig.num };
-        let hash_function = match tmpsig.molecule.to_lowercase().as_ref() {
-            "protein" => HashFunctions::Murmur64Protein,
-            "dayhoff" => HashFunctions::Murmur64Dayhoff,
-            "hp" => HashFunctions::Murmur64Hp,
-            "dna" => HashFunctions::Murmur64Dna,
-            _ => unimplemented!(), // TODO: throw error here
-        };
+        let hash_function =
+            HashFunctions::try_from(tmpsig.molecule.as_str()).map_err(serde::de::Error::custom)?;
 
         // This shouldn't be necessary, but at some point we
         // created signatures with unordered mins =(
@@ -235,7 +230,7 @@ impl KmerMinHash {
     }
 
     pub fn is_protein(&self) -> bool {
-        self.hash_function == HashFunctions::Murmur64Protein
+        self.hash_function.protein()
     }
 
     pub fn max_hash(&self) -> u64 {
@@ -659,8 +654,8 @@ impl KmerMinHash {
         let mut prod = 0;
         let mut other_iter = other.mins.iter().enumerate();
         let mut next_hash = other_iter.next();
-        let a_sq: u64 = abunds.iter().map(|a| (a * a)).sum();
-        let b_sq: u64 = other_abunds.iter().map(|a| (a * a)).sum();
+        let a_sq: u64 = abunds.iter().map(|a| a * a).sum();
+        let b_sq: u64 = other_abunds.iter().map(|a| a * a).sum();
 
         for (i, hash) in self.mins.iter().enumerate() {
             while let Some((j, k)) = next_hash {
@@ -919,6 +914,14 @@ impl SigsTrait for KmerMinHash {
             return Err(Error::MismatchKSizes);
         }
         if self.hash_function != other.hash_function {
+            if matches!(self.hash_function, HashFunctions::Custom(_))
+                || matches!(other.hash_function, HashFunctions::Custom(_))
+            {
+                return Err(Error::MismatchHashFunction {
+                    h1: self.hash_function.to_string(),
+                    h2: other.hash_function.to_string(),
+                });
+            }
             // TODO: fix this error
             return Err(Error::MismatchDNAProt);
         }
@@ -1098,13 +1101,8 @@ impl<'de> Deserialize<'de> for KmerMinHashBTree {
         let tmpsig = TempSig::deserialize(deserializer)?;
 
         let num = if tmpsig.max_hash != 0 { 0 } else { tmpsig.num };
-        let hash_function = match tmpsig.molecule.to_lowercase().as_ref() {
-            "protein" => HashFunctions::Murmur64Protein,
-            "dayhoff" => HashFunctions::Murmur64Dayhoff,
-            "hp" => HashFunctions::Murmur64Hp,
-            "dna" => HashFunctions::Murmur64Dna,
-            _ => unimplemented!(), // TODO: throw error here
-        };
+        let hash_function =
+            HashFunctions::try_from(tmpsig.molecule.as_str()).map_err(serde::de::Error::custom)?;
 
         let current_max;
         // This shouldn't be necessary, but at some point we
@@ -1182,7 +1180,7 @@ impl KmerMinHashBTree {
     }
 
     pub fn is_protein(&self) -> bool {
-        self.hash_function == HashFunctions::Murmur64Protein
+        self.hash_function.protein()
     }
 
     pub fn max_hash(&self) -> u64 {
@@ -1504,8 +1502,8 @@ impl KmerMinHashBTree {
         let other_abunds = other.abunds.as_ref().unwrap();
 
         let mut prod = 0;
-        let a_sq: u64 = abunds.values().map(|a| (a * a)).sum();
-        let b_sq: u64 = other_abunds.values().map(|a| (a * a)).sum();
+        let a_sq: u64 = abunds.values().map(|a| a * a).sum();
+        let b_sq: u64 = other_abunds.values().map(|a| a * a).sum();
 
         for (hash, value) in abunds.iter() {
             if let Some(oa) = other_abunds.get(hash) {
@@ -1677,6 +1675,14 @@ impl SigsTrait for KmerMinHashBTree {
             return Err(Error::MismatchKSizes);
         }
         if self.hash_function != other.hash_function {
+            if matches!(self.hash_function, HashFunctions::Custom(_))
+                || matches!(other.hash_function, HashFunctions::Custom(_))
+            {
+                return Err(Error::MismatchHashFunction {
+                    h1: self.hash_function.to_string(),
+                    h2: other.hash_function.to_string(),
+                });
+            }
             // TODO: fix this error
             return Err(Error::MismatchDNAProt);
         }
@@ -0,0 +1,227 @@
+//! Cache decoded signatures from another storage.
+//!
+//! Loading a signature means reading it from the storage and parsing the
+//! JSON, which dominates the time of searches going over the same datasets
+//! many times (like each round of a gather). `CachedStorage` keeps the most
+//! recently used signatures in memory, up to a number of entries or bytes.
+
+use std::collections::{BTreeMap, HashMap};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+
+use getset::CopyGetters;
+
+use crate::signature::{Signature, SigsTrait};
+use crate::sketch::Sketch;
+use crate::storage::{InnerStorage, SigStore, Storage, StorageArgs};
+use crate::Result;
+
+/// Upper bound for the signatures kept by a `CachedStorage`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CacheLimit {
+    /// Number of signatures.
+    Entries(usize),
+    /// Approximate memory used by the sketches.
+    Bytes(usize),
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, CopyGetters)]
+#[getset(get_copy = "pub")]
+pub struct CacheStats {
+    hits: usize,
+    misses: usize,
+    /// Signatures currently in the cache.
+    entries: usize,
+    /// Approximate memory used by the cached sketches.
+    bytes: usize,
+}
+
+/// Path and (optionally) md5 of the sketch loaded from it.
+type CacheKey = (String, Option<String>);
+
+#[derive(Default)]
+struct Lru {
+    entries: HashMap<CacheKey, (SigStore, usize, u64)>,
+    // last use -> key, oldest first
+    order: BTreeMap<u64, CacheKey>,
+    tick: u64,
+    bytes: usize,
+}
+
+impl Lru {
+    fn get(&mut self, key: &CacheKey) -> Option<SigStore> {
+        self.tick += 1;
+        let tick = self.tick;
+        let (sig, _, last_used) = self.entries.get_mut(key)?;
+        self.order.remove(last_used);
+        self.order.insert(tick, key.clone());
+        *last_used = tick;
+        Some(sig.clone())
+    }
+
+    fn insert(&mut self, key: CacheKey, sig: SigStore, limit: CacheLimit) {
+        let size = sig_size(&sig);
+        if let CacheLimit::Bytes(max_bytes) = limit {
+            if size > max_bytes {
+                return;
+            }
+        }
+
+        self.remove(&key);
+        self.tick += 1;
+        self.order.insert(self.tick, key.clone());
+        self.entries.insert(key, (sig, size, self.tick));
+        self.bytes += size;
+
+        while match limit {
+            CacheLimit::Entries(max_entries) => self.entries.len() > max_entries,
+            CacheLimit::Bytes(max_bytes) => self.bytes > max_bytes,
+        } {
+            let Some((_, oldest)) = self.order.pop_first() else {
+                break;
+            };
+            if let Some((_, size, _)) = self.entries.remove(&oldest) {
+                self.bytes -= size;
+            }
+        }
+    }
+
+    fn remove(&mut self, key: &CacheKey) {
+        if let Some((_, size, last_used)) = self.entries.remove(key) {
+            self.order.remove(&last_used);
+            self.bytes -= size;
+        }
+    }
+
+    fn remove_path(&mut self, path: &str) {
+        let keys: Vec<_> = self
+            .entries
+            .keys()
+            .filter(|(p, _)| p == path)
+            .cloned()
+            .collect();
+        for key in keys {
+            self.remove(&key);
+        }
+    }
+}
+
+/// Approximate memory used by the sketches in `sig`.
+fn sig_size(sig: &Signature) -> usize {
+    sig.iter()
+        .map(|sketch| match sketch {
+            Sketch::MinHash(mh) => mh.size() * if mh.track_abundance() { 16 } else { 8 },
+            Sketch::LargeMinHash(mh) => mh.size() * if mh.track_abundance() { 16 } else { 8 },
+            Sketch::HyperLogLog(hll) => hll.size(),
+        })
+        .sum()
+}
+
+/// A read-through cache for signatures loaded from another storage.
+///
+/// Raw data (`load`) is not cached, and saving to a path drops the cached
+/// signatures for it. The spec and args are the ones from the wrapped
+/// storage, so indices save the original storage. Clones share the cache.
+#[derive(Clone)]
+pub struct CachedStorage {
+    inner: InnerStorage,
+    limit: CacheLimit,
+    cache: Arc<Mutex<Lru>>,
+    hits: Arc<AtomicUsize>,
+    misses: Arc<AtomicUsize>,
+}
+
+impl CachedStorage {
+    pub fn new(inner: InnerStorage, limit: CacheLimit) -> Self {
+        Self {
+            inner,
+            limit,
+            cache: Default::default(),
+            hits: Default::default(),
+            misses: Default::default(),
+        }
+    }
+
+    pub fn inner(&self) -> &InnerStorage {
+        &self.inner
+    }
+
+    pub fn limit(&self) -> CacheLimit {
+        self.limit
+    }
+
+    pub fn stats(&self) -> CacheStats {
+        let cache = self.cache.lock().unwrap();
+        CacheStats {
+            hits: self.hits.load(Ordering::Relaxed),
+            misses: self.misses.load(Ordering::Relaxed),
+            entries: cache.entries.len(),
+            bytes: cache.bytes,
+        }
+    }
+
+    /// Drop all cached signatures (statistics are kept).
+    pub fn clear(&self) {
+        *self.cache.lock().unwrap() = Lru::default();
+    }
+
+    fn cached<F>(&self, key: CacheKey, load: F) -> Result<SigStore>
+    where
+        F: FnOnce() -> Result<SigStore>,
+    {
+        if let Some(sig) = self.cache.lock().unwrap().get(&key) {
+            self.hits.fetch_add(1, Ordering::Relaxed);
+            return Ok(sig);
+        }
+        self.misses.fetch_add(1, Ordering::Relaxed);
+
+        // the lock is not held while loading, so other threads can keep
+        // using the cache
+        let sig = load()?;
+        self.cache
+            .lock()
+            .unwrap()
+            .insert(key, sig.clone(), self.limit);
+        Ok(sig)
+    }
+}
+
+impl Storage for CachedStorage {
+    fn save(&self, path: &str, content: &[u8]) -> Result<String> {
+        let saved = self.inner.save(path, content)?;
+        self.cache.lock().unwrap().remove_path(&saved);
+        Ok(saved)
+    }
+
+    fn load(&self, path: &str) -> Result<Vec<u8>> {
+        self.inner.load(path)
+    }
+
+    fn args(&self) -> Result<StorageArgs> {
+        self.inner.args()
+    }
+
+    fn load_sig(&self, path: &str) -> Result<SigStore> {
+        self.cached((path.into(), None), || self.inner.load_sig(path))
+    }
+
+    fn load_sig_with_md5(&self, path: &str, md5: &str) -> Result<SigStore> {
+        self.cached((path.into(), Some(md5.into())), || {
+            self.inner.load_sig_with_md5(path, md5)
+        })
+    }
+
+    fn spec(&self) -> String {
+        self.inner.spec()
+    }
+
+    fn save_sig(&self, path: &str, sig: Signature) -> Result<String> {
+        let saved = self.inner.save_sig(path, sig)?;
+        self.cache.lock().unwrap().remove_path(&saved);
+        Ok(saved)
+    }
+
+    fn cache_stats(&self) -> Option<CacheStats> {
+        Some(self.stats())
+    }
+}
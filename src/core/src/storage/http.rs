@@ -0,0 +1,500 @@
+//! Read zip files from HTTP servers, using range requests.
+//!
+//! Only the end of the file (to find the central directory), the central
+//! directory and the members actually loaded are downloaded. Both the
+//! central directory and members can be kept in a disk cache, which is
+//! discarded if the remote file changes (by size, `ETag` or
+//! `Last-Modified`).
+
+use std::collections::HashMap;
+use std::fs::{DirBuilder, File};
+use std::io::Read;
+use std::ops::Range;
+
+use camino::Utf8Path as Path;
+use camino::Utf8PathBuf as PathBuf;
+use log::warn;
+use serde::{Deserialize, Serialize};
+
+use super::zip_writer::{
+    CENTRAL_HEADER_SIG, EOCD_SIG, LOCAL_HEADER_SIG, ZIP64_EOCD_SIG, ZIP64_LOCATOR_SIG,
+};
+use crate::storage::{Storage, StorageArgs, StorageError};
+use crate::Result;
+
+// End of central directory record without comment
+const EOCD_SIZE: usize = 22;
+const ZIP64_LOCATOR_SIZE: usize = 20;
+const ZIP64_EOCD_SIZE: usize = 56;
+const LOCAL_HEADER_SIZE: usize = 30;
+
+// Enough for the end of central directory records of zip files without
+// comments. If not found, the largest possible comment is downloaded too.
+const TAIL_SIZE: u64 = 4096;
+const MAX_TAIL_SIZE: u64 = (EOCD_SIZE + ZIP64_LOCATOR_SIZE + ZIP64_EOCD_SIZE + 65535) as u64;
+
+// Guess for the size of local extra fields, to load members with only one
+// request in most cases.
+const LOCAL_EXTRA_SLACK: u64 = 256;
+
+const METHOD_STORED: u16 = 0;
+const METHOD_DEFLATED: u16 = 8;
+
+const INDEX_FILE: &str = "index.json";
+const MEMBERS_DIR: &str = "members";
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct Member {
+    offset: u64,
+    compressed_size: u64,
+    size: u64,
+    method: u16,
+    crc32: u32,
+}
+
+impl Member {
+    /// Whether `content` has the size and checksum from the central directory.
+    fn matches(&self, content: &[u8]) -> bool {
+        content.len() as u64 == self.size && crc32fast::hash(content) == self.crc32
+    }
+}
+
+/// Parsed central directory, saved in the disk cache.
+#[derive(Debug, Serialize, Deserialize)]
+struct Index {
+    url: String,
+    length: u64,
+    validator: Option<String>,
+    members: HashMap<String, Member>,
+    dirs: Vec<String>,
+}
+
+/// Load files from a zip file in a HTTP server (read-only).
+///
+/// The server must support range requests. Use `http://` or `https://`
+/// URLs as spec with `InnerStorage::from_spec` (for example, as the
+/// storage spec of a RevIndex).
+pub struct HttpZipStorage {
+    url: String,
+    agent: ureq::Agent,
+    index: Index,
+    subdir: Option<String>,
+    cache_dir: Option<PathBuf>,
+}
+
+/// Disk cache used by `HttpZipStorage::from_url`: `SOURMASH_HTTP_CACHE`, or
+/// `sourmash-http-cache` in the temporary directory.
+pub fn default_cache_dir() -> PathBuf {
+    match std::env::var("SOURMASH_HTTP_CACHE") {
+        Ok(dir) => dir.into(),
+        Err(_) => {
+            let tmp = std::env::temp_dir().join("sourmash-http-cache");
+            PathBuf::from_path_buf(tmp).unwrap_or_else(|_| "sourmash-http-cache".into())
+        }
+    }
+}
+
+impl HttpZipStorage {
+    /// Open `url`, caching data in `default_cache_dir()`.
+    pub fn from_url(url: &str) -> Result<Self> {
+        Self::new(url, Some(&default_cache_dir()))
+    }
+
+    /// Open `url`, caching data in `cache_dir` (if any).
+    pub fn new(url: &str, cache_dir: Option<&Path>) -> Result<Self> {
+        let agent = ureq::AgentBuilder::new().build();
+
+        let (tail, length, validator) = fetch_tail(&agent, url, TAIL_SIZE)?;
+        let cache_dir = cache_dir.map(|dir| dir.join(format!("{:x}", md5::compute(url))));
+
+        let cached = cache_dir
+            .as_ref()
+            .and_then(|dir| load_index(dir))
+            .filter(|index| {
+                index.url == url && index.length == length && index.validator == validator
+            });
+
+        let index = match cached {
+            Some(index) => index,
+            None => {
+                let (members, dirs) = read_central_directory(&agent, url, tail, length)?;
+                let index = Index {
+                    url: url.into(),
+                    length,
+                    validator,
+                    members,
+                    dirs,
+                };
+                // the cache is best-effort, it can be read-only or full
+                if let Some(dir) = &cache_dir {
+                    if let Err(e) = save_index(dir, &index) {
+                        warn!("couldn't cache the index of {} in '{}': {}", url, dir, e);
+                    }
+                }
+                index
+            }
+        };
+
+        // same as ZipStorage
+        let subdir = if index.dirs.len() == 1 {
+            Some(index.dirs[0].clone())
+        } else {
+            None
+        };
+
+        Ok(Self {
+            url: url.into(),
+            agent,
+            index,
+            subdir,
+            cache_dir,
+        })
+    }
+
+    pub fn url(&self) -> &str {
+        &self.url
+    }
+
+    pub fn subdir(&self) -> Option<String> {
+        self.subdir.clone()
+    }
+
+    pub fn filenames(&self) -> Vec<String> {
+        let mut filenames: Vec<_> = self.index.members.keys().cloned().collect();
+        filenames.sort_unstable();
+        filenames
+    }
+
+    fn member_cache_path(&self, name: &str) -> Option<PathBuf> {
+        let dir = self.cache_dir.as_ref()?;
+        Some(
+            dir.join(MEMBERS_DIR)
+                .join(format!("{:x}", md5::compute(name))),
+        )
+    }
+
+    fn fetch_member(&self, name: &str, member: &Member) -> Result<Vec<u8>> {
+        let error = || StorageError::DataReadError(format!("{}: {}", self.url, name));
+
+        let start = member.offset;
+        let guess = LOCAL_HEADER_SIZE as u64
+            + name.len() as u64
+            + LOCAL_EXTRA_SLACK
+            + member.compressed_size;
+        let end = start.saturating_add(guess).min(self.index.length);
+        let mut raw = fetch(&self.agent, &self.url, start..end)?;
+
+        if raw.len() < LOCAL_HEADER_SIZE || le_u32(&raw, 0) != LOCAL_HEADER_SIG {
+            return Err(error().into());
+        }
+        let data_start = LOCAL_HEADER_SIZE + le_u16(&raw, 26) as usize + le_u16(&raw, 28) as usize;
+        let data_end = usize::try_from(member.compressed_size)
+            .ok()
+            .and_then(|size| data_start.checked_add(size))
+            .ok_or_else(error)?;
+        if data_end > raw.len() {
+            // local extra fields larger than expected
+            let rest_end = start.checked_add(data_end as u64).ok_or_else(error)?;
+            let rest = fetch(&self.agent, &self.url, start + raw.len() as u64..rest_end)?;
+            raw.extend(rest);
+        }
+        let data = &raw[data_start..data_end];
+
+        let content = match member.method {
+            METHOD_STORED => data.to_vec(),
+            METHOD_DEFLATED => {
+                // no preallocation, and stop decoding once past `size`:
+                // both come from the server
+                let mut content = Vec::new();
+                flate2::read::DeflateDecoder::new(data)
+                    .take(member.size.saturating_add(1))
+                    .read_to_end(&mut content)
+                    .map_err(|_| error())?;
+                content
+            }
+            _ => return Err(error().into()),
+        };
+        if !member.matches(&content) {
+            return Err(error().into());
+        }
+
+        Ok(content)
+    }
+}
+
+impl Storage for HttpZipStorage {
+    fn save(&self, _path: &str, _content: &[u8]) -> Result<String> {
+        Err(StorageError::ReadOnly(self.spec()).into())
+    }
+
+    fn load(&self, path: &str) -> Result<Vec<u8>> {
+        let (name, member) = self
+            .index
+            .members
+            .get_key_value(path)
+            .or_else(|| {
+                let subdir = self.subdir.as_ref()?;
+                self.index
+                    .members
+                    .get_key_value(&(subdir.to_owned() + path))
+            })
+            .ok_or_else(|| StorageError::PathNotFoundError(path.into()))?;
+
+        let cache_path = self.member_cache_path(name);
+        if let Some(cache_path) = &cache_path {
+            if let Ok(content) = std::fs::read(cache_path) {
+                if member.matches(&content) {
+                    return Ok(content);
+                }
+                warn!("evicting corrupted {} from '{}'", name, cache_path);
+                let _ = std::fs::remove_file(cache_path);
+            }
+        }
+
+        let content = self.fetch_member(name, member)?;
+        if let Some(cache_path) = &cache_path {
+            if let Err(e) = write_atomic(cache_path, &content) {
+                warn!("couldn't cache {} in '{}': {}", name, cache_path, e);
+            }
+        }
+        Ok(content)
+    }
+
+    fn args(&self) -> Result<StorageArgs> {
+        Ok(StorageArgs::HttpZipStorage {
+            url: self.url.clone(),
+        })
+    }
+
+    fn spec(&self) -> String {
+        self.url.clone()
+    }
+}
+
+fn remote_error(url: &str, e: impl ToString) -> crate::Error {
+    StorageError::RemoteError(url.into(), e.to_string()).into()
+}
+
+/// Download the last `size` bytes of `url`, returning them with the total
+/// length and a validator (`ETag` or `Last-Modified`) for the file.
+fn fetch_tail(agent: &ureq::Agent, url: &str, size: u64) -> Result<(Vec<u8>, u64, Option<String>)> {
+    let response = agent
+        .get(url)
+        .set("Range", &format!("bytes=-{size}"))
+        .call()
+        .map_err(|e| remote_error(url, e))?;
+    if response.status() != 206 {
+        return Err(remote_error(url, "server doesn't support range requests"));
+    }
+
+    // Content-Range: bytes <start>-<end>/<length>
+    let length = response
+        .header("Content-Range")
+        .and_then(|range| range.rsplit_once('/'))
+        .and_then(|(_, length)| length.parse().ok())
+        .ok_or_else(|| remote_error(url, "missing length in Content-Range"))?;
+    let validator = response
+        .header("ETag")
+        .or_else(|| response.header("Last-Modified"))
+        .map(String::from);
+
+    let mut tail = vec![];
+    response
+        .into_reader()
+        .take(size)
+        .read_to_end(&mut tail)
+        .map_err(|e| remote_error(url, e))?;
+    Ok((tail, length, validator))
+}
+
+fn fetch(agent: &ureq::Agent, url: &str, range: Range<u64>) -> Result<Vec<u8>> {
+    if range.is_empty() {
+        return Ok(vec![]);
+    }
+    let response = agent
+        .get(url)
+        .set("Range", &format!("bytes={}-{}", range.start, range.end - 1))
+        .call()
+        .map_err(|e| remote_error(url, e))?;
+    if response.status() != 206 {
+        return Err(remote_error(url, "server doesn't support range requests"));
+    }
+
+    let size = range.end - range.start;
+    let mut data = Vec::new();
+    response
+        .into_reader()
+        .take(size)
+        .read_to_end(&mut data)
+        .map_err(|e| remote_error(url, e))?;
+    if data.len() as u64 != size {
+        return Err(remote_error(url, "incomplete response"));
+    }
+    Ok(data)
+}
+
+type CentralDirectory = (HashMap<String, Member>, Vec<String>);
+
+fn read_central_directory(
+    agent: &ureq::Agent,
+    url: &str,
+    mut tail: Vec<u8>,
+    length: u64,
+) -> Result<CentralDirectory> {
+    let error = || StorageError::DataReadError(url.into());
+
+    let mut eocd = find_eocd(&tail);
+    if eocd.is_none() && (tail.len() as u64) < length.min(MAX_TAIL_SIZE) {
+        // there is a comment at the end of the file
+        tail = fetch_tail(agent, url, MAX_TAIL_SIZE)?.0;
+        eocd = find_eocd(&tail);
+    }
+    let eocd = eocd.ok_or_else(error)?;
+    let tail_start = length - tail.len() as u64;
+
+    // read a range, reusing the tail if possible
+    let read = |range: Range<u64>| -> Result<Vec<u8>> {
+        if range.start >= tail_start && range.end <= length {
+            let start = (range.start - tail_start) as usize;
+            let end = (range.end - tail_start) as usize;
+            Ok(tail[start..end].to_vec())
+        } else {
+            fetch(agent, url, range)
+        }
+    };
+
+    let mut n_entries = le_u16(&tail, eocd + 10) as u64;
+    let mut cd_size = le_u32(&tail, eocd + 12) as u64;
+    let mut cd_offset = le_u32(&tail, eocd + 16) as u64;
+
+    if n_entries == 0xFFFF || cd_size == 0xFFFF_FFFF || cd_offset == 0xFFFF_FFFF {
+        let locator = eocd.checked_sub(ZIP64_LOCATOR_SIZE).ok_or_else(error)?;
+        if le_u32(&tail, locator) != ZIP64_LOCATOR_SIG {
+            return Err(error().into());
+        }
+        let offset = le_u64(&tail, locator + 8);
+        let record_end = offset
+            .checked_add(ZIP64_EOCD_SIZE as u64)
+            .ok_or_else(error)?;
+        let record = read(offset..record_end)?;
+        if le_u32(&record, 0) != ZIP64_EOCD_SIG {
+            return Err(error().into());
+        }
+        n_entries = le_u64(&record, 32);
+        cd_size = le_u64(&record, 40);
+        cd_offset = le_u64(&record, 48);
+    }
+
+    let end = cd_offset
+        .checked_add(cd_size)
+        .filter(|end| *end <= length)
+        .ok_or_else(error)?;
+    let cd = read(cd_offset..end)?;
+    parse_central_directory(&cd, n_entries).ok_or_else(|| error().into())
+}
+
+fn find_eocd(tail: &[u8]) -> Option<usize> {
+    (0..=tail.len().checked_sub(EOCD_SIZE)?).rev().find(|&i| {
+        le_u32(tail, i) == EOCD_SIG && i + EOCD_SIZE + le_u16(tail, i + 20) as usize == tail.len()
+    })
+}
+
+fn parse_central_directory(cd: &[u8], n_entries: u64) -> Option<CentralDirectory> {
+    let mut members = HashMap::default();
+    let mut dirs = vec![];
+    let mut pos = 0;
+
+    for _ in 0..n_entries {
+        let header = cd.get(pos..pos + 46)?;
+        if le_u32(header, 0) != CENTRAL_HEADER_SIG {
+            return None;
+        }
+        let name_len = le_u16(header, 28) as usize;
+        let extra_len = le_u16(header, 30) as usize;
+        let comment_len = le_u16(header, 32) as usize;
+        let name = cd.get(pos + 46..pos + 46 + name_len)?;
+        let name = String::from_utf8_lossy(name).into_owned();
+        let extra = cd.get(pos + 46 + name_len..pos + 46 + name_len + extra_len)?;
+
+        let mut member = Member {
+            method: le_u16(header, 10),
+            crc32: le_u32(header, 16),
+            compressed_size: le_u32(header, 20) as u64,
+            size: le_u32(header, 24) as u64,
+            offset: le_u32(header, 42) as u64,
+        };
+        apply_zip64_extra(&mut member, extra)?;
+
+        if name.ends_with('/') {
+            dirs.push(name);
+        } else {
+            members.insert(name, member);
+        }
+        pos += 46 + name_len + extra_len + comment_len;
+    }
+
+    Some((members, dirs))
+}
+
+/// Sizes and offset that don't fit in the central header are in the zip64
+/// extra field, in this order.
+fn apply_zip64_extra(member: &mut Member, mut extra: &[u8]) -> Option<()> {
+    while extra.len() >= 4 {
+        let id = le_u16(extra, 0);
+        let len = le_u16(extra, 2) as usize;
+        let data = extra.get(4..4 + len)?;
+        if id == 0x0001 {
+            let mut fields = data.chunks_exact(8).map(|chunk| le_u64(chunk, 0));
+            if member.size == 0xFFFF_FFFF {
+                member.size = fields.next()?;
+            }
+            if member.compressed_size == 0xFFFF_FFFF {
+                member.compressed_size = fields.next()?;
+            }
+            if member.offset == 0xFFFF_FFFF {
+                member.offset = fields.next()?;
+            }
+        }
+        extra = &extra[4 + len..];
+    }
+    Some(())
+}
+
+fn load_index(dir: &Path) -> Option<Index> {
+    let file = File::open(dir.join(INDEX_FILE)).ok()?;
+    serde_json::from_reader(std::io::BufReader::new(file)).ok()
+}
+
+/// Start a new cache for `index`, removing data for older versions.
+fn save_index(dir: &Path, index: &Index) -> Result<()> {
+    if dir.exists() {
+        std::fs::remove_dir_all(dir)?;
+    }
+    DirBuilder::new()
+        .recursive(true)
+        .create(dir.join(MEMBERS_DIR))?;
+    write_atomic(&dir.join(INDEX_FILE), &serde_json::to_vec(index)?)
+}
+
+/// Write to a temporary file first, so other processes sharing the cache
+/// never see partial files.
+fn write_atomic(path: &Path, content: &[u8]) -> Result<()> {
+    let tmp = PathBuf::from(format!("{}.{}.tmp", path, std::process::id()));
+    let result = std::fs::write(&tmp, content).and_then(|_| std::fs::rename(&tmp, path));
+    if result.is_err() {
+        let _ = std::fs::remove_file(&tmp);
+    }
+    Ok(result?)
+}
+
+fn le_u16(data: &[u8], at: usize) -> u16 {
+    u16::from_le_bytes([data[at], data[at + 1]])
+}
+
+fn le_u32(data: &[u8], at: usize) -> u32 {
+    u32::from_le_bytes(data[at..at + 4].try_into().unwrap())
+}
+
+fn le_u64(data: &[u8], at: usize) -> u64 {
+    u64::from_le_bytes(data[at..at + 8].try_into().unwrap())
+}
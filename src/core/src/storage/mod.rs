@@ -8,7 +8,7 @@ use std::sync::{Arc, RwLock};
 use camino::Utf8Path as Path;
 use camino::Utf8PathBuf as PathBuf;
 use cfg_if::cfg_if;
-use once_cell::sync::OnceCell;
+use once_cell::sync::{Lazy, OnceCell};
 use serde::{Deserialize, Serialize};
 use thiserror::Error;
 use typed_builder::TypedBuilder;
@@ -28,21 +28,35 @@ pub trait Storage {
     /// Load bytes from path
     fn load(&self, path: &str) -> Result<Vec<u8>>;
 
-    /// Args for initializing a new Storage
-    fn args(&self) -> StorageArgs;
+    /// Args for initializing a new Storage. Fails if the storage can't be
+    /// opened again, like a `ZipStorage` not backed by a file.
+    fn args(&self) -> Result<StorageArgs>;
 
-    /// Load signature from internal path
+    /// Load signature from internal path. Fails if there are multiple
+    /// signatures in it, use `load_sig_with_md5` for these.
     fn load_sig(&self, path: &str) -> Result<SigStore> {
         let raw = self.load(path)?;
         let mut vs = Signature::from_reader(&mut &raw[..])?;
         if vs.len() > 1 {
-            unimplemented!("only one Signature currently allowed");
+            return Err(StorageError::MultipleSignatures(path.into()).into());
         }
         let sig = vs.swap_remove(0);
 
         Ok(sig.into())
     }
 
+    /// Load the sketch with `md5` (as in a manifest record) from internal
+    /// path, which can contain multiple signatures. The signature is
+    /// returned with only that sketch.
+    fn load_sig_with_md5(&self, path: &str, md5: &str) -> Result<SigStore> {
+        let raw = self.load(path)?;
+        let sigs = Signature::from_reader(&mut &raw[..])?;
+        let sig = sig_with_md5(sigs, md5)
+            .ok_or_else(|| StorageError::MissingSketch(md5.into(), path.into()))?;
+
+        Ok(sig.into())
+    }
+
     /// Return a spec for creating/opening a storage
     fn spec(&self) -> String;
 
@@ -54,6 +68,11 @@ pub trait Storage {
         }
         self.save(path, &buffer)
     }
+
+    /// Statistics for storages caching signatures, like `CachedStorage`.
+    fn cache_stats(&self) -> Option<CacheStats> {
+        None
+    }
 }
 
 #[non_exhaustive]
@@ -70,6 +89,33 @@ pub enum StorageError {
 
     #[error("Storage for path {1} requires the '{0}' feature to be enabled")]
     MissingFeature(String, String),
+
+    #[error("Storage {0} is read-only")]
+    ReadOnly(String),
+
+    #[error("Zip file {0} was already finished")]
+    ArchiveFinished(String),
+
+    #[error("No storage available to save {0}")]
+    MissingStorage(String),
+
+    #[error("{0} contains multiple signatures")]
+    MultipleSignatures(String),
+
+    #[error("No sketch with md5 {0} in {1}")]
+    MissingSketch(String, String),
+
+    #[error("Invalid storage spec: {0}")]
+    InvalidSpec(String),
+
+    #[error("No storage registered for scheme '{0}'")]
+    UnknownScheme(String),
+
+    #[error("A storage is already registered for scheme '{0}'")]
+    SchemeAlreadyRegistered(String),
+
+    #[error("Error fetching {0}: {1}")]
+    RemoteError(String, String),
 }
 
 /// InnerStorage: a catch-all type that allows using any Storage in
@@ -111,10 +157,79 @@ impl PartialEq for SigStore {
     }
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
-#[serde(untagged)]
+/// Args for initializing a storage, serialized like the `storage` entry of
+/// an SBT description (`{"backend": ..., "args": ...}`).
+///
+/// The untagged `{"path": ...}` form written by earlier versions is still
+/// read, as `FSStorage`.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(remote = "Self", tag = "backend", content = "args")]
 pub enum StorageArgs {
-    FSStorage { path: String },
+    FSStorage {
+        path: String,
+    },
+    ZipStorage {
+        path: String,
+    },
+    TarStorage {
+        path: String,
+    },
+    MemStorage,
+    RocksDBStorage {
+        path: String,
+    },
+    /// A zip file in a HTTP server, read with range requests.
+    HttpZipStorage {
+        url: String,
+    },
+    /// A storage added with `register_storage`.
+    Custom {
+        scheme: String,
+        location: String,
+    },
+}
+
+impl Serialize for StorageArgs {
+    fn serialize<S>(&self, serializer: S) -> std::result::Result<S::Ok, S::Error>
+    where
+        S: serde::Serializer,
+    {
+        StorageArgs::serialize(self, serializer)
+    }
+}
+
+impl<'de> Deserialize<'de> for StorageArgs {
+    fn deserialize<D>(deserializer: D) -> std::result::Result<Self, D::Error>
+    where
+        D: serde::Deserializer<'de>,
+    {
+        #[derive(Deserialize)]
+        #[serde(untagged)]
+        enum Repr {
+            Tagged(#[serde(with = "StorageArgs")] StorageArgs),
+            Legacy { path: String },
+        }
+
+        Ok(match Repr::deserialize(deserializer)? {
+            Repr::Tagged(args) => args,
+            Repr::Legacy { path } => StorageArgs::FSStorage { path },
+        })
+    }
+}
+
+impl StorageArgs {
+    /// The spec for opening this storage with `InnerStorage::from_spec`.
+    pub fn spec(&self) -> String {
+        match self {
+            StorageArgs::FSStorage { path } => format!("fs://{path}"),
+            StorageArgs::ZipStorage { path } => format!("zip://{path}"),
+            StorageArgs::TarStorage { path } => format!("tar://{path}"),
+            StorageArgs::MemStorage => "memory://".into(),
+            StorageArgs::RocksDBStorage { path } => format!("rocksdb://{path}"),
+            StorageArgs::HttpZipStorage { url } => url.clone(),
+            StorageArgs::Custom { scheme, location } => format!("{scheme}://{location}"),
+        }
+    }
 }
 
 /// Store files locally into a directory
@@ -146,12 +261,25 @@ pub struct ZipStorage {
 #[derive(TypedBuilder, Debug, Clone, Default)]
 pub struct MemStorage {
     //store: HashMap<String, Vec<u8>>,
-    sigs: Arc<RwLock<HashMap<String, SigStore>>>,
+    sigs: Arc<RwLock<HashMap<String, Vec<SigStore>>>>,
 }
 
+mod cache;
+mod tar;
+mod zip_writer;
+pub use self::cache::{CacheLimit, CacheStats, CachedStorage};
+pub use self::tar::TarStorage;
+pub use self::zip_writer::{ZipStorageWriter, MANIFEST_PATH};
+
 #[cfg(all(feature = "branchwater", not(target_arch = "wasm32")))]
 pub mod rocksdb;
 
+#[cfg(all(feature = "http", not(target_arch = "wasm32")))]
+mod http;
+
+#[cfg(all(feature = "http", not(target_arch = "wasm32")))]
+pub use self::http::{default_cache_dir, HttpZipStorage};
+
 #[cfg(all(feature = "branchwater", not(target_arch = "wasm32")))]
 pub use self::rocksdb::RocksDBStorage;
 
@@ -164,31 +292,97 @@ impl InnerStorage {
         InnerStorage(Arc::new(RwLock::new(inner)))
     }
 
+    /// Open a storage from a `scheme://location` spec, using the
+    /// constructor registered for `scheme`.
     pub fn from_spec(spec: String) -> Result<Self> {
-        Ok(match spec {
-            x if x.starts_with("fs") => {
-                let path = x.split("://").last().expect("not a valid path");
-                InnerStorage::new(FSStorage::new("", path))
+        let (scheme, location) = spec
+            .split_once("://")
+            .ok_or_else(|| StorageError::InvalidSpec(spec.clone()))?;
+
+        let constructor = STORAGES
+            .read()
+            .unwrap()
+            .get(scheme)
+            .cloned()
+            .ok_or_else(|| StorageError::UnknownScheme(scheme.into()))?;
+        constructor(location)
+    }
+
+    pub fn from_args(args: &StorageArgs) -> Result<Self> {
+        Self::from_spec(args.spec())
+    }
+}
+
+type StorageConstructor = dyn Fn(&str) -> Result<InnerStorage> + Send + Sync;
+
+static STORAGES: Lazy<RwLock<HashMap<String, Arc<StorageConstructor>>>> = Lazy::new(|| {
+    let mut storages: HashMap<String, Arc<StorageConstructor>> = HashMap::default();
+    storages.insert(
+        "fs".into(),
+        Arc::new(|path| Ok(InnerStorage::new(FSStorage::new("", path)))),
+    );
+    storages.insert(
+        "memory".into(),
+        Arc::new(|_| Ok(InnerStorage::new(MemStorage::new()))),
+    );
+    storages.insert(
+        "zip".into(),
+        Arc::new(|path| Ok(InnerStorage::new(ZipStorage::from_file(path)?))),
+    );
+    storages.insert(
+        "tar".into(),
+        Arc::new(|path| Ok(InnerStorage::new(TarStorage::from_file(path)?))),
+    );
+    storages.insert(
+        "rocksdb".into(),
+        Arc::new(|path| {
+            cfg_if! {
+                if #[cfg(all(feature = "branchwater", not(target_arch = "wasm32")))] {
+                    Ok(InnerStorage::new(RocksDBStorage::from_path(path)?))
+                } else {
+                    Err(StorageError::MissingFeature("branchwater".into(), path.into()).into())
+                }
             }
-            x if x.starts_with("memory") => InnerStorage::new(MemStorage::new()),
-            x if x.starts_with("rocksdb") => {
-                let path = x.split("://").last().expect("not a valid path");
-
+        }),
+    );
+    for scheme in ["http", "https"] {
+        storages.insert(
+            scheme.into(),
+            Arc::new(move |location| {
+                let url = format!("{scheme}://{location}");
                 cfg_if! {
-                    if #[cfg(all( feature = "branchwater", not(target_arch = "wasm32")))] {
-                        InnerStorage::new(RocksDBStorage::from_path(path))
+                    if #[cfg(all(feature = "http", not(target_arch = "wasm32")))] {
+                        Ok(InnerStorage::new(HttpZipStorage::from_url(&url)?))
                     } else {
-                        return Err(StorageError::MissingFeature("branchwater".into(), path.into()).into())
+                        Err(StorageError::MissingFeature("http".into(), url).into())
                     }
                 }
-            }
-            x if x.starts_with("zip") => {
-                let path = x.split("://").last().expect("not a valid path");
-                InnerStorage::new(ZipStorage::from_file(path)?)
-            }
-            _ => todo!("storage not supported, throw error"),
-        })
+            }),
+        );
+    }
+    RwLock::new(storages)
+});
+
+/// Register a constructor for storages with specs like `scheme://location`,
+/// making them available to `InnerStorage::from_spec` (and so to indices
+/// saving their storage spec). The constructor receives the location.
+///
+/// Schemes can't be registered twice, including the built-in ones
+/// (`fs`, `memory`, `zip`, `tar`, `rocksdb`, `http` and `https`).
+pub fn register_storage<F>(scheme: &str, constructor: F) -> Result<()>
+where
+    F: Fn(&str) -> Result<InnerStorage> + Send + Sync + 'static,
+{
+    if scheme.is_empty() || scheme.contains("://") {
+        return Err(StorageError::InvalidSpec(scheme.into()).into());
     }
+
+    let mut storages = STORAGES.write().unwrap();
+    if storages.contains_key(scheme) {
+        return Err(StorageError::SchemeAlreadyRegistered(scheme.into()).into());
+    }
+    storages.insert(scheme.into(), Arc::new(constructor));
+    Ok(())
 }
 
 impl Storage for InnerStorage {
@@ -200,7 +394,7 @@ impl Storage for InnerStorage {
         self.0.load(path)
     }
 
-    fn args(&self) -> StorageArgs {
+    fn args(&self) -> Result<StorageArgs> {
         self.0.args()
     }
 
@@ -210,24 +404,41 @@ impl Storage for InnerStorage {
         Ok(store)
     }
 
+    fn load_sig_with_md5(&self, path: &str, md5: &str) -> Result<SigStore> {
+        let mut store = self.0.load_sig_with_md5(path, md5)?;
+        store.storage = Some(self.clone());
+        Ok(store)
+    }
+
     fn spec(&self) -> String {
         self.0.spec()
     }
+
+    fn save_sig(&self, path: &str, sig: Signature) -> Result<String> {
+        self.0.save_sig(path, sig)
+    }
+
+    fn cache_stats(&self) -> Option<CacheStats> {
+        self.0.cache_stats()
+    }
 }
 
-impl From<&StorageArgs> for FSStorage {
-    fn from(other: &StorageArgs) -> FSStorage {
+impl TryFrom<&StorageArgs> for FSStorage {
+    type Error = Error;
+
+    fn try_from(other: &StorageArgs) -> Result<FSStorage> {
         match other {
             StorageArgs::FSStorage { path } => {
                 let mut fullpath = PathBuf::new();
                 fullpath.push(".");
                 fullpath.push(path);
 
-                FSStorage {
+                Ok(FSStorage {
                     fullpath,
                     subdir: path.clone(),
-                }
+                })
             }
+            _ => Err(StorageError::InvalidSpec(other.spec()).into()),
         }
     }
 }
@@ -244,7 +455,7 @@ where
         self.read().unwrap().load(path)
     }
 
-    fn args(&self) -> StorageArgs {
+    fn args(&self) -> Result<StorageArgs> {
         self.read().unwrap().args()
     }
 
@@ -252,9 +463,21 @@ where
         self.read().unwrap().load_sig(path)
     }
 
+    fn load_sig_with_md5(&self, path: &str, md5: &str) -> Result<SigStore> {
+        self.read().unwrap().load_sig_with_md5(path, md5)
+    }
+
     fn spec(&self) -> String {
         self.read().unwrap().spec()
     }
+
+    fn save_sig(&self, path: &str, sig: Signature) -> Result<String> {
+        self.read().unwrap().save_sig(path, sig)
+    }
+
+    fn cache_stats(&self) -> Option<CacheStats> {
+        self.read().unwrap().cache_stats()
+    }
 }
 
 impl FSStorage {
@@ -275,6 +498,30 @@ impl FSStorage {
         fullpath.push(&self.subdir);
         self.fullpath = fullpath;
     }
+
+    /// Paths of all files under the storage directory (recursively),
+    /// relative to it and sorted.
+    pub fn filenames(&self) -> Result<Vec<String>> {
+        let mut filenames = vec![];
+        let mut dirs = vec![self.fullpath.clone()];
+        while let Some(dir) = dirs.pop() {
+            for entry in dir.read_dir_utf8()? {
+                let entry = entry?;
+                // symlinks to files are followed, but not to directories
+                if entry.file_type()?.is_dir() {
+                    dirs.push(entry.path().into());
+                } else if entry.path().is_file() {
+                    let path = entry
+                        .path()
+                        .strip_prefix(&self.fullpath)
+                        .expect("path outside the storage directory");
+                    filenames.push(path.to_string());
+                }
+            }
+        }
+        filenames.sort_unstable();
+        Ok(filenames)
+    }
 }
 
 impl Storage for FSStorage {
@@ -295,30 +542,21 @@ impl Storage for FSStorage {
     }
 
     fn load(&self, path: &str) -> Result<Vec<u8>> {
-        let path = self.fullpath.join(path);
-        let file = File::open(path)?;
+        let fpath = self.fullpath.join(path);
+        let file = File::open(fpath).map_err(|e| match e.kind() {
+            std::io::ErrorKind::NotFound => StorageError::PathNotFoundError(path.into()).into(),
+            _ => Error::from(e),
+        })?;
         let mut buf_reader = BufReader::new(file);
         let mut contents = Vec::new();
         buf_reader.read_to_end(&mut contents)?;
         Ok(contents)
     }
 
-    fn args(&self) -> StorageArgs {
-        StorageArgs::FSStorage {
+    fn args(&self) -> Result<StorageArgs> {
+        Ok(StorageArgs::FSStorage {
             path: self.subdir.clone(),
-        }
-    }
-
-    fn load_sig(&self, path: &str) -> Result<SigStore> {
-        let raw = self.load(path)?;
-
-        let mut vs = Signature::from_reader(&mut &raw[..])?;
-        if vs.len() > 1 {
-            unimplemented!("only one Signature currently allowed when using 'load_sig'");
-        }
-        let sig = vs.swap_remove(0);
-
-        Ok(sig.into())
+        })
     }
 
     fn spec(&self) -> String {
@@ -326,6 +564,22 @@ impl Storage for FSStorage {
     }
 }
 
+/// Find the sketch with `md5` in `sigs`, returning its signature with only
+/// that sketch.
+fn sig_with_md5(sigs: Vec<Signature>, md5: &str) -> Option<Signature> {
+    sigs.into_iter().find_map(|mut sig| {
+        let sketch = sig.iter().find(|sketch| match sketch {
+            Sketch::MinHash(mh) => mh.md5sum() == md5,
+            Sketch::LargeMinHash(mh) => mh.md5sum() == md5,
+            Sketch::HyperLogLog(_) => false,
+        })?;
+        let sketch = sketch.clone();
+        sig.reset_sketches();
+        sig.push(sketch);
+        Some(sig)
+    })
+}
+
 fn lookup<'a, P: AsRef<Path>>(
     metadata: &'a Metadata,
     path: P,
@@ -351,8 +605,10 @@ fn find_subdirs<'a>(archive: &'a piz::ZipArchive<'a>) -> Result<Option<String>>
 }
 
 impl Storage for ZipStorage {
+    /// Zip files are opened read-only; use `ZipStorageWriter` to create
+    /// new ones.
     fn save(&self, _path: &str, _content: &[u8]) -> Result<String> {
-        unimplemented!();
+        Err(StorageError::ReadOnly(self.spec()).into())
     }
 
     fn load(&self, path: &str) -> Result<Vec<u8>> {
@@ -378,23 +634,13 @@ impl Storage for ZipStorage {
         Ok(contents)
     }
 
-    fn args(&self) -> StorageArgs {
-        unimplemented!();
-    }
-
-    fn load_sig(&self, path: &str) -> Result<SigStore> {
-        let raw = self.load(path)?;
-        let mut vs = Signature::from_reader(&mut &raw[..])?;
-        if vs.len() > 1 {
-            unimplemented!("only one Signature currently allowed");
-        }
-        let sig = vs.swap_remove(0);
-
-        Ok(sig.into())
+    fn args(&self) -> Result<StorageArgs> {
+        let path = self.path().ok_or(StorageError::EmptyPathError)?;
+        Ok(StorageArgs::ZipStorage { path: path.into() })
     }
 
     fn spec(&self) -> String {
-        format!("zip://{}", self.path().unwrap_or_else(|| "".into()))
+        format!("zip://{}", self.path().unwrap_or_default())
     }
 }
 
@@ -523,19 +769,15 @@ impl ReadData<Signature> for SigStore {
 }
 
 impl SigStore {
+    /// Save the signature into `path` in its storage, loading it first
+    /// if needed. Returns the path used by the storage.
     pub fn save(&self, path: &str) -> Result<String> {
-        if let Some(storage) = &self.storage {
-            if let Some(data) = self.data.get() {
-                let mut buffer = Vec::new();
-                data.to_writer(&mut buffer)?;
-
-                Ok(storage.save(path, &buffer)?)
-            } else {
-                unimplemented!()
-            }
-        } else {
-            unimplemented!()
-        }
+        let storage = self
+            .storage
+            .as_ref()
+            .ok_or_else(|| StorageError::MissingStorage(path.into()))?;
+        let sig = self.data()?.clone();
+        storage.save_sig(path, sig)
     }
 }
 
@@ -662,35 +904,67 @@ impl MemStorage {
     }
 }
 
+impl MemStorage {
+    fn sigs_for_path(&self, path: &str) -> Result<Vec<SigStore>> {
+        self.sigs
+            .read()
+            .unwrap()
+            .get(path)
+            .cloned()
+            .ok_or_else(|| StorageError::PathNotFoundError(path.into()).into())
+    }
+}
+
 impl Storage for MemStorage {
-    fn save(&self, _path: &str, _content: &[u8]) -> Result<String> {
-        unimplemented!()
+    fn save(&self, path: &str, content: &[u8]) -> Result<String> {
+        let sigs = Signature::from_reader(content)?;
+        if sigs.is_empty() {
+            return Err(StorageError::DataReadError(path.into()).into());
+        }
+        let sigs = sigs.into_iter().map(SigStore::from).collect();
+        self.sigs.write().unwrap().insert(path.into(), sigs);
+        Ok(path.into())
     }
 
     fn load(&self, path: &str) -> Result<Vec<u8>> {
-        let store = self.sigs.read().unwrap();
-        let sig = store.get(path).unwrap();
+        let sigs = self.sigs_for_path(path)?;
+        let sigs: Vec<&Signature> = sigs.iter().map(|sig| sig.deref()).collect();
 
         let mut buffer = vec![];
         {
-            sig.to_writer(&mut buffer).unwrap();
+            sigs.to_writer(&mut buffer).unwrap();
         }
 
         Ok(buffer)
     }
 
-    fn args(&self) -> StorageArgs {
-        unimplemented!()
+    fn args(&self) -> Result<StorageArgs> {
+        Ok(StorageArgs::MemStorage)
     }
 
     fn load_sig(&self, path: &str) -> Result<SigStore> {
-        Ok(self.sigs.read().unwrap().get(path).unwrap().clone())
+        let mut sigs = self.sigs_for_path(path)?;
+        if sigs.len() > 1 {
+            return Err(StorageError::MultipleSignatures(path.into()).into());
+        }
+        Ok(sigs.swap_remove(0))
+    }
+
+    fn load_sig_with_md5(&self, path: &str, md5: &str) -> Result<SigStore> {
+        let sigs = self.sigs_for_path(path)?;
+        let sigs = sigs.into_iter().map(Signature::from).collect();
+        let sig = sig_with_md5(sigs, md5)
+            .ok_or_else(|| StorageError::MissingSketch(md5.into(), path.into()))?;
+        Ok(sig.into())
     }
 
     fn save_sig(&self, path: &str, sig: Signature) -> Result<String> {
         // side-step saving to store
         let sig_store: SigStore = sig.into();
-        self.sigs.write().unwrap().insert(path.into(), sig_store);
+        self.sigs
+            .write()
+            .unwrap()
+            .insert(path.into(), vec![sig_store]);
         Ok(path.into())
     }
 
@@ -8,12 +8,14 @@ use crate::Result;
 // Column families
 pub(crate) const HASHES: &str = "hashes";
 pub(crate) const COLORS: &str = "colors";
+pub(crate) const PENDING: &str = "pending";
 pub(crate) const METADATA: &str = "metadata";
 
 // Column family for using rocksdb as a Storage
 pub(crate) const STORAGE: &str = "storage";
 
 pub(crate) const ALL_CFS: [&str; 3] = [HASHES, METADATA, STORAGE];
+pub(crate) const COLOR_CFS: [&str; 5] = [HASHES, COLORS, PENDING, METADATA, STORAGE];
 
 pub type DB = rocksdb::DBWithThreadMode<rocksdb::MultiThreaded>;
 
@@ -24,7 +26,7 @@ pub struct RocksDBStorage {
 }
 
 impl RocksDBStorage {
-    pub fn from_path(path: &str) -> Self {
+    pub fn from_path(path: &str) -> Result<Self> {
         let mut opts = db_options();
         opts.create_if_missing(true);
         opts.create_missing_column_families(true);
@@ -33,9 +35,9 @@ impl RocksDBStorage {
         // prepare column family descriptors
         let cfs = cf_descriptors();
 
-        let db = Arc::new(DB::open_cf_descriptors(&opts, path, cfs).unwrap());
+        let db = Arc::new(DB::open_cf_descriptors(&opts, path, cfs)?);
 
-        Self { db }
+        Ok(Self { db })
     }
 
     pub fn from_db(db: Arc<DB>) -> Self {
@@ -57,8 +59,10 @@ impl Storage for RocksDBStorage {
         data.ok_or_else(|| StorageError::DataReadError(path.into()).into())
     }
 
-    fn args(&self) -> StorageArgs {
-        unimplemented!()
+    fn args(&self) -> Result<StorageArgs> {
+        Ok(StorageArgs::RocksDBStorage {
+            path: self.db.path().display().to_string(),
+        })
     }
 
     fn spec(&self) -> String {
@@ -107,6 +111,33 @@ pub(crate) fn cf_descriptors() -> Vec<ColumnFamilyDescriptor> {
     vec![cf_hashes, cf_metadata, cf_storage]
 }
 
+/// Column families for a colored RevIndex: `hashes` maps to colors,
+/// `colors` to datasets, and `pending` holds datasets not yet colored.
+pub(crate) fn color_cf_descriptors() -> Vec<ColumnFamilyDescriptor> {
+    let mut cfs = cf_descriptors();
+
+    let mut cfopts = Options::default();
+    cfopts.set_max_write_buffer_number(16);
+    // Updated default
+    cfopts.set_level_compaction_dynamic_level_bytes(true);
+
+    cfs.push(ColumnFamilyDescriptor::new(COLORS, cfopts));
+
+    let mut cfopts = Options::default();
+    cfopts.set_max_write_buffer_number(16);
+    cfopts.set_merge_operator_associative(
+        "datasets operator",
+        crate::index::revindex::disk_revindex::merge_datasets,
+    );
+    cfopts.set_min_write_buffer_number_to_merge(10);
+    // Updated default
+    cfopts.set_level_compaction_dynamic_level_bytes(true);
+
+    cfs.push(ColumnFamilyDescriptor::new(PENDING, cfopts));
+
+    cfs
+}
+
 pub(crate) fn db_options() -> rocksdb::Options {
     let mut opts = rocksdb::Options::default();
     opts.set_max_open_files(500);
@@ -0,0 +1,234 @@
+//! Read tar archives, optionally compressed.
+//!
+//! Plain tar files are memory-mapped, and compressed ones are decompressed
+//! into a temporary file which is then mapped. All member offsets are
+//! indexed when opening, so
+//! members can be loaded in any order. Long names from GNU (`L`) and pax
+//! (`x`) headers are supported.
+
+use std::collections::{HashMap, HashSet};
+use std::fs::File;
+use std::ops::Range;
+
+use camino::Utf8Path as Path;
+use camino::Utf8PathBuf as PathBuf;
+
+use crate::storage::{Storage, StorageArgs, StorageError};
+use crate::Result;
+
+const BLOCK_SIZE: usize = 512;
+
+/// Load files from a tar archive (read-only).
+pub struct TarStorage {
+    path: PathBuf,
+    data: memmap2::Mmap,
+    members: HashMap<String, Range<usize>>,
+    subdir: Option<String>,
+}
+
+impl TarStorage {
+    /// Open a tar archive, compressed or not.
+    ///
+    /// Compressed archives are decompressed into an anonymous temporary
+    /// file, which needs as much disk space as their uncompressed size.
+    pub fn from_file<P: AsRef<Path>>(location: P) -> Result<Self> {
+        let path = location.as_ref();
+        let (mut reader, format) = niffler::get_reader(Box::new(File::open(path)?))?;
+        let file = if format == niffler::Format::No {
+            drop(reader);
+            File::open(path)?
+        } else {
+            let mut file = tempfile::tempfile()?;
+            std::io::copy(&mut reader, &mut file)?;
+            file
+        };
+        let data = unsafe { memmap2::Mmap::map(&file)? };
+
+        let members =
+            index_members(&data).ok_or_else(|| StorageError::DataReadError(path.to_string()))?;
+        let subdir = find_subdir(&members);
+
+        Ok(Self {
+            path: path.into(),
+            data,
+            members,
+            subdir,
+        })
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Directory containing all members, if there is only one.
+    pub fn subdir(&self) -> Option<&str> {
+        self.subdir.as_deref()
+    }
+
+    /// Paths of all files in the archive, sorted.
+    pub fn filenames(&self) -> Vec<String> {
+        let mut filenames: Vec<_> = self.members.keys().cloned().collect();
+        filenames.sort_unstable();
+        filenames
+    }
+}
+
+impl Storage for TarStorage {
+    fn save(&self, _path: &str, _content: &[u8]) -> Result<String> {
+        Err(StorageError::ReadOnly(self.spec()).into())
+    }
+
+    fn load(&self, path: &str) -> Result<Vec<u8>> {
+        let path = path.strip_prefix("./").unwrap_or(path);
+        let range = self
+            .members
+            .get(path)
+            .or_else(|| {
+                let subdir = self.subdir.as_ref()?;
+                self.members.get(&(subdir.to_owned() + path))
+            })
+            .ok_or_else(|| StorageError::PathNotFoundError(path.into()))?;
+
+        Ok(self.data[range.clone()].to_vec())
+    }
+
+    fn args(&self) -> Result<StorageArgs> {
+        Ok(StorageArgs::TarStorage {
+            path: self.path.to_string(),
+        })
+    }
+
+    fn spec(&self) -> String {
+        format!("tar://{}", self.path)
+    }
+}
+
+/// Map the path of each regular file in the archive to the range of its
+/// content, or None if `data` is not a valid tar archive.
+fn index_members(data: &[u8]) -> Option<HashMap<String, Range<usize>>> {
+    let mut members = HashMap::default();
+    let mut long_name = None;
+    let mut offset = 0;
+
+    while offset + BLOCK_SIZE <= data.len() {
+        let header = &data[offset..offset + BLOCK_SIZE];
+        if header.iter().all(|b| *b == 0) {
+            // end of archive
+            break;
+        }
+        if !valid_checksum(header) {
+            return None;
+        }
+
+        let size = parse_number(&header[124..136])?;
+        let start = offset + BLOCK_SIZE;
+        let end = start.checked_add(size).filter(|end| *end <= data.len())?;
+        let content = &data[start..end];
+
+        match header[156] {
+            // GNU long name for the next member
+            b'L' => long_name = Some(c_str(content)),
+            // pax extended header for the next member
+            b'x' => long_name = pax_path(content).or(long_name),
+            // regular files
+            b'0' | b'7' | 0 => {
+                let name = long_name.take().unwrap_or_else(|| header_name(header));
+                let name = name.strip_prefix("./").unwrap_or(&name);
+                members.insert(name.to_string(), start..end);
+            }
+            // directories, links, global pax headers...
+            _ => long_name = None,
+        }
+
+        offset = start + (size + BLOCK_SIZE - 1) / BLOCK_SIZE * BLOCK_SIZE;
+    }
+
+    Some(members)
+}
+
+fn valid_checksum(header: &[u8]) -> bool {
+    let Some(expected) = parse_number(&header[148..156]) else {
+        return false;
+    };
+    // the checksum field itself is summed as spaces
+    let sum: usize = header
+        .iter()
+        .enumerate()
+        .map(|(i, b)| {
+            if (148..156).contains(&i) {
+                32
+            } else {
+                *b as usize
+            }
+        })
+        .sum();
+    sum == expected
+}
+
+/// Numeric fields are octal strings, or big-endian base-256 if the high bit
+/// of the first byte is set.
+fn parse_number(field: &[u8]) -> Option<usize> {
+    if field[0] & 0x80 != 0 {
+        return field[1..]
+            .iter()
+            .try_fold((field[0] & 0x7f) as usize, |n, b| {
+                n.checked_mul(256)?.checked_add(*b as usize)
+            });
+    }
+
+    let s = std::str::from_utf8(field).ok()?;
+    let s = s.trim_matches(|c| c == '\0' || c == ' ');
+    if s.is_empty() {
+        return Some(0);
+    }
+    usize::from_str_radix(s, 8).ok()
+}
+
+fn c_str(bytes: &[u8]) -> String {
+    let end = bytes.iter().position(|b| *b == 0).unwrap_or(bytes.len());
+    String::from_utf8_lossy(&bytes[..end]).into_owned()
+}
+
+fn header_name(header: &[u8]) -> String {
+    let name = c_str(&header[..100]);
+    // POSIX ustar headers can split long names into prefix and name (GNU
+    // headers use "ustar " as magic, and other fields in that space)
+    if &header[257..263] == b"ustar\0" {
+        let prefix = c_str(&header[345..500]);
+        if !prefix.is_empty() {
+            return format!("{prefix}/{name}");
+        }
+    }
+    name
+}
+
+/// Extract `path` from pax records (`<len> <key>=<value>\n`).
+fn pax_path(mut records: &[u8]) -> Option<String> {
+    let mut path = None;
+    while !records.is_empty() {
+        let space = records.iter().position(|b| *b == b' ')?;
+        let len: usize = std::str::from_utf8(&records[..space]).ok()?.parse().ok()?;
+        if len <= space || len > records.len() {
+            return None;
+        }
+        let record = &records[space + 1..len];
+        let record = record.strip_suffix(b"\n").unwrap_or(record);
+        if let Some(value) = record.strip_prefix(b"path=") {
+            path = Some(String::from_utf8_lossy(value).into_owned());
+        }
+        records = &records[len..];
+    }
+    path
+}
+
+/// Archives often have all files in one directory (`db/...`).
+fn find_subdir(members: &HashMap<String, Range<usize>>) -> Option<String> {
+    let dirs: HashSet<_> = members
+        .keys()
+        .map(|name| name.split_once('/').map(|(dir, _)| dir))
+        .collect();
+    match dirs.into_iter().collect::<Vec<_>>()[..] {
+        [Some(dir)] => Some(format!("{dir}/")),
+        _ => None,
+    }
+}
@@ -0,0 +1,448 @@
+//! Create new zip files.
+//!
+//! Entries are stored without compression (signatures are gzipped before
+//! being saved), and written as soon as they are saved, so only the central
+//! directory is kept in memory. Zip64 records are added when the archive
+//! grows past the limits of the original format.
+
+use std::collections::{HashMap, HashSet};
+use std::fs::File;
+use std::io::{BufWriter, Read, Seek, SeekFrom, Write};
+use std::sync::Mutex;
+
+use camino::Utf8Path as Path;
+use camino::Utf8PathBuf as PathBuf;
+
+use crate::manifest::{Manifest, Record};
+use crate::prelude::*;
+use crate::storage::{StorageArgs, StorageError};
+use crate::{Error, Result};
+
+/// Standard location for the manifest in a zip collection.
+pub const MANIFEST_PATH: &str = "SOURMASH-MANIFEST.csv";
+
+pub(super) const LOCAL_HEADER_SIG: u32 = 0x0403_4b50;
+pub(super) const CENTRAL_HEADER_SIG: u32 = 0x0201_4b50;
+pub(super) const EOCD_SIG: u32 = 0x0605_4b50;
+pub(super) const ZIP64_EOCD_SIG: u32 = 0x0606_4b50;
+pub(super) const ZIP64_LOCATOR_SIG: u32 = 0x0706_4b50;
+
+const VERSION: u16 = 20;
+const VERSION_ZIP64: u16 = 45;
+// upper byte: UNIX, so external attributes are read as permissions
+const VERSION_MADE_BY: u16 = (3 << 8) | VERSION_ZIP64;
+// names are UTF-8
+const FLAGS: u16 = 1 << 11;
+// 1980-01-01 00:00:00, the MS-DOS epoch. Fixed for reproducible archives.
+const DOS_TIME: u16 = 0;
+const DOS_DATE: u16 = (1 << 5) | 1;
+// regular file, rw-r--r--
+const EXTERNAL_ATTRS: u32 = 0o100644 << 16;
+
+/// Write files into a new zip file.
+///
+/// The archive is only valid after [`ZipStorageWriter::finish`] is called
+/// (or the writer is dropped), which writes the manifest for signatures
+/// added with [`ZipStorageWriter::add_sig`] and the zip central directory.
+pub struct ZipStorageWriter {
+    path: PathBuf,
+    dedup: bool,
+    inner: Mutex<ZipWriterState>,
+}
+
+struct Entry {
+    name: String,
+    crc32: u32,
+    size: u64,
+    offset: u64,
+}
+
+impl Entry {
+    fn data_offset(&self) -> u64 {
+        self.offset + 30 + self.name.len() as u64
+    }
+}
+
+struct ZipWriterState {
+    writer: Option<BufWriter<File>>,
+    position: u64,
+    entries: Vec<Entry>,
+    names: HashMap<String, usize>,
+    records: Vec<Record>,
+    md5s: HashSet<String>,
+}
+
+impl ZipStorageWriter {
+    /// Create a new zip file at `path`, overwriting existing files.
+    ///
+    /// If `dedup` is set, signatures with an md5sum already in the archive
+    /// are skipped by `add_sig`.
+    pub fn new<P: AsRef<Path>>(path: P, dedup: bool) -> Result<Self> {
+        let file = File::options()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(true)
+            .open(path.as_ref())?;
+
+        Ok(Self {
+            path: path.as_ref().into(),
+            dedup,
+            inner: Mutex::new(ZipWriterState {
+                writer: Some(BufWriter::new(file)),
+                position: 0,
+                entries: vec![],
+                names: HashMap::default(),
+                records: vec![],
+                md5s: HashSet::default(),
+            }),
+        })
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Add all sketches in `sig` to the archive, one file per sketch,
+    /// and return the manifest records for the new files.
+    pub fn add_sig(&self, sig: &Signature) -> Result<Vec<Record>> {
+        let mut added = vec![];
+
+        for sketch in sig.iter() {
+            let mut single = sig.clone();
+            single.reset_sketches();
+            single.push(sketch.clone());
+
+            let md5 = single.md5sum();
+            if self.dedup && self.inner.lock().unwrap().md5s.contains(&md5) {
+                continue;
+            }
+
+            let mut buffer = vec![];
+            {
+                let mut writer = niffler::get_writer(
+                    Box::new(&mut buffer),
+                    niffler::compression::Format::Gzip,
+                    niffler::compression::Level::Six,
+                )?;
+                single.to_writer(&mut writer)?;
+            }
+            let path = self.save(&format!("signatures/{md5}.sig.gz"), &buffer)?;
+
+            // only recorded after a successful write, so failed writes don't
+            // skip later copies. Concurrent copies are saved to the same path.
+            let mut state = self.inner.lock().unwrap();
+            if self.dedup && !state.md5s.insert(md5) {
+                continue;
+            }
+            let records = Record::from_sig(&single, &path);
+            state.records.extend(records.iter().cloned());
+            added.extend(records);
+        }
+
+        Ok(added)
+    }
+
+    /// Number of signatures added with `add_sig`.
+    pub fn len(&self) -> usize {
+        self.inner.lock().unwrap().records.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Write the manifest and the central directory, completing the archive.
+    ///
+    /// Returns the manifest for the signatures added. Saving into the
+    /// archive after this is an error.
+    pub fn finish(&self) -> Result<Manifest> {
+        let mut state = self.inner.lock().unwrap();
+        if state.writer.is_none() {
+            return Err(StorageError::ArchiveFinished(self.path.to_string()).into());
+        }
+
+        let manifest: Manifest = state.records.clone().into();
+        if !state.names.contains_key(MANIFEST_PATH) {
+            let mut buffer = vec![];
+            manifest.to_writer(&mut buffer)?;
+            state.write_entry(MANIFEST_PATH, &buffer)?;
+        }
+        state.write_central_directory()?;
+
+        let mut writer = state.writer.take().expect("writer already checked");
+        writer.flush()?;
+        Ok(manifest)
+    }
+}
+
+impl Drop for ZipStorageWriter {
+    fn drop(&mut self) {
+        let finished = self.inner.lock().map_or(true, |s| s.writer.is_none());
+        if !finished {
+            if let Err(e) = self.finish() {
+                log::error!("Error finishing zip file {}: {}", self.path, e);
+            }
+        }
+    }
+}
+
+impl Storage for ZipStorageWriter {
+    /// Save `content` into `path`. If `path` already exists with a different
+    /// content, a new name is generated (`name_1.ext`, `name_2.ext`, ...),
+    /// and the path actually used is returned.
+    fn save(&self, path: &str, content: &[u8]) -> Result<String> {
+        if path.is_empty() {
+            return Err(StorageError::EmptyPathError.into());
+        }
+
+        let mut state = self.inner.lock().unwrap();
+        if state.writer.is_none() {
+            return Err(StorageError::ArchiveFinished(self.path.to_string()).into());
+        }
+
+        let (stem, ext) = split_extension(path);
+        let mut name = path.to_string();
+        let mut n = 0;
+        while state.names.contains_key(&name) {
+            if state.read_entry(&name)? == content {
+                return Ok(name);
+            }
+            n += 1;
+            name = format!("{stem}_{n}{ext}");
+        }
+
+        state.write_entry(&name, content)?;
+        Ok(name)
+    }
+
+    fn load(&self, path: &str) -> Result<Vec<u8>> {
+        self.inner.lock().unwrap().read_entry(path)
+    }
+
+    /// Args for reading the zip file (after it is finished).
+    fn args(&self) -> Result<StorageArgs> {
+        Ok(StorageArgs::ZipStorage {
+            path: self.path.to_string(),
+        })
+    }
+
+    fn spec(&self) -> String {
+        format!("zip://{}", self.path)
+    }
+}
+
+/// Split `path` into stem and extensions, where the extensions start at the
+/// first `.` of the file name (`signatures/abc.sig.gz` -> `signatures/abc`,
+/// `.sig.gz`).
+fn split_extension(path: &str) -> (&str, &str) {
+    let start = path.rfind('/').map_or(0, |i| i + 1);
+    match path[start..].find('.') {
+        Some(0) | None => (path, ""),
+        Some(i) => path.split_at(start + i),
+    }
+}
+
+impl ZipWriterState {
+    fn writer(&mut self) -> &mut BufWriter<File> {
+        self.writer.as_mut().expect("zip file already finished")
+    }
+
+    fn write_entry(&mut self, name: &str, content: &[u8]) -> Result<()> {
+        let size = u32::try_from(content.len()).map_err(|_| Error::Internal {
+            message: format!("{name} is too large to be stored in a zip file"),
+        })?;
+        let crc32 = crc32fast::hash(content);
+        let name_len = u16::try_from(name.len()).map_err(|_| Error::Internal {
+            message: format!("path too long for a zip file: {name}"),
+        })?;
+
+        let offset = self.position;
+        let w = self.writer();
+        write_u32(w, LOCAL_HEADER_SIG)?;
+        write_u16(w, VERSION)?;
+        write_u16(w, FLAGS)?;
+        write_u16(w, 0)?; // stored
+        write_u16(w, DOS_TIME)?;
+        write_u16(w, DOS_DATE)?;
+        write_u32(w, crc32)?;
+        write_u32(w, size)?; // compressed
+        write_u32(w, size)?; // uncompressed
+        write_u16(w, name_len)?;
+        write_u16(w, 0)?; // extra field
+        w.write_all(name.as_bytes())?;
+        w.write_all(content)?;
+
+        let entry = Entry {
+            name: name.into(),
+            crc32,
+            size: size as u64,
+            offset,
+        };
+        self.position = entry.data_offset() + entry.size;
+        self.names.insert(name.into(), self.entries.len());
+        self.entries.push(entry);
+        Ok(())
+    }
+
+    fn read_entry(&mut self, name: &str) -> Result<Vec<u8>> {
+        let entry = self
+            .names
+            .get(name)
+            .map(|&i| &self.entries[i])
+            .ok_or_else(|| StorageError::PathNotFoundError(name.into()))?;
+        let (start, size) = (entry.data_offset(), entry.size as usize);
+
+        let w = self
+            .writer
+            .as_mut()
+            .ok_or_else(|| StorageError::DataReadError(name.into()))?;
+        w.flush()?;
+        let mut file = w.get_ref();
+
+        let mut content = vec![0; size];
+        file.seek(SeekFrom::Start(start))?;
+        file.read_exact(&mut content)?;
+        file.seek(SeekFrom::End(0))?;
+        Ok(content)
+    }
+
+    fn write_central_directory(&mut self) -> Result<()> {
+        let cd_start = self.position;
+        let entries = std::mem::take(&mut self.entries);
+
+        let mut cd_size = 0;
+        for entry in &entries {
+            let zip64 = entry.offset >= u32::MAX as u64;
+            let extra_len: u16 = if zip64 { 12 } else { 0 };
+
+            let w = self.writer();
+            write_u32(w, CENTRAL_HEADER_SIG)?;
+            write_u16(w, VERSION_MADE_BY)?;
+            write_u16(w, if zip64 { VERSION_ZIP64 } else { VERSION })?;
+            write_u16(w, FLAGS)?;
+            write_u16(w, 0)?; // stored
+            write_u16(w, DOS_TIME)?;
+            write_u16(w, DOS_DATE)?;
+            write_u32(w, entry.crc32)?;
+            write_u32(w, entry.size as u32)?;
+            write_u32(w, entry.size as u32)?;
+            write_u16(w, entry.name.len() as u16)?;
+            write_u16(w, extra_len)?;
+            write_u16(w, 0)?; // comment
+            write_u16(w, 0)?; // disk number
+            write_u16(w, 0)?; // internal attributes
+            write_u32(w, EXTERNAL_ATTRS)?;
+            write_u32(w, if zip64 { u32::MAX } else { entry.offset as u32 })?;
+            w.write_all(entry.name.as_bytes())?;
+            if zip64 {
+                write_u16(w, 0x0001)?; // zip64 extended information
+                write_u16(w, 8)?;
+                write_u64(w, entry.offset)?;
+            }
+
+            cd_size += 46 + entry.name.len() as u64 + extra_len as u64;
+        }
+
+        let n_entries = entries.len() as u64;
+        let needs_zip64 = n_entries >= u16::MAX as u64
+            || cd_start >= u32::MAX as u64
+            || cd_size >= u32::MAX as u64;
+
+        let w = self.writer();
+        if needs_zip64 {
+            let zip64_eocd_start = cd_start + cd_size;
+
+            write_u32(w, ZIP64_EOCD_SIG)?;
+            write_u64(w, 44)?; // size of the remaining record
+            write_u16(w, VERSION_MADE_BY)?;
+            write_u16(w, VERSION_ZIP64)?;
+            write_u32(w, 0)?; // disk number
+            write_u32(w, 0)?; // disk with central directory
+            write_u64(w, n_entries)?;
+            write_u64(w, n_entries)?;
+            write_u64(w, cd_size)?;
+            write_u64(w, cd_start)?;
+
+            write_u32(w, ZIP64_LOCATOR_SIG)?;
+            write_u32(w, 0)?; // disk with zip64 end of central directory
+            write_u64(w, zip64_eocd_start)?;
+            write_u32(w, 1)?; // total disks
+        }
+
+        write_u32(w, EOCD_SIG)?;
+        write_u16(w, 0)?; // disk number
+        write_u16(w, 0)?; // disk with central directory
+        write_u16(w, n_entries.min(u16::MAX as u64) as u16)?;
+        write_u16(w, n_entries.min(u16::MAX as u64) as u16)?;
+        write_u32(w, cd_size.min(u32::MAX as u64) as u32)?;
+        write_u32(w, cd_start.min(u32::MAX as u64) as u32)?;
+        write_u16(w, 0)?; // comment
+
+        self.entries = entries;
+        Ok(())
+    }
+}
+
+fn write_u16<W: Write>(w: &mut W, value: u16) -> Result<()> {
+    Ok(w.write_all(&value.to_le_bytes())?)
+}
+
+fn write_u32<W: Write>(w: &mut W, value: u32) -> Result<()> {
+    Ok(w.write_all(&value.to_le_bytes())?)
+}
+
+fn write_u64<W: Write>(w: &mut W, value: u64) -> Result<()> {
+    Ok(w.write_all(&value.to_le_bytes())?)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    use crate::storage::ZipStorage;
+
+    #[test]
+    fn extensions() {
+        assert_eq!(
+            split_extension("signatures/abc.sig.gz"),
+            ("signatures/abc", ".sig.gz")
+        );
+        assert_eq!(split_extension("a.b/c"), ("a.b/c", ""));
+        assert_eq!(split_extension(".hidden"), (".hidden", ""));
+    }
+
+    #[test]
+    fn save_and_read_back() -> Result<()> {
+        let dir = tempfile::TempDir::new()?;
+        let path = PathBuf::from_path_buf(dir.path().join("test.zip")).unwrap();
+
+        let writer = ZipStorageWriter::new(&path, false)?;
+        assert_eq!(writer.save("a/b.txt", b"first")?, "a/b.txt");
+        // same content, same path
+        assert_eq!(writer.save("a/b.txt", b"first")?, "a/b.txt");
+        // different content, new path
+        assert_eq!(writer.save("a/b.txt", b"second")?, "a/b_1.txt");
+        assert_eq!(writer.load("a/b_1.txt")?, b"second");
+        assert!(writer.save("", b"").is_err());
+
+        let manifest = writer.finish()?;
+        assert!(manifest.is_empty());
+        assert!(matches!(
+            writer.save("c.txt", b"late"),
+            Err(crate::Error::StorageError(StorageError::ArchiveFinished(_)))
+        ));
+        assert!(matches!(
+            writer.finish(),
+            Err(crate::Error::StorageError(StorageError::ArchiveFinished(_)))
+        ));
+        drop(writer);
+
+        let storage = ZipStorage::from_file(&path)?;
+        assert_eq!(storage.load("a/b.txt")?, b"first");
+        assert_eq!(storage.load("a/b_1.txt")?, b"second");
+        assert!(storage.load(MANIFEST_PATH).is_ok());
+
+        Ok(())
+    }
+}
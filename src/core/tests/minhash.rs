@@ -388,7 +388,7 @@ fn load_save_minhash_sketches() {
     let reader = BufReader::new(file);
     let sigs = Signature::from_reader(reader).expect("Loading error");
 
-    let sig = sigs.get(0).unwrap();
+    let sig = sigs.first().unwrap();
     let sketches = sig.sketches();
     let mut buffer = vec![];
 
@@ -489,7 +489,7 @@ fn load_save_minhash_sketches_abund() {
     let reader = BufReader::new(file);
     let sigs = Signature::from_reader(reader).expect("Loading error");
 
-    let sig = sigs.get(0).unwrap();
+    let sig = sigs.first().unwrap();
     let sketches = sig.sketches();
     let mut buffer = vec![];
 
@@ -2,8 +2,12 @@ use std::path::PathBuf;
 
 use tempfile::TempDir;
 
+use sourmash::collection::Collection;
 use sourmash::signature::Signature;
-use sourmash::storage::{FSStorage, InnerStorage, Storage, StorageArgs, ZipStorage};
+use sourmash::storage::{
+    register_storage, CacheLimit, CachedStorage, FSStorage, InnerStorage, MemStorage, SigStore,
+    Storage, StorageArgs, StorageError, TarStorage, ZipStorage, ZipStorageWriter,
+};
 
 #[test]
 fn zipstorage_load_file() -> Result<(), Box<dyn std::error::Error>> {
@@ -85,7 +89,7 @@ fn zipstorage_parallel_access() -> Result<(), Box<dyn std::error::Error>> {
 fn innerstorage_save_sig() -> Result<(), Box<dyn std::error::Error>> {
     let output = TempDir::new()?;
 
-    let fst = FSStorage::new("".into(), output.path().as_os_str().to_str().unwrap());
+    let fst = FSStorage::new("", output.path().as_os_str().to_str().unwrap());
 
     let instorage = InnerStorage::new(fst);
 
@@ -108,7 +112,7 @@ fn innerstorage_save_sig() -> Result<(), Box<dyn std::error::Error>> {
 fn innerstorage_load() -> Result<(), Box<dyn std::error::Error>> {
     let output = TempDir::new()?;
 
-    let fst = FSStorage::new("".into(), output.path().as_os_str().to_str().unwrap());
+    let fst = FSStorage::new("", output.path().as_os_str().to_str().unwrap());
 
     let instorage = InnerStorage::new(fst);
 
@@ -133,15 +137,13 @@ fn innerstorage_args() -> Result<(), Box<dyn std::error::Error>> {
     let output = TempDir::new()?;
     let path = output.path().as_os_str().to_str().unwrap();
 
-    let fst = FSStorage::new("".into(), path);
+    let fst = FSStorage::new("", path);
 
     let instorage = InnerStorage::new(fst);
 
-    let args = instorage.args();
+    let args = instorage.args()?;
 
-    assert!(matches!(args, StorageArgs::FSStorage { .. }));
-    let StorageArgs::FSStorage { path: p } = args;
-    assert_eq!(p, path);
+    assert_eq!(args, StorageArgs::FSStorage { path: path.into() });
 
     Ok(())
 }
@@ -151,19 +153,669 @@ fn innerstorage_from_args() -> Result<(), Box<dyn std::error::Error>> {
     let output = TempDir::new()?;
     let path = output.path().as_os_str().to_str().unwrap();
 
-    let fst = FSStorage::new("".into(), path);
-    let args = fst.args();
+    let fst = FSStorage::new("", path);
+    let args = fst.args()?;
 
-    let instorage = InnerStorage::new(FSStorage::from(&args));
-    let inargs = instorage.args();
+    let instorage = InnerStorage::new(FSStorage::try_from(&args)?);
+    let inargs = instorage.args()?;
 
-    assert!(matches!(inargs, StorageArgs::FSStorage { .. }));
-    let StorageArgs::FSStorage { path: p1 } = inargs;
-    assert_eq!(p1, path);
+    assert_eq!(inargs, StorageArgs::FSStorage { path: path.into() });
+    assert_eq!(args, StorageArgs::FSStorage { path: path.into() });
 
-    assert!(matches!(args, StorageArgs::FSStorage { .. }));
-    let StorageArgs::FSStorage { path: p2 } = args;
-    assert_eq!(p2, path);
+    assert!(FSStorage::try_from(&StorageArgs::MemStorage).is_err());
+
+    Ok(())
+}
+
+#[test]
+fn storage_args_serde() -> Result<(), Box<dyn std::error::Error>> {
+    let args = StorageArgs::ZipStorage {
+        path: "data.zip".into(),
+    };
+    let raw = serde_json::to_string(&args)?;
+    assert_eq!(
+        raw,
+        r#"{"backend":"ZipStorage","args":{"path":"data.zip"}}"#
+    );
+    assert_eq!(serde_json::from_str::<StorageArgs>(&raw)?, args);
+
+    let args: StorageArgs = serde_json::from_str(r#"{"backend":"MemStorage"}"#)?;
+    assert_eq!(args, StorageArgs::MemStorage);
+
+    // untagged form from earlier versions
+    let args: StorageArgs = serde_json::from_str(r#"{"path":"sigs"}"#)?;
+    assert_eq!(
+        args,
+        StorageArgs::FSStorage {
+            path: "sigs".into()
+        }
+    );
+
+    Ok(())
+}
+
+#[test]
+fn zipstorage_writer_roundtrip() -> Result<(), Box<dyn std::error::Error>> {
+    let output = TempDir::new()?;
+    let zippath = output.path().join("test.sig.zip");
+    let zippath = zippath.to_str().unwrap();
+
+    let mut filename = PathBuf::from(env!("CARGO_MANIFEST_DIR"));
+    filename.push("../../tests/test-data/genome-s10.fa.gz.sig");
+    let sig = Signature::from_path(filename)?.swap_remove(0);
+
+    let writer = ZipStorageWriter::new(zippath, false)?;
+    let records = writer.add_sig(&sig)?;
+    assert_eq!(records.len(), 1);
+    let location = records[0].internal_location().to_string();
+    assert_eq!(location, format!("signatures/{}.sig.gz", sig.md5sum()));
+
+    let manifest = writer.finish()?;
+    assert_eq!(manifest.len(), 1);
+    drop(writer);
+
+    let zs = ZipStorage::from_file(zippath)?;
+    let loaded_sig = zs.load_sig(&location)?;
+    assert_eq!(sig.name_str(), loaded_sig.name());
+    assert_eq!(sig.md5sum(), loaded_sig.md5sum());
+    assert!(zs.save("other", b"data").is_err());
+
+    Ok(())
+}
+
+#[test]
+fn sigstore_save_memstorage() -> Result<(), Box<dyn std::error::Error>> {
+    let mut filename = PathBuf::from(env!("CARGO_MANIFEST_DIR"));
+    filename.push("../../tests/test-data/genome-s10.fa.gz.sig");
+    let sig = Signature::from_path(filename)?.swap_remove(0);
+
+    let instorage = InnerStorage::new(MemStorage::new());
+    let sigstore = SigStore::new_with_storage(sig.clone(), instorage.clone());
+    let path = sigstore.save("copy")?;
+
+    let loaded_sig = instorage.load_sig(&path)?;
+    assert_eq!(sig.md5sum(), loaded_sig.md5sum());
+
+    // no storage to save into
+    let sigstore: SigStore = sig.into();
+    assert!(sigstore.save("copy").is_err());
+
+    Ok(())
+}
+
+fn check_spec_roundtrip(storage: &dyn Storage) -> Result<InnerStorage, Box<dyn std::error::Error>> {
+    let spec = storage.spec();
+    let args = storage.args()?;
+    assert_eq!(args.spec(), spec);
+
+    let from_spec = InnerStorage::from_spec(spec.clone())?;
+    assert_eq!(from_spec.spec(), spec);
+    assert_eq!(from_spec.args()?, args);
+
+    let from_args = InnerStorage::from_args(&args)?;
+    assert_eq!(from_args.spec(), spec);
+
+    Ok(from_spec)
+}
+
+#[test]
+fn storage_spec_roundtrip() -> Result<(), Box<dyn std::error::Error>> {
+    let mut filename = PathBuf::from(env!("CARGO_MANIFEST_DIR"));
+    filename.push("../../tests/test-data/genome-s10.fa.gz.sig");
+    let sig = Signature::from_path(filename)?.swap_remove(0);
+
+    let output = TempDir::new()?;
+    let fspath = output.path().join("fs");
+    let fst = FSStorage::new("", fspath.to_str().unwrap());
+    fst.save_sig("test", sig.clone())?;
+    let storage = check_spec_roundtrip(&fst)?;
+    assert_eq!(storage.load_sig("test")?.md5sum(), sig.md5sum());
+
+    check_spec_roundtrip(&MemStorage::new())?;
+
+    let mut zippath = PathBuf::from(env!("CARGO_MANIFEST_DIR"));
+    zippath.push("../../tests/test-data/v6.sbt.zip");
+    let zs = ZipStorage::from_file(zippath.to_str().unwrap())?;
+    let storage = check_spec_roundtrip(&zs)?;
+    assert_eq!(storage.load("v6.sbt.json")?, zs.load("v6.sbt.json")?);
+
+    let mut tarpath = PathBuf::from(env!("CARGO_MANIFEST_DIR"));
+    tarpath.push("../../tests/test-data/prot/protein.tar.gz");
+    let ts = TarStorage::from_file(tarpath.to_str().unwrap())?;
+    let storage = check_spec_roundtrip(&ts)?;
+    assert_eq!(
+        storage.load("SOURMASH-MANIFEST.csv")?,
+        ts.load("SOURMASH-MANIFEST.csv")?
+    );
+
+    let zippath = output.path().join("test.sig.zip");
+    let writer = ZipStorageWriter::new(zippath.to_str().unwrap(), false)?;
+    let records = writer.add_sig(&sig)?;
+    writer.finish()?;
+    let storage = check_spec_roundtrip(&writer)?;
+    let location = records[0].internal_location().as_str();
+    assert_eq!(storage.load_sig(location)?.md5sum(), sig.md5sum());
+
+    Ok(())
+}
+
+#[test]
+fn storage_unknown_spec() {
+    for spec in ["ipfs://QmHash", "zip:/missing-separator", ""] {
+        assert!(matches!(
+            InnerStorage::from_spec(spec.into()),
+            Err(sourmash::Error::StorageError(
+                StorageError::UnknownScheme(_) | StorageError::InvalidSpec(_)
+            ))
+        ));
+    }
+}
+
+#[cfg(not(feature = "http"))]
+#[test]
+fn storage_http_missing_feature() {
+    assert!(matches!(
+        InnerStorage::from_spec("https://example.com/db.zip".into()),
+        Err(sourmash::Error::StorageError(StorageError::MissingFeature(
+            ..
+        )))
+    ));
+}
+
+/// A MemStorage keeping everything under a prefix
+struct PrefixStorage {
+    prefix: String,
+    inner: MemStorage,
+}
+
+impl Storage for PrefixStorage {
+    fn save(&self, path: &str, content: &[u8]) -> sourmash::Result<String> {
+        self.inner
+            .save(&format!("{}/{}", self.prefix, path), content)
+    }
+
+    fn load(&self, path: &str) -> sourmash::Result<Vec<u8>> {
+        self.inner.load(&format!("{}/{}", self.prefix, path))
+    }
+
+    fn args(&self) -> sourmash::Result<StorageArgs> {
+        Ok(StorageArgs::Custom {
+            scheme: "prefix".into(),
+            location: self.prefix.clone(),
+        })
+    }
+
+    fn spec(&self) -> String {
+        format!("prefix://{}", self.prefix)
+    }
+}
+
+#[test]
+fn storage_register_custom() -> Result<(), Box<dyn std::error::Error>> {
+    register_storage("prefix", |location| {
+        Ok(InnerStorage::new(PrefixStorage {
+            prefix: location.into(),
+            inner: MemStorage::new(),
+        }))
+    })?;
+
+    let storage = PrefixStorage {
+        prefix: "data".into(),
+        inner: MemStorage::new(),
+    };
+    assert_eq!(storage.spec(), "prefix://data");
+    check_spec_roundtrip(&storage)?;
+
+    // no duplicates or shadowing built-in storages
+    for scheme in ["prefix", "zip", "memory"] {
+        assert!(matches!(
+            register_storage(scheme, |_| Ok(InnerStorage::new(MemStorage::new()))),
+            Err(sourmash::Error::StorageError(
+                StorageError::SchemeAlreadyRegistered(_)
+            ))
+        ));
+    }
+
+    Ok(())
+}
+
+#[test]
+fn cachedstorage_lru() -> Result<(), Box<dyn std::error::Error>> {
+    let mut filename = PathBuf::from(env!("CARGO_MANIFEST_DIR"));
+    filename.push("../../tests/test-data/prot/all.zip");
+
+    let collection = Collection::from_zipfile(filename.to_str().unwrap())?;
+    let storage = CachedStorage::new(collection.storage().clone(), CacheLimit::Entries(2));
+    assert_eq!(storage.spec(), collection.storage().spec());
+    assert_eq!(storage.args()?, collection.storage().args()?);
+
+    let records: Vec<_> = collection.manifest().iter().take(3).collect();
+    let load = |i: usize| {
+        let record = records[i];
+        storage.load_sig_with_md5(record.internal_location().as_str(), record.md5())
+    };
+
+    let first = load(0)?;
+    assert_eq!(load(0)?.md5sum(), first.md5sum());
+    assert_eq!((storage.stats().hits(), storage.stats().misses()), (1, 1));
+
+    // 0 is the least recently used, and gets evicted
+    load(1)?;
+    load(2)?;
+    assert_eq!(storage.stats().entries(), 2);
+    load(0)?;
+    assert_eq!((storage.stats().hits(), storage.stats().misses()), (1, 4));
+
+    // 1 was evicted to make room for 0, 2 is still cached
+    load(2)?;
+    load(1)?;
+    assert_eq!((storage.stats().hits(), storage.stats().misses()), (2, 5));
+
+    storage.clear();
+    assert_eq!(storage.stats().entries(), 0);
+    assert_eq!(storage.stats().bytes(), 0);
+
+    Ok(())
+}
+
+#[test]
+fn cachedstorage_bytes_limit() -> Result<(), Box<dyn std::error::Error>> {
+    let mut filename = PathBuf::from(env!("CARGO_MANIFEST_DIR"));
+    filename.push("../../tests/test-data/genome-s10.fa.gz.sig");
+    let sig = Signature::from_path(filename)?.swap_remove(0);
+
+    let inner = InnerStorage::new(MemStorage::new());
+    inner.save_sig("a", sig.clone())?;
+    inner.save_sig("b", sig.clone())?;
+
+    let storage = CachedStorage::new(inner.clone(), CacheLimit::Bytes(usize::MAX));
+    storage.load_sig("a")?;
+    let size = storage.stats().bytes();
+    assert!(size > 0);
+
+    // only room for one signature
+    let storage = CachedStorage::new(inner.clone(), CacheLimit::Bytes(size));
+    storage.load_sig("a")?;
+    storage.load_sig("b")?;
+    assert_eq!(storage.stats().entries(), 1);
+    assert_eq!(storage.stats().bytes(), size);
+
+    // too large to be cached
+    let storage = CachedStorage::new(inner, CacheLimit::Bytes(size - 1));
+    storage.load_sig("a")?;
+    storage.load_sig("a")?;
+    assert_eq!(storage.stats().entries(), 0);
+    assert_eq!(storage.stats().misses(), 2);
+
+    Ok(())
+}
+
+#[test]
+fn cachedstorage_save_invalidates() -> Result<(), Box<dyn std::error::Error>> {
+    let mut filename = PathBuf::from(env!("CARGO_MANIFEST_DIR"));
+    filename.push("../../tests/test-data/genome-s10.fa.gz.sig");
+    let sig = Signature::from_path(filename)?.swap_remove(0);
+    let mut filename = PathBuf::from(env!("CARGO_MANIFEST_DIR"));
+    filename.push("../../tests/test-data/genome-s11.fa.gz.sig");
+    let other = Signature::from_path(filename)?.swap_remove(0);
+
+    let storage = CachedStorage::new(
+        InnerStorage::new(MemStorage::new()),
+        CacheLimit::Entries(10),
+    );
+    storage.save_sig("sig", sig.clone())?;
+    assert_eq!(storage.load_sig("sig")?.md5sum(), sig.md5sum());
+
+    storage.save_sig("sig", other.clone())?;
+    assert_eq!(storage.load_sig("sig")?.md5sum(), other.md5sum());
+    assert_eq!(storage.stats().hits(), 0);
+
+    Ok(())
+}
+
+#[test]
+fn collection_with_cache() -> Result<(), Box<dyn std::error::Error>> {
+    let mut filename = PathBuf::from(env!("CARGO_MANIFEST_DIR"));
+    filename.push("../../tests/test-data/prot/all.zip");
+
+    let collection = Collection::from_zipfile(filename.to_str().unwrap())?;
+    assert!(collection.storage().cache_stats().is_none());
+
+    let cached = collection.clone().with_cache(CacheLimit::Entries(100));
+    for _ in 0..2 {
+        for (idx, _) in cached.iter() {
+            let sig = cached.sig_for_dataset(idx)?;
+            assert_eq!(sig.md5sum(), collection.sig_for_dataset(idx)?.md5sum());
+        }
+    }
+
+    let stats = cached.storage().cache_stats().unwrap();
+    assert_eq!(stats.misses(), collection.len());
+    assert_eq!(stats.hits(), collection.len());
+    assert_eq!(cached.storage().spec(), collection.storage().spec());
+
+    Ok(())
+}
+
+#[test]
+fn tarstorage_load() -> Result<(), Box<dyn std::error::Error>> {
+    let mut basedir = PathBuf::from(env!("CARGO_MANIFEST_DIR"));
+    basedir.push("../../tests/test-data/prot/");
+    let zs = ZipStorage::from_file(basedir.join("protein.zip").to_str().unwrap())?;
+
+    let ts = TarStorage::from_file(basedir.join("protein.tar.gz").to_str().unwrap())?;
+    assert_eq!(ts.subdir(), Some("db/"));
+    assert_eq!(ts.filenames().len(), 3);
+    for path in [
+        "SOURMASH-MANIFEST.csv",
+        "protein/GCA_001593925.1_ASM159392v1_protein.faa.gz.sig",
+        "./protein/GCA_001593935.1_ASM159393v1_protein.faa.gz.sig",
+    ] {
+        assert_eq!(ts.load(path)?, zs.load(path.trim_start_matches("./"))?);
+    }
+    assert!(matches!(
+        ts.load("missing.sig"),
+        Err(sourmash::Error::StorageError(
+            StorageError::PathNotFoundError(_)
+        ))
+    ));
+    assert!(ts.save("other", b"data").is_err());
+
+    let ts = TarStorage::from_file(basedir.join("protein.tar").to_str().unwrap())?;
+    assert_eq!(ts.subdir(), Some("protein/"));
+    for path in ts.filenames() {
+        let name = path.rsplit('/').next().unwrap();
+        assert_eq!(ts.load(&path)?, zs.load(&format!("protein/{name}"))?);
+    }
+
+    // not a tar file
+    assert!(TarStorage::from_file(basedir.join("protein.zip").to_str().unwrap()).is_err());
+
+    Ok(())
+}
+
+/// Minimal static file server with range requests, for `HttpZipStorage`.
+#[cfg(feature = "http")]
+struct StaticServer {
+    url: String,
+    bytes_served: std::sync::Arc<std::sync::atomic::AtomicUsize>,
+}
+
+#[cfg(feature = "http")]
+impl StaticServer {
+    fn start(root: &std::path::Path) -> std::io::Result<Self> {
+        use std::io::{BufRead, BufReader, Write};
+        use std::sync::atomic::Ordering;
+
+        let listener = std::net::TcpListener::bind("127.0.0.1:0")?;
+        let url = format!("http://{}", listener.local_addr()?);
+        let bytes_served = std::sync::Arc::new(std::sync::atomic::AtomicUsize::new(0));
+
+        let root = root.to_path_buf();
+        let served = bytes_served.clone();
+        std::thread::spawn(move || {
+            for mut stream in listener.incoming().flatten() {
+                let mut reader = BufReader::new(stream.try_clone().unwrap());
+                let mut request = String::new();
+                let mut range = None;
+                reader.read_line(&mut request).unwrap();
+                loop {
+                    let mut line = String::new();
+                    if reader.read_line(&mut line).unwrap() == 0 || line.trim().is_empty() {
+                        break;
+                    }
+                    if let Some((name, value)) = line.trim().split_once(": ") {
+                        if name.eq_ignore_ascii_case("range") {
+                            range = value.strip_prefix("bytes=").map(String::from);
+                        }
+                    }
+                }
+
+                let path = request.split(' ').nth(1).unwrap_or("/");
+                let Ok(data) = std::fs::read(root.join(path.trim_start_matches('/'))) else {
+                    let _ = stream.write_all(
+                        b"HTTP/1.1 404 Not Found\r\nContent-Length: 0\r\nConnection: close\r\n\r\n",
+                    );
+                    continue;
+                };
+                let etag = format!("\"{:x}\"", md5::compute(&data));
+
+                let len = data.len();
+                let (start, end) = match range.as_deref().and_then(|r| r.split_once('-')) {
+                    Some(("", n)) => (len - n.parse::<usize>().unwrap().min(len), len),
+                    Some((a, "")) => (a.parse().unwrap(), len),
+                    Some((a, b)) => (
+                        a.parse().unwrap(),
+                        (b.parse::<usize>().unwrap() + 1).min(len),
+                    ),
+                    None => (0, len),
+                };
+                let header = if range.is_some() {
+                    format!(
+                        "HTTP/1.1 206 Partial Content\r\nContent-Range: bytes {}-{}/{len}\r\n",
+                        start,
+                        end - 1
+                    )
+                } else {
+                    "HTTP/1.1 200 OK\r\n".into()
+                };
+                let header = format!(
+                    "{header}ETag: {etag}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n",
+                    end - start
+                );
+                served.fetch_add(end - start, Ordering::SeqCst);
+                let _ = stream.write_all(header.as_bytes());
+                let _ = stream.write_all(&data[start..end]);
+            }
+        });
+
+        Ok(Self { url, bytes_served })
+    }
+
+    /// Bytes served since the last call.
+    fn take_bytes_served(&self) -> usize {
+        self.bytes_served
+            .swap(0, std::sync::atomic::Ordering::SeqCst)
+    }
+}
+
+#[cfg(feature = "http")]
+#[test]
+fn httpzipstorage_load() -> Result<(), Box<dyn std::error::Error>> {
+    use sourmash::storage::HttpZipStorage;
+
+    let mut basedir = PathBuf::from(env!("CARGO_MANIFEST_DIR"));
+    basedir.push("../../tests/test-data/prot/");
+    let zipsize = std::fs::metadata(basedir.join("protein.zip"))?.len() as usize;
+    let zs = ZipStorage::from_file(basedir.join("protein.zip").to_str().unwrap())?;
+
+    let server = StaticServer::start(&basedir)?;
+    let url = format!("{}/protein.zip", server.url);
+    let cache = TempDir::new()?;
+    let cache_dir = camino::Utf8Path::from_path(cache.path()).unwrap();
+
+    let hs = HttpZipStorage::new(&url, Some(cache_dir))?;
+    assert_eq!(hs.subdir(), Some("protein/".into()));
+    assert_eq!(hs.filenames().len(), 3);
+    let path = "protein/GCA_001593925.1_ASM159392v1_protein.faa.gz.sig";
+    assert_eq!(
+        hs.load("SOURMASH-MANIFEST.csv")?,
+        zs.load("SOURMASH-MANIFEST.csv")?
+    );
+    assert_eq!(hs.load(path)?, zs.load(path)?);
+    // only the central directory, manifest and one signature are fetched
+    assert!(server.take_bytes_served() < zipsize);
+
+    // the subdir can be omitted
+    let sig = hs.load_sig("GCA_001593925.1_ASM159392v1_protein.faa.gz.sig")?;
+    assert_eq!(sig.md5sum(), zs.load_sig(path)?.md5sum());
+    assert!(matches!(
+        hs.load("missing.sig"),
+        Err(sourmash::Error::StorageError(
+            StorageError::PathNotFoundError(_)
+        ))
+    ));
+    assert!(hs.save("other", b"data").is_err());
+
+    // reopening only checks the end of the file, and loads from the cache
+    server.take_bytes_served();
+    let hs = HttpZipStorage::new(&url, Some(cache_dir))?;
+    assert_eq!(hs.load(path)?, zs.load(path)?);
+    assert_eq!(server.take_bytes_served(), 4096);
+
+    // without a cache
+    let hs = HttpZipStorage::new(&url, None)?;
+    assert_eq!(hs.load(path)?, zs.load(path)?);
+
+    assert!(matches!(
+        HttpZipStorage::new(&format!("{}/missing.zip", server.url), None),
+        Err(sourmash::Error::StorageError(StorageError::RemoteError(..)))
+    ));
+
+    Ok(())
+}
+
+#[cfg(feature = "http")]
+#[test]
+fn httpzipstorage_cache_invalidation() -> Result<(), Box<dyn std::error::Error>> {
+    use sourmash::storage::HttpZipStorage;
+
+    let root = TempDir::new()?;
+    let zippath = root.path().join("db.zip");
+    let cache = TempDir::new()?;
+    let cache_dir = camino::Utf8Path::from_path(cache.path()).unwrap();
+    let server = StaticServer::start(root.path())?;
+    let url = format!("{}/db.zip", server.url);
+
+    let mut filename = PathBuf::from(env!("CARGO_MANIFEST_DIR"));
+    filename.push("../../tests/test-data/genome-s10.fa.gz.sig");
+    let sig10 = Signature::from_path(filename)?.swap_remove(0);
+    let mut filename = PathBuf::from(env!("CARGO_MANIFEST_DIR"));
+    filename.push("../../tests/test-data/genome-s11.fa.gz.sig");
+    let sig11 = Signature::from_path(filename)?.swap_remove(0);
+
+    for sig in [sig10, sig11] {
+        let writer = ZipStorageWriter::new(zippath.to_str().unwrap(), false)?;
+        let location = writer.add_sig(&sig)?[0].internal_location().to_string();
+        writer.finish()?;
+        drop(writer);
+
+        let hs = HttpZipStorage::new(&url, Some(cache_dir))?;
+        assert_eq!(hs.filenames().len(), 2);
+        assert_eq!(hs.load_sig(&location)?.md5sum(), sig.md5sum());
+        assert!(hs.load(sourmash::storage::MANIFEST_PATH).is_ok());
+    }
+
+    Ok(())
+}
+
+#[cfg(feature = "http")]
+#[test]
+fn httpzipstorage_cache_errors() -> Result<(), Box<dyn std::error::Error>> {
+    use sourmash::storage::HttpZipStorage;
+
+    let mut basedir = PathBuf::from(env!("CARGO_MANIFEST_DIR"));
+    basedir.push("../../tests/test-data/prot/");
+    let server = StaticServer::start(&basedir)?;
+    let url = format!("{}/protein.zip", server.url);
+
+    // a cache that can't be created
+    let root = TempDir::new()?;
+    let not_a_dir = root.path().join("file");
+    std::fs::write(&not_a_dir, b"")?;
+    let cache_dir = camino::Utf8Path::from_path(&not_a_dir).unwrap();
+    let hs = HttpZipStorage::new(&url, Some(cache_dir))?;
+    assert!(hs.load(sourmash::storage::MANIFEST_PATH).is_ok());
+
+    // a cache removed by another process
+    let cache = TempDir::new()?;
+    let cache_dir = camino::Utf8Path::from_path(cache.path()).unwrap();
+    let hs = HttpZipStorage::new(&url, Some(cache_dir))?;
+    std::fs::remove_dir_all(cache.path())?;
+    assert!(hs.load(sourmash::storage::MANIFEST_PATH).is_ok());
+
+    // a corrupted member in the cache is evicted and fetched again
+    let cache = TempDir::new()?;
+    let cache_dir = camino::Utf8Path::from_path(cache.path()).unwrap();
+    let hs = HttpZipStorage::new(&url, Some(cache_dir))?;
+    let manifest = hs.load(sourmash::storage::MANIFEST_PATH)?;
+    let cached = cache
+        .path()
+        .join(format!("{:x}", md5::compute(&url)))
+        .join("members")
+        .join(format!(
+            "{:x}",
+            md5::compute(sourmash::storage::MANIFEST_PATH)
+        ));
+    assert_eq!(std::fs::read(&cached)?, manifest);
+
+    std::fs::write(&cached, b"corrupted")?;
+    assert_eq!(hs.load(sourmash::storage::MANIFEST_PATH)?, manifest);
+    assert_eq!(std::fs::read(&cached)?, manifest);
+
+    Ok(())
+}
+
+#[cfg(feature = "http")]
+#[test]
+fn httpzipstorage_size_mismatch() -> Result<(), Box<dyn std::error::Error>> {
+    use sourmash::storage::HttpZipStorage;
+
+    let mut basedir = PathBuf::from(env!("CARGO_MANIFEST_DIR"));
+    basedir.push("../../tests/test-data/prot/");
+    let mut data = std::fs::read(basedir.join("protein.zip"))?;
+
+    // shrink the uncompressed size of the manifest in the central directory
+    let name = sourmash::storage::MANIFEST_PATH.as_bytes();
+    let header = data
+        .windows(4)
+        .enumerate()
+        .filter(|(_, sig)| *sig == b"PK\x01\x02")
+        .map(|(i, _)| i)
+        .find(|i| data[i + 46..].starts_with(name))
+        .unwrap();
+    // compressed with deflate
+    assert_eq!(&data[header + 10..header + 12], &[8, 0]);
+    data[header + 24..header + 28].copy_from_slice(&1u32.to_le_bytes());
+
+    let root = TempDir::new()?;
+    std::fs::write(root.path().join("db.zip"), &data)?;
+    let server = StaticServer::start(root.path())?;
+    let url = format!("{}/db.zip", server.url);
+
+    let hs = HttpZipStorage::new(&url, None)?;
+    assert!(matches!(
+        hs.load(sourmash::storage::MANIFEST_PATH),
+        Err(sourmash::Error::StorageError(StorageError::DataReadError(
+            _
+        )))
+    ));
+
+    Ok(())
+}
+
+#[cfg(feature = "http")]
+#[test]
+fn httpzipstorage_spec() -> Result<(), Box<dyn std::error::Error>> {
+    let mut basedir = PathBuf::from(env!("CARGO_MANIFEST_DIR"));
+    basedir.push("../../tests/test-data/prot/");
+    let server = StaticServer::start(&basedir)?;
+    let url = format!("{}/protein.zip", server.url);
+
+    let storage = InnerStorage::from_spec(url.clone())?;
+    assert_eq!(storage.spec(), url);
+    assert_eq!(
+        storage.args()?,
+        StorageArgs::HttpZipStorage { url: url.clone() }
+    );
+    check_spec_roundtrip(&storage)?;
+
+    let collection = Collection::from_url(&url)?;
+    assert_eq!(collection.len(), 2);
+    for (_, record) in collection.iter() {
+        let sig = collection.sig_from_record(record)?;
+        assert_eq!(sig.md5sum(), record.md5().as_str());
+    }
 
     Ok(())
 }